@@ -1,4 +1,4 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
 
@@ -6,7 +6,10 @@ use serde::{Deserialize, Serialize};
 #[serde(rename_all = "camelCase")]
 pub struct ChapterFileList {
     pub hash: String,
-    pub data: Rc<Vec<String>>,
+    pub data: Arc<Vec<String>>,
+    /// Smaller, recompressed versions of the same pages, served from `data-saver/`
+    #[serde(default)]
+    pub data_saver: Arc<Vec<String>>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+/// Token endpoint for MangaDex personal API clients, see
+/// https://api.mangadex.org/docs/02-authentication/personal-clients/
+pub const TOKEN_URL: &str = "https://auth.mangadex.org/realms/mangadex/protocol/openid-connect/token";
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "grant_type", rename_all = "snake_case")]
+pub enum TokenRequest {
+    Password {
+        username: String,
+        password: String,
+        client_id: String,
+        client_secret: String,
+    },
+    RefreshToken {
+        refresh_token: String,
+        client_id: String,
+        client_secret: String,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// Lifetime of the access token, in seconds
+    pub expires_in: u64,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_token_request_form() {
+        let request = TokenRequest::RefreshToken {
+            refresh_token: "refresh".to_owned(),
+            client_id: "personal-client-abc".to_owned(),
+            client_secret: "secret".to_owned(),
+        };
+        let built = reqwest::Client::new().post(TOKEN_URL).form(&request).build().unwrap();
+        let body = std::str::from_utf8(built.body().unwrap().as_bytes().unwrap()).unwrap();
+        assert_eq!(
+            body,
+            "grant_type=refresh_token&refresh_token=refresh&client_id=personal-client-abc&client_secret=secret"
+        );
+    }
+}
@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorAttributes {
+    pub name: String,
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorData {
+    pub id: Uuid,
+    #[serde(rename = "type")]
+    pub data_type: String,
+    pub attributes: AuthorAttributes,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorListResponse {
+    pub result: String,
+    pub response: String,
+    pub limit: usize,
+    pub offset: usize,
+    pub total: usize,
+    pub data: Vec<AuthorData>,
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn can_parse_author_list() {
+        let response: super::AuthorListResponse = serde_json::from_str(
+            r#"{
+                "result": "ok",
+                "response": "collection",
+                "data": [{
+                    "id": "a5ee1f4b-7a28-4e23-9e5c-94ff5e3a7e2f",
+                    "type": "author",
+                    "attributes": {"name": "Yanagida Fumita", "twitter": null}
+                }],
+                "limit": 100,
+                "offset": 0,
+                "total": 1
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(response.data[0].attributes.name, "Yanagida Fumita");
+    }
+}
@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -5,9 +6,22 @@ use uuid::Uuid;
 #[serde(rename_all = "camelCase")]
 pub struct ChapterAttributes {
     pub title: Option<String>,
+    pub volume: Option<String>,
     pub chapter: Option<String>,
     pub pages: usize,
     pub translated_language: String,
+    /// Set for chapters hosted on another site, these have no pages on MangaDex
+    pub external_url: Option<String>,
+    pub publish_at: Option<DateTime<Utc>>,
+    pub readable_at: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Incremented every time the chapter is edited
+    #[serde(default)]
+    pub version: u32,
+    /// Attributes we don't use directly, kept so they can be exported as metadata
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -23,9 +37,37 @@ pub struct ChapterData {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ChapterRelationShip {
-    id: String,
+    pub id: String,
     #[serde(rename = "type")]
-    relationship_type: String,
+    pub relationship_type: String,
+    /// Only present when the entity was requested with `includes[]`
+    pub attributes: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+impl ChapterData {
+    /// Names of the groups that scanlated this chapter, as far as they were included
+    /// in the response
+    pub fn scanlation_group_names(&self) -> Vec<String> {
+        self.relationships
+            .iter()
+            .filter(|r| r.relationship_type == "scanlation_group")
+            .filter_map(|r| r.attributes.as_ref()?.get("name")?.as_str().map(str::to_owned))
+            .collect()
+    }
+
+    /// When the chapter came out, i.e. when it became readable, or when it was set to be
+    /// published for chapters from before that was recorded
+    pub fn release_date(&self) -> Option<DateTime<Utc>> {
+        self.attributes.readable_at.or(self.attributes.publish_at)
+    }
+
+    /// Id of the user that uploaded the chapter
+    pub fn uploader(&self) -> Option<Uuid> {
+        self.relationships
+            .iter()
+            .find(|r| r.relationship_type == "user")
+            .and_then(|r| Uuid::parse_str(&r.id).ok())
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -38,6 +80,44 @@ pub struct ChapterResponse {
 
 #[cfg(test)]
 mod test {
+    #[test]
+    fn can_parse_chapter_attributes() {
+        let data: super::ChapterData = serde_json::from_str(
+            r#"{
+                "id": "417d64e1-6c88-48f8-b507-ad43e9636888",
+                "type": "chapter",
+                "attributes": {
+                    "volume": null,
+                    "chapter": "953.5",
+                    "title": "Extra",
+                    "translatedLanguage": "en",
+                    "externalUrl": null,
+                    "publishAt": "2021-05-24T17:01:45+00:00",
+                    "readableAt": "2021-05-24T17:01:45+00:00",
+                    "createdAt": "2021-05-24T17:01:45+00:00",
+                    "updatedAt": "2022-01-02T03:04:05+00:00",
+                    "pages": 4,
+                    "version": 3
+                },
+                "relationships": [
+                    {"id": "f8cc4f8a-e596-4618-ab05-ef6572980bbf", "type": "user"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(data.attributes.version, 3);
+        assert_eq!(data.attributes.external_url, None);
+        assert_eq!(
+            data.attributes.updated_at.unwrap().to_rfc3339(),
+            "2022-01-02T03:04:05+00:00"
+        );
+        assert_eq!(
+            data.uploader().unwrap().to_string(),
+            "f8cc4f8a-e596-4618-ab05-ef6572980bbf"
+        );
+        assert!(data.attributes.other.is_empty());
+    }
+
     #[tokio::test]
     async fn can_get_chapter_response() -> Result<(), reqwest::Error> {
         use crate::client::CLIENT;
@@ -0,0 +1,60 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub const COVER_UPLOADS_URL: &str = "https://uploads.mangadex.org/covers";
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CoverAttributes {
+    pub volume: Option<String>,
+    pub file_name: String,
+    pub locale: Option<String>,
+    pub created_at: String,
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CoverData {
+    pub id: Uuid,
+    #[serde(rename = "type")]
+    pub data_type: String,
+    pub attributes: CoverAttributes,
+}
+
+impl CoverData {
+    pub fn image_url(&self, manga_id: Uuid) -> String {
+        format!("{}/{}/{}", COVER_UPLOADS_URL, manga_id, self.attributes.file_name)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CoverListResponse {
+    pub result: String,
+    pub response: String,
+    pub limit: usize,
+    pub offset: usize,
+    pub total: usize,
+    pub data: Vec<CoverData>,
+}
+
+#[cfg(test)]
+mod test {
+    #[tokio::test]
+    async fn can_get_cover_list_response() -> Result<(), reqwest::Error> {
+        // Tomo-chan wa onna no ko!
+        // Url: https://api.mangadex.org/cover?manga[]=76ee7069-23b4-493c-bc44-34ccbf3051a8
+        let title_id = "76ee7069-23b4-493c-bc44-34ccbf3051a8";
+        let url = url::Url::parse(&format!("https://api.mangadex.org/cover?manga[]={}", title_id)).unwrap();
+        crate::client::CLIENT
+            .clone()
+            .get(url.clone())
+            .send()
+            .await?
+            .json::<super::CoverListResponse>()
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+
+/// Body of a failed API request, see https://api.mangadex.org/docs/swagger.html#model-ErrorResponse
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ErrorResponse {
+    pub result: String,
+    #[serde(default)]
+    pub errors: Vec<ApiError>,
+}
+
+/// Why MangaDex refused a request, e.g. because the chapter doesn't exist
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ApiError {
+    /// HTTP status of the response
+    pub status: u16,
+    /// Short summary, e.g. "Not found"
+    pub title: String,
+    /// Explanation meant for users, e.g. "Chapter could not be found"
+    pub detail: Option<String>,
+}
+
+impl ApiError {
+    /// The first error in the body of a failed response, if it is an error response
+    pub fn from_body(body: &[u8]) -> Option<ApiError> {
+        let response: ErrorResponse = serde_json::from_slice(body).ok()?;
+        if response.result != "error" {
+            return None;
+        }
+        response.errors.into_iter().next()
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        self.status == 404
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.detail {
+            Some(ref detail) if !detail.is_empty() => write!(f, "{}", detail),
+            _ => write!(f, "{}", self.title),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_body() {
+        let body = br#"{
+            "result": "error",
+            "errors": [{
+                "id": "b2e8e2f4-5d0a-4c3b-9d1e-4f6a7b8c9d0e",
+                "status": 404,
+                "title": "Not found",
+                "detail": "Chapter could not be found",
+                "context": null
+            }]
+        }"#;
+        let error = ApiError::from_body(body).unwrap();
+        assert_eq!(error.status, 404);
+        assert!(error.is_not_found());
+        assert_eq!(error.to_string(), "Chapter could not be found");
+
+        let body = br#"{"result": "error", "errors": [{"status": 403, "title": "Forbidden", "detail": null}]}"#;
+        assert_eq!(ApiError::from_body(body).unwrap().to_string(), "Forbidden");
+        assert_eq!(ApiError::from_body(br#"{"result": "ok"}"#), None);
+        assert_eq!(ApiError::from_body(b"<html>Bad gateway</html>"), None);
+    }
+}
@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupAttributes {
+    pub name: String,
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupData {
+    pub id: Uuid,
+    #[serde(rename = "type")]
+    pub data_type: String,
+    pub attributes: GroupAttributes,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupListResponse {
+    pub result: String,
+    pub response: String,
+    pub limit: usize,
+    pub offset: usize,
+    pub total: usize,
+    pub data: Vec<GroupData>,
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn can_parse_group_list() {
+        let response: super::GroupListResponse = serde_json::from_str(
+            r#"{
+                "result": "ok",
+                "response": "collection",
+                "data": [{
+                    "id": "a5ee1f4b-7a28-4e23-9e5c-94ff5e3a7e2f",
+                    "type": "scanlation_group",
+                    "attributes": {"name": "Some Scans", "website": null, "locked": false}
+                }],
+                "limit": 100,
+                "offset": 0,
+                "total": 1
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(response.data[0].attributes.name, "Some Scans");
+    }
+}
@@ -1,4 +1,4 @@
-use std::rc::Rc;
+use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
@@ -8,7 +8,14 @@ use api::chapter::ChapterData;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
-pub struct MangaRelationShip {}
+pub struct MangaRelationShip {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub relationship_type: String,
+    /// Anything else the API sent, e.g. attributes of referenced entities
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>,
+}
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -21,14 +28,202 @@ pub struct MangaFeedResponse {
     pub data: Vec<ChapterData>,
 }
 
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Hash, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PublicationStatus {
+    Ongoing,
+    Completed,
+    Hiatus,
+    Cancelled,
+}
+
+impl std::fmt::Display for PublicationStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PublicationStatus::Ongoing => write!(f, "ongoing"),
+            PublicationStatus::Completed => write!(f, "completed"),
+            PublicationStatus::Hiatus => write!(f, "hiatus"),
+            PublicationStatus::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl std::str::FromStr for PublicationStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ongoing" => Ok(PublicationStatus::Ongoing),
+            "completed" => Ok(PublicationStatus::Completed),
+            "hiatus" => Ok(PublicationStatus::Hiatus),
+            "cancelled" => Ok(PublicationStatus::Cancelled),
+            v => Err(format!("Unknown publication status: {}", v)),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MangaAttributes {
-    pub title: Option<String>,
-    pub chapter: Option<String>,
-    pub hash: String,
-    pub data: Rc<Vec<String>>,
-    pub translated_language: String,
+    /// Keyed by language code
+    pub title: HashMap<String, String>,
+    pub status: Option<PublicationStatus>,
+    /// Attributes we don't use directly, kept so they can be exported as metadata
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>,
+}
+
+/// How explicit a title is, as MangaDex rates it
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Hash, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentRating {
+    Safe,
+    Suggestive,
+    Erotica,
+    Pornographic,
+}
+
+impl std::fmt::Display for ContentRating {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContentRating::Safe => write!(f, "safe"),
+            ContentRating::Suggestive => write!(f, "suggestive"),
+            ContentRating::Erotica => write!(f, "erotica"),
+            ContentRating::Pornographic => write!(f, "pornographic"),
+        }
+    }
+}
+
+impl std::str::FromStr for ContentRating {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "safe" => Ok(ContentRating::Safe),
+            "suggestive" => Ok(ContentRating::Suggestive),
+            "erotica" => Ok(ContentRating::Erotica),
+            "pornographic" => Ok(ContentRating::Pornographic),
+            v => Err(format!(
+                "Unknown content rating \"{}\", expected safe, suggestive, erotica or pornographic",
+                v
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TagAttributes {
+    /// Keyed by language code
+    pub name: HashMap<String, String>,
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TagData {
+    pub id: Uuid,
+    #[serde(rename = "type")]
+    pub data_type: String,
+    pub attributes: TagAttributes,
+}
+
+impl TagData {
+    /// The english name if there is one, otherwise whatever the API gave us
+    pub fn display_name(&self) -> Option<&str> {
+        self.attributes
+            .name
+            .get("en")
+            .or_else(|| self.attributes.name.values().next())
+            .map(String::as_str)
+    }
+
+    /// What kind of tag this is: genre, theme, format or content
+    pub fn group(&self) -> Option<&str> {
+        self.attributes.other.get("group")?.as_str()
+    }
+}
+
+/// Every tag there is, from `GET /manga/tag`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TagListResponse {
+    pub result: String,
+    pub data: Vec<TagData>,
+}
+
+impl MangaAttributes {
+    /// The english title if there is one, otherwise whatever the API gave us
+    pub fn display_title(&self) -> Option<&str> {
+        self.title
+            .get("en")
+            .or_else(|| self.title.values().next())
+            .map(String::as_str)
+    }
+
+    /// Language the title was first published in, e.g. `ja` for manga
+    pub fn original_language(&self) -> Option<&str> {
+        self.other.get("originalLanguage")?.as_str()
+    }
+
+    /// The title in the first of `lang_codes` that it has, from the main title or the
+    /// alternative ones, falling back to [`Self::display_title`]
+    pub fn title_in(&self, lang_codes: &[String]) -> Option<&str> {
+        let alt_titles = self.other.get("altTitles").and_then(|titles| titles.as_array());
+        for lang_code in lang_codes {
+            if let Some(title) = self.title.get(lang_code) {
+                return Some(title);
+            }
+            let alt_title = alt_titles
+                .into_iter()
+                .flatten()
+                .find_map(|titles| titles.get(lang_code)?.as_str());
+            if alt_title.is_some() {
+                return alt_title;
+            }
+        }
+        self.display_title()
+    }
+
+    /// Other titles the manga is known by, as language code and title
+    pub fn alt_titles(&self) -> Vec<(String, String)> {
+        let alt_titles: Vec<HashMap<String, String>> = self
+            .other
+            .get("altTitles")
+            .and_then(|titles| serde_json::from_value(titles.clone()).ok())
+            .unwrap_or_default();
+        alt_titles.into_iter().flatten().collect()
+    }
+
+    /// The english description if there is one, otherwise whatever the API gave us
+    pub fn description(&self) -> Option<&str> {
+        let descriptions = self.other.get("description")?.as_object()?;
+        descriptions
+            .get("en")
+            .or_else(|| descriptions.values().next())?
+            .as_str()
+            .filter(|description| !description.trim().is_empty())
+    }
+
+    pub fn content_rating(&self) -> Option<ContentRating> {
+        serde_json::from_value(self.other.get("contentRating")?.clone()).ok()
+    }
+
+    /// Languages that chapters of the title have been translated to
+    pub fn available_languages(&self) -> Vec<String> {
+        self.other
+            .get("availableTranslatedLanguages")
+            .and_then(|languages| serde_json::from_value(languages.clone()).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn tags(&self) -> Vec<TagData> {
+        self.other
+            .get("tags")
+            .and_then(|tags| serde_json::from_value(tags.clone()).ok())
+            .unwrap_or_default()
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -41,6 +236,18 @@ pub struct MangaData {
     pub relationships: Vec<MangaRelationShip>,
 }
 
+impl MangaData {
+    /// Names of the authors or artists of the title, as far as they were included in
+    /// the response
+    pub fn creator_names(&self, relationship_type: &str) -> Vec<String> {
+        self.relationships
+            .iter()
+            .filter(|r| r.relationship_type == relationship_type)
+            .filter_map(|r| r.other.get("attributes")?.get("name")?.as_str().map(str::to_owned))
+            .collect()
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MangaResponse {
@@ -48,8 +255,35 @@ pub struct MangaResponse {
     pub data: MangaData,
 }
 
+/// A page of titles from `GET /manga`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MangaListResponse {
+    pub result: String,
+    pub limit: usize,
+    pub offset: usize,
+    pub total: usize,
+    pub data: Vec<MangaData>,
+}
+
 #[cfg(test)]
 mod test {
+    #[tokio::test]
+    async fn can_get_manga_data_response() -> Result<(), reqwest::Error> {
+        // Tomo-chan wa onna no ko!
+        // Url: https://api.mangadex.org/manga/76ee7069-23b4-493c-bc44-34ccbf3051a8
+        let title_id = "76ee7069-23b4-493c-bc44-34ccbf3051a8";
+        let url = url::Url::parse(&format!("https://api.mangadex.org/manga/{}", title_id)).unwrap();
+        crate::client::CLIENT
+            .clone()
+            .get(url.clone())
+            .send()
+            .await?
+            .json::<super::MangaResponse>()
+            .await?;
+        Ok(())
+    }
+
     #[tokio::test]
     async fn can_get_manga_response() -> Result<(), reqwest::Error> {
         // Tomo-chan wa onna no ko!
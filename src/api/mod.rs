@@ -1,4 +1,11 @@
 pub(crate) mod at_home;
+pub(crate) mod auth;
+pub(crate) mod author;
 pub(crate) mod chapter;
+pub(crate) mod cover;
+pub(crate) mod error;
+pub(crate) mod group;
 pub(crate) mod manga;
+pub(crate) mod report;
+pub(crate) mod statistics;
 pub(crate) mod util;
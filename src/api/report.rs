@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+
+pub const REPORT_URL: &str = "https://api.mangadex.org/report";
+
+/// Body of the MD@Home report sent after every image download, see
+/// https://api.mangadex.org/docs/retrieving-chapter/#the-mangadexhome-report-endpoint
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReportRequest {
+    pub url: String,
+    pub success: bool,
+    pub bytes: usize,
+    /// Time taken to download the image, in milliseconds
+    pub duration: u128,
+    pub cached: bool,
+}
+
+impl ReportRequest {
+    /// MangaDex only wants reports for at-home nodes, not for its own servers
+    pub fn should_report(url: &url::Url) -> bool {
+        !url.host_str().is_some_and(|host| host.ends_with("mangadex.org"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_should_report() {
+        let node = url::Url::parse("https://abc.def.mangadex.network:443/token/data/hash/1.png").unwrap();
+        let uploads = url::Url::parse("https://uploads.mangadex.org/data/hash/1.png").unwrap();
+        assert!(super::ReportRequest::should_report(&node));
+        assert!(!super::ReportRequest::should_report(&uploads));
+    }
+}
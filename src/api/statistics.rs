@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommentStatistics {
+    pub thread_id: u64,
+    pub replies_count: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RatingStatistics {
+    /// Out of 10, None until someone rated the title
+    pub average: Option<f64>,
+    /// The average weighted towards the one of all titles, which MangaDex sorts by
+    pub bayesian: Option<f64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MangaStatistics {
+    /// None until someone starts the forum thread of the title
+    pub comments: Option<CommentStatistics>,
+    pub rating: RatingStatistics,
+    pub follows: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MangaStatisticsResponse {
+    pub result: String,
+    pub statistics: HashMap<Uuid, MangaStatistics>,
+}
+
+#[cfg(test)]
+mod test {
+    #[tokio::test]
+    async fn can_get_manga_statistics_response() -> Result<(), reqwest::Error> {
+        // Tomo-chan wa onna no ko!
+        // Url: https://api.mangadex.org/statistics/manga/76ee7069-23b4-493c-bc44-34ccbf3051a8
+        let title_id = "76ee7069-23b4-493c-bc44-34ccbf3051a8";
+        let url = url::Url::parse(&format!("https://api.mangadex.org/statistics/manga/{}", title_id)).unwrap();
+        crate::client::CLIENT
+            .clone()
+            .get(url.clone())
+            .send()
+            .await?
+            .json::<super::MangaStatisticsResponse>()
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use log::{debug, info};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::manifest::{find_manifest_dirs, MANIFEST_FILE_NAME};
+use crate::retry::{DownloadError, Result};
+
+pub const ATTESTATION_FILE_NAME: &str = "ATTESTATION";
+const HEADER: &str = "mdscrape attestation v1";
+const KEY_FILE_NAME: &str = "attestation.key";
+
+/// A signed statement that a title directory holds exactly the chapters listed in its
+/// checksum manifests. The signature covers a hash over every manifest, so together
+/// with the manifests themselves any change to a page can be detected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attestation {
+    pub title_id: Uuid,
+    pub manifest_digest: String,
+    pub public_key: VerifyingKey,
+    pub signature: Signature,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex<const N: usize>(value: &str) -> Option<[u8; N]> {
+    let value = value.trim();
+    if value.len() != 2 * N || !value.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&value[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+fn attestation_error(message: impl Into<String>) -> DownloadError {
+    DownloadError::AttestationError(message.into())
+}
+
+/// Parse a public key written as hex, like the `public-key` line of an attestation
+pub fn parse_public_key(value: &str) -> Result<VerifyingKey> {
+    from_hex::<32>(value)
+        .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+        .ok_or_else(|| attestation_error("Invalid public key"))
+}
+
+/// Hash of every manifest under root, keyed by its path relative to root
+pub fn manifest_digest(root: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    for dir in find_manifest_dirs(root) {
+        let manifest = dir.join(MANIFEST_FILE_NAME);
+        let relative = manifest.strip_prefix(root).unwrap_or(&manifest);
+        let digest = Sha256::digest(fs::read(&manifest)?);
+        hasher.update(format!("{:x}  {}\n", digest, relative.to_string_lossy()));
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn key_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("mdscrape").join(KEY_FILE_NAME))
+}
+
+/// Load the signing key mdscrape uses for attestations, creating one the first time
+pub fn load_or_create_key() -> Result<SigningKey> {
+    let path = key_path().ok_or_else(|| attestation_error("Couldn't determine the config directory"))?;
+    if path.exists() {
+        let secret = from_hex::<32>(&fs::read_to_string(&path)?)
+            .ok_or_else(|| attestation_error(format!("Invalid signing key in {:?}", path)))?;
+        return Ok(SigningKey::from_bytes(&secret));
+    }
+    info!("Creating attestation signing key at {:?}", path);
+    let key = SigningKey::from_bytes(&rand::random());
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, to_hex(key.as_bytes()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(key)
+}
+
+impl Attestation {
+    fn payload(title_id: Uuid, manifest_digest: &str) -> String {
+        format!("{}\ntitle {}\nmanifest-sha256 {}\n", HEADER, title_id, manifest_digest)
+    }
+
+    pub fn create(title_id: Uuid, root: &Path, key: &SigningKey) -> Result<Self> {
+        let manifest_digest = manifest_digest(root)?;
+        let signature = key.sign(Self::payload(title_id, &manifest_digest).as_bytes());
+        Ok(Attestation {
+            title_id,
+            manifest_digest,
+            public_key: key.verifying_key(),
+            signature,
+        })
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut lines = contents.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(attestation_error("Not an mdscrape attestation"));
+        }
+        let mut field = |name: &str| {
+            lines
+                .next()
+                .and_then(|line| line.strip_prefix(name))
+                .and_then(|value| value.strip_prefix(' '))
+                .ok_or_else(|| attestation_error(format!("Attestation is missing {}", name)))
+        };
+        let title_id = Uuid::parse_str(field("title")?).map_err(|e| attestation_error(e.to_string()))?;
+        let manifest_digest = field("manifest-sha256")?.to_owned();
+        let public_key = parse_public_key(field("public-key")?)
+            .map_err(|_| attestation_error("Invalid public key in attestation"))?;
+        let signature = from_hex::<64>(field("signature")?)
+            .map(|bytes| Signature::from_bytes(&bytes))
+            .ok_or_else(|| attestation_error("Invalid signature in attestation"))?;
+        Ok(Attestation {
+            title_id,
+            manifest_digest,
+            public_key,
+            signature,
+        })
+    }
+
+    pub fn read(dir: &Path) -> Result<Self> {
+        Self::parse(&fs::read_to_string(dir.join(ATTESTATION_FILE_NAME))?)
+    }
+
+    pub fn write(&self, dir: &Path) -> Result<()> {
+        let mut contents = Self::payload(self.title_id, &self.manifest_digest);
+        contents.push_str(&format!("public-key {}\n", to_hex(self.public_key.as_bytes())));
+        contents.push_str(&format!("signature {}\n", to_hex(&self.signature.to_bytes())));
+        fs::write(dir.join(ATTESTATION_FILE_NAME), contents)?;
+        Ok(())
+    }
+
+    /// Check that the attestation was signed with the trusted key, and that the
+    /// manifests under root are the ones that were signed. The pages themselves are
+    /// checked against the manifests separately. A key taken from the attestation itself
+    /// proves nothing, anyone who changes the pages can sign them again with their own.
+    pub fn verify(&self, root: &Path, trusted_key: &VerifyingKey) -> Result<()> {
+        if self.public_key != *trusted_key {
+            return Err(attestation_error(format!(
+                "Signed with {}, which isn't the trusted key",
+                self.fingerprint()
+            )));
+        }
+        self.public_key
+            .verify(
+                Self::payload(self.title_id, &self.manifest_digest).as_bytes(),
+                &self.signature,
+            )
+            .map_err(|_| attestation_error("Signature doesn't match, the attestation was tampered with"))?;
+        if manifest_digest(root)? != self.manifest_digest {
+            return Err(attestation_error(
+                "Checksum manifests changed since the title was attested",
+            ));
+        }
+        debug!("Attestation for {} is valid", self.title_id);
+        Ok(())
+    }
+
+    pub fn fingerprint(&self) -> String {
+        to_hex(self.public_key.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::manifest::Manifest;
+
+    #[test]
+    fn test_attestation_roundtrip() {
+        let root = std::env::temp_dir().join(format!("mdscrape-test-{}", Uuid::from_u128(rand::random())));
+        let chapter = root.join("md00001");
+        fs::create_dir_all(&chapter).unwrap();
+        let page = chapter.join("0001.png");
+        fs::write(&page, b"page").unwrap();
+        Manifest::from_files(Uuid::from_u128(1), std::slice::from_ref(&page))
+            .unwrap()
+            .write(&chapter)
+            .unwrap();
+
+        let key = SigningKey::from_bytes(&[7u8; 32]);
+        let attestation = Attestation::create(Uuid::from_u128(2), &root, &key).unwrap();
+        attestation.write(&root).unwrap();
+        let read = Attestation::read(&root).unwrap();
+        assert_eq!(read, attestation);
+        assert!(read.verify(&root, &key.verifying_key()).is_ok());
+        assert_eq!(parse_public_key(&read.fingerprint()).unwrap(), key.verifying_key());
+
+        // A valid signature by anyone else's key isn't enough
+        let other = SigningKey::from_bytes(&[8u8; 32]);
+        let resigned = Attestation::create(Uuid::from_u128(2), &root, &other).unwrap();
+        assert!(resigned.verify(&root, &other.verifying_key()).is_ok());
+        assert!(resigned.verify(&root, &key.verifying_key()).is_err());
+
+        // Rewriting a manifest to match a tampered page is caught
+        fs::write(&page, b"tampered").unwrap();
+        Manifest::from_files(Uuid::from_u128(1), std::slice::from_ref(&page))
+            .unwrap()
+            .write(&chapter)
+            .unwrap();
+        assert!(read.verify(&root, &key.verifying_key()).is_err());
+
+        // As is editing the attestation itself
+        let forged = Attestation {
+            manifest_digest: manifest_digest(&root).unwrap(),
+            ..read
+        };
+        assert!(forged.verify(&root, &key.verifying_key()).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,218 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use log::{debug, warn};
+use reqwest::header::{HeaderMap, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{RequestBuilder, Url};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::retry::Result;
+
+const CACHE_DIR_NAME: &str = "responses";
+/// How long chapter feeds are answered from the cache
+pub const FEED_TTL: Duration = Duration::from_secs(10 * 60);
+/// How long at-home server responses are answered from the cache. The servers hand
+/// out tokens that expire, so this has to stay well below their lifetime.
+pub const AT_HOME_TTL: Duration = Duration::from_secs(5 * 60);
+/// Responses older than this are removed when the cache is opened
+const MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// What a server said identifies the version of a response, sent back with the next
+/// request so that the server can answer 304 Not Modified instead of repeating it
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Validators {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name| Some(headers.get(name)?.to_str().ok()?.to_owned());
+        Validators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    /// Make a request conditional on the response having changed
+    pub fn apply(&self, mut request: RequestBuilder) -> RequestBuilder {
+        if let Some(ref etag) = self.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(ref last_modified) = self.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        request
+    }
+}
+
+/// A cached response that is too old to be used as it is, but can be checked with the
+/// server by a conditional request
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StaleResponse {
+    pub body: Vec<u8>,
+    pub validators: Validators,
+}
+
+/// Whether the response cache is read and written, set with `--no-cache`, `--refresh`
+/// and `--offline`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CacheMode {
+    #[default]
+    Use,
+    /// Make every request again, but keep the responses for later runs
+    Refresh,
+    Off,
+    /// Use responses however old they are, and keep them all, since nothing else can be
+    /// asked
+    Offline,
+}
+
+/// Responses of API calls, kept in the cache directory. Those that are expensive to
+/// repeat are used as they are for a short while, so re-running soon after a failure
+/// starts the downloads again without asking for the same chapter lists and servers
+/// first. After that, and for every other API call, the server is asked whether the
+/// response changed, which a server can answer without counting it against the rate
+/// limit as heavily.
+#[derive(Debug, Default)]
+pub struct ResponseCache {
+    dir: Option<PathBuf>,
+    mode: CacheMode,
+}
+
+impl ResponseCache {
+    /// Open the cache in the cache directory, dropping responses too old to be of use
+    pub fn load(mode: CacheMode) -> Self {
+        let dir = match mode {
+            CacheMode::Off => None,
+            _ => dirs::cache_dir().map(|dir| dir.join("mdscrape").join(CACHE_DIR_NAME)),
+        };
+        if let (Some(ref dir), false) = (&dir, mode == CacheMode::Offline) {
+            Self::prune(dir);
+        }
+        ResponseCache { dir, mode }
+    }
+
+    #[cfg(test)]
+    fn in_dir(dir: PathBuf, mode: CacheMode) -> Self {
+        ResponseCache { dir: Some(dir), mode }
+    }
+
+    fn prune(dir: &Path) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for entry in entries.flatten() {
+            if age(&entry.path()).is_some_and(|age| age > MAX_AGE) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+
+    fn path(&self, url: &Url, extension: &str) -> Option<PathBuf> {
+        let digest = Sha256::digest(url.as_str().as_bytes());
+        let name: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+        Some(self.dir.as_ref()?.join(format!("{}.{}", name, extension)))
+    }
+
+    /// The body of the response to `url`, if one was stored less than `ttl` ago
+    pub fn get(&self, url: &Url, ttl: Duration) -> Option<Vec<u8>> {
+        if !matches!(self.mode, CacheMode::Use | CacheMode::Offline) {
+            return None;
+        }
+        let path = self.path(url, "json")?;
+        if self.mode != CacheMode::Offline && age(&path)? >= ttl {
+            return None;
+        }
+        let body = fs::read(&path).ok()?;
+        debug!("Using the cached response for {}", url);
+        Some(body)
+    }
+
+    /// The last response to `url`, if the server gave a way to check whether it changed
+    pub fn stale(&self, url: &Url) -> Option<StaleResponse> {
+        let validators: Validators = serde_json::from_slice(&fs::read(self.path(url, "validators")?).ok()?).ok()?;
+        let body = fs::read(self.path(url, "json")?).ok()?;
+        Some(StaleResponse { body, validators })
+    }
+
+    /// Keep a response, with what identifies its version. It is only worth keeping if it
+    /// is used as it is for a while, or can be checked with the server.
+    pub fn put(&self, url: &Url, body: &[u8], validators: &Validators) {
+        let (path, validators_path) = match (self.path(url, "json"), self.path(url, "validators")) {
+            (Some(path), Some(validators_path)) => (path, validators_path),
+            _ => return,
+        };
+        let result = (|| -> Result<()> {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, body)?;
+            if validators.is_empty() {
+                if validators_path.exists() {
+                    fs::remove_file(&validators_path)?;
+                }
+            } else {
+                fs::write(&validators_path, serde_json::to_vec(validators)?)?;
+            }
+            Ok(())
+        })();
+        if let Err(e) = result {
+            warn!("Failed to cache the response for {}: {}", url, e);
+        }
+    }
+}
+
+/// How long ago a file was last written
+fn age(path: &Path) -> Option<Duration> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(SystemTime::now().duration_since(modified).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_response_cache() {
+        let dir = std::env::temp_dir().join(format!("mdscrape-test-{}", Uuid::from_u128(rand::random())));
+        let url = Url::parse("https://api.mangadex.org/at-home/server/417d64e1-6c88-48f8-b507-ad43e9636888").unwrap();
+        let cache = ResponseCache::in_dir(dir.clone(), CacheMode::Use);
+        assert_eq!(cache.get(&url, AT_HOME_TTL), None);
+        cache.put(&url, b"{}", &Validators::default());
+        assert_eq!(cache.get(&url, AT_HOME_TTL), Some(b"{}".to_vec()));
+        assert_eq!(cache.get(&url.join("other").unwrap(), AT_HOME_TTL), None);
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(cache.get(&url, Duration::from_millis(10)), None);
+        assert_eq!(cache.stale(&url), None);
+
+        // Refreshing makes the request again, but still stores the response
+        let refresh = ResponseCache::in_dir(dir.clone(), CacheMode::Refresh);
+        assert_eq!(refresh.get(&url, AT_HOME_TTL), None);
+        let validators = Validators {
+            etag: Some("\"abc\"".to_owned()),
+            last_modified: None,
+        };
+        refresh.put(&url, b"[]", &validators);
+        assert_eq!(cache.get(&url, AT_HOME_TTL), Some(b"[]".to_vec()));
+        assert_eq!(
+            refresh.stale(&url),
+            Some(StaleResponse {
+                body: b"[]".to_vec(),
+                validators
+            })
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
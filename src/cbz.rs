@@ -0,0 +1,128 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::metadata::COMIC_INFO_FILE_NAME;
+use crate::retry::Result;
+
+/// Path of an archive packaged from the chapter in dir, next to the directory
+pub fn sibling_path(dir: &Path, extension: &str) -> PathBuf {
+    let mut path = dir.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+/// Where `--cbz` puts the archive of the chapter in dir
+pub fn cbz_path(dir: &Path) -> PathBuf {
+    sibling_path(dir, "cbz")
+}
+
+/// Whether a file in a chapter directory is one of its pages, e.g. `0001.png`
+pub fn is_page_file(file_name: &str) -> bool {
+    file_name
+        .split_once('.')
+        .is_some_and(|(stem, _)| stem.len() == 4 && stem.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Write a zip archive to path with `build`. It is written next to it first, so an
+/// interrupted run never leaves a truncated archive behind.
+pub fn write_archive(
+    path: &Path,
+    build: impl FnOnce(&mut ZipWriter<File>) -> zip::result::ZipResult<()>,
+) -> Result<()> {
+    let part_path = sibling_path(path, "part");
+    let mut zip = ZipWriter::new(File::create(&part_path)?);
+    let result = build(&mut zip).and_then(|()| zip.finish().map(|_| ()));
+    if let Err(e) = result {
+        let _ = fs::remove_file(&part_path);
+        return Err(std::io::Error::other(e).into());
+    }
+    fs::rename(&part_path, path)?;
+    Ok(())
+}
+
+/// Package the pages of the chapter in dir, and its ComicInfo.xml if there is one, as
+/// a comic book archive next to it. Returns the path of the archive.
+pub fn write_chapter_cbz(dir: &Path) -> Result<PathBuf> {
+    let path = cbz_path(dir);
+    let mut pages = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let file_name = entry?.file_name().to_string_lossy().into_owned();
+        if is_page_file(&file_name) {
+            pages.push(file_name);
+        }
+    }
+    pages.sort();
+    let comic_info = dir.join(COMIC_INFO_FILE_NAME);
+    debug!("Packaging {} pages of {:?} into {:?}", pages.len(), dir, path);
+    write_archive(&path, |zip| {
+        // Images are compressed already
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        for page in &pages {
+            zip.start_file(page.as_str(), stored)?;
+            zip.write_all(&fs::read(dir.join(page))?)?;
+        }
+        if comic_info.exists() {
+            let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+            zip.start_file(COMIC_INFO_FILE_NAME, deflated)?;
+            zip.write_all(&fs::read(&comic_info)?)?;
+        }
+        Ok(())
+    })?;
+    Ok(path)
+}
+
+/// Unpack the pages of the comic book archive of the chapter in dir into the
+/// directory, for chapters whose loose pages were removed. Returns how many there were.
+pub fn extract_pages(dir: &Path) -> Result<usize> {
+    let mut archive = zip::ZipArchive::new(File::open(cbz_path(dir))?).map_err(std::io::Error::other)?;
+    fs::create_dir_all(dir)?;
+    let mut pages = 0;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(std::io::Error::other)?;
+        // Only plain page names, which can't point outside the directory
+        let file_name = file.name().to_owned();
+        if !is_page_file(&file_name) || file_name.contains(['/', '\\']) {
+            continue;
+        }
+        std::io::copy(&mut file, &mut File::create(dir.join(&file_name))?)?;
+        pages += 1;
+    }
+    debug!("Extracted {} pages of {:?}", pages, cbz_path(dir));
+    Ok(pages)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_chapter_cbz() {
+        let dir = std::env::temp_dir().join(format!("mdscrape-test-{}", Uuid::from_u128(rand::random())));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("0002.jpg"), b"second").unwrap();
+        fs::write(dir.join("0001.png"), b"first").unwrap();
+        fs::write(dir.join(COMIC_INFO_FILE_NAME), "<ComicInfo/>").unwrap();
+        fs::write(dir.join("checksums.sha256"), "").unwrap();
+        let path = write_chapter_cbz(&dir).unwrap();
+        assert_eq!(path, sibling_path(&dir, "cbz"));
+        assert!(!sibling_path(&path, "part").exists());
+
+        let archive = zip::ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let names: Vec<_> = archive.file_names().collect();
+        assert_eq!(names, vec!["0001.png", "0002.jpg", COMIC_INFO_FILE_NAME]);
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(extract_pages(&dir).unwrap(), 2);
+        assert_eq!(fs::read(dir.join("0001.png")).unwrap(), b"first");
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
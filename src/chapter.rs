@@ -1,173 +1,826 @@
 use reqwest::Url;
-use serde::de::DeserializeOwned;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
-use std::rc::Rc;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use crate::api;
+use crate::cache;
+use crate::cbz;
+use crate::common::run_blocking;
 
-use log::debug;
+use log::{debug, warn};
 
-use crate::common::*;
 use crate::context::ScrapeContext;
+use crate::convert;
+use crate::epub::{self, EpubInfo, ReadingDirection};
+use crate::existing::ExistingPolicy;
+use crate::feed::FeedEntry;
+use crate::manifest::{self, Manifest};
+use crate::metadata::{ComicInfo, COMIC_INFO_FILE_NAME};
+use crate::names;
+use crate::nodes::node_key;
+use crate::plan::ChapterPlan;
+use crate::progress::ProgressEvent;
 use crate::retry::{DownloadError, Result};
+use crate::store::PageStore;
+use crate::validate::{self, ExtensionScheme};
+use crate::workspace::Workspace;
 use uuid::Uuid;
 
-async fn download_image(url: &Url, context: &ScrapeContext) -> Result<Vec<u8>> {
-    use futures::StreamExt;
-    let mut collected_data = Vec::new();
-    // Make request
-    let response = CLIENT.get(url.clone()).send().await?.error_for_status()?;
-    // Get response size, if known so progress bar can render
-    let content_length = response.content_length();
-    // Get data
-    let mut data_stream = response.bytes_stream();
-    // Create progress bar
-    let bar = context
-        .progress
-        .add(indicatif::ProgressBar::new(if let Some(size) = content_length {
-            size
-        } else {
-            2
-        }));
-    let image_bar_style = indicatif::ProgressStyle::default_bar()
-        .template("<{elapsed_precise}> [{bar:80.yellow/red}] {pos}/{len} bytes received")
-        .progress_chars("=>-");
-    bar.set_style(image_bar_style);
-    bar.tick();
-    // Show progress bar while downloading
-    while let Some(data) = data_stream.next().await {
-        collected_data.extend_from_slice(&data?);
-        bar.set_position(if content_length.is_some() {
-            collected_data.len() as u64
-        } else {
-            1
-        });
+pub const METADATA_FILE_NAME: &str = "metadata.json";
+
+/// How many times to ask for a different at-home server before giving up on a chapter
+const MAX_SERVER_FALLBACKS: usize = 2;
+
+/// Number of pages if the chapter in dir is already fully downloaded, with everything
+/// the current options ask for, so it can be skipped without contacting at-home
+pub fn completed_pages(dir: &Path, data: &api::chapter::ChapterData, context: &ScrapeContext) -> Option<usize> {
+    if context.pages.is_some() || context.existing.rechecks_complete() {
+        return None;
+    }
+    let mut extra_files = Vec::new();
+    if context.write_comic_info {
+        extra_files.push(COMIC_INFO_FILE_NAME);
+    }
+    if context.write_metadata {
+        extra_files.push(METADATA_FILE_NAME);
+    }
+    if context.epub && !epub::epub_path(dir).exists() {
+        return None;
+    }
+    if context.cbz && !cbz::cbz_path(dir).exists() {
+        return None;
     }
-    if context.verbose {
-        bar.println(format!("Finished Downloading {}", url));
+    manifest::completed_pages(dir, data.id, data.attributes.pages, &extra_files, context.completion)
+}
+
+/// Name of page `index` before it is downloaded, from the server's name of the file
+fn page_file_name(index: usize, filename: &str, extensions: &ExtensionScheme) -> String {
+    let extension = match extensions {
+        ExtensionScheme::Fixed(extension) => extension,
+        _ => filename.split('.').next_back().unwrap_or("png"),
+    };
+    format!("{:04}.{}", (index + 1), extension)
+}
+
+/// Index of the page a file in a chapter directory holds, e.g. 0 for `0001.png`
+fn page_index(file_name: &str) -> Option<usize> {
+    let (stem, extension) = file_name.split_once('.')?;
+    if stem.len() != 4 || extension.contains('.') {
+        return None;
+    }
+    stem.parse::<usize>().ok()?.checked_sub(1)
+}
+
+/// Give a downloaded page the extension of what it actually is, returning its name
+fn settle_extension(path: &Path) -> Result<String> {
+    let mut path = path.to_owned();
+    if let Some(format) = validate::detect_file_format(&path)? {
+        if path.extension().and_then(OsStr::to_str) != Some(format.extension()) {
+            let renamed = path.with_extension(format.extension());
+            debug!("{:?} is a {} image, renaming it to {:?}", path, format, renamed);
+            std::fs::rename(&path, &renamed)?;
+            path = renamed;
+        }
     }
-    Ok(collected_data)
+    Ok(path.file_name().unwrap_or_default().to_string_lossy().into_owned())
+}
+
+/// What happened to the pages of one pass over a server
+#[derive(Debug, Default)]
+struct PageResults {
+    /// Pages that were missing in original quality and downloaded from data saver
+    data_saver: Vec<usize>,
+    /// Pages that failed because of the server
+    failed: Vec<(usize, DownloadError)>,
+    /// File names of the pages that were downloaded
+    names: Vec<(usize, String)>,
 }
 
 #[derive(Clone, Debug)]
 pub struct ChapterInfo {
-    _id: Uuid,
+    id: Uuid,
     _lang_code: String,
+    chapter: Option<String>,
+    title: Option<String>,
     hash: String,
     server: String,
-    page_array: Rc<Vec<String>>,
+    page_array: Arc<Vec<String>>,
+    saver_array: Arc<Vec<String>>,
+    /// Pages that are kept in data saver quality in the chapter directory
+    saver_pages: HashSet<usize>,
+    extensions: ExtensionScheme,
+    /// File names of pages that don't have the name their url suggests
+    page_names: HashMap<usize, String>,
+    reported_pages: usize,
+    data: api::chapter::ChapterData,
+    series: Option<String>,
+    original_language: Option<String>,
+    authors: Vec<String>,
+    artists: Vec<String>,
+    description: Option<String>,
+    genres: Vec<String>,
+    tags: Vec<String>,
 }
 
 impl ChapterInfo {
-    async fn download<T>(url: Url, context: &ScrapeContext) -> Result<T>
-    where
-        T: DeserializeOwned,
-    {
-        let origin = url.origin();
-        context
-            .with_retry_for_origin(&origin, || async {
-                let resp = CLIENT.get(url.clone()).send().await?.error_for_status()?;
-                Ok(resp.json::<T>().await?)
-            })
-            .await
+    /// Name of the manga this chapter belongs to, used for metadata
+    pub fn set_series(&mut self, series: Option<String>) {
+        self.series = series;
     }
 
-    pub async fn from_chapter_data(data: api::chapter::ChapterData, context: &ScrapeContext) -> Result<Self> {
-        let md_at_home_info_url = Url::parse(&format!("https://api.mangadex.org/at-home/server/{}", data.id)).unwrap();
+    /// Language the manga was first published in, which decides its reading direction
+    pub fn set_original_language(&mut self, language: Option<String>) {
+        self.original_language = language;
+    }
+
+    /// Authors and artists of the manga, used for metadata
+    pub fn set_creators(&mut self, authors: Vec<String>, artists: Vec<String>) {
+        self.authors = authors;
+        self.artists = artists;
+    }
+
+    /// Description, genres and other tags of the manga, used for metadata
+    pub fn set_details(&mut self, description: Option<String>, genres: Vec<String>, tags: Vec<String>) {
+        self.description = description;
+        self.genres = genres;
+        self.tags = tags;
+    }
+
+    /// Where the pages are downloaded from, in original quality
+    pub fn page_urls(&self) -> Result<Vec<Url>> {
+        self.page_array
+            .iter()
+            .map(|filename| Ok(Url::parse(&format!("{}/data/{}/{}", self.server, self.hash, filename))?))
+            .collect()
+    }
+
+    pub fn display_name(&self) -> String {
+        match (&self.chapter, &self.title) {
+            (Some(chapter), Some(title)) => format!("Ch. {} - {}", chapter, title),
+            (Some(chapter), None) => format!("Ch. {}", chapter),
+            (None, Some(title)) => title.clone(),
+            (None, None) => self.id.to_string(),
+        }
+    }
+
+    async fn query_server(
+        chapter_id: Uuid,
+        force_port_443: bool,
+        cached: bool,
+        context: &ScrapeContext,
+    ) -> Result<api::at_home::ServerInfoResponse> {
+        let mut md_at_home_info_url =
+            Url::parse(&format!("https://api.mangadex.org/at-home/server/{}", chapter_id)).unwrap();
+        if force_port_443 || context.force_port_443 {
+            md_at_home_info_url
+                .query_pairs_mut()
+                .append_pair("forcePort443", "true");
+        }
 
         debug!(
             "Going to determine owning server address from \"{}\"",
             md_at_home_info_url
         );
-        let server_info: api::at_home::ServerInfoResponse = Self::download(md_at_home_info_url, context).await?;
+        if cached {
+            context
+                .http()
+                .get_json_cached(&md_at_home_info_url, cache::AT_HOME_TTL)
+                .await
+        } else {
+            context.http().get_json(&md_at_home_info_url).await
+        }
+    }
+
+    /// Query for a server, asking for another one if the first has been slow or
+    /// unreliable so far and keeping whichever of the two has the better history.
+    /// Only the first query may be answered from the response cache.
+    async fn query_preferred_server(
+        chapter_id: Uuid,
+        force_port_443: bool,
+        cached: bool,
+        context: &ScrapeContext,
+    ) -> Result<api::at_home::ServerInfoResponse> {
+        let server_info = Self::query_server(chapter_id, force_port_443, cached, context).await?;
+        let node = node_key(&server_info.base_url);
+        if !context.node_scores.is_poor(&node) {
+            return Ok(server_info);
+        }
+        debug!("Server {} has a poor history, asking for another one", node);
+        let alternative = Self::query_server(chapter_id, true, false, context).await?;
+        let alternative_node = node_key(&alternative.base_url);
+        if context.node_scores.prefer(&node, &alternative_node) == alternative_node {
+            Ok(alternative)
+        } else {
+            Ok(server_info)
+        }
+    }
+
+    pub async fn from_chapter_data(data: api::chapter::ChapterData, context: &ScrapeContext) -> Result<Self> {
+        let server_info = Self::query_preferred_server(data.id, false, true, context).await?;
         Ok(ChapterInfo {
             server: server_info.base_url,
-            _id: data.id,
+            id: data.id,
             page_array: server_info.chapter.data,
+            saver_array: server_info.chapter.data_saver,
+            saver_pages: HashSet::new(),
+            extensions: context.extensions.clone(),
+            page_names: HashMap::new(),
             hash: server_info.chapter.hash,
             _lang_code: data.attributes.translated_language.clone(),
+            reported_pages: data.attributes.pages,
+            chapter: data.attributes.chapter.clone(),
+            title: data.attributes.title.clone(),
+            data,
+            series: None,
+            original_language: None,
+            authors: Vec::new(),
+            artists: Vec::new(),
+            description: None,
+            genres: Vec::new(),
+            tags: Vec::new(),
         })
     }
 
-    pub async fn from_chapter_response(
-        response: api::chapter::ChapterResponse,
-        context: &ScrapeContext,
-    ) -> Result<Self> {
-        Self::from_chapter_data(response.data, context).await
+    /// Ask the at-home network for a different server to download this chapter from
+    async fn switch_server(&mut self, force_port_443: bool, context: &ScrapeContext) -> Result<()> {
+        let server_info = Self::query_preferred_server(self.id, force_port_443, false, context).await?;
+        debug!(
+            "Switching chapter {} from server {} to {}",
+            self.id, self.server, server_info.base_url
+        );
+        if server_info.chapter.data.len() != self.page_array.len() {
+            warn!(
+                "New server for chapter {} reports {} pages, previously had {}",
+                self.id,
+                server_info.chapter.data.len(),
+                self.page_array.len()
+            );
+        }
+        self.server = server_info.base_url;
+        self.hash = server_info.chapter.hash;
+        self.page_array = server_info.chapter.data;
+        self.saver_array = server_info.chapter.data_saver;
+        Ok(())
     }
 
-    pub async fn download_for_chapter(chapter_id: Uuid, context: &ScrapeContext) -> Result<Self> {
-        let chapter_info_url = Url::parse(&format!("https://api.mangadex.org/chapter/{}", chapter_id)).unwrap();
+    pub async fn download_chapter_data(chapter_id: Uuid, context: &ScrapeContext) -> Result<api::chapter::ChapterData> {
+        let chapter_info_url = Url::parse(&format!(
+            "https://api.mangadex.org/chapter/{}?includes[]=scanlation_group",
+            chapter_id
+        ))
+        .unwrap();
 
         debug!("Going to download chapter info from \"{}\"", chapter_info_url);
-        let response: api::chapter::ChapterResponse = Self::download(chapter_info_url, context).await?;
-        Self::from_chapter_response(response, context).await
+        let response: api::chapter::ChapterResponse = context.http().get_json(&chapter_info_url).await?;
+        let mut data = response.data;
+        names::resolve_group_names(std::slice::from_mut(&mut data), context).await?;
+        Ok(data)
+    }
+
+    pub async fn download_for_chapter(chapter_id: Uuid, context: &ScrapeContext) -> Result<Self> {
+        let data = Self::download_chapter_data(chapter_id, context).await?;
+        Self::from_chapter_data(data, context).await
+    }
+
+    /// Name of page i in the chapter directory, which depends on whether we have the
+    /// original or the data saver version
+    fn page_name(&self, i: usize) -> String {
+        if let Some(name) = self.page_names.get(&i) {
+            return name.clone();
+        }
+        match self.saver_array.get(i) {
+            Some(filename) if self.saver_pages.contains(&i) => page_file_name(i, filename, &self.extensions),
+            _ => page_file_name(i, &self.page_array[i], &self.extensions),
+        }
+    }
+
+    /// Pick up which pages a previous run had to download in data saver quality
+    fn load_saver_pages(&mut self, destination: &Path) {
+        let flagged = match Manifest::read(destination) {
+            Ok(manifest) if manifest.chapter_id == Some(self.id) => manifest.data_saver,
+            _ => return,
+        };
+        self.saver_pages = flagged
+            .iter()
+            .filter_map(|name| page_index(name))
+            .filter(|&i| i < self.saver_array.len())
+            .collect();
+    }
+
+    /// Pick up the names earlier runs gave pages in dir. With detected extensions these
+    /// can't be told from the urls.
+    fn load_page_names(&mut self, dir: &Path) -> Result<()> {
+        if self.extensions != ExtensionScheme::Detected || !dir.is_dir() {
+            return Ok(());
+        }
+        for entry in std::fs::read_dir(dir)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if let Some(i) = page_index(&name).filter(|&i| i < self.page_array.len()) {
+                self.page_names.insert(i, name);
+            }
+        }
+        Ok(())
     }
 
-    pub async fn download_to_directory(self, path: &impl AsRef<OsStr>, context: &ScrapeContext) -> Result<()> {
+    /// Download the given pages from the current server. A page that is missing in
+    /// original quality is downloaded from data saver instead. Pages that failed
+    /// because of the server are returned so that they can be retried elsewhere, any
+    /// other error aborts the chapter.
+    async fn download_pages(
+        &self,
+        pages: &[usize],
+        staging: &Path,
+        chapter_bar: &indicatif::ProgressBar,
+        context: &ScrapeContext,
+    ) -> Result<PageResults> {
         use futures::stream::{FuturesUnordered, StreamExt};
-        let chapter_bar = Rc::new({
-            let style = indicatif::ProgressStyle::default_bar()
-                .template("<{elapsed_precise}> [{bar:80.yellow/red}] {pos}/{len} images downloaded")
-                .progress_chars("=>-");
-            let chapter_bar = context
-                .progress
-                .add(indicatif::ProgressBar::new(self.page_array.len() as u64));
-            chapter_bar.set_style(style);
-            chapter_bar
-        });
+        use futures::FutureExt;
         let url_base = format!("{}/data/{}", self.server, self.hash);
-        let origin = Url::parse(&url_base)?.origin();
-        context.get_ticket(&origin).await;
         debug!("Determined url_base as {}", url_base);
-        let mut tasks = self
-            .page_array
+        let mut tasks = pages
             .iter()
-            .enumerate()
+            .filter_map(|&i| self.page_array.get(i).map(|filename| (i, filename)))
             .map(|(i, filename)| {
-                let chapter_bar = chapter_bar.clone();
                 let url_base = &url_base;
-                let origin = &origin;
                 async move {
                     debug!("Async closure called");
-                    let mut path_buf = PathBuf::from(&path);
+                    if let Some(ref dashboard) = context.dashboard {
+                        dashboard.checkpoint(self.id).await?;
+                    }
                     // Determine resource names
                     let file_url = format!("{}/{}", url_base, filename);
                     let url = Url::parse(&file_url)?;
-                    let extension = filename.split('.').last().unwrap_or("png");
-                    path_buf.push(format!("{:04}.{}", (i + 1), extension));
-                    {
-                        let path = path_buf.as_path();
-                        if path.exists() {
-                            if context.verbose {
-                                debug!("Skipping {:#?}, since it already exists", path);
-                            }
-                        } else {
-                            debug!("Getting {} as {:#?}", file_url, path);
-                            let chapter_data = context
-                                .with_retry_for_origin(origin, || async { download_image(&url, context).await })
-                                .await?;
-                            // Create output file
-                            let mut out_file = File::create(path)?;
-                            // Write data
-                            out_file.write_all(&chapter_data)?;
+                    let file_name = page_file_name(i, filename, &self.extensions);
+                    let path = staging.join(&file_name);
+                    debug!("Getting {} as {:#?}", file_url, path);
+                    let (bytes, data_saver, path) = match context.http().download_image(&url, &path).await {
+                        Ok(bytes) => (bytes, false, path),
+                        Err(e) if e.is_not_found() && self.saver_array.get(i).is_some() => {
+                            let saver_name = &self.saver_array[i];
+                            warn!(
+                                "Page {} of chapter {} is missing, downloading the data saver version",
+                                i + 1,
+                                self.id
+                            );
+                            let url = Url::parse(&format!("{}/data-saver/{}/{}", self.server, self.hash, saver_name))?;
+                            let path = staging.join(page_file_name(i, saver_name, &self.extensions));
+                            (context.http().download_image(&url, &path).await?, true, path)
                         }
+                        Err(e) => return Err(e),
+                    };
+                    let mut name = match self.extensions {
+                        ExtensionScheme::Detected => settle_extension(&path)?,
+                        _ => path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+                    };
+                    if context.conversion.is_active() {
+                        let keep_name = matches!(self.extensions, ExtensionScheme::Fixed(_));
+                        let converted =
+                            convert::process_page(staging.join(&name), &context.conversion, keep_name).await?;
+                        name = converted.file_name().unwrap_or_default().to_string_lossy().into_owned();
                     }
-                    // Update bar
-                    chapter_bar.set_position(chapter_bar.position() + 1);
-                    Ok::<(), DownloadError>(())
+                    context.stats.add_page(bytes as usize);
+                    context.progress.page_done(context.stats.bytes());
+                    context.progress.emit(ProgressEvent::PageDone {
+                        chapter_id: self.id,
+                        page: i + 1,
+                        bytes,
+                    });
+                    chapter_bar.inc(1);
+                    if let Some(ref dashboard) = context.dashboard {
+                        dashboard.page_done(self.id);
+                    }
+                    Ok::<(bool, String), DownloadError>((data_saver, name))
                 }
+                .map(move |result| {
+                    result
+                        .map(|(data_saver, name)| (i, data_saver, name))
+                        .map_err(|e| (i, e))
+                })
             })
             .collect::<FuturesUnordered<_>>();
 
+        let mut results = PageResults::default();
         while let Some(result) = tasks.next().await {
-            result?;
+            match result {
+                Ok((i, data_saver, name)) => {
+                    if data_saver {
+                        results.data_saver.push(i);
+                    }
+                    results.names.push((i, name));
+                }
+                Err((
+                    i,
+                    e @ (DownloadError::ReqwestError(_)
+                    | DownloadError::RateLimitError(_)
+                    | DownloadError::RetryExhausted { .. }),
+                )) => results.failed.push((i, e)),
+                Err((_, e)) => return Err(e),
+            }
+        }
+        Ok(results)
+    }
+
+    async fn download_pages_with_fallback(
+        &mut self,
+        pages: &[usize],
+        staging: &Path,
+        chapter_bar: &indicatif::ProgressBar,
+        context: &ScrapeContext,
+    ) -> Result<Vec<(usize, DownloadError)>> {
+        // Every page gets another try at original quality
+        for i in pages {
+            self.saver_pages.remove(i);
+        }
+        let mut pending = pages.to_vec();
+        let mut fallbacks = 0;
+        loop {
+            let results = self.download_pages(&pending, staging, chapter_bar, context).await?;
+            self.saver_pages.extend(results.data_saver);
+            self.page_names.extend(results.names);
+            let failed = results.failed;
+            if failed.is_empty() || fallbacks >= MAX_SERVER_FALLBACKS {
+                return Ok(failed);
+            }
+            fallbacks += 1;
+            warn!(
+                "{} pages of chapter {} failed to download from {}, trying another server",
+                failed.len(),
+                self.id,
+                self.server
+            );
+            // Servers on non-standard ports are a common cause of failures, so insist on
+            // port 443 for the last attempt
+            self.switch_server(fallbacks == MAX_SERVER_FALLBACKS, context).await?;
+            pending = failed.into_iter().map(|(i, _)| i).collect();
+        }
+    }
+
+    /// Check every page that should now be on disk, deleting and returning the ones
+    /// that look broken
+    fn verify_pages(
+        &self,
+        pages: &[usize],
+        destination: &Path,
+        staging: &Path,
+        context: &ScrapeContext,
+    ) -> Result<Vec<usize>> {
+        let mut corrupt = Vec::new();
+        for &i in pages {
+            let file_name = self.page_name(i);
+            let staged_path = staging.join(&file_name);
+            let path = if staged_path.exists() {
+                staged_path
+            } else {
+                destination.join(&file_name)
+            };
+            if let Some(problem) = validate::check_page(&path, &context.page_policy)? {
+                warn!("Page {} of chapter {} is broken: {}", i + 1, self.id, problem);
+                if path.exists() {
+                    std::fs::remove_file(&path)?;
+                }
+                corrupt.push(i);
+            }
+        }
+        Ok(corrupt)
+    }
+
+    /// Record checksums for every page of the chapter that is on disk, flagging the
+    /// ones in data saver quality and the missing ones
+    async fn write_manifest(&self, destination: &Path, staging: &Path, missing: &[usize]) -> Result<()> {
+        let mut files = Vec::new();
+        let mut data_saver = Vec::new();
+        for i in 0..self.page_array.len() {
+            let file_name = self.page_name(i);
+            let staged_path = staging.join(&file_name);
+            let existing_path = destination.join(&file_name);
+            if staged_path.exists() {
+                files.push(staged_path);
+            } else if existing_path.exists() {
+                files.push(existing_path);
+            } else {
+                continue;
+            }
+            if self.saver_pages.contains(&i) {
+                data_saver.push(file_name);
+            }
+        }
+        let chapter_id = self.id;
+        let mut manifest = run_blocking(move || Manifest::from_files(chapter_id, &files)).await?;
+        manifest.data_saver = data_saver;
+        manifest.missing = missing.iter().map(|&i| self.page_name(i)).collect();
+        manifest.write(staging)
+    }
+
+    fn epub_info(&self) -> EpubInfo {
+        let mut creators = self.authors.clone();
+        creators.extend(
+            self.artists
+                .iter()
+                .filter(|artist| !self.authors.contains(artist))
+                .cloned(),
+        );
+        EpubInfo {
+            id: self.id,
+            title: match self.series {
+                Some(ref series) => format!("{} - {}", series, self.display_name()),
+                None => self.display_name(),
+            },
+            language: self.data.attributes.translated_language.clone(),
+            creators,
+            direction: ReadingDirection::for_original_language(self.original_language.as_deref()),
+        }
+    }
+
+    /// Indices of the pages the options ask for
+    fn selected_pages(&self, context: &ScrapeContext) -> Result<Vec<usize>> {
+        let selected: Vec<usize> = match context.pages {
+            Some(ref pages) => {
+                // Page numbers are 1-indexed
+                let (first, last) = pages.bounds();
+                if first == 0 {
+                    return Err(DownloadError::NoSuchPage(first));
+                }
+                if last > self.page_array.len() {
+                    return Err(DownloadError::NoSuchPage(last));
+                }
+                (0..self.page_array.len()).filter(|i| pages.contains(i + 1)).collect()
+            }
+            None => {
+                if self.reported_pages != self.page_array.len() {
+                    warn!(
+                        "Chapter {} should have {} pages, but the server lists {}",
+                        self.id,
+                        self.reported_pages,
+                        self.page_array.len()
+                    );
+                }
+                (0..self.page_array.len()).collect()
+            }
+        };
+        Ok(selected)
+    }
+
+    /// The selected pages that have to be downloaded, given what is already in
+    /// destination and the policy for existing files
+    fn pages_to_fetch(
+        &self,
+        selected: &[usize],
+        destination: &Path,
+        existing: ExistingPolicy,
+        verbose: bool,
+    ) -> Result<Vec<usize>> {
+        let manifest = Manifest::read(destination)
+            .ok()
+            .filter(|manifest| manifest.chapter_id == Some(self.id));
+        let mut pages = Vec::new();
+        for &i in selected {
+            let file_name = self.page_name(i);
+            let digest = manifest.as_ref().and_then(|manifest| {
+                manifest
+                    .entries
+                    .iter()
+                    .find(|(_, name)| *name == file_name)
+                    .map(|(digest, _)| digest.as_str())
+            });
+            if existing.needs_download(&destination.join(&file_name), digest)? {
+                pages.push(i);
+            } else if verbose {
+                debug!("Keeping {:#?}, since it already exists", file_name);
+            }
+        }
+        Ok(pages)
+    }
+
+    /// Fail the chapter with the first error if the pages that couldn't be downloaded
+    /// leave too few for it to count as complete
+    fn check_completion(
+        &self,
+        failed: &mut Vec<(usize, DownloadError)>,
+        selected: usize,
+        context: &ScrapeContext,
+    ) -> Result<()> {
+        if !failed.is_empty() && !context.completion.is_complete(selected - failed.len(), selected) {
+            return Err(failed.remove(0).1);
+        }
+        Ok(())
+    }
+
+    /// Work out which files a download into path would create, without fetching anything
+    pub fn plan(&mut self, path: &impl AsRef<OsStr>, context: &ScrapeContext) -> Result<ChapterPlan> {
+        let destination = PathBuf::from(path);
+        self.load_saver_pages(&destination);
+        self.load_page_names(&destination)?;
+        let selected = self.selected_pages(context)?;
+        let mut files: Vec<PathBuf> = self
+            .pages_to_fetch(&selected, &destination, context.existing, context.verbose)?
+            .into_iter()
+            .map(|i| destination.join(self.page_name(i)))
+            .collect();
+        let pages = files.len();
+        if context.write_comic_info {
+            files.push(destination.join(COMIC_INFO_FILE_NAME));
+        }
+        if context.write_metadata {
+            files.push(destination.join(METADATA_FILE_NAME));
+        }
+        Ok(ChapterPlan {
+            id: self.id,
+            name: self.display_name(),
+            pages,
+            files,
+        })
+    }
+
+    pub async fn download_to_directory(
+        self,
+        path: &impl AsRef<OsStr>,
+        workspace: &Workspace,
+        context: &ScrapeContext,
+    ) -> Result<()> {
+        let chapter_id = self.id;
+        let result = self.download_chapter(path, workspace, context).await;
+        context.progress.chapter_done(chapter_id, &result);
+        result
+    }
+
+    async fn download_chapter(
+        mut self,
+        path: &impl AsRef<OsStr>,
+        workspace: &Workspace,
+        context: &ScrapeContext,
+    ) -> Result<()> {
+        let chapter_bar = context.progress.chapter_bar(&self.display_name());
+        let destination = PathBuf::from(path);
+        let staging = if context.existing.resumes() {
+            workspace.resume_chapter(self.id)?
+        } else {
+            workspace.stage_chapter(self.id)?
+        };
+        debug!("Staging chapter {} in {:?}", self.id, staging);
+        self.load_saver_pages(&destination);
+        self.load_page_names(&destination)?;
+        let selected = self.selected_pages(context)?;
+        // Checking existing pages may hash all of them
+        let mut to_fetch = {
+            let chapter = self.clone();
+            let (selected, destination) = (selected.clone(), destination.clone());
+            let (existing, verbose) = (context.existing, context.verbose);
+            run_blocking(move || chapter.pages_to_fetch(&selected, &destination, existing, verbose)).await?
+        };
+        // Pages a previous run finished are already staged
+        let existing_names: Vec<String> = (0..self.page_array.len()).map(|i| self.page_name(i)).collect();
+        self.load_page_names(&staging)?;
+        to_fetch.retain(|&i| !staging.join(self.page_name(i)).exists());
+        for &i in &to_fetch {
+            // Data saver copies also make way, the original may be back by now
+            if context.existing == ExistingPolicy::Rename || self.saver_pages.contains(&i) {
+                context.existing.set_aside(&destination.join(self.page_name(i)))?;
+            }
+        }
+        chapter_bar.set_length(selected.len() as u64);
+        chapter_bar.set_position((selected.len() - to_fetch.len()) as u64);
+        context.progress.add_pages(to_fetch.len());
+        context.progress.emit(ProgressEvent::ChapterStarted {
+            chapter_id: self.id,
+            name: &self.display_name(),
+            pages: to_fetch.len(),
+            already_done: selected.len() - to_fetch.len(),
+        });
+        let _dashboard_entry = context.dashboard.as_ref().map(|dashboard| {
+            dashboard.start_chapter(
+                self.id,
+                self.display_name(),
+                selected.len(),
+                selected.len() - to_fetch.len(),
+            )
+        });
+        let mut failed = self
+            .download_pages_with_fallback(&to_fetch, &staging, &chapter_bar, context)
+            .await?;
+        self.check_completion(&mut failed, selected.len(), context)?;
+
+        // Integrity pass, anything that looks broken (or is missing) gets one more chance
+        let corrupt = self.verify_pages(&selected, &destination, &staging, context)?;
+        if !corrupt.is_empty() {
+            warn!("Re-downloading {} broken pages of chapter {}", corrupt.len(), self.id);
+            chapter_bar.set_position(chapter_bar.position().saturating_sub(corrupt.len() as u64));
+            context.progress.add_pages(corrupt.len());
+            failed = self
+                .download_pages_with_fallback(&corrupt, &staging, &chapter_bar, context)
+                .await?;
+            self.check_completion(&mut failed, selected.len(), context)?;
+            let recheck: Vec<usize> = corrupt
+                .into_iter()
+                .filter(|i| !failed.iter().any(|(missing, _)| missing == i))
+                .collect();
+            if let Some(&i) = self.verify_pages(&recheck, &destination, &staging, context)?.first() {
+                return Err(DownloadError::CorruptPage(i + 1));
+            }
+        }
+        // A failed page may still have its copy from an earlier run
+        let missing: Vec<usize> = failed
+            .into_iter()
+            .map(|(i, _)| i)
+            .filter(|&i| !destination.join(self.page_name(i)).exists())
+            .collect();
+        if !missing.is_empty() {
+            warn!(
+                "Keeping chapter {} with {} of {} pages, the missing ones are recorded for repair",
+                self.id,
+                selected.len() - missing.len(),
+                selected.len()
+            );
         }
 
+        // A page that turned out to be another format replaces its copy under the old name
+        for (i, name) in existing_names.iter().enumerate() {
+            if *name != self.page_name(i) && staging.join(self.page_name(i)).exists() {
+                let old_path = destination.join(name);
+                if old_path.exists() {
+                    std::fs::remove_file(old_path)?;
+                }
+            }
+        }
+        let new_pages = std::fs::read_dir(&staging)?.count();
+        self.write_manifest(&destination, &staging, &missing).await?;
+        if context.write_comic_info {
+            let mut comic_info = ComicInfo::from_chapter(&self.data, self.series.as_deref(), self.page_array.len());
+            comic_info.writers = self.authors.clone();
+            comic_info.pencillers = self.artists.clone();
+            comic_info.summary = self.description.clone();
+            comic_info.genres = self.genres.clone();
+            comic_info.tags = self.tags.clone();
+            std::fs::write(staging.join(COMIC_INFO_FILE_NAME), comic_info.to_xml())?;
+        }
+        if context.write_metadata {
+            std::fs::write(staging.join(METADATA_FILE_NAME), serde_json::to_vec_pretty(&self.data)?)?;
+        }
+        workspace.commit_chapter(staging, &destination)?;
+        if let Some(links) = context.page_store {
+            let (store, dir) = (PageStore::new(&workspace.output_root(), links), destination.clone());
+            run_blocking(move || store.link_chapter(&dir)).await?;
+        }
+        if context.epub && (new_pages > 0 || !epub::epub_path(&destination).exists()) {
+            let (dir, info) = (destination.clone(), self.epub_info());
+            run_blocking(move || epub::write_chapter_epub(&dir, &info)).await?;
+        }
+        if context.cbz && (new_pages > 0 || !cbz::cbz_path(&destination).exists()) {
+            let dir = destination.clone();
+            run_blocking(move || cbz::write_chapter_cbz(&dir)).await?;
+        }
+        if new_pages > 0 {
+            context.downloaded_chapters.lock().unwrap().push(self.display_name());
+            if let Some(ref feed) = context.feed {
+                feed.add_entry(FeedEntry {
+                    chapter_id: self.id,
+                    title: self.display_name(),
+                    path: std::fs::canonicalize(&destination)?,
+                    downloaded_at: chrono::Utc::now(),
+                })?;
+            }
+            context
+                .hooks
+                .chapter_done(
+                    &std::fs::canonicalize(&destination)?,
+                    self.id.to_string(),
+                    self.display_name(),
+                    self.series.as_deref(),
+                    context,
+                )
+                .await;
+        }
         chapter_bar.finish_and_clear();
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_page_names() {
+        let detected = ExtensionScheme::Detected;
+        assert_eq!(page_file_name(0, "x1-abc.jpg", &detected), "0001.jpg");
+        assert_eq!(
+            page_file_name(11, "x1-abc.png", &ExtensionScheme::Fixed("jpg".to_owned())),
+            "0012.jpg"
+        );
+        assert_eq!(page_index("0012.png"), Some(11));
+        assert_eq!(page_index("0001.1.png"), None);
+        assert_eq!(page_index("0001.png.part"), None);
+        assert_eq!(page_index("0000.png"), None);
+        assert_eq!(page_index("ComicInfo.xml"), None);
+
+        // A png that the server named .jpg
+        let dir = std::env::temp_dir().join(format!("mdscrape-test-{}", Uuid::from_u128(rand::random())));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("0001.jpg"), b"\x89PNG\r\n\x1a\n....").unwrap();
+        assert_eq!(settle_extension(&dir.join("0001.jpg")).unwrap(), "0001.png");
+        assert!(dir.join("0001.png").exists());
+        assert!(!dir.join("0001.jpg").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -3,6 +3,39 @@ pub use crate::client::CLIENT;
 pub type OpaqueError = Box<dyn std::error::Error>;
 pub type OpaqueResult<T> = Result<T, OpaqueError>;
 
-pub fn escape_path_string(s: String) -> String {
-    s.chars().map(|x| if x == '/' { '-' } else { x }).collect()
+/// Run slow disk or CPU bound work, like hashing pages or packing archives, on the
+/// blocking thread pool so that downloads carry on meanwhile
+pub async fn run_blocking<T, E, F>(f: F) -> crate::retry::Result<T>
+where
+    T: Send + 'static,
+    E: Send + 'static,
+    crate::retry::DownloadError: From<E>,
+    F: FnOnce() -> Result<T, E> + Send + 'static,
+{
+    Ok(tokio::task::spawn_blocking(f).await.map_err(std::io::Error::from)??)
+}
+
+/// Write a file unless it already has exactly these contents, so that re-running a
+/// download doesn't touch files that haven't changed. Returns whether it was written.
+pub fn write_if_changed(path: &std::path::Path, contents: &[u8]) -> std::io::Result<bool> {
+    if std::fs::read(path).is_ok_and(|existing| existing == contents) {
+        return Ok(false);
+    }
+    std::fs::write(path, contents)?;
+    Ok(true)
+}
+
+pub fn escape_xml(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            v => escaped.push(v),
+        }
+    }
+    escaped
 }
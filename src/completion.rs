@@ -0,0 +1,62 @@
+/// When a chapter counts as complete, set with `--complete-when`. Chapters that are
+/// complete are skipped by later downloads and syncs, pages they are missing are
+/// recorded in the manifest so that `--verify --refetch` can repair them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompletionPolicy {
+    /// Every page has to be downloaded
+    #[default]
+    AllPages,
+    /// At least this percentage of the pages has to be downloaded
+    Percent(u8),
+    /// Any chapter that was downloaded at all counts, however many pages it got
+    MetadataOnly,
+}
+
+impl std::str::FromStr for CompletionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "all" => Ok(CompletionPolicy::AllPages),
+            "metadata" => Ok(CompletionPolicy::MetadataOnly),
+            v => match v.strip_suffix('%').map(|percent| percent.trim().parse::<u8>()) {
+                Some(Ok(percent)) if (1..=100).contains(&percent) => Ok(CompletionPolicy::Percent(percent)),
+                _ => Err(format!(
+                    "Unknown completion criterion \"{}\", expected all, metadata or a percentage like 90%",
+                    v
+                )),
+            },
+        }
+    }
+}
+
+impl CompletionPolicy {
+    /// Whether a chapter with `present` of its `expected` pages counts as complete
+    pub fn is_complete(self, present: usize, expected: usize) -> bool {
+        match self {
+            CompletionPolicy::AllPages => present >= expected,
+            CompletionPolicy::Percent(percent) => present * 100 >= expected * percent as usize,
+            CompletionPolicy::MetadataOnly => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_completion_policies() {
+        assert_eq!("all".parse(), Ok(CompletionPolicy::AllPages));
+        assert_eq!("90%".parse(), Ok(CompletionPolicy::Percent(90)));
+        assert_eq!("metadata".parse(), Ok(CompletionPolicy::MetadataOnly));
+        assert!("0%".parse::<CompletionPolicy>().is_err());
+        assert!("most".parse::<CompletionPolicy>().is_err());
+
+        assert!(CompletionPolicy::AllPages.is_complete(20, 20));
+        assert!(!CompletionPolicy::AllPages.is_complete(19, 20));
+        assert!(CompletionPolicy::Percent(90).is_complete(18, 20));
+        assert!(!CompletionPolicy::Percent(90).is_complete(17, 20));
+        assert!(CompletionPolicy::MetadataOnly.is_complete(0, 20));
+    }
+}
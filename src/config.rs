@@ -0,0 +1,110 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// Defaults for command line options, read from `~/.config/mdscrape/config.toml`.
+/// Every setting is optional, and anything given on the command line wins.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub lang_code: Option<String>,
+    pub title_lang: Option<String>,
+    pub path_template: Option<String>,
+    pub group_by_volume: bool,
+    pub global_threshold: Option<usize>,
+    pub per_origin_threshold: Option<usize>,
+    pub rate_limit_wait_time: Option<String>,
+    pub requests_per_minute: Option<usize>,
+    pub feed_page_size: Option<usize>,
+    pub origin_limits: Vec<String>,
+    pub max_bandwidth: Option<String>,
+    pub api_backoff: Option<String>,
+    pub image_backoff: Option<String>,
+    pub retry_max_time: Option<String>,
+    pub retry_jitter: Option<String>,
+    pub proxy: Option<String>,
+    pub user_agent: Option<String>,
+    pub headers: Vec<String>,
+    pub insecure: bool,
+    pub ca_bundle: Option<PathBuf>,
+    pub connect_timeout: Option<String>,
+    pub read_timeout: Option<String>,
+    pub stall_timeout: Option<String>,
+    pub pool_max_idle: Option<usize>,
+    pub pool_idle_timeout: Option<String>,
+    pub tcp_keepalive: Option<String>,
+    pub http2: bool,
+    pub force_443: bool,
+    pub ignored_groups: Vec<usize>,
+    pub write_metadata: bool,
+    pub comic_info: bool,
+    pub epub: bool,
+    pub cbz: bool,
+    pub layout: Option<String>,
+    pub page_store: Option<String>,
+    pub notify: Vec<String>,
+    pub content_rating: Option<String>,
+    pub exclude_tags: Vec<String>,
+    pub on_chapter_done: Option<String>,
+    pub on_title_done: Option<String>,
+    pub covers: Option<String>,
+    pub complete_when: Option<String>,
+    pub page_extensions: Option<String>,
+    pub convert: Option<String>,
+    pub quality: Option<u8>,
+    pub strip_metadata: bool,
+}
+
+impl Config {
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("mdscrape").join(CONFIG_FILE_NAME))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        toml::from_str(contents).map_err(|e| e.to_string())
+    }
+
+    /// Read the config file, if there is one
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Self::parse(&contents).map_err(|e| format!("Invalid config file {:?}: {}", path, e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("Failed to read config file {:?}: {}", path, e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse(
+            r#"
+            lang-code = "en,es-la"
+            path-template = "{manga}/{chapter:04}"
+            global-threshold = 4
+            rate-limit-wait-time = "5m"
+            ignored-groups = [12, 34]
+            comic-info = true
+            layout = "komga"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.lang_code.as_deref(), Some("en,es-la"));
+        assert_eq!(config.global_threshold, Some(4));
+        assert_eq!(config.per_origin_threshold, None);
+        assert_eq!(config.ignored_groups, vec![12, 34]);
+        assert!(config.comic_info);
+        assert_eq!(config.layout.as_deref(), Some("komga"));
+        assert!(!config.write_metadata);
+
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        assert!(Config::parse("lang = \"en\"").is_err());
+        assert!(Config::load(Path::new("/nonexistent/mdscrape/config.toml")).is_ok());
+    }
+}
@@ -1,14 +1,50 @@
+use log::LevelFilter;
 use url::Origin;
 
-use std::cell::RefCell;
 use std::collections::HashSet;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use ed25519_dalek::VerifyingKey;
 use uuid::Uuid;
 
 use crate::{
-    retry::{self, DownloadError},
-    throttle::{Ticket, TicketPolicy, Ticketer},
+    attestation,
+    cache::{CacheMode, ResponseCache},
+    client::{ClientSettings, Credentials, HttpClient, PoolSettings, DEFAULT_READ_TIMEOUT, DEFAULT_STALL_TIMEOUT},
+    completion::CompletionPolicy,
+    config::Config,
+    convert::ConvertPolicy,
+    cover::CoverSelection,
+    existing::ExistingPolicy,
+    exit,
+    export::ExportFormat,
+    feed::ChapterFeed,
+    filter::TitleFilter,
+    hooks::{self, HookCommand, Hooks},
+    ids::{parse_resource_id, parse_resource_list, ResourceKind},
+    languages,
+    layout::LibraryLayout,
+    library::SyncPolicy,
+    names::{self, NameCache},
+    naming::PathTemplate,
+    nodes::NodeScores,
+    notify::{self, Notifier},
+    plan::DownloadHistory,
+    progress::{ProgressDisplay, ProgressEvent, ProgressFormat},
+    retry::{self, BackoffPolicy, DownloadError, Jitter, RetryPolicy},
+    selection::{self, DateRange, RangeSet},
+    store::StoreLinks,
+    summary::RunStats,
+    throttle::{
+        OriginLimit, OriginState, RateBudget, Ticket, TicketClass, TicketPolicy, TicketStats, Ticketer, TokenBucket,
+    },
+    title::{self, TitleData},
+    tui::Dashboard,
+    units,
+    validate::{ExtensionScheme, ValidationPolicy},
 };
 
 // TODO: Support lookups for old id format
@@ -18,52 +54,623 @@ pub enum DownloadType {
     Chapter(Uuid),
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Download(Vec<DownloadType>),
+    /// Print what we know about titles or chapters without downloading them
+    Info(Vec<DownloadType>),
+    /// Resolve titles and chapters down to their pages, and print what would be downloaded
+    DryRun(Vec<DownloadType>),
+    /// Estimate the work of downloading titles from their feeds alone
+    Plan(Vec<Uuid>),
+    /// Check a previously downloaded library against its checksum manifests
+    Verify(PathBuf),
+    Sync(PathBuf),
+    Follows,
+    /// Download missing cover art for every title in a library
+    Covers(PathBuf),
+    /// Package the chapters of a downloaded title again from what is on disk
+    Export(PathBuf),
+    /// Finish the download into a directory that was interrupted, from its journal
+    Resume(PathBuf),
+}
+
+impl Command {
+    /// Whether this downloads chapters, and so has a run summary to report
+    pub fn downloads_chapters(&self) -> bool {
+        matches!(
+            self,
+            Command::Download(_) | Command::Sync(_) | Command::Follows | Command::Resume(_)
+        )
+    }
+}
+
+/// Everything a run needs: its options, the throttle, and what it has done so far
 #[derive(Debug)]
 pub struct ScrapeContext {
     pub verbose: bool,
-    pub lang_code: String,
+    /// Only errors are written to the terminal, for unattended runs
+    pub quiet: bool,
+    pub log_level: LevelFilter,
+    pub log_file: Option<PathBuf>,
+    pub lang_codes: Vec<String>,
     pub start_chapter: Option<usize>,
     pub end_chapter: Option<usize>,
+    /// Chapters to ask for in every request for the feed of a title
+    pub feed_page_size: usize,
     pub ignored_groups: HashSet<usize>,
-    pub download_type: DownloadType,
+    pub command: Command,
+    pub refetch: bool,
+    /// Record failed chapters and titles and carry on with the rest
+    pub keep_going: bool,
+    /// Take over the lock of the output directory even if another run seems to hold it
+    pub force: bool,
+    pub json: bool,
+    pub check_attestation: bool,
+    /// The key attestations have to be signed with to pass `--attestation`
+    pub trusted_key: Option<VerifyingKey>,
+    pub attest: bool,
     pub show_progress: bool,
-    pub progress: Arc<indicatif::MultiProgress>,
+    pub progress: ProgressDisplay,
+    /// The `--tui` dashboard, which replaces the progress bars
+    pub dashboard: Option<Arc<Dashboard>>,
+    /// Where to write the json summary of the run
+    pub report: Option<PathBuf>,
+    pub feed: Option<ChapterFeed>,
+    pub pages: Option<RangeSet<usize>>,
+    /// Chapter numbers of titles to download
+    pub chapters: Option<RangeSet<f64>>,
+    /// Languages to name titles in, in order of preference
+    pub title_langs: Vec<String>,
+    /// When chapters of titles to download came out
+    pub published: DateRange,
+    /// Which titles to download
+    pub title_filter: TitleFilter,
+    pub page_policy: ValidationPolicy,
+    /// How page files get their extensions
+    pub extensions: ExtensionScheme,
+    /// What happens to pages once they are downloaded
+    pub conversion: ConvertPolicy,
+    pub write_metadata: bool,
+    pub write_comic_info: bool,
+    /// Also package every chapter as an EPUB next to its directory
+    pub epub: bool,
+    /// Also package every chapter as a .cbz next to its directory
+    pub cbz: bool,
+    /// Self-hosted reader the output is arranged for
+    pub layout: Option<LibraryLayout>,
+    /// Keep pages once by their checksum, linked into the chapters this way
+    pub page_store: Option<StoreLinks>,
+    /// What `--export` packages chapters as
+    pub export_format: ExportFormat,
+    /// Where `--export` moves chapters to first, if anywhere
+    pub export_template: Option<PathTemplate>,
+    pub covers: Option<CoverSelection>,
+    pub existing: ExistingPolicy,
+    pub completion: CompletionPolicy,
+    pub path_template: PathTemplate,
+    pub sync_policy: SyncPolicy,
+    pub notifiers: Vec<Box<dyn Notifier>>,
+    /// Commands to run once chapters and titles are downloaded
+    pub hooks: Hooks,
+    /// Repeat `--sync` or `--follows` this often, until interrupted
+    pub watch: Option<Duration>,
+    /// Display names of the chapters that were newly downloaded during this run
+    pub downloaded_chapters: Mutex<Vec<String>>,
+    pub stats: RunStats,
+    pub node_scores: NodeScores,
+    pub group_names: NameCache,
+    pub author_names: NameCache,
+    /// Recent API responses, so that re-running doesn't repeat them
+    pub response_cache: ResponseCache,
+    /// How fast previous runs were, for estimates
+    pub history: DownloadHistory,
+    /// Limit on the combined rate of image downloads, if any
+    pub bandwidth: Option<TokenBucket>,
+    /// How long to wait for an image server to start answering
+    pub read_timeout: Duration,
+    /// How long an image download may go without data before it is tried again
+    pub stall_timeout: Duration,
+    /// Only download from at-home servers on port 443, for firewalls that block others
+    pub force_port_443: bool,
+    /// Never use the network, answering API requests from the response cache only
+    pub offline: bool,
+    /// Where to save the chapter list of a title, for `--from-snapshot`
+    pub snapshot_feed: Option<PathBuf>,
+    pub from_snapshot: Option<PathBuf>,
+    pub credentials: Credentials,
     ticketer: Ticketer<Origin>,
+    retry_policy: RetryPolicy,
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("mdscrape: {}", message);
+    std::process::exit(exit::USAGE);
+}
+
+/// A timeout option, which has to allow some time
+fn parse_timeout(value: &str, option: &str) -> Duration {
+    match units::parse_duration(value, Duration::from_secs(1)) {
+        Ok(timeout) if timeout.is_zero() => usage_error(&format!("{} has to be more than 0", option)),
+        Ok(timeout) => timeout,
+        Err(e) => usage_error(&e),
+    }
+}
+
+/// Validate the resource id and the batch file up front, reporting every malformed id
+fn parse_resource_ids(resource_id: &str, batch_file: Option<&Path>, kind: ResourceKind) -> Vec<Uuid> {
+    let mut ids = Vec::new();
+    let mut errors = Vec::new();
+    if !resource_id.is_empty() {
+        match parse_resource_id(resource_id, kind) {
+            Ok(id) => ids.push(id),
+            Err(e) => errors.push(e),
+        }
+    }
+    if let Some(path) = batch_file {
+        let contents =
+            std::fs::read_to_string(path).unwrap_or_else(|e| usage_error(&format!("Failed to read {:?}: {}", path, e)));
+        match parse_resource_list(&contents, kind) {
+            Ok(mut batch) => ids.append(&mut batch),
+            Err(batch_errors) => errors.extend(batch_errors.into_iter().map(|e| format!("{:?} {}", path, e))),
+        }
+    }
+    if !errors.is_empty() {
+        for e in &errors {
+            eprintln!("mdscrape: {}", e);
+        }
+        usage_error(&format!("{} invalid resource ids", errors.len()));
+    }
+    ids
+}
+
+/// Builds a [`ScrapeContext`] without a command line, for programs that embed
+/// mdscrape. Anything that isn't set keeps the default of the command line option, the
+/// public fields of the context can still be changed once it is built.
+pub struct ScrapeContextBuilder {
+    context: ScrapeContext,
+    policy: TicketPolicy,
+}
+
+impl ScrapeContextBuilder {
+    pub fn lang_codes(mut self, lang_codes: Vec<String>) -> Self {
+        self.context.lang_codes = lang_codes;
+        self
+    }
+
+    /// Chapters to ask for in every request for the feed of a title, at most
+    /// [`title::MAX_FEED_PAGE_SIZE`]
+    pub fn feed_page_size(mut self, feed_page_size: usize) -> Self {
+        self.context.feed_page_size = feed_page_size.clamp(1, title::MAX_FEED_PAGE_SIZE);
+        self
+    }
+
+    pub fn path_template(mut self, path_template: PathTemplate) -> Self {
+        self.context.path_template = path_template;
+        self
+    }
+
+    pub fn ticket_policy(mut self, policy: TicketPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.context.retry_policy = retry_policy;
+        self
+    }
+
+    /// Limit the combined rate of image downloads
+    pub fn max_bandwidth(mut self, bytes_per_sec: u64) -> Self {
+        self.context.bandwidth = Some(TokenBucket::new(bytes_per_sec));
+        self
+    }
+
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.context.read_timeout = timeout;
+        self
+    }
+
+    pub fn stall_timeout(mut self, timeout: Duration) -> Self {
+        self.context.stall_timeout = timeout;
+        self
+    }
+
+    pub fn force_port_443(mut self, force_port_443: bool) -> Self {
+        self.context.force_port_443 = force_port_443;
+        self
+    }
+
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.context.offline = offline;
+        self
+    }
+
+    pub fn existing(mut self, existing: ExistingPolicy) -> Self {
+        self.context.existing = existing;
+        self
+    }
+
+    pub fn completion(mut self, completion: CompletionPolicy) -> Self {
+        self.context.completion = completion;
+        self
+    }
+
+    pub fn page_policy(mut self, page_policy: ValidationPolicy) -> Self {
+        self.context.page_policy = page_policy;
+        self
+    }
+
+    pub fn page_extensions(mut self, extensions: ExtensionScheme) -> Self {
+        self.context.extensions = extensions;
+        self
+    }
+
+    pub fn conversion(mut self, conversion: ConvertPolicy) -> Self {
+        self.context.conversion = conversion;
+        self
+    }
+
+    /// Only download the chapters of titles with these numbers
+    pub fn chapters(mut self, chapters: RangeSet<f64>) -> Self {
+        self.context.chapters = Some(chapters);
+        self
+    }
+
+    /// Skip titles by content rating and tags, like `--content-rating` and `--exclude-tag`
+    pub fn title_filter(mut self, title_filter: TitleFilter) -> Self {
+        self.context.title_filter = title_filter;
+        self
+    }
+
+    pub fn title_langs(mut self, title_langs: Vec<String>) -> Self {
+        self.context.title_langs = title_langs;
+        self
+    }
+
+    pub fn published(mut self, published: DateRange) -> Self {
+        self.context.published = published;
+        self
+    }
+
+    /// Only download these pages of chapters, numbered from 1
+    pub fn pages(mut self, pages: RangeSet<usize>) -> Self {
+        self.context.pages = Some(pages);
+        self
+    }
+
+    pub fn keep_going(mut self, keep_going: bool) -> Self {
+        self.context.keep_going = keep_going;
+        self
+    }
+
+    pub fn force(mut self, force: bool) -> Self {
+        self.context.force = force;
+        self
+    }
+
+    /// Report progress here, nothing is shown by default
+    pub fn progress(mut self, progress: ProgressDisplay) -> Self {
+        self.context.progress = progress;
+        self
+    }
+
+    pub fn write_metadata(mut self, write_metadata: bool) -> Self {
+        self.context.write_metadata = write_metadata;
+        self
+    }
+
+    pub fn write_comic_info(mut self, write_comic_info: bool) -> Self {
+        self.context.write_comic_info = write_comic_info;
+        self
+    }
+
+    pub fn epub(mut self, epub: bool) -> Self {
+        self.context.epub = epub;
+        self
+    }
+
+    pub fn cbz(mut self, cbz: bool) -> Self {
+        self.context.cbz = cbz;
+        self
+    }
+
+    /// Arrange output for a self-hosted reader, which also packages every chapter as a
+    /// .cbz with a ComicInfo.xml
+    pub fn layout(mut self, layout: LibraryLayout) -> Self {
+        self.context.path_template = layout.path_template();
+        self.context.write_comic_info = true;
+        self.context.cbz = true;
+        self.context.layout = Some(layout);
+        self
+    }
+
+    /// Keep every page once in a content-addressable store under the output directory,
+    /// and link the chapter directories to it
+    pub fn page_store(mut self, links: StoreLinks) -> Self {
+        self.context.page_store = Some(links);
+        self
+    }
+
+    pub fn export_format(mut self, export_format: ExportFormat) -> Self {
+        self.context.export_format = export_format;
+        self
+    }
+
+    pub fn export_template(mut self, export_template: PathTemplate) -> Self {
+        self.context.export_template = Some(export_template);
+        self
+    }
+
+    pub fn credentials(mut self, credentials: Credentials) -> Self {
+        self.context.credentials = credentials;
+        self
+    }
+
+    pub fn cache_mode(mut self, mode: CacheMode) -> Self {
+        self.context.response_cache = ResponseCache::load(mode);
+        self
+    }
+
+    pub fn notifier(mut self, notifier: Box<dyn Notifier>) -> Self {
+        self.context.notifiers.push(notifier);
+        self
+    }
+
+    pub fn on_chapter_done(mut self, hook: HookCommand) -> Self {
+        self.context.hooks.on_chapter_done = Some(hook);
+        self
+    }
+
+    pub fn on_title_done(mut self, hook: HookCommand) -> Self {
+        self.context.hooks.on_title_done = Some(hook);
+        self
+    }
+
+    pub fn build(mut self) -> ScrapeContext {
+        self.context.ticketer = Ticketer::new(&self.policy);
+        self.context
+    }
 }
 
 impl ScrapeContext {
+    /// Start building a context to carry out `command` with. The HTTP client is set up
+    /// with [`ClientSettings::install`], before the first request.
+    pub fn builder(command: Command) -> ScrapeContextBuilder {
+        let policy = TicketPolicy::default();
+        ScrapeContextBuilder {
+            context: ScrapeContext {
+                verbose: false,
+                quiet: false,
+                log_level: LevelFilter::Warn,
+                log_file: None,
+                lang_codes: vec!["en".to_owned()],
+                start_chapter: None,
+                end_chapter: None,
+                feed_page_size: title::MAX_FEED_PAGE_SIZE,
+                ignored_groups: Default::default(),
+                command,
+                refetch: false,
+                keep_going: false,
+                force: false,
+                json: false,
+                check_attestation: false,
+                trusted_key: None,
+                attest: false,
+                show_progress: false,
+                progress: ProgressDisplay::new(false, ProgressFormat::Bars),
+                dashboard: None,
+                report: None,
+                feed: None,
+                pages: None,
+                chapters: None,
+                published: Default::default(),
+                title_langs: Vec::new(),
+                title_filter: Default::default(),
+                page_policy: Default::default(),
+                extensions: Default::default(),
+                conversion: Default::default(),
+                write_metadata: false,
+                write_comic_info: false,
+                epub: false,
+                cbz: false,
+                layout: None,
+                page_store: None,
+                export_format: ExportFormat::default(),
+                export_template: None,
+                covers: None,
+                existing: Default::default(),
+                completion: Default::default(),
+                path_template: Default::default(),
+                sync_policy: Default::default(),
+                notifiers: Vec::new(),
+                hooks: Default::default(),
+                watch: None,
+                downloaded_chapters: Default::default(),
+                stats: Default::default(),
+                node_scores: NodeScores::load(),
+                group_names: NameCache::load(names::GROUP_NAMES_FILE_NAME),
+                author_names: NameCache::load(names::AUTHOR_NAMES_FILE_NAME),
+                response_cache: ResponseCache::load(CacheMode::Use),
+                history: DownloadHistory::load(),
+                bandwidth: None,
+                read_timeout: DEFAULT_READ_TIMEOUT,
+                stall_timeout: DEFAULT_STALL_TIMEOUT,
+                force_port_443: false,
+                offline: false,
+                snapshot_feed: None,
+                from_snapshot: None,
+                credentials: Default::default(),
+                ticketer: Ticketer::new(&policy),
+                retry_policy: Default::default(),
+            },
+            policy,
+        }
+    }
+
+    /// Build the context from the command line and the config file, exiting with a
+    /// usage error if they are invalid
     pub fn from_args() -> Self {
+        let config = match Config::default_path() {
+            Some(path) => Config::load(&path).unwrap_or_else(|e| usage_error(&e)),
+            None => Config::default(),
+        };
         let mut verbose = false;
-        let mut download_type_is_title = true;
+        let mut quiet = false;
+        let mut log_level: Option<String> = None;
+        let mut log_file: Option<PathBuf> = None;
+        let mut download_type_is_title: Option<bool> = None;
         let mut resource_id = String::new();
-        let mut lang_code = "en".to_owned();
+        let mut batch_file: Option<PathBuf> = None;
+        let mut keep_going = false;
+        let mut force = false;
+        let mut snapshot_feed: Option<PathBuf> = None;
+        let mut from_snapshot: Option<PathBuf> = None;
+        let mut lang_code = config.lang_code.unwrap_or_else(|| "en".to_owned());
+        let mut title_lang = config.title_lang;
         let mut start_chapter = None;
         let mut end_chapter = None;
         let mut print_info = false;
+        let mut json = false;
+        let mut dry_run = false;
+        let mut plan = false;
         let mut show_progress = true;
-        let mut ignored_groups_str = String::new();
-        let mut global_threshold = 1;
-        let mut per_origin_threshold = 1;
-        let mut wait_time = 150_000.0f64;
+        let mut tui = false;
+        let mut progress_format: Option<String> = None;
+        let mut ignored_groups_str = config
+            .ignored_groups
+            .iter()
+            .map(usize::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut global_threshold = config.global_threshold.unwrap_or(1);
+        let mut per_origin_threshold = config.per_origin_threshold.unwrap_or(1);
+        let mut wait_time = config.rate_limit_wait_time.unwrap_or_else(|| "150s".to_owned());
+        let mut max_bandwidth = config.max_bandwidth;
+        let mut requests_per_minute = config.requests_per_minute;
+        let mut feed_page_size = config.feed_page_size;
+        let mut origin_limits = config.origin_limits;
+        let mut api_backoff = config.api_backoff;
+        let mut image_backoff = config.image_backoff;
+        let mut retry_max_time = config.retry_max_time;
+        let mut retry_jitter = config.retry_jitter;
+        let mut proxy = config.proxy;
+        let mut user_agent = config.user_agent;
+        let mut headers = config.headers;
+        let mut insecure = config.insecure;
+        let mut ca_bundle = config.ca_bundle;
+        let mut connect_timeout = config.connect_timeout;
+        let mut read_timeout = config.read_timeout;
+        let mut stall_timeout = config.stall_timeout;
+        let mut pool_max_idle = config.pool_max_idle;
+        let mut pool_idle_timeout = config.pool_idle_timeout;
+        let mut tcp_keepalive = config.tcp_keepalive;
+        let mut http2 = config.http2;
+        let mut force_port_443 = config.force_443;
+        let mut feed_path: Option<String> = None;
+        let mut report: Option<PathBuf> = None;
+        let mut pages_str: Option<String> = None;
+        let mut chapters_str: Option<String> = None;
+        let mut published_since: Option<String> = None;
+        let mut content_rating = config.content_rating;
+        let mut excluded_tags = config.exclude_tags;
+        let mut published_before: Option<String> = None;
+        let mut check_images = false;
+        let mut min_page_bytes: Option<String> = None;
+        let mut min_page_dimensions: Option<String> = None;
+        let mut page_extensions = config.page_extensions;
+        let mut convert_to = config.convert;
+        let mut quality = config.quality;
+        let mut strip_metadata = config.strip_metadata;
+        let mut page_formats: Option<String> = None;
+        let mut write_metadata = config.write_metadata;
+        let mut write_comic_info = config.comic_info;
+        let mut epub = config.epub;
+        let mut cbz = config.cbz;
+        let mut layout = config.layout;
+        let mut page_store = config.page_store;
+        let mut covers: Option<String> = config.covers;
+        let mut existing: Option<String> = None;
+        let mut complete_when = config.complete_when;
+        let mut path_template: Option<String> = config.path_template;
+        let mut group_by_volume = config.group_by_volume;
+        let mut verify_dir: Option<PathBuf> = None;
+        let mut sync_dir: Option<PathBuf> = None;
+        let mut watch: Option<String> = None;
+        let mut follows = false;
+        let mut library_covers: Option<PathBuf> = None;
+        let mut export_dir: Option<PathBuf> = None;
+        let mut resume_dir: Option<PathBuf> = None;
+        let mut export_format: Option<String> = None;
+        let mut export_template: Option<String> = None;
+        let mut credentials = Credentials::default();
+        let mut refetch = false;
+        let mut offline = false;
+        let mut cache_mode = CacheMode::Use;
+        let mut check_attestation = false;
+        let mut trusted_key_str: Option<String> = None;
+        let mut attest = false;
+        let mut check_intervals: Option<String> = None;
+        let mut notifier_specs = config.notify;
+        let mut on_chapter_done = config.on_chapter_done;
+        let mut on_title_done = config.on_title_done;
         {
-            use argparse::{ArgumentParser, Store, StoreFalse, StoreOption, StoreTrue};
+            use argparse::{ArgumentParser, Collect, Store, StoreConst, StoreFalse, StoreOption, StoreTrue};
             let mut parser = ArgumentParser::new();
             parser.set_description("Scraper for mangadex.org");
             parser
                 .refer(&mut verbose)
                 .add_option(&["-v", "--verbose"], StoreTrue, "Be verbose");
+            parser.refer(&mut quiet).add_option(
+                &["-q", "--quiet"],
+                StoreTrue,
+                "Only print errors, without progress bars or summaries",
+            );
+            parser.refer(&mut log_file).add_option(
+                &["--log-file"],
+                StoreOption,
+                "Also write log messages to this file, as a json object per line",
+            );
+            parser.refer(&mut log_level).add_option(
+                &["--log-level"],
+                StoreOption,
+                "The least severe messages to log: error, warn, info, debug or trace, defaults to warn (info with \
+                 --verbose)",
+            );
             parser
                 .refer(&mut show_progress)
                 .add_option(&["--no-progress"], StoreFalse, "Don't report progress");
+            parser.refer(&mut progress_format).add_option(
+                &["--progress-format"],
+                StoreOption,
+                "How to report progress: bars, or json to write an event per line to stdout",
+            );
+            parser.refer(&mut tui).add_option(
+                &["--tui"],
+                StoreTrue,
+                "Show a full screen dashboard of the downloads, where chapters can be paused and skipped",
+            );
             parser
                 .refer(&mut download_type_is_title)
-                .add_option(&["-c", "--chapter"], StoreFalse, "Download a single manga chapter")
-                .add_option(&["-t", "--title"], StoreTrue, "Download an entire manga title")
-                .required();
+                .add_option(
+                    &["-c", "--chapter"],
+                    StoreConst(Some(false)),
+                    "Download a single manga chapter",
+                )
+                .add_option(
+                    &["-t", "--title"],
+                    StoreConst(Some(true)),
+                    "Download an entire manga title",
+                );
             parser.refer(&mut lang_code).add_option(
                 &["-l", "--lang-code"],
                 Store,
-                "The language code, defaults to en (English)",
+                "The language code or name, or several separated by commas (e.g. en,es-la or japanese), defaults to en (English)",
+            );
+            parser.refer(&mut title_lang).add_option(
+                &["--title-lang"],
+                StoreOption,
+                "Language to name titles in, or several to try in order (e.g. ja-ro,en), defaults to English",
             );
             parser.refer(&mut start_chapter).add_option(
                 &["-s", "--start-chapter"],
@@ -75,9 +682,52 @@ impl ScrapeContext {
                 StoreOption,
                 "Last chapter to download for a title",
             );
-            parser
-                .refer(&mut print_info)
-                .add_option(&["-i", "--info"], StoreTrue, "Only print info about the chapter");
+            parser.refer(&mut chapters_str).add_option(
+                &["--chapters"],
+                StoreOption,
+                "Only download these chapter numbers of a title, e.g. 1-10,12,15.5-20",
+            );
+            parser.refer(&mut published_since).add_option(
+                &["--published-since"],
+                StoreOption,
+                "Only download chapters of titles that came out on or after this date, e.g. 2024-01-01, or this long \
+                 ago, e.g. 30d",
+            );
+            parser.refer(&mut published_before).add_option(
+                &["--published-before"],
+                StoreOption,
+                "Only download chapters of titles that came out before this date",
+            );
+            parser.refer(&mut content_rating).add_option(
+                &["--content-rating"],
+                StoreOption,
+                "Only download titles with these content ratings, e.g. safe,suggestive",
+            );
+            parser.refer(&mut excluded_tags).add_option(
+                &["--exclude-tag"],
+                Collect,
+                "Don't download titles with this tag, by name or id, can be given more than once",
+            );
+            parser.refer(&mut print_info).add_option(
+                &["-i", "--info"],
+                StoreTrue,
+                "Only print info about the title or chapter, without downloading it",
+            );
+            parser.refer(&mut json).add_option(
+                &["--json"],
+                StoreTrue,
+                "With --info or --plan, print the report as json",
+            );
+            parser.refer(&mut dry_run).add_option(
+                &["--dry-run"],
+                StoreTrue,
+                "Print the files a download would create and roughly how big it is, without fetching any images",
+            );
+            parser.refer(&mut plan).add_option(
+                &["--plan"],
+                StoreTrue,
+                "Estimate how many chapters and pages a title download would fetch and how long it would take",
+            );
             parser.refer(&mut global_threshold).add_option(
                 &["-g", "--global-threshold"],
                 Store,
@@ -91,37 +741,748 @@ impl ScrapeContext {
             parser.refer(&mut wait_time).add_option(
                 &["-w", "--rate-limit-wait-time"],
                 Store,
-                "Time to wait (in seconds) after being rate limited",
+                "Time to wait after being rate limited, e.g. 90s or 5m (a bare number is milliseconds), defaults to 150s",
+            );
+            parser.refer(&mut requests_per_minute).add_option(
+                &["--requests-per-minute"],
+                StoreOption,
+                "Max number of requests to send to each origin in any minute",
+            );
+            parser.refer(&mut feed_page_size).add_option(
+                &["--feed-page-size"],
+                StoreOption,
+                "Chapters to ask for in each request for the chapter list of a title, at most 500 (the default)",
+            );
+            parser.refer(&mut origin_limits).add_option(
+                &["--origin-limit"],
+                Collect,
+                "Connection and per minute limits for one host, or every subdomain with *.domain, as \
+                 HOST=CONNECTIONS[/PER_MINUTE], e.g. api.mangadex.org=1/240",
+            );
+            parser.refer(&mut max_bandwidth).add_option(
+                &["--max-bandwidth"],
+                StoreOption,
+                "Limit the combined download rate of images, e.g. 5MiB/s",
+            );
+            parser.refer(&mut api_backoff).add_option(
+                &["--api-backoff"],
+                StoreOption,
+                "How to retry failed API requests, as INITIAL,FACTOR,ATTEMPTS, defaults to 500ms,4,4",
+            );
+            parser.refer(&mut image_backoff).add_option(
+                &["--image-backoff"],
+                StoreOption,
+                "How to retry failed image downloads, as INITIAL,FACTOR,ATTEMPTS, defaults to 100ms,2,6",
+            );
+            parser.refer(&mut retry_max_time).add_option(
+                &["--retry-max-time"],
+                StoreOption,
+                "Give up on a request once retrying it has taken this long, e.g. 2m",
+            );
+            parser.refer(&mut retry_jitter).add_option(
+                &["--retry-jitter"],
+                StoreOption,
+                "How much of each retry delay is random: full, equal (at least half the delay) or none, defaults \
+                 to full",
+            );
+            parser.refer(&mut proxy).add_option(
+                &["--proxy"],
+                StoreOption,
+                "Send every request through this HTTP, HTTPS or SOCKS5 proxy, e.g. socks5://localhost:1080, \
+                 instead of the one in HTTPS_PROXY or ALL_PROXY",
+            );
+            parser.refer(&mut user_agent).add_option(
+                &["--user-agent"],
+                StoreOption,
+                "Send this User-Agent header instead of the default browser one",
+            );
+            parser.refer(&mut headers).add_option(
+                &["--header"],
+                Collect,
+                "Send an extra header with every request, as \"Name: value\", can be given more than once",
+            );
+            parser.refer(&mut ca_bundle).add_option(
+                &["--ca-bundle"],
+                StoreOption,
+                "Also trust the certificate authorities in this PEM file, e.g. for a proxy that intercepts TLS",
+            );
+            parser.refer(&mut insecure).add_option(
+                &["--insecure"],
+                StoreTrue,
+                "Don't check TLS certificates at all. Anyone on the network can tamper with downloads!",
+            );
+            parser.refer(&mut connect_timeout).add_option(
+                &["--connect-timeout"],
+                StoreOption,
+                "Give up connecting to an image server after this long, e.g. 10s",
+            );
+            parser.refer(&mut read_timeout).add_option(
+                &["--read-timeout"],
+                StoreOption,
+                "Try a page again if its server takes longer than this to start answering, defaults to 30s",
+            );
+            parser.refer(&mut stall_timeout).add_option(
+                &["--stall-timeout"],
+                StoreOption,
+                "Try a page again if no data arrives for this long, defaults to 30s",
+            );
+            parser.refer(&mut pool_max_idle).add_option(
+                &["--pool-max-idle"],
+                StoreOption,
+                "Idle connections to keep open per image server",
+            );
+            parser.refer(&mut pool_idle_timeout).add_option(
+                &["--pool-idle-timeout"],
+                StoreOption,
+                "How long to keep idle connections to image servers open, e.g. 90s",
+            );
+            parser.refer(&mut tcp_keepalive).add_option(
+                &["--tcp-keepalive"],
+                StoreOption,
+                "Send TCP keepalive probes on image server connections this often, e.g. 60s",
+            );
+            parser.refer(&mut http2).add_option(
+                &["--http2"],
+                StoreTrue,
+                "Talk HTTP/2 to image servers right away, so concurrent downloads share a connection",
+            );
+            parser.refer(&mut force_port_443).add_option(
+                &["--force-443"],
+                StoreTrue,
+                "Only download from image servers on port 443, for networks that block other ports",
+            );
+            parser.refer(&mut resource_id).add_argument(
+                "resource id",
+                Store,
+                "The resource id, either the uuid or the url of a title or chapter",
+            );
+            parser.refer(&mut batch_file).add_option(
+                &["--batch"],
+                StoreOption,
+                "Also download every id in this file, one per line",
+            );
+            parser.refer(&mut keep_going).add_option(
+                &["-k", "--keep-going"],
+                StoreTrue,
+                "Carry on with the other chapters and titles when one fails, and list the failures at the end",
+            );
+            parser.refer(&mut force).add_option(
+                &["--force"],
+                StoreTrue,
+                "Write to the output directory even if another run seems to be using it",
+            );
+            parser.refer(&mut snapshot_feed).add_option(
+                &["--snapshot-feed"],
+                StoreOption,
+                "Save the chapter list of the title to this file, so the download can be repeated with --from-snapshot",
+            );
+            parser.refer(&mut from_snapshot).add_option(
+                &["--from-snapshot"],
+                StoreOption,
+                "Download the title and chapters saved with --snapshot-feed, instead of the current chapter list",
             );
-            parser
-                .refer(&mut resource_id)
-                .add_argument("resource id", Store, "The resource id (the number in the URL)")
-                .required();
             parser.refer(&mut ignored_groups_str).add_option(
                 &["--ignored-groups"],
                 Store,
                 "Groups not to download chapters from, separated by commas",
             );
-            parser.parse_args_or_exit();
+            parser.refer(&mut feed_path).add_option(
+                &["--atom-feed"],
+                StoreOption,
+                "Write an Atom feed of newly downloaded chapters to this file",
+            );
+            parser.refer(&mut report).add_option(
+                &["--report"],
+                StoreOption,
+                "Also write the summary of the run to this file as json",
+            );
+            parser.refer(&mut pages_str).add_option(
+                &["--pages"],
+                StoreOption,
+                "Only download these pages of a chapter, e.g. 1,5-10, replacing them if they exist unless \
+                 --existing says otherwise",
+            );
+            parser.refer(&mut check_images).add_option(
+                &["--check-images"],
+                StoreTrue,
+                "Check that downloaded pages are valid png/jpeg/gif/webp images",
+            );
+            parser.refer(&mut page_extensions).add_option(
+                &["--page-extensions"],
+                StoreOption,
+                "How pages get their file extensions: detected from the image (the default), url to keep the \
+                 server's, or one extension for every page, e.g. jpg",
+            );
+            parser.refer(&mut convert_to).add_option(
+                &["--convert"],
+                StoreOption,
+                "Convert downloaded pages to webp, jpeg or png (needs the convert feature)",
+            );
+            parser.refer(&mut quality).add_option(
+                &["--quality"],
+                StoreOption,
+                "Quality of pages converted to jpeg, from 1 to 100, defaults to 85",
+            );
+            parser.refer(&mut strip_metadata).add_option(
+                &["--strip-metadata"],
+                StoreTrue,
+                "Re-encode pages to drop their metadata (needs the convert feature)",
+            );
+            parser.refer(&mut min_page_bytes).add_option(
+                &["--min-page-size"],
+                StoreOption,
+                "Reject and re-download pages smaller than this, e.g. 2KiB",
+            );
+            parser.refer(&mut min_page_dimensions).add_option(
+                &["--min-page-dimensions"],
+                StoreOption,
+                "Reject and re-download pages smaller than WIDTHxHEIGHT pixels, e.g. 400x400",
+            );
+            parser.refer(&mut page_formats).add_option(
+                &["--page-formats"],
+                StoreOption,
+                "Image formats accepted as pages, e.g. png,jpeg",
+            );
+            parser.refer(&mut verify_dir).add_option(
+                &["--verify"],
+                StoreOption,
+                "Check a downloaded library against its checksum manifests instead of downloading",
+            );
+            parser.refer(&mut sync_dir).add_option(
+                &["--sync"],
+                StoreOption,
+                "Check every title previously downloaded under a directory for new chapters and download them",
+            );
+            parser.refer(&mut follows).add_option(
+                &["--follows"],
+                StoreTrue,
+                "Download new chapters of the manga followed by the logged in user",
+            );
+            parser.refer(&mut library_covers).add_option(
+                &["--library-covers"],
+                StoreOption,
+                "Download missing cover art for every title previously downloaded under a directory, selected \
+                 with --covers (defaults to all)",
+            );
+            parser.refer(&mut export_dir).add_option(
+                &["--export"],
+                StoreOption,
+                "Package the chapters of a downloaded title directory again, without downloading anything",
+            );
+            parser.refer(&mut resume_dir).add_option(
+                &["--resume"],
+                StoreOption,
+                "Finish a download into a directory that was interrupted, e.g. by a crash, without looking up the \
+                 title again",
+            );
+            parser.refer(&mut export_format).add_option(
+                &["--export-format"],
+                StoreOption,
+                "What --export packages chapters as: cbz, epub or pdf, defaults to cbz",
+            );
+            parser.refer(&mut export_template).add_option(
+                &["--export-template"],
+                StoreOption,
+                "Move the chapters to where this path template puts them before packaging them with --export",
+            );
+            parser.refer(&mut credentials.username).add_option(
+                &["--username"],
+                StoreOption,
+                "MangaDex username to log in with",
+            );
+            parser.refer(&mut credentials.password).add_option(
+                &["--password"],
+                StoreOption,
+                "MangaDex password to log in with",
+            );
+            parser.refer(&mut credentials.client_id).add_option(
+                &["--client-id"],
+                StoreOption,
+                "Id of your MangaDex personal API client",
+            );
+            parser.refer(&mut credentials.client_secret).add_option(
+                &["--client-secret"],
+                StoreOption,
+                "Secret of your MangaDex personal API client",
+            );
+            parser.refer(&mut credentials.token_file).add_option(
+                &["--token-file"],
+                StoreOption,
+                "File to keep the login session in, so later runs don't need a password",
+            );
+            parser.refer(&mut refetch).add_option(
+                &["--refetch"],
+                StoreTrue,
+                "Download pages that fail verification again",
+            );
+            parser
+                .refer(&mut cache_mode)
+                .add_option(
+                    &["--no-cache"],
+                    StoreConst(CacheMode::Off),
+                    "Don't use or keep cached API responses",
+                )
+                .add_option(
+                    &["--refresh"],
+                    StoreConst(CacheMode::Refresh),
+                    "Ask the API again instead of using cached responses, and cache the new ones",
+                );
+            parser.refer(&mut offline).add_option(
+                &["--offline"],
+                StoreTrue,
+                "Don't use the network, only cached API responses and what is on disk, for --info, --plan, \
+                 --dry-run, --verify and --export",
+            );
+            parser.refer(&mut check_attestation).add_option(
+                &["--attestation"],
+                StoreTrue,
+                "With --verify, also check the signed ATTESTATION file of a title",
+            );
+            parser.refer(&mut trusted_key_str).add_option(
+                &["--trusted-key"],
+                StoreOption,
+                "With --attestation, the public key (in hex) the title has to be signed with",
+            );
+            parser.refer(&mut attest).add_option(
+                &["--attest"],
+                StoreTrue,
+                "Write a signed ATTESTATION file once a title has been downloaded",
+            );
+            parser.refer(&mut watch).add_option(
+                &["--watch"],
+                StoreOption,
+                "With --sync or --follows, keep running and check again at this interval, e.g. 30m",
+            );
+            parser.refer(&mut check_intervals).add_option(
+                &["--check-intervals"],
+                StoreOption,
+                "How often to check titles for updates by publication status, e.g. ongoing=12h,completed=30d",
+            );
+            parser.refer(&mut notifier_specs).add_option(
+                &["--notify"],
+                Collect,
+                "Notify about new chapters, as KIND=URL where KIND is webhook, discord, ntfy or email, or desktop",
+            );
+            parser.refer(&mut on_chapter_done).add_option(
+                &["--on-chapter-done"],
+                StoreOption,
+                "Command to run after each new chapter, e.g. \"cmd {path}\" with {path}, {id}, {title} and {manga} \
+                 filled in",
+            );
+            parser.refer(&mut on_title_done).add_option(
+                &["--on-title-done"],
+                StoreOption,
+                "Command to run after each title with new chapters, with {path}, {id}, {title} and {new_chapters} \
+                 filled in",
+            );
+            parser.refer(&mut write_metadata).add_option(
+                &["--write-metadata"],
+                StoreTrue,
+                "Write the API metadata for titles and chapters to metadata.json files",
+            );
+            parser.refer(&mut write_comic_info).add_option(
+                &["--comic-info"],
+                StoreTrue,
+                "Write a ComicInfo.xml into each chapter directory for comic readers",
+            );
+            parser.refer(&mut epub).add_option(
+                &["--epub"],
+                StoreTrue,
+                "Also package each chapter as a fixed layout EPUB next to its directory, e.g. for Kobo or Kindle",
+            );
+            parser.refer(&mut cbz).add_option(
+                &["--cbz"],
+                StoreTrue,
+                "Also package each chapter as a .cbz archive next to its directory",
+            );
+            parser.refer(&mut layout).add_option(
+                &["--layout"],
+                StoreOption,
+                "Arrange titles as a library for a self-hosted reader, komga or kavita, with a .cbz and ComicInfo.xml \
+                 for each chapter",
+            );
+            parser.refer(&mut page_store).add_option(
+                &["--page-store"],
+                StoreOption,
+                "Keep pages once in .mdscrape/objects by their checksum, and link chapters to them with hardlink or \
+                 symlink",
+            );
+            parser.refer(&mut covers).add_option(
+                &["--covers"],
+                StoreOption,
+                "Download cover art of a title into a covers directory, either all or latest",
+            );
+            parser
+                .refer(&mut existing)
+                .add_option(
+                    &["--existing", "--if-exists"],
+                    StoreOption,
+                    "What to do with pages that are already downloaded: skip (the default), overwrite, verify against \
+                     their checksums, rename the old copy, or resume to also continue unfinished chapters and images",
+                )
+                .metavar("EXISTING");
+            parser.refer(&mut complete_when).add_option(
+                &["--complete-when"],
+                StoreOption,
+                "When a chapter counts as complete and is skipped: all pages (the default), a percentage of them, \
+                 e.g. 90%, or metadata for any chapter that was downloaded once",
+            );
+            parser.refer(&mut path_template).add_option(
+                &["--path-template"],
+                StoreOption,
+                "Template for chapter directories of a title, defaults to \"md{index:05} - {id} - {title}\"",
+            );
+            parser.refer(&mut group_by_volume).add_option(
+                &["--group-by-volume"],
+                StoreTrue,
+                "Save chapters in Volume 01, Volume 02, ... directories, and No Volume for chapters without one",
+            );
+            // --help exits with 0, unknown options and missing values with USAGE
+            if let Err(code) = parser.parse_args() {
+                std::process::exit(if code == 0 { exit::SUCCESS } else { exit::USAGE });
+            }
+        }
+        if watch.is_some() && !follows && sync_dir.is_none() {
+            usage_error("--watch needs --sync or --follows");
+        }
+        if follows && (verify_dir.is_some() || sync_dir.is_some()) {
+            usage_error("--follows can't be used together with --verify or --sync");
         }
-        let wait_seconds = (wait_time / 1000.0) as u64;
-        let wait_nsec = (wait_time % 1000.0) as u32 * 1_000_000;
+        if library_covers.is_some() && (follows || verify_dir.is_some() || sync_dir.is_some()) {
+            usage_error("--library-covers can't be used together with --follows, --verify or --sync");
+        }
+        if export_dir.is_some() && (follows || verify_dir.is_some() || sync_dir.is_some() || library_covers.is_some()) {
+            usage_error("--export can't be used together with --follows, --verify, --sync or --library-covers");
+        }
+        if resume_dir.is_some()
+            && (follows
+                || verify_dir.is_some()
+                || sync_dir.is_some()
+                || library_covers.is_some()
+                || export_dir.is_some())
+        {
+            usage_error(
+                "--resume can't be used together with --follows, --verify, --sync, --library-covers or --export",
+            );
+        }
+        if export_dir.is_none() && (export_format.is_some() || export_template.is_some()) {
+            usage_error("--export-format and --export-template need --export");
+        }
+        if from_snapshot.is_some() && (!resource_id.is_empty() || batch_file.is_some()) {
+            usage_error("--from-snapshot already names the title, it can't be combined with other ids");
+        }
+        let command = match (verify_dir, sync_dir, download_type_is_title) {
+            _ if follows => Command::Follows,
+            _ if library_covers.is_some() => Command::Covers(library_covers.unwrap()),
+            _ if export_dir.is_some() => Command::Export(export_dir.unwrap()),
+            _ if resume_dir.is_some() => Command::Resume(resume_dir.unwrap()),
+            (None, None, _) if from_snapshot.is_some() => {
+                let path = from_snapshot.as_deref().unwrap();
+                let title = TitleData::read_snapshot(path)
+                    .unwrap_or_else(|e| usage_error(&format!("Failed to read snapshot {:?}: {}", path, e)));
+                Command::Download(vec![DownloadType::Title(title.id())])
+            }
+            (Some(_), Some(_), _) => usage_error("--verify and --sync can't be used together"),
+            (Some(dir), None, _) => Command::Verify(dir),
+            (None, Some(dir), _) => Command::Sync(dir),
+            (None, None, None) => usage_error("One of --chapter or --title is required"),
+            (None, None, Some(_)) if resource_id.is_empty() && batch_file.is_none() => {
+                usage_error("A resource id is required")
+            }
+            (None, None, Some(is_title)) => {
+                let kind = if is_title {
+                    ResourceKind::Title
+                } else {
+                    ResourceKind::Chapter
+                };
+                let ids = parse_resource_ids(&resource_id, batch_file.as_deref(), kind);
+                Command::Download(
+                    ids.into_iter()
+                        .map(|id| match kind {
+                            ResourceKind::Title => DownloadType::Title(id),
+                            ResourceKind::Chapter => DownloadType::Chapter(id),
+                        })
+                        .collect(),
+                )
+            }
+        };
+        let command = match command {
+            Command::Download(downloads) if print_info => Command::Info(downloads),
+            _ if print_info => usage_error("--info needs a title or chapter to report on"),
+            command => command,
+        };
+        let command = match command {
+            Command::Download(downloads) if plan && !print_info => Command::Plan(
+                downloads
+                    .into_iter()
+                    .map(|download| match download {
+                        DownloadType::Title(id) => id,
+                        DownloadType::Chapter(_) => usage_error("--plan can only be used with titles"),
+                    })
+                    .collect(),
+            ),
+            _ if plan && print_info => usage_error("--plan and --info can't be used together"),
+            _ if plan => usage_error("--plan needs titles to estimate"),
+            command => command,
+        };
+        if chapters_str.is_some()
+            && !(download_type_is_title == Some(true) && matches!(command, Command::Download(_) | Command::Plan(_)))
+        {
+            usage_error("--chapters can only be used when downloading titles, or with --plan");
+        }
+        let now = chrono::Utc::now();
+        let parse_published = |value: Option<String>| {
+            value.map(|value| selection::parse_date(&value, now).unwrap_or_else(|e| usage_error(&e)))
+        };
+        let published = DateRange {
+            since: parse_published(published_since),
+            before: parse_published(published_before),
+        };
+        if let (Some(since), Some(before)) = (published.since, published.before) {
+            if since >= before {
+                usage_error("--published-since has to be before --published-before");
+            }
+        }
+        if !published.is_unbounded() && download_type_is_title == Some(false) {
+            usage_error("--published-since and --published-before only apply to titles");
+        }
+        let mut title_filter = TitleFilter {
+            excluded_tags,
+            ..Default::default()
+        };
+        if let Some(value) = content_rating {
+            title_filter
+                .set_content_ratings(&value)
+                .unwrap_or_else(|e| usage_error(&e));
+        }
+        if !title_filter.is_empty() && download_type_is_title == Some(false) {
+            usage_error("--content-rating and --exclude-tag only apply to titles");
+        }
+        if json && !(print_info || plan) {
+            usage_error("--json can only be used together with --info or --plan");
+        }
+        let single_title = match command {
+            Command::Download(ref downloads) | Command::Info(ref downloads) => {
+                matches!(downloads[..], [DownloadType::Title(_)])
+            }
+            Command::Plan(ref titles) => titles.len() == 1,
+            _ => false,
+        };
+        if snapshot_feed.is_some() && !single_title {
+            usage_error("--snapshot-feed can only be used with a single title");
+        }
+        if report.is_some() && !command.downloads_chapters() {
+            usage_error("--report can only be used when downloading, syncing or with --follows");
+        }
+        if tui && !command.downloads_chapters() {
+            usage_error("--tui can only be used when downloading, syncing or with --follows");
+        }
+        let progress_format: ProgressFormat = progress_format
+            .map(|format| format.parse().unwrap_or_else(|e: String| usage_error(&e)))
+            .unwrap_or_default();
+        if tui && quiet {
+            usage_error("--tui can't be used together with --quiet");
+        }
+        let log_level = match log_level {
+            Some(level) => level.parse().unwrap_or_else(|_| {
+                usage_error(&format!(
+                    "Unknown log level \"{}\", expected error, warn, info, debug or trace",
+                    level
+                ))
+            }),
+            None if verbose => LevelFilter::Info,
+            None => LevelFilter::Warn,
+        };
+        let show_progress = show_progress && !quiet;
+        if tui && progress_format != ProgressFormat::Bars {
+            usage_error("--tui can't be used together with --progress-format");
+        }
+        if check_attestation && !matches!(command, Command::Verify(_)) {
+            usage_error("--attestation can only be used together with --verify");
+        }
+        if check_attestation != trusted_key_str.is_some() {
+            usage_error("--attestation and --trusted-key have to be used together");
+        }
+        let trusted_key = trusted_key_str.map(|value| {
+            attestation::parse_public_key(&value)
+                .unwrap_or_else(|_| usage_error(&format!("--trusted-key: Invalid public key \"{}\"", value)))
+        });
+        if pages_str.is_some()
+            && !matches!(command, Command::Download(ref downloads) if downloads.len() == 1 && download_type_is_title == Some(false))
+        {
+            usage_error("--pages can only be used when downloading a single chapter");
+        }
+        let command = match command {
+            Command::Download(downloads) if dry_run => Command::DryRun(downloads),
+            _ if dry_run => usage_error("--dry-run can only be used when downloading a title or chapter"),
+            command => command,
+        };
+        if offline {
+            match command {
+                Command::Download(_)
+                | Command::Sync(_)
+                | Command::Follows
+                | Command::Covers(_)
+                | Command::Resume(_) => {
+                    usage_error("--offline can only be used with --info, --plan, --dry-run, --verify and --export")
+                }
+                Command::Verify(_) if refetch => {
+                    usage_error("--refetch downloads pages, it can't be used with --offline")
+                }
+                _ if cache_mode != CacheMode::Use => usage_error(
+                    "--offline answers from the response cache, it can't be used with --no-cache or --refresh",
+                ),
+                _ => {}
+            }
+        }
+        let lang_codes: Vec<String> = lang_code
+            .split(',')
+            .map(str::trim)
+            .filter(|lang_code| !lang_code.is_empty())
+            .map(|lang_code| match languages::resolve_language(lang_code) {
+                Ok(code) => code.to_owned(),
+                Err(e) => usage_error(&format!("--lang-code: {}", e)),
+            })
+            .collect();
+        if lang_codes.is_empty() {
+            usage_error("--lang-code needs at least one language code");
+        }
+        let title_langs: Vec<String> = title_lang
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|lang_code| !lang_code.is_empty())
+            .map(|lang_code| match languages::resolve_language(lang_code) {
+                Ok(code) => code.to_owned(),
+                Err(e) => usage_error(&format!("--title-lang: {}", e)),
+            })
+            .collect();
+        let mut page_policy = ValidationPolicy {
+            check_format: check_images,
+            ..Default::default()
+        };
+        if let Some(value) = min_page_bytes {
+            page_policy.set_min_bytes(&value).unwrap_or_else(|e| usage_error(&e));
+        }
+        if let Some(value) = min_page_dimensions {
+            page_policy
+                .set_min_dimensions(&value)
+                .unwrap_or_else(|e| usage_error(&e));
+        }
+        if let Some(value) = page_formats {
+            page_policy
+                .set_allowed_formats(&value)
+                .unwrap_or_else(|e| usage_error(&e));
+        }
+        let extensions: ExtensionScheme = page_extensions
+            .map(|scheme| scheme.parse().unwrap_or_else(|e: String| usage_error(&e)))
+            .unwrap_or_default();
+        let mut conversion = ConvertPolicy {
+            strip_metadata,
+            ..Default::default()
+        };
+        if let Some(value) = convert_to {
+            conversion.set_format(&value).unwrap_or_else(|e| usage_error(&e));
+        }
+        if let Some(value) = quality {
+            conversion.set_quality(value).unwrap_or_else(|e| usage_error(&e));
+        }
+        conversion.check_supported().unwrap_or_else(|e| usage_error(&e));
+        if conversion.format.is_some() && extensions == ExtensionScheme::Url {
+            // The next run wouldn't recognise the converted pages by their new extensions
+            usage_error("--convert can't be used with --page-extensions url");
+        }
+        // A bare number has always been milliseconds here
+        let rate_limit_wait_time =
+            units::parse_duration(&wait_time, Duration::from_millis(1)).unwrap_or_else(|e| usage_error(&e));
         let policy = TicketPolicy {
             max_global: global_threshold,
             max_per_site: per_origin_threshold,
-            rate_limit_wait_time: tokio::time::Duration::new(wait_seconds, wait_nsec),
+            rate_limit_wait_time,
+            max_per_minute: match requests_per_minute {
+                Some(0) => usage_error("--requests-per-minute has to be more than 0"),
+                limit => limit,
+            },
+            per_origin: origin_limits
+                .iter()
+                .map(|value| OriginLimit::parse(value).unwrap_or_else(|e| usage_error(&e)))
+                .collect(),
         };
+        let mut retry_policy = RetryPolicy::default();
+        if let Some(value) = api_backoff {
+            retry_policy.api = BackoffPolicy::parse(&value).unwrap_or_else(|e| usage_error(&e));
+        }
+        if let Some(value) = image_backoff {
+            retry_policy.images = BackoffPolicy::parse(&value).unwrap_or_else(|e| usage_error(&e));
+        }
+        if let Some(value) = retry_max_time {
+            retry_policy.set_max_time(
+                units::parse_duration(&value, Duration::from_secs(1)).unwrap_or_else(|e| usage_error(&e)),
+            );
+        }
+        if let Some(value) = retry_jitter {
+            retry_policy.set_jitter(value.parse::<Jitter>().unwrap_or_else(|e| usage_error(&e)));
+        }
+        let layout = layout.map(|layout| layout.parse::<LibraryLayout>().unwrap_or_else(|e| usage_error(&e)));
+        if layout.is_some() {
+            write_comic_info = true;
+            cbz = true;
+        }
+        let mut path_template = match (path_template, layout) {
+            (Some(template), _) => PathTemplate::parse(&template).unwrap_or_else(|e| usage_error(&e)),
+            (None, Some(layout)) => layout.path_template(),
+            (None, None) => PathTemplate::default(),
+        };
+        if group_by_volume {
+            path_template = path_template.grouped_by_volume();
+        }
+        ClientSettings {
+            proxy,
+            user_agent,
+            headers,
+            insecure,
+            ca_bundle,
+            image_pool: PoolSettings {
+                connect_timeout: connect_timeout.map(|value| {
+                    units::parse_duration(&value, Duration::from_secs(1)).unwrap_or_else(|e| usage_error(&e))
+                }),
+                max_idle_per_host: pool_max_idle,
+                idle_timeout: pool_idle_timeout.map(|value| {
+                    units::parse_duration(&value, Duration::from_secs(1)).unwrap_or_else(|e| usage_error(&e))
+                }),
+                tcp_keepalive: tcp_keepalive.map(|value| {
+                    units::parse_duration(&value, Duration::from_secs(1)).unwrap_or_else(|e| usage_error(&e))
+                }),
+                http2_prior_knowledge: http2,
+            },
+        }
+        .install()
+        .unwrap_or_else(|e| usage_error(&e));
         ScrapeContext {
             verbose,
-            lang_code,
+            quiet,
+            log_level,
+            log_file,
+            lang_codes,
             start_chapter,
             end_chapter,
-            show_progress,
-            download_type: if download_type_is_title {
-                DownloadType::Title(Uuid::parse_str(&resource_id).expect("Failed to parse title UUID"))
-            } else {
-                DownloadType::Chapter(Uuid::parse_str(&resource_id).expect("Failed to parse chapter UUID"))
+            feed_page_size: match feed_page_size {
+                Some(size) if size == 0 || size > title::MAX_FEED_PAGE_SIZE => usage_error(&format!(
+                    "--feed-page-size has to be between 1 and {}",
+                    title::MAX_FEED_PAGE_SIZE
+                )),
+                Some(size) => size,
+                None => title::MAX_FEED_PAGE_SIZE,
             },
+            show_progress: show_progress && !tui && progress_format == ProgressFormat::Bars,
+            refetch,
+            keep_going,
+            force,
+            json,
+            check_attestation,
+            trusted_key,
+            attest,
             ignored_groups: if !ignored_groups_str.is_empty() {
                 ignored_groups_str
                     .split(',')
@@ -134,27 +1495,191 @@ impl ScrapeContext {
             } else {
                 Default::default()
             },
-            progress: Arc::new(indicatif::MultiProgress::new()),
-            ticketer: Ticketer::new(&policy),
+            progress: ProgressDisplay::new(show_progress && !tui, progress_format),
+            dashboard: tui.then(Default::default),
+            page_policy,
+            extensions,
+            conversion,
+            write_metadata,
+            write_comic_info,
+            epub,
+            cbz,
+            layout,
+            page_store: page_store.map(|links| links.parse().unwrap_or_else(|e: String| usage_error(&e))),
+            export_format: export_format
+                .map(|format| format.parse().unwrap_or_else(|e: String| usage_error(&e)))
+                .unwrap_or_default(),
+            export_template: export_template.map(|template| {
+                let template = PathTemplate::parse(&template).unwrap_or_else(|e| usage_error(&e));
+                if template.uses("index") {
+                    usage_error("--export-template can't use {index}, chapters don't know their position in the title");
+                }
+                template
+            }),
+            covers: covers.map(|covers| covers.parse().unwrap_or_else(|e: String| usage_error(&e))),
+            existing: match existing {
+                Some(existing) => existing.parse().unwrap_or_else(|e: String| usage_error(&e)),
+                // Pages are asked for by number to fetch them again, e.g. broken ones
+                None if pages_str.is_some() => ExistingPolicy::Overwrite,
+                // Continue the chapters the interrupted run had started
+                None if matches!(command, Command::Resume(_)) => ExistingPolicy::Resume,
+                None => ExistingPolicy::default(),
+            },
+            completion: complete_when
+                .map(|criterion| criterion.parse().unwrap_or_else(|e: String| usage_error(&e)))
+                .unwrap_or_default(),
+            path_template,
+            notifiers: notifier_specs
+                .iter()
+                .map(|spec| notify::parse_notifier(spec).unwrap_or_else(|e| usage_error(&e)))
+                .collect(),
+            watch: watch.map(|value| parse_timeout(&value, "--watch")),
+            hooks: Hooks::new(
+                on_chapter_done.map(|command| {
+                    HookCommand::parse(&command, hooks::CHAPTER_PLACEHOLDERS).unwrap_or_else(|e| usage_error(&e))
+                }),
+                on_title_done.map(|command| {
+                    HookCommand::parse(&command, hooks::TITLE_PLACEHOLDERS).unwrap_or_else(|e| usage_error(&e))
+                }),
+            ),
+            credentials,
+            sync_policy: match check_intervals {
+                Some(intervals) => SyncPolicy::parse(&intervals).unwrap_or_else(|e| usage_error(&e)),
+                None => Default::default(),
+            },
+            pages: pages_str.map(|pages| RangeSet::parse(&pages).unwrap_or_else(|e| usage_error(&e.to_string()))),
+            published,
+            title_langs,
+            title_filter,
+            chapters: chapters_str
+                .map(|chapters| RangeSet::parse(&chapters).unwrap_or_else(|e| usage_error(&e.to_string()))),
+            report,
+            feed: feed_path.map(|path| {
+                ChapterFeed::open(&path)
+                    .unwrap_or_else(|e| usage_error(&format!("--atom-feed: Failed to load {:?}: {}", path, e)))
+            }),
+            bandwidth: max_bandwidth.map(|value| match units::parse_size(&value) {
+                Ok(0) => usage_error("--max-bandwidth has to be more than 0"),
+                Ok(bytes_per_sec) => TokenBucket::new(bytes_per_sec),
+                Err(e) => usage_error(&e),
+            }),
+            read_timeout: read_timeout.map_or(DEFAULT_READ_TIMEOUT, |value| parse_timeout(&value, "--read-timeout")),
+            stall_timeout: stall_timeout
+                .map_or(DEFAULT_STALL_TIMEOUT, |value| parse_timeout(&value, "--stall-timeout")),
+            force_port_443,
+            offline,
+            snapshot_feed,
+            from_snapshot,
+            ..ScrapeContext::builder(command)
+                .ticket_policy(policy)
+                .retry_policy(retry_policy)
+                .cache_mode(if offline { CacheMode::Offline } else { cache_mode })
+                .build()
         }
     }
 
+    /// The client to make requests with, throttled and retried for this run
+    pub fn http(&self) -> HttpClient<'_> {
+        HttpClient::new(self)
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    /// Tell the user how the run is going, unless `--quiet`. With `--tui` this goes on
+    /// the dashboard, since the terminal belongs to it.
+    pub fn print_status(&self, message: String) {
+        match self.dashboard {
+            Some(ref dashboard) => dashboard.add_message(message),
+            None if !self.quiet => println!("{}", message),
+            None => {}
+        }
+    }
+
+    /// What the throttle of every origin used so far is doing
+    pub fn throttle_states(&self) -> Vec<OriginState<Origin>> {
+        self.ticketer.origin_states()
+    }
+
+    /// How many tickets every origin used so far got, how long they waited for them
+    /// and how often it rate limited us
+    pub fn throttle_stats(&self) -> Vec<(Origin, TicketStats)> {
+        self.ticketer.stats()
+    }
+
+    pub fn reset_throttle_stats(&self) {
+        self.ticketer.reset_stats();
+    }
+
+    /// Remember the request budget an origin announced in the headers of a response
+    pub fn record_rate_budget(&self, origin: &Origin, headers: &reqwest::header::HeaderMap) {
+        if let Some(budget) = RateBudget::from_headers(headers) {
+            self.ticketer.record_budget(origin, budget);
+        }
+    }
+
+    /// Requests waiting for a connection
+    pub fn waiting_requests(&self) -> usize {
+        self.ticketer.waiting()
+    }
+
     pub async fn get_ticket(&self, origin: &Origin) -> Ticket {
         self.ticketer.get_ticket(origin).await
     }
 
-    pub async fn with_retry_for_origin<T, F>(&self, origin: &Origin, f: impl Fn() -> F) -> Result<T, DownloadError>
+    pub async fn get_priority_ticket(&self, origin: &Origin) -> Ticket {
+        self.ticketer.get_priority_ticket(origin).await
+    }
+
+    async fn get_ticket_in(&self, origin: &Origin, class: TicketClass) -> Ticket {
+        match class {
+            TicketClass::Normal => self.get_ticket(origin).await,
+            TicketClass::Priority => self.get_priority_ticket(origin).await,
+        }
+    }
+
+    /// Run f with a ticket of the given class for origin, retrying with backoff
+    pub async fn with_retry_for_origin<T, F>(
+        &self,
+        origin: &Origin,
+        class: TicketClass,
+        backoff: &BackoffPolicy,
+        f: impl Fn() -> F,
+    ) -> Result<T, DownloadError>
     where
         F: futures::Future<Output = Result<T, DownloadError>>,
     {
         log::info!("With retry for origin {:?}", origin);
-        let ticket = RefCell::new(Some(self.get_ticket(origin).await));
-        retry::with_retry(f, || async {
+        let ticket = Mutex::new(Some(self.get_ticket_in(origin, class).await));
+        let attempts = AtomicUsize::new(0);
+        let origin_name = origin.ascii_serialization();
+        let counted = || {
+            let attempt = attempts.fetch_add(1, Ordering::Relaxed) + 1;
+            if attempt > 1 {
+                self.progress.emit(ProgressEvent::Retry {
+                    origin: &origin_name,
+                    attempt,
+                });
+            }
+            f()
+        };
+        let result = retry::with_retry(backoff, counted, || async {
             self.ticketer.mark_origin_locked(origin);
+            self.progress.emit(ProgressEvent::RateLimited { origin: &origin_name });
             // Reacquire the ticket
-            ticket.replace(None);
-            ticket.replace(Some(self.get_ticket(origin).await));
+            *ticket.lock().unwrap() = None;
+            let reacquired = self.get_ticket_in(origin, class).await;
+            *ticket.lock().unwrap() = Some(reacquired);
         })
-        .await
+        .await;
+        self.stats
+            .add_retries(attempts.load(Ordering::Relaxed).saturating_sub(1));
+        if let Err(DownloadError::RetryExhausted { ref attempts, .. }) = result {
+            for attempt in attempts {
+                log::info!("Attempt failed after {:?}: {}", attempt.after, attempt.error);
+            }
+        }
+        result
     }
 }
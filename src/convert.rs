@@ -0,0 +1,178 @@
+use std::path::{Path, PathBuf};
+
+use crate::retry::{DownloadError, Result};
+use crate::validate::ImageFormat;
+
+/// Quality of re-encoded jpeg pages, unless `--quality` says otherwise
+pub const DEFAULT_QUALITY: u8 = 85;
+
+/// What happens to pages once they are downloaded, set with `--convert`, `--quality` and
+/// `--strip-metadata`. Converting needs mdscrape to be built with the `convert` feature.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConvertPolicy {
+    /// Format to convert every page to
+    pub format: Option<ImageFormat>,
+    /// Quality of jpeg pages, from 1 to 100. WebP pages are always lossless.
+    pub quality: u8,
+    /// Re-encode pages that are already in the right format, which drops their metadata
+    pub strip_metadata: bool,
+}
+
+impl Default for ConvertPolicy {
+    fn default() -> Self {
+        ConvertPolicy {
+            format: None,
+            quality: DEFAULT_QUALITY,
+            strip_metadata: false,
+        }
+    }
+}
+
+impl ConvertPolicy {
+    /// Whether pages are touched at all
+    pub fn is_active(&self) -> bool {
+        self.format.is_some() || self.strip_metadata
+    }
+
+    pub fn set_format(&mut self, value: &str) -> std::result::Result<(), String> {
+        let format: ImageFormat = value.parse()?;
+        if format == ImageFormat::Gif {
+            return Err("Pages can be converted to webp, jpeg or png".to_owned());
+        }
+        self.format = Some(format);
+        Ok(())
+    }
+
+    pub fn set_quality(&mut self, quality: u8) -> std::result::Result<(), String> {
+        if !(1..=100).contains(&quality) {
+            return Err(format!("Invalid quality {}, expected 1 to 100", quality));
+        }
+        self.quality = quality;
+        Ok(())
+    }
+
+    /// Check that this build can do what the policy asks for
+    pub fn check_supported(&self) -> std::result::Result<(), String> {
+        if self.is_active() && !cfg!(feature = "convert") {
+            return Err(
+                "--convert and --strip-metadata need mdscrape to be built with the convert feature, e.g. \
+                 cargo install mdscrape --features convert"
+                    .to_owned(),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Convert a downloaded page as the policy asks, returning where it ended up. The new
+/// format's extension replaces the old one unless `keep_name` is set. Decoding and
+/// encoding images is slow, so this runs on the blocking thread pool and downloads
+/// carry on meanwhile.
+pub async fn process_page(path: PathBuf, policy: &ConvertPolicy, keep_name: bool) -> Result<PathBuf> {
+    let policy = policy.clone();
+    tokio::task::spawn_blocking(move || convert_file(&path, &policy, keep_name))
+        .await
+        .map_err(|e| DownloadError::ConversionFailed(e.to_string()))?
+}
+
+#[cfg(feature = "convert")]
+fn convert_file(path: &Path, policy: &ConvertPolicy, keep_name: bool) -> Result<PathBuf> {
+    use image::codecs::jpeg::JpegEncoder;
+    use image::codecs::png::PngEncoder;
+    use image::codecs::webp::WebPEncoder;
+    use image::DynamicImage;
+
+    let failed = |e: &dyn std::fmt::Display| DownloadError::ConversionFailed(format!("{:?}: {}", path, e));
+    let current = crate::validate::detect_file_format(path)?;
+    let format = match policy.format.or(current) {
+        Some(format) => format,
+        None => return Err(failed(&"not a png, jpeg, gif or webp image")),
+    };
+    if current == Some(format) && !policy.strip_metadata {
+        return Ok(path.to_owned());
+    }
+    let image = image::ImageReader::open(path)?
+        .with_guessed_format()?
+        .decode()
+        .map_err(|e| failed(&e))?;
+    let target = if keep_name {
+        path.to_owned()
+    } else {
+        path.with_extension(format.extension())
+    };
+    // Written next to the page first, so an interrupted conversion leaves the original
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".converting");
+    let temporary = PathBuf::from(temporary);
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(&temporary)?);
+    let result = match format {
+        ImageFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut writer, policy.quality)),
+        ImageFormat::Png => image.write_with_encoder(PngEncoder::new(&mut writer)),
+        ImageFormat::Webp if image.color().has_alpha() => {
+            DynamicImage::ImageRgba8(image.to_rgba8()).write_with_encoder(WebPEncoder::new_lossless(&mut writer))
+        }
+        ImageFormat::Webp => {
+            DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(WebPEncoder::new_lossless(&mut writer))
+        }
+        ImageFormat::Gif => return Err(failed(&"can't convert to gif")),
+    };
+    drop(writer);
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&temporary);
+        return Err(failed(&e));
+    }
+    std::fs::rename(&temporary, &target)?;
+    if target != path {
+        std::fs::remove_file(path)?;
+    }
+    Ok(target)
+}
+
+#[cfg(not(feature = "convert"))]
+fn convert_file(path: &Path, _policy: &ConvertPolicy, _keep_name: bool) -> Result<PathBuf> {
+    Err(DownloadError::ConversionFailed(format!(
+        "{:?}: mdscrape was built without the convert feature",
+        path
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_convert_policy() {
+        let mut policy = ConvertPolicy::default();
+        assert!(!policy.is_active());
+        assert!(policy.check_supported().is_ok());
+        assert!(policy.set_format("gif").is_err());
+        assert!(policy.set_quality(0).is_err());
+        policy.set_format("webp").unwrap();
+        policy.set_quality(70).unwrap();
+        assert!(policy.is_active());
+        assert_eq!(policy.format, Some(ImageFormat::Webp));
+        assert_eq!(policy.check_supported().is_ok(), cfg!(feature = "convert"));
+    }
+
+    #[cfg(feature = "convert")]
+    #[tokio::test]
+    async fn test_convert_page() {
+        let dir = std::env::temp_dir().join(format!("mdscrape-test-{}", uuid::Uuid::from_u128(rand::random())));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("0001.png");
+        image::RgbImage::from_pixel(4, 3, image::Rgb([200, 10, 10]))
+            .save(&path)
+            .unwrap();
+        let mut policy = ConvertPolicy::default();
+        policy.set_format("jpeg").unwrap();
+        let converted = process_page(path.clone(), &policy, false).await.unwrap();
+        assert_eq!(converted, dir.join("0001.jpg"));
+        assert!(!path.exists());
+        assert_eq!(
+            crate::validate::detect_file_format(&converted).unwrap(),
+            Some(ImageFormat::Jpeg)
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use log::{debug, error, info};
+use reqwest::Url;
+use uuid::Uuid;
+
+use crate::api::cover::{CoverData, CoverListResponse};
+use crate::context::ScrapeContext;
+use crate::library::{find_title_dirs, TitleRecord};
+use crate::naming::sanitize_component;
+use crate::retry::Result;
+
+pub const COVERS_DIR_NAME: &str = "covers";
+
+/// Which covers of a title to download with `--covers`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CoverSelection {
+    All,
+    Latest,
+}
+
+impl std::str::FromStr for CoverSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(CoverSelection::All),
+            "latest" => Ok(CoverSelection::Latest),
+            v => Err(format!("Unknown cover selection \"{}\", expected all or latest", v)),
+        }
+    }
+}
+
+async fn download_cover_list(manga_id: Uuid, context: &ScrapeContext) -> Result<Vec<CoverData>> {
+    let mut offset = 0usize;
+    let mut covers = Vec::new();
+    loop {
+        let url = Url::parse(&format!(
+            "https://api.mangadex.org/cover?manga[]={}&limit=100&offset={}&order[volume]=asc",
+            manga_id, offset
+        ))?;
+        debug!("Going to download cover list from {}", url);
+        let mut resp: CoverListResponse = context.http().get_json(&url).await?;
+        offset += resp.data.len();
+        let done = resp.data.is_empty() || offset >= resp.total;
+        covers.append(&mut resp.data);
+        if done {
+            return Ok(covers);
+        }
+    }
+}
+
+/// Name covers after their volume, e.g. `Volume 03.jpg`. If several covers share a
+/// volume (usually different locales) the later ones get their id appended.
+fn cover_file_names(covers: &[CoverData]) -> Vec<String> {
+    let mut taken = HashSet::new();
+    covers
+        .iter()
+        .map(|cover| {
+            let extension = cover.attributes.file_name.split('.').next_back().unwrap_or("jpg");
+            let stem = match cover.attributes.volume.as_deref() {
+                Some(volume) if !volume.is_empty() => {
+                    let integer_len = volume.chars().take_while(|c| c.is_ascii_digit()).count();
+                    format!("Volume {}{}", "0".repeat(2usize.saturating_sub(integer_len)), volume)
+                }
+                _ => "No volume".to_owned(),
+            };
+            let mut name = sanitize_component(&format!("{}.{}", stem, extension));
+            if !taken.insert(name.clone()) {
+                name = sanitize_component(&format!("{} - {}.{}", stem, cover.id, extension));
+                taken.insert(name.clone());
+            }
+            name
+        })
+        .collect()
+}
+
+/// Download the cover art of a title into a `covers` directory under `path`. Covers
+/// that were already downloaded are skipped.
+pub async fn download_covers(
+    manga_id: Uuid,
+    selection: CoverSelection,
+    path: &Path,
+    context: &ScrapeContext,
+) -> Result<()> {
+    let mut covers = download_cover_list(manga_id, context).await?;
+    if selection == CoverSelection::Latest {
+        // created_at is an RFC 3339 timestamp, which sorts lexicographically
+        covers = covers
+            .into_iter()
+            .max_by(|a, b| a.attributes.created_at.cmp(&b.attributes.created_at))
+            .into_iter()
+            .collect();
+    }
+    let covers_dir = path.join(COVERS_DIR_NAME);
+    fs::create_dir_all(&covers_dir)?;
+    for (cover, name) in covers.iter().zip(cover_file_names(&covers)) {
+        let destination = covers_dir.join(name);
+        if destination.exists() {
+            debug!("Cover {:?} already exists, skipping", destination);
+            continue;
+        }
+        let url = Url::parse(&cover.image_url(manga_id))?;
+        info!("Saving cover {:?}", destination);
+        context.http().download_image(&url, &destination).await?;
+    }
+    Ok(())
+}
+
+/// Download missing cover art for every title in the library under root, e.g. for
+/// titles downloaded before `--covers` existed. A title that fails doesn't stop the
+/// others, the number of failed titles is returned.
+pub async fn download_library_covers(root: &Path, selection: CoverSelection, context: &ScrapeContext) -> Result<usize> {
+    let mut failed = 0;
+    for dir in find_title_dirs(root) {
+        let record = match TitleRecord::read(&dir)? {
+            Some(record) => record,
+            None => continue,
+        };
+        info!("Checking covers of title {} in {:?}", record.title_id, dir);
+        if let Err(e) = download_covers(record.title_id, selection, &dir, context).await {
+            error!("Failed to download covers for {:?}: {}", dir, e);
+            context.stats.add_failure(dir.display().to_string(), &e);
+            failed += 1;
+        }
+    }
+    Ok(failed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::cover::CoverAttributes;
+
+    fn cover(volume: Option<&str>, file_name: &str) -> CoverData {
+        CoverData {
+            id: Uuid::from_u128(7),
+            data_type: "cover_art".to_owned(),
+            attributes: CoverAttributes {
+                volume: volume.map(str::to_owned),
+                file_name: file_name.to_owned(),
+                locale: Some("ja".to_owned()),
+                created_at: "2021-05-24T17:01:45+00:00".to_owned(),
+                other: Default::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_cover_file_names() {
+        let covers = [
+            cover(Some("1"), "abc.jpg"),
+            cover(Some("10"), "def.png"),
+            cover(None, "ghi.jpg"),
+            cover(Some("1"), "jkl.jpg"),
+        ];
+        assert_eq!(
+            cover_file_names(&covers),
+            vec![
+                "Volume 01.jpg",
+                "Volume 10.png",
+                "No volume.jpg",
+                "Volume 01 - 00000000-0000-0000-0000-000000000007.jpg"
+            ]
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use uuid::Uuid;
+use zip::write::SimpleFileOptions;
+use zip::CompressionMethod;
+
+use crate::cbz;
+use crate::common::escape_xml;
+use crate::retry::Result;
+use crate::validate::{self, ImageFormat};
+
+/// Size of pages whose dimensions can't be read, a common size of manga scans
+const FALLBACK_PAGE_SIZE: (u32, u32) = (800, 1200);
+
+/// Which way pages are turned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadingDirection {
+    LeftToRight,
+    RightToLeft,
+}
+
+impl ReadingDirection {
+    /// Manga read right to left, manhwa and manhua left to right. Titles of unknown
+    /// origin are taken to be manga.
+    pub fn for_original_language(language: Option<&str>) -> Self {
+        match language {
+            Some(language) if language == "ko" || language.starts_with("zh") => ReadingDirection::LeftToRight,
+            _ => ReadingDirection::RightToLeft,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            ReadingDirection::LeftToRight => "ltr",
+            ReadingDirection::RightToLeft => "rtl",
+        }
+    }
+}
+
+/// What the EPUB of a chapter says about it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EpubInfo {
+    pub id: Uuid,
+    pub title: String,
+    pub language: String,
+    pub creators: Vec<String>,
+    pub direction: ReadingDirection,
+}
+
+/// A page as it goes into the book
+struct EpubPage {
+    /// Name of the image in the chapter directory
+    file_name: String,
+    format: ImageFormat,
+    width: u32,
+    height: u32,
+}
+
+fn media_type(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Png => "image/png",
+        ImageFormat::Jpeg => "image/jpeg",
+        ImageFormat::Gif => "image/gif",
+        ImageFormat::Webp => "image/webp",
+    }
+}
+
+/// Where `--epub` puts the book of the chapter in dir, next to the directory
+pub fn epub_path(dir: &Path) -> PathBuf {
+    cbz::sibling_path(dir, "epub")
+}
+
+/// The pages of the chapter in dir, in reading order
+fn read_pages(dir: &Path) -> Result<Vec<EpubPage>> {
+    let mut pages = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        if !cbz::is_page_file(&file_name) {
+            continue;
+        }
+        let data = fs::read(entry.path())?;
+        let format = match validate::detect_format(&data) {
+            Some(format) => format,
+            None => continue,
+        };
+        let (width, height) = validate::image_dimensions(&data, format).unwrap_or(FALLBACK_PAGE_SIZE);
+        pages.push(EpubPage {
+            file_name,
+            format,
+            width,
+            height,
+        });
+    }
+    pages.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+    Ok(pages)
+}
+
+fn container_xml() -> String {
+    r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#
+    .to_owned()
+}
+
+fn content_opf(info: &EpubInfo, pages: &[EpubPage]) -> String {
+    let mut opf = String::new();
+    opf.push_str(&format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="book-id" xml:lang="{language}">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="book-id">urn:uuid:{id}</dc:identifier>
+    <dc:title>{title}</dc:title>
+    <dc:language>{language}</dc:language>
+"#,
+        id = info.id,
+        title = escape_xml(&info.title),
+        language = escape_xml(&info.language),
+    ));
+    for creator in &info.creators {
+        opf.push_str(&format!("    <dc:creator>{}</dc:creator>\n", escape_xml(creator)));
+    }
+    opf.push_str(&format!(
+        r#"    <meta property="dcterms:modified">{}</meta>
+    <meta property="rendition:layout">pre-paginated</meta>
+    <meta property="rendition:orientation">portrait</meta>
+    <meta property="rendition:spread">none</meta>
+    <meta name="cover" content="image-1"/>
+  </metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+"#,
+        chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ")
+    ));
+    for (i, page) in pages.iter().enumerate() {
+        let cover = if i == 0 { r#" properties="cover-image""# } else { "" };
+        opf.push_str(&format!(
+            "    <item id=\"image-{n}\" href=\"images/{file}\" media-type=\"{media_type}\"{cover}/>\n    \
+             <item id=\"page-{n}\" href=\"pages/{n:04}.xhtml\" media-type=\"application/xhtml+xml\"/>\n",
+            n = i + 1,
+            file = escape_xml(&page.file_name),
+            media_type = media_type(page.format),
+            cover = cover,
+        ));
+    }
+    opf.push_str(&format!(
+        "  </manifest>\n  <spine page-progression-direction=\"{}\">\n",
+        info.direction.as_str()
+    ));
+    for i in 0..pages.len() {
+        opf.push_str(&format!("    <itemref idref=\"page-{}\"/>\n", i + 1));
+    }
+    opf.push_str("  </spine>\n</package>\n");
+    opf
+}
+
+fn nav_xhtml(info: &EpubInfo) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head><title>{title}</title></head>
+<body>
+  <nav epub:type="toc"><ol><li><a href="pages/0001.xhtml">{title}</a></li></ol></nav>
+</body>
+</html>
+"#,
+        title = escape_xml(&info.title)
+    )
+}
+
+fn page_xhtml(n: usize, page: &EpubPage) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml">
+<head>
+  <title>{n}</title>
+  <meta name="viewport" content="width={width}, height={height}"/>
+  <style>body {{ margin: 0; }} img {{ width: 100%; height: 100%; }}</style>
+</head>
+<body><img src="../images/{file}" alt="{n}"/></body>
+</html>
+"#,
+        n = n,
+        width = page.width,
+        height = page.height,
+        file = escape_xml(&page.file_name),
+    )
+}
+
+/// Package the pages of the chapter in dir as a fixed layout EPUB next to it, which
+/// e-readers show a page per screen in the right reading direction. Returns the path
+/// of the book.
+pub fn write_chapter_epub(dir: &Path, info: &EpubInfo) -> Result<PathBuf> {
+    let path = epub_path(dir);
+    let pages = read_pages(dir)?;
+    debug!("Packaging {} pages of {:?} into {:?}", pages.len(), dir, path);
+
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    cbz::write_archive(&path, |zip| {
+        // Readers recognise an EPUB by this first, uncompressed entry
+        zip.start_file("mimetype", stored)?;
+        zip.write_all(b"application/epub+zip")?;
+        zip.start_file("META-INF/container.xml", deflated)?;
+        zip.write_all(container_xml().as_bytes())?;
+        zip.start_file("OEBPS/content.opf", deflated)?;
+        zip.write_all(content_opf(info, &pages).as_bytes())?;
+        zip.start_file("OEBPS/nav.xhtml", deflated)?;
+        zip.write_all(nav_xhtml(info).as_bytes())?;
+        for (i, page) in pages.iter().enumerate() {
+            zip.start_file(format!("OEBPS/pages/{:04}.xhtml", i + 1), deflated)?;
+            zip.write_all(page_xhtml(i + 1, page).as_bytes())?;
+            // Images are compressed already
+            zip.start_file(format!("OEBPS/images/{}", page.file_name), stored)?;
+            zip.write_all(&fs::read(dir.join(&page.file_name))?)?;
+        }
+        Ok(())
+    })?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::File;
+    use std::io::Read;
+
+    #[test]
+    fn test_chapter_epub() {
+        let dir = std::env::temp_dir().join(format!("mdscrape-test-{}", Uuid::from_u128(rand::random())));
+        fs::create_dir_all(&dir).unwrap();
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend_from_slice(&800u32.to_be_bytes());
+        png.extend_from_slice(&1200u32.to_be_bytes());
+        fs::write(dir.join("0002.png"), &png).unwrap();
+        fs::write(dir.join("0001.png"), &png).unwrap();
+        fs::write(dir.join("ComicInfo.xml"), "<ComicInfo/>").unwrap();
+        let info = EpubInfo {
+            id: Uuid::from_u128(1),
+            title: "Tomo-chan & friends - Ch. 1".to_owned(),
+            language: "en".to_owned(),
+            creators: vec!["Yanagida Fumita".to_owned()],
+            direction: ReadingDirection::for_original_language(Some("ja")),
+        };
+        let path = write_chapter_epub(&dir, &info).unwrap();
+        assert_eq!(path, epub_path(&dir));
+
+        let mut archive = zip::ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(archive.by_index(0).unwrap().name(), "mimetype");
+        let mut opf = String::new();
+        archive
+            .by_name("OEBPS/content.opf")
+            .unwrap()
+            .read_to_string(&mut opf)
+            .unwrap();
+        assert!(opf.contains("<dc:title>Tomo-chan &amp; friends - Ch. 1</dc:title>"));
+        assert!(opf.contains(r#"<spine page-progression-direction="rtl">"#));
+        assert!(opf.contains(r#"href="images/0001.png" media-type="image/png" properties="cover-image""#));
+        let mut page = String::new();
+        archive
+            .by_name("OEBPS/pages/0002.xhtml")
+            .unwrap()
+            .read_to_string(&mut page)
+            .unwrap();
+        assert!(page.contains("width=800, height=1200"));
+        assert!(page.contains("../images/0002.png"));
+        assert!(archive.by_name("OEBPS/images/ComicInfo.xml").is_err());
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            ReadingDirection::for_original_language(Some("ko")),
+            ReadingDirection::LeftToRight
+        );
+    }
+}
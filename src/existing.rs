@@ -0,0 +1,147 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+
+use crate::manifest::hash_file;
+use crate::retry::Result;
+
+/// What to do about files that are already in the output directory, set with
+/// `--existing` (or `--if-exists`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExistingPolicy {
+    /// Keep them as they are
+    #[default]
+    Skip,
+    /// Always download them again
+    Overwrite,
+    /// Re-hash them against the chapter manifest and replace the ones that don't match
+    Verify,
+    /// Download them again, keeping the old file under a new name
+    Rename,
+    /// Keep them like `Skip`, and also keep what an unfinished download got so far, to
+    /// continue it next time instead of starting over
+    Resume,
+}
+
+impl std::str::FromStr for ExistingPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(ExistingPolicy::Skip),
+            "overwrite" => Ok(ExistingPolicy::Overwrite),
+            "verify" => Ok(ExistingPolicy::Verify),
+            "rename" => Ok(ExistingPolicy::Rename),
+            "resume" => Ok(ExistingPolicy::Resume),
+            v => Err(format!(
+                "Unknown existing file policy \"{}\", expected skip, overwrite, verify, rename or resume",
+                v
+            )),
+        }
+    }
+}
+
+/// First free name for keeping an old copy of path, e.g. `0001.1.png`
+fn aside_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|e| format!(".{}", e.to_string_lossy()));
+    (1..)
+        .map(|n| path.with_file_name(format!("{}.{}{}", stem, n, extension.as_deref().unwrap_or(""))))
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
+
+impl ExistingPolicy {
+    /// Whether the file at path has to be downloaded (again). `digest` is its checksum
+    /// from the chapter manifest, if there is one. Files without a known checksum can't
+    /// be verified and are kept, unless they are empty.
+    pub fn needs_download(self, path: &Path, digest: Option<&str>) -> Result<bool> {
+        if !path.exists() {
+            return Ok(true);
+        }
+        Ok(match self {
+            ExistingPolicy::Skip | ExistingPolicy::Resume => false,
+            ExistingPolicy::Overwrite | ExistingPolicy::Rename => true,
+            ExistingPolicy::Verify => match digest {
+                Some(digest) => hash_file(path)? != digest,
+                None => {
+                    debug!("No checksum for {:?}, only checking its size", path);
+                    fs::metadata(path)?.len() == 0
+                }
+            },
+        })
+    }
+
+    /// Whether chapters that look complete should be checked page by page anyway
+    pub fn rechecks_complete(self) -> bool {
+        !matches!(self, ExistingPolicy::Skip | ExistingPolicy::Resume)
+    }
+
+    /// Whether unfinished chapters and images are kept to be continued later
+    pub fn resumes(self) -> bool {
+        self == ExistingPolicy::Resume
+    }
+
+    /// Get an existing file out of the way of its replacement. With `rename` it is
+    /// kept under a new name, otherwise it is deleted.
+    pub fn set_aside(self, path: &Path) -> Result<()> {
+        if !path.exists() {
+            return Ok(());
+        }
+        if self == ExistingPolicy::Rename {
+            let aside = aside_path(path);
+            debug!("Keeping {:?} as {:?}", path, aside);
+            fs::rename(path, aside)?;
+        } else {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_existing_policies() {
+        let dir = std::env::temp_dir().join(format!("mdscrape-test-{}", Uuid::from_u128(rand::random())));
+        fs::create_dir_all(&dir).unwrap();
+        let page = dir.join("0001.png");
+        assert!(ExistingPolicy::Skip.needs_download(&page, None).unwrap());
+
+        fs::write(&page, b"page").unwrap();
+        let digest = hash_file(&page).unwrap();
+        assert!(!ExistingPolicy::Skip.needs_download(&page, Some("wrong")).unwrap());
+        assert!(ExistingPolicy::Overwrite.needs_download(&page, Some(&digest)).unwrap());
+        assert!(!ExistingPolicy::Verify.needs_download(&page, Some(&digest)).unwrap());
+        assert!(ExistingPolicy::Verify.needs_download(&page, Some("wrong")).unwrap());
+        assert!(!ExistingPolicy::Verify.needs_download(&page, None).unwrap());
+        assert!(!ExistingPolicy::Resume.needs_download(&page, None).unwrap());
+        let empty = dir.join("0002.png");
+        fs::write(&empty, b"").unwrap();
+        assert!(ExistingPolicy::Verify.needs_download(&empty, None).unwrap());
+        fs::remove_file(&empty).unwrap();
+
+        ExistingPolicy::Rename.set_aside(&page).unwrap();
+        fs::write(&page, b"new page").unwrap();
+        ExistingPolicy::Rename.set_aside(&page).unwrap();
+        assert!(!page.exists());
+        assert_eq!(fs::read(dir.join("0001.1.png")).unwrap(), b"page");
+        assert_eq!(fs::read(dir.join("0001.2.png")).unwrap(), b"new page");
+
+        fs::write(&page, b"page").unwrap();
+        ExistingPolicy::Verify.set_aside(&page).unwrap();
+        assert!(!page.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!("verify".parse(), Ok(ExistingPolicy::Verify));
+        assert_eq!("resume".parse(), Ok(ExistingPolicy::Resume));
+        assert!("delete".parse::<ExistingPolicy>().is_err());
+    }
+}
@@ -0,0 +1,78 @@
+//! Exit codes of the `mdscrape` binary, so that scripts can tell why a run failed
+
+use crate::retry::DownloadError;
+
+pub const SUCCESS: i32 = 0;
+/// Anything that doesn't have a code of its own
+pub const FAILURE: i32 = 1;
+/// Some chapters or titles failed but the rest were downloaded, with `--keep-going`
+/// or while syncing
+pub const PARTIAL_FAILURE: i32 = 2;
+/// MangaDex kept rate limiting us
+pub const RATE_LIMITED: i32 = 3;
+/// The command line or config file is invalid
+pub const USAGE: i32 = 4;
+/// Logging in to MangaDex failed
+pub const AUTH_FAILED: i32 = 5;
+/// A server couldn't be reached or kept failing
+pub const NETWORK: i32 = 6;
+/// Writing the download failed, e.g. because the disk is full
+pub const FILESYSTEM: i32 = 7;
+/// Stopped with Ctrl-C, the usual 128 + SIGINT
+pub const INTERRUPTED: i32 = 130;
+
+/// The exit code for a run that failed with error
+pub fn code_for(error: &(dyn std::error::Error + 'static)) -> i32 {
+    match error.downcast_ref::<DownloadError>() {
+        Some(error) => code_for_download_error(error),
+        None => FAILURE,
+    }
+}
+
+fn code_for_download_error(error: &DownloadError) -> i32 {
+    match error {
+        DownloadError::DownloadsFailed(_)
+        | DownloadError::SyncFailed(_)
+        | DownloadError::CoversFailed(_)
+        | DownloadError::VerificationFailed(_) => PARTIAL_FAILURE,
+        DownloadError::RateLimitError(_) => RATE_LIMITED,
+        DownloadError::Api(e) if e.status == 429 => RATE_LIMITED,
+        DownloadError::AuthError(_) => AUTH_FAILED,
+        DownloadError::Api(e) if e.status == 401 => AUTH_FAILED,
+        DownloadError::Api(e) if e.status >= 500 => NETWORK,
+        DownloadError::ReqwestError(_) | DownloadError::Timeout(_) | DownloadError::Offline(_) => NETWORK,
+        DownloadError::RetryExhausted { last, .. } => match code_for_download_error(last) {
+            FAILURE => NETWORK,
+            code => code,
+        },
+        DownloadError::IOError(_)
+        | DownloadError::ReadOnlyFilesystem(_)
+        | DownloadError::PermissionDenied(_)
+        | DownloadError::DiskFull(_) => FILESYSTEM,
+        DownloadError::Interrupted => INTERRUPTED,
+        _ => FAILURE,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::OpaqueError;
+
+    #[test]
+    fn test_exit_codes() {
+        let code = |error: DownloadError| code_for(OpaqueError::from(error).as_ref());
+        assert_eq!(code(DownloadError::DownloadsFailed(2)), PARTIAL_FAILURE);
+        assert_eq!(code(DownloadError::AuthError("bad password".to_owned())), AUTH_FAILED);
+        assert_eq!(code(DownloadError::Interrupted), INTERRUPTED);
+        assert_eq!(code(DownloadError::InvalidId("abc".to_owned())), FAILURE);
+        let disk_full = std::io::Error::from(std::io::ErrorKind::StorageFull);
+        assert_eq!(code(DownloadError::from(disk_full)), FILESYSTEM);
+        let timed_out = DownloadError::RetryExhausted {
+            attempts: Vec::new(),
+            last: Box::new(DownloadError::Timeout(std::time::Duration::from_secs(30))),
+        };
+        assert_eq!(code(timed_out), NETWORK);
+        assert_eq!(code_for(OpaqueError::from("not a download error").as_ref()), FAILURE);
+    }
+}
@@ -0,0 +1,283 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use log::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::api::chapter::ChapterData;
+use crate::cbz;
+use crate::chapter::METADATA_FILE_NAME;
+use crate::epub::{self, EpubInfo, ReadingDirection};
+use crate::library::TitleRecord;
+use crate::manifest::{find_manifest_dirs, Manifest};
+use crate::naming::{is_contained, sanitize_component, NamingFields, PathTemplate};
+use crate::pdf;
+use crate::retry::{DownloadError, Result};
+
+/// What `--export` packages the chapters of a title as
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    #[default]
+    Cbz,
+    Epub,
+    Pdf,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "cbz" => Ok(ExportFormat::Cbz),
+            "epub" => Ok(ExportFormat::Epub),
+            "pdf" => Ok(ExportFormat::Pdf),
+            v => Err(format!("Unknown export format \"{}\", expected cbz, epub or pdf", v)),
+        }
+    }
+}
+
+/// Archives that are kept next to a chapter directory, and move with it
+const ARCHIVE_EXTENSIONS: &[&str] = &["cbz", "epub", "pdf"];
+
+/// A chapter of the title being exported
+struct Chapter {
+    dir: PathBuf,
+    /// What was saved with `--write-metadata`, if anything
+    data: Option<ChapterData>,
+}
+
+impl Chapter {
+    fn read(dir: PathBuf) -> Self {
+        let data = fs::read(dir.join(METADATA_FILE_NAME))
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok());
+        Chapter { dir, data }
+    }
+
+    fn has_pages(&self) -> Result<bool> {
+        for entry in fs::read_dir(&self.dir)? {
+            if cbz::is_page_file(&entry?.file_name().to_string_lossy()) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Name of the chapter as its EPUB or PDF title, like downloads name it
+    fn title(&self, record: Option<&TitleRecord>) -> String {
+        let name = match self.data {
+            Some(ref data) => match (&data.attributes.chapter, &data.attributes.title) {
+                (Some(chapter), Some(title)) => format!("Ch. {} - {}", chapter, title),
+                (Some(chapter), None) => format!("Ch. {}", chapter),
+                (None, Some(title)) => title.clone(),
+                (None, None) => data.id.to_string(),
+            },
+            None => self.dir.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+        };
+        match record.and_then(|record| record.title.as_deref()) {
+            Some(series) => format!("{} - {}", series, name),
+            None => name,
+        }
+    }
+
+    fn epub_info(&self, record: Option<&TitleRecord>) -> EpubInfo {
+        let id = match self.data {
+            Some(ref data) => data.id,
+            None => Manifest::read(&self.dir)
+                .ok()
+                .and_then(|manifest| manifest.chapter_id)
+                .unwrap_or(Uuid::nil()),
+        };
+        let language = match (&self.data, record) {
+            (Some(data), _) => data.attributes.translated_language.clone(),
+            (None, Some(record)) => record.lang_code.split(',').next().unwrap_or_default().to_owned(),
+            (None, None) => "und".to_owned(),
+        };
+        EpubInfo {
+            id,
+            title: self.title(record),
+            language,
+            creators: record.map(|record| record.authors.clone()).unwrap_or_default(),
+            direction: ReadingDirection::for_original_language(
+                record.and_then(|record| record.original_language.as_deref()),
+            ),
+        }
+    }
+
+    /// Move the chapter, and the archives next to it, to where template puts it.
+    /// Returns false if it couldn't be named.
+    fn move_to_template(&mut self, root: &Path, template: &PathTemplate, record: Option<&TitleRecord>) -> Result<bool> {
+        let Some(ref data) = self.data else {
+            warn!(
+                "Not moving {:?}, there is no {} to name it by",
+                self.dir, METADATA_FILE_NAME
+            );
+            return Ok(false);
+        };
+        let manga = record.and_then(|record| record.title.as_deref());
+        let authors = record.map(|record| record.authors.as_slice()).unwrap_or_default();
+        let mut relative = template.render(&NamingFields::from_chapter(0, data, manga, authors));
+        // Like downloads of several languages, which each get their own subtree
+        let languages = record.map_or(1, |record| record.lang_code.split(',').count());
+        if languages > 1 && !template.uses("lang") {
+            relative = PathBuf::from(sanitize_component(&data.attributes.translated_language)).join(relative);
+        }
+        if !is_contained(&relative) {
+            return Err(DownloadError::UnsafePath(relative));
+        }
+        let destination = root.join(relative);
+        if destination == self.dir {
+            return Ok(true);
+        }
+        if destination.exists() {
+            warn!("Not moving {:?}, {:?} already exists", self.dir, destination);
+            return Ok(false);
+        }
+        debug!("Moving {:?} to {:?}", self.dir, destination);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&self.dir, &destination)?;
+        for extension in ARCHIVE_EXTENSIONS {
+            let archive = cbz::sibling_path(&self.dir, extension);
+            if archive.exists() {
+                fs::rename(&archive, cbz::sibling_path(&destination, extension))?;
+            }
+        }
+        // Directories like the old volume directories are left empty
+        let mut parent = self.dir.parent();
+        while let Some(dir) = parent.filter(|dir| *dir != root && dir.starts_with(root)) {
+            if fs::remove_dir(dir).is_err() {
+                break;
+            }
+            parent = dir.parent();
+        }
+        self.dir = destination;
+        Ok(true)
+    }
+}
+
+/// Check that what template names chapters by is stored with them
+fn check_template(template: &PathTemplate, record: Option<&TitleRecord>) -> Result<()> {
+    if template.uses("index") {
+        return Err(DownloadError::ExportFailed(
+            "{index} depends on the whole chapter list of the title, which isn't stored with the chapters".to_owned(),
+        ));
+    }
+    if template.uses("manga") && record.is_none_or(|record| record.title.is_none()) {
+        return Err(DownloadError::ExportFailed(
+            "the title was downloaded by an older version that didn't record its name for {manga}, download it \
+             again first"
+                .to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// Package every chapter downloaded into the title directory at root as format, without
+/// fetching anything. With a template, the chapters are moved to where it would put them
+/// first, going by the metadata saved with `--write-metadata`. Returns the number of
+/// chapters that were packaged.
+pub fn export_title(root: &Path, format: ExportFormat, template: Option<&PathTemplate>) -> Result<usize> {
+    let mut record = TitleRecord::read(root)?;
+    if let Some(template) = template {
+        check_template(template, record.as_ref())?;
+    }
+    let mut exported = 0;
+    let mut all_moved = true;
+    for dir in find_manifest_dirs(root) {
+        let mut chapter = Chapter::read(dir);
+        if let Some(template) = template {
+            all_moved &= chapter.move_to_template(root, template, record.as_ref())?;
+        }
+        if !chapter.has_pages()? {
+            if !cbz::cbz_path(&chapter.dir).exists() {
+                warn!("Skipping {:?}, it has no pages", chapter.dir);
+                continue;
+            }
+            cbz::extract_pages(&chapter.dir)?;
+        }
+        let path = match format {
+            ExportFormat::Cbz => cbz::write_chapter_cbz(&chapter.dir)?,
+            ExportFormat::Epub => epub::write_chapter_epub(&chapter.dir, &chapter.epub_info(record.as_ref()))?,
+            ExportFormat::Pdf => pdf::write_chapter_pdf(&chapter.dir, &chapter.title(record.as_ref()))?,
+        };
+        info!("Exported {:?}", path);
+        exported += 1;
+    }
+    // Syncs put new chapters next to the moved ones, unless some stayed where they were
+    if let (Some(template), Some(record), true) = (template, record.as_mut(), all_moved) {
+        record.path_template = Some(template.as_str().to_owned());
+        record.write(root)?;
+    }
+    Ok(exported)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::manifest::MANIFEST_FILE_NAME;
+
+    #[test]
+    fn test_export_title() {
+        let root = std::env::temp_dir().join(format!("mdscrape-test-{}", Uuid::from_u128(rand::random())));
+        let old = root.join("Volume 01").join("md00001 - Ch. 3");
+        fs::create_dir_all(&old).unwrap();
+        fs::write(old.join(MANIFEST_FILE_NAME), "").unwrap();
+        fs::write(old.join("0001.png"), b"page").unwrap();
+        let metadata = serde_json::json!({
+            "id": "a54c491c-8e4c-4e97-8873-5b79e59da210",
+            "type": "chapter",
+            "attributes": {
+                "volume": "1",
+                "chapter": "3",
+                "title": "Tomo",
+                "translatedLanguage": "en",
+                "pages": 1,
+                "version": 1,
+                "createdAt": "2021-01-01T00:00:00+00:00",
+                "updatedAt": "2021-01-01T00:00:00+00:00",
+                "publishAt": "2021-01-01T00:00:00+00:00",
+                "readableAt": "2021-01-01T00:00:00+00:00"
+            },
+            "relationships": []
+        });
+        fs::write(old.join(METADATA_FILE_NAME), metadata.to_string()).unwrap();
+        let mut record = TitleRecord {
+            title_id: Uuid::from_u128(1),
+            lang_code: "en".to_owned(),
+            status: None,
+            last_checked: chrono::Utc::now(),
+            path_template: Some("{volume_dir}/md{index:05} - Ch. {chapter}".to_owned()),
+            title: None,
+            authors: Vec::new(),
+            original_language: None,
+        };
+        record.write(&root).unwrap();
+
+        let by_manga = PathTemplate::parse("{manga}/{chapter:03}").unwrap();
+        assert!(export_title(&root, ExportFormat::Cbz, Some(&by_manga)).is_err());
+        let by_index = PathTemplate::parse("{index}").unwrap();
+        assert!(export_title(&root, ExportFormat::Cbz, Some(&by_index)).is_err());
+
+        record.title = Some("Tomo-chan".to_owned());
+        record.write(&root).unwrap();
+        assert_eq!(export_title(&root, ExportFormat::Cbz, Some(&by_manga)).unwrap(), 1);
+        let new = root.join("Tomo-chan").join("003");
+        assert!(new.join("0001.png").exists());
+        assert!(cbz::cbz_path(&new).exists());
+        // The old volume directory went with it
+        assert!(!root.join("Volume 01").exists());
+        let record = TitleRecord::read(&root).unwrap().unwrap();
+        assert_eq!(record.path_template.as_deref(), Some("{manga}/{chapter:03}"));
+
+        // Chapters that only have their archive left are unpacked
+        fs::remove_file(new.join("0001.png")).unwrap();
+        assert_eq!(export_title(&root, ExportFormat::Epub, None).unwrap(), 1);
+        assert!(new.join("0001.png").exists());
+        assert!(epub::epub_path(&new).exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,119 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::common::escape_xml;
+use crate::retry::Result;
+
+/// Older entries are dropped from the feed once it grows past this many chapters
+const MAX_FEED_ENTRIES: usize = 200;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FeedEntry {
+    pub chapter_id: Uuid,
+    pub title: String,
+    pub path: PathBuf,
+    pub downloaded_at: DateTime<Utc>,
+}
+
+/// An Atom feed of newly downloaded chapters, so a feed reader can follow updates to
+/// the local library. The entries are kept in a json file next to the feed, and the
+/// feed itself is rewritten every time a chapter is added.
+#[derive(Debug)]
+pub struct ChapterFeed {
+    path: PathBuf,
+    entries: Mutex<Vec<FeedEntry>>,
+}
+
+fn format_timestamp(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+impl ChapterFeed {
+    fn state_path(path: &Path) -> PathBuf {
+        let mut state_path = path.as_os_str().to_owned();
+        state_path.push(".json");
+        PathBuf::from(state_path)
+    }
+
+    pub fn open(path: &impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_owned();
+        let state_path = Self::state_path(&path);
+        let entries = if state_path.exists() {
+            debug!("Loading feed entries from {:?}", state_path);
+            serde_json::from_slice(&fs::read(&state_path)?)?
+        } else {
+            Vec::new()
+        };
+        Ok(ChapterFeed {
+            path,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    pub fn add_entry(&self, entry: FeedEntry) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.push(entry);
+        if entries.len() > MAX_FEED_ENTRIES {
+            let excess = entries.len() - MAX_FEED_ENTRIES;
+            entries.drain(..excess);
+        }
+        fs::write(Self::state_path(&self.path), serde_json::to_vec_pretty(&*entries)?)?;
+        fs::write(&self.path, Self::render(&entries))?;
+        Ok(())
+    }
+
+    fn render(entries: &[FeedEntry]) -> String {
+        let updated = entries.last().map(|e| e.downloaded_at).unwrap_or_else(Utc::now);
+        let mut feed = String::new();
+        feed.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        feed.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+        feed.push_str("  <title>mdscrape downloads</title>\n");
+        feed.push_str("  <id>urn:mdscrape:downloads</id>\n");
+        // Atom wants an author for every entry, which the entries get from here
+        feed.push_str("  <author><name>mdscrape</name></author>\n");
+        feed.push_str(&format!("  <updated>{}</updated>\n", format_timestamp(&updated)));
+        // Newest entries first
+        for entry in entries.iter().rev() {
+            let link = url::Url::from_file_path(&entry.path)
+                .map(|u| u.to_string())
+                .unwrap_or_else(|_| entry.path.to_string_lossy().into_owned());
+            feed.push_str("  <entry>\n");
+            feed.push_str(&format!("    <title>{}</title>\n", escape_xml(&entry.title)));
+            feed.push_str(&format!("    <id>urn:uuid:{}</id>\n", entry.chapter_id));
+            feed.push_str(&format!("    <link href=\"{}\"/>\n", escape_xml(&link)));
+            feed.push_str(&format!(
+                "    <updated>{}</updated>\n",
+                format_timestamp(&entry.downloaded_at)
+            ));
+            feed.push_str("  </entry>\n");
+        }
+        feed.push_str("</feed>\n");
+        feed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render_escapes_entries() {
+        let entry = FeedEntry {
+            chapter_id: Uuid::from_u128(1),
+            title: "Ch. 1 - <Tom & Jerry>".to_owned(),
+            path: PathBuf::from("/library/md00001"),
+            downloaded_at: Utc::now(),
+        };
+        let feed = ChapterFeed::render(&[entry]);
+        assert!(feed.contains("<title>Ch. 1 - &lt;Tom &amp; Jerry&gt;</title>"));
+        assert!(feed.contains("<link href=\"file:///library/md00001\"/>"));
+        assert!(feed.contains("<id>urn:uuid:00000000-0000-0000-0000-000000000001</id>"));
+        assert!(feed.contains("<author><name>mdscrape</name></author>"));
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashSet;
+
+use log::debug;
+use reqwest::Url;
+use uuid::Uuid;
+
+use crate::api::manga::{ContentRating, MangaData, MangaListResponse, TagData, TagListResponse};
+use crate::context::ScrapeContext;
+use crate::retry::{DownloadError, Result};
+
+/// Most titles the API returns for one request
+const IDS_PER_REQUEST: usize = 100;
+
+/// Which titles to download from lists like the follows feed, set with `--content-rating`
+/// and `--exclude-tag`. Lists are filtered by the API, titles downloaded by id are
+/// checked against their own attributes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TitleFilter {
+    /// Ratings to download, any rating if empty
+    pub content_ratings: Vec<ContentRating>,
+    /// Tags to leave out, by id or by name in any language
+    pub excluded_tags: Vec<String>,
+}
+
+impl TitleFilter {
+    pub fn is_empty(&self) -> bool {
+        self.content_ratings.is_empty() && self.excluded_tags.is_empty()
+    }
+
+    /// Parse ratings like `safe,suggestive`
+    pub fn set_content_ratings(&mut self, value: &str) -> std::result::Result<(), String> {
+        self.content_ratings = value
+            .split(',')
+            .map(|rating| rating.trim().parse())
+            .collect::<std::result::Result<_, _>>()?;
+        Ok(())
+    }
+
+    fn excludes(&self, tag: &TagData) -> bool {
+        self.excluded_tags
+            .iter()
+            .any(|excluded| match Uuid::parse_str(excluded) {
+                Ok(id) => id == tag.id,
+                Err(_) => tag
+                    .attributes
+                    .name
+                    .values()
+                    .any(|name| name.eq_ignore_ascii_case(excluded)),
+            })
+    }
+
+    /// Whether a title passes the filter, from the attributes it was fetched with
+    pub fn matches(&self, manga: &MangaData) -> bool {
+        let rating_matches = self.content_ratings.is_empty()
+            || manga
+                .attributes
+                .content_rating()
+                .is_some_and(|rating| self.content_ratings.contains(&rating));
+        rating_matches && !manga.attributes.tags().iter().any(|tag| self.excludes(tag))
+    }
+
+    /// The ids of the excluded tags, looking up the ones given by name
+    async fn excluded_tag_ids(&self, context: &ScrapeContext) -> Result<Vec<Uuid>> {
+        let mut ids: Vec<Uuid> = self
+            .excluded_tags
+            .iter()
+            .filter_map(|tag| Uuid::parse_str(tag).ok())
+            .collect();
+        let names: Vec<&String> = self
+            .excluded_tags
+            .iter()
+            .filter(|tag| Uuid::parse_str(tag).is_err())
+            .collect();
+        if names.is_empty() {
+            return Ok(ids);
+        }
+        let url = Url::parse("https://api.mangadex.org/manga/tag")?;
+        let tags: TagListResponse = context.http().get_json(&url).await?;
+        for name in names {
+            let tag = tags
+                .data
+                .iter()
+                .find(|tag| tag.attributes.name.values().any(|n| n.eq_ignore_ascii_case(name)))
+                .ok_or_else(|| DownloadError::UnknownTag(name.clone()))?;
+            ids.push(tag.id);
+        }
+        Ok(ids)
+    }
+
+    /// Query parameters that make the API apply the filter to a list
+    fn query(&self, excluded_tag_ids: &[Uuid]) -> String {
+        let ratings = self
+            .content_ratings
+            .iter()
+            .map(|rating| format!("&contentRating[]={}", rating));
+        let tags = excluded_tag_ids.iter().map(|id| format!("&excludedTags[]={}", id));
+        ratings.chain(tags).collect()
+    }
+
+    /// Query parameters for feeds of chapters, which can only be filtered by rating
+    pub fn feed_query(&self) -> String {
+        self.query(&[])
+    }
+
+    /// The titles that pass the filter, in their original order
+    pub async fn filter_titles(&self, ids: Vec<Uuid>, context: &ScrapeContext) -> Result<Vec<Uuid>> {
+        if self.is_empty() || ids.is_empty() {
+            return Ok(ids);
+        }
+        let query = self.query(&self.excluded_tag_ids(context).await?);
+        let mut passed = HashSet::new();
+        for chunk in ids.chunks(IDS_PER_REQUEST) {
+            let list: String = chunk.iter().map(|id| format!("&ids[]={}", id)).collect();
+            let url = Url::parse(&format!(
+                "https://api.mangadex.org/manga?limit={}{}{}",
+                IDS_PER_REQUEST, list, query
+            ))?;
+            debug!("Filtering {} titles with {}", chunk.len(), url);
+            let response: MangaListResponse = context.http().get_json(&url).await?;
+            passed.extend(response.data.into_iter().map(|manga| manga.id));
+        }
+        Ok(ids.into_iter().filter(|id| passed.contains(id)).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_title_filter() {
+        let manga: MangaData = serde_json::from_value(serde_json::json!({
+            "id": "76ee7069-23b4-493c-bc44-34ccbf3051a8",
+            "type": "manga",
+            "attributes": {
+                "title": {"en": "Tomo-chan wa Onna no ko!"},
+                "contentRating": "suggestive",
+                "tags": [{"id": "423e2eae-a7a2-4a8b-ac03-a8351462d71d", "type": "tag",
+                          "attributes": {"name": {"en": "Romance"}}}]
+            },
+            "relationships": []
+        }))
+        .unwrap();
+        let mut filter = TitleFilter::default();
+        assert!(filter.matches(&manga));
+        filter.set_content_ratings("safe, suggestive").unwrap();
+        assert!(filter.matches(&manga));
+        assert!(filter.set_content_ratings("nsfw").is_err());
+        filter.set_content_ratings("safe").unwrap();
+        assert!(!filter.matches(&manga));
+
+        let by_name = TitleFilter {
+            excluded_tags: vec!["romance".to_owned()],
+            ..Default::default()
+        };
+        assert!(!by_name.matches(&manga));
+        let by_id = TitleFilter {
+            content_ratings: vec![ContentRating::Suggestive],
+            excluded_tags: vec!["423e2eae-a7a2-4a8b-ac03-a8351462d71d".to_owned()],
+        };
+        assert!(!by_id.matches(&manga));
+        assert_eq!(
+            by_id.query(&[Uuid::parse_str("423e2eae-a7a2-4a8b-ac03-a8351462d71d").unwrap()]),
+            "&contentRating[]=suggestive&excludedTags[]=423e2eae-a7a2-4a8b-ac03-a8351462d71d"
+        );
+        assert_eq!(by_id.feed_query(), "&contentRating[]=suggestive");
+    }
+}
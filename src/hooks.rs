@@ -0,0 +1,258 @@
+use std::path::Path;
+
+use log::{debug, warn};
+use tokio::process::Command;
+use tokio::sync::Mutex;
+
+use crate::context::ScrapeContext;
+
+/// Placeholders that can be used in `--on-chapter-done`:
+///
+/// - `{path}`: absolute path of the chapter directory
+/// - `{id}`: chapter uuid
+/// - `{title}`: chapter number and title, as in notifications
+/// - `{manga}`: title of the manga
+pub const CHAPTER_PLACEHOLDERS: &[&str] = &["path", "id", "title", "manga"];
+
+/// Placeholders that can be used in `--on-title-done`:
+///
+/// - `{path}`: absolute path of the directory the title was downloaded into
+/// - `{id}`: manga uuid
+/// - `{title}`: title of the manga
+/// - `{new_chapters}`: number of chapters this run downloaded
+pub const TITLE_PLACEHOLDERS: &[&str] = &["path", "id", "title", "new_chapters"];
+
+/// A command run after a download, split into words the way a shell would. Placeholders
+/// are substituted into the words after splitting, so a value with spaces or quotes in
+/// it stays a single argument and is never interpreted by a shell. The values are also
+/// in the environment as `MDSCRAPE_PATH` and so on, for commands that go through one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HookCommand {
+    source: String,
+    words: Vec<String>,
+}
+
+/// Split a command line into words, honouring single and double quotes and backslashes
+fn split_words(command: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(format!("Unclosed ' in command \"{}\"", command)),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(format!("Unclosed \" in command \"{}\"", command)),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(format!("Unclosed \" in command \"{}\"", command)),
+                    }
+                }
+            }
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    word.get_or_insert_with(String::new).push(c);
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+impl HookCommand {
+    pub fn parse(command: &str, placeholders: &[&str]) -> Result<Self, String> {
+        let words = split_words(command)?;
+        if words.is_empty() {
+            return Err("Empty hook command".to_owned());
+        }
+        let placeholder = regex::Regex::new(r"\{([a-z_]*)\}").unwrap();
+        for name in words
+            .iter()
+            .flat_map(|word| placeholder.captures_iter(word))
+            .map(|captures| captures[1].to_owned())
+        {
+            if !placeholders.contains(&name.as_str()) {
+                return Err(format!(
+                    "Unknown placeholder {{{}}} in command \"{}\", expected one of: {}",
+                    name,
+                    command,
+                    placeholders.join(", ")
+                ));
+            }
+        }
+        Ok(HookCommand {
+            source: command.to_owned(),
+            words,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// The program and its arguments with the placeholders filled in
+    fn render(&self, values: &[(&str, String)]) -> Vec<String> {
+        self.words
+            .iter()
+            .map(|word| {
+                values.iter().fold(word.clone(), |word, (name, value)| {
+                    word.replace(&format!("{{{}}}", name), value)
+                })
+            })
+            .collect()
+    }
+}
+
+/// Commands to run once chapters and titles are downloaded, set with `--on-chapter-done`
+/// and `--on-title-done`
+#[derive(Debug, Default)]
+pub struct Hooks {
+    pub on_chapter_done: Option<HookCommand>,
+    pub on_title_done: Option<HookCommand>,
+    /// Chapters finish concurrently, but their hooks run one at a time so that e.g. two
+    /// library scans or uploads never overlap
+    running: Mutex<()>,
+}
+
+impl Hooks {
+    pub fn new(on_chapter_done: Option<HookCommand>, on_title_done: Option<HookCommand>) -> Self {
+        Hooks {
+            on_chapter_done,
+            on_title_done,
+            running: Mutex::new(()),
+        }
+    }
+
+    /// Run `--on-chapter-done` for a chapter that was just downloaded
+    pub async fn chapter_done(
+        &self,
+        path: &Path,
+        id: String,
+        title: String,
+        manga: Option<&str>,
+        context: &ScrapeContext,
+    ) {
+        if let Some(ref hook) = self.on_chapter_done {
+            let values = [
+                ("path", path.display().to_string()),
+                ("id", id),
+                ("title", title),
+                ("manga", manga.unwrap_or_default().to_owned()),
+            ];
+            self.run(hook, &values, context).await;
+        }
+    }
+
+    /// Run `--on-title-done` for a title whose chapters were all downloaded
+    pub async fn title_done(
+        &self,
+        path: &Path,
+        id: String,
+        title: Option<&str>,
+        new_chapters: usize,
+        context: &ScrapeContext,
+    ) {
+        if let Some(ref hook) = self.on_title_done {
+            let values = [
+                ("path", path.display().to_string()),
+                ("id", id),
+                ("title", title.unwrap_or_default().to_owned()),
+                ("new_chapters", new_chapters.to_string()),
+            ];
+            self.run(hook, &values, context).await;
+        }
+    }
+
+    /// Run a hook and wait for it. A hook that fails is reported in the summary of the
+    /// run, but doesn't fail the download it was run for.
+    async fn run(&self, hook: &HookCommand, values: &[(&str, String)], context: &ScrapeContext) {
+        let args = hook.render(values);
+        let _running = self.running.lock().await;
+        debug!("Running hook {:?}", args);
+        let mut command = Command::new(&args[0]);
+        command.args(&args[1..]).stdin(std::process::Stdio::null());
+        for (name, value) in values {
+            command.env(format!("MDSCRAPE_{}", name.to_uppercase()), value);
+        }
+        let error = match command.status().await {
+            Ok(status) if status.success() => return,
+            Ok(status) => format!("exited with {}", status),
+            Err(e) => format!("failed to start: {}", e),
+        };
+        warn!("Hook \"{}\" {}", hook.as_str(), error);
+        context.stats.add_hook_failure(hook.as_str(), error);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::context::Command as RunCommand;
+
+    #[test]
+    fn test_parse_hook() {
+        assert_eq!(
+            split_words(r#"notify-send "New chapter" '{title}' a\ b "say \"hi\"""#).unwrap(),
+            vec!["notify-send", "New chapter", "{title}", "a b", "say \"hi\""]
+        );
+        assert!(split_words("echo 'unclosed").is_err());
+        let hook = HookCommand::parse(
+            "curl -X POST http://localhost:5000/scan?path={path}",
+            CHAPTER_PLACEHOLDERS,
+        )
+        .unwrap();
+        assert_eq!(
+            hook.render(&[("path", "/library/Ch. 1 & 2".to_owned())]),
+            vec![
+                "curl",
+                "-X",
+                "POST",
+                "http://localhost:5000/scan?path=/library/Ch. 1 & 2"
+            ]
+        );
+        assert!(HookCommand::parse("echo {new_chapters}", CHAPTER_PLACEHOLDERS).is_err());
+        assert!(HookCommand::parse("echo {new_chapters}", TITLE_PLACEHOLDERS).is_ok());
+        assert!(HookCommand::parse("  ", TITLE_PLACEHOLDERS).is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_hook_failures() {
+        let context = crate::context::ScrapeContext::builder(RunCommand::Follows).build();
+        let hooks = Hooks {
+            on_chapter_done: Some(HookCommand::parse("test {manga} = Tomo-chan", CHAPTER_PLACEHOLDERS).unwrap()),
+            on_title_done: Some(HookCommand::parse("false", TITLE_PLACEHOLDERS).unwrap()),
+            ..Default::default()
+        };
+        let path = Path::new("/library/Tomo-chan");
+        hooks
+            .chapter_done(path, "1".to_owned(), "Ch. 1".to_owned(), Some("Tomo-chan"), &context)
+            .await;
+        assert_eq!(context.stats.hook_failures().len(), 0);
+        hooks
+            .title_done(path, "1".to_owned(), Some("Tomo-chan"), 1, &context)
+            .await;
+        assert_eq!(context.stats.hook_failures()[0].what, "false");
+    }
+}
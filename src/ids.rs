@@ -0,0 +1,136 @@
+use uuid::Uuid;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResourceKind {
+    Title,
+    Chapter,
+}
+
+impl ResourceKind {
+    fn name(self) -> &'static str {
+        match self {
+            ResourceKind::Title => "title",
+            ResourceKind::Chapter => "chapter",
+        }
+    }
+
+    fn other(self) -> Self {
+        match self {
+            ResourceKind::Title => ResourceKind::Chapter,
+            ResourceKind::Chapter => ResourceKind::Title,
+        }
+    }
+
+    fn flag(self) -> &'static str {
+        match self {
+            ResourceKind::Title => "--title",
+            ResourceKind::Chapter => "--chapter",
+        }
+    }
+}
+
+/// Parse a resource id as given by the user: either a uuid, or the url of a title or
+/// chapter page on mangadex.org. Errors say what the expected form looks like.
+pub fn parse_resource_id(value: &str, kind: ResourceKind) -> Result<Uuid, String> {
+    let value = value.trim();
+    if let Ok(uuid) = Uuid::parse_str(value) {
+        return Ok(uuid);
+    }
+    if let Ok(url) = url::Url::parse(value) {
+        let segments: Vec<&str> = url.path_segments().map(|s| s.collect()).unwrap_or_default();
+        return match segments.as_slice() {
+            [segment_kind, id, ..] if *segment_kind == kind.name() => {
+                Uuid::parse_str(id).map_err(|_| format!("\"{}\" doesn't contain a valid {} uuid", value, kind.name()))
+            }
+            [other @ ("title" | "chapter"), ..] => Err(format!(
+                "\"{}\" is a {} url, use {} to download it",
+                value,
+                other,
+                kind.other().flag()
+            )),
+            _ => Err(format!(
+                "\"{}\" is not a mangadex {} url, expected e.g. https://mangadex.org/{}/<uuid>",
+                value,
+                kind.name(),
+                kind.name()
+            )),
+        };
+    }
+    if !value.is_empty() && value.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!(
+            "\"{}\" looks like a legacy numeric id, which the current MangaDex api doesn't accept. \
+             Open the {} on mangadex.org and use the uuid from its url instead",
+            value,
+            kind.name()
+        ));
+    }
+    Err(format!(
+        "\"{}\" is not a valid {} id, expected a uuid like 76ee7069-23b4-493c-bc44-34ccbf3051a8 or a url like https://mangadex.org/{}/<uuid>",
+        value,
+        kind.name(),
+        kind.name()
+    ))
+}
+
+/// Parse a list of ids, one per line. Blank lines and lines starting with `#` are
+/// skipped. Every malformed line is reported, not just the first one.
+pub fn parse_resource_list(contents: &str, kind: ResourceKind) -> Result<Vec<Uuid>, Vec<String>> {
+    let mut ids = Vec::new();
+    let mut errors = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_resource_id(line, kind) {
+            Ok(id) => ids.push(id),
+            Err(e) => errors.push(format!("line {}: {}", i + 1, e)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(ids)
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TITLE: &str = "76ee7069-23b4-493c-bc44-34ccbf3051a8";
+
+    #[test]
+    fn test_parse_resource_id() {
+        let expected = Uuid::parse_str(TITLE).unwrap();
+        assert_eq!(parse_resource_id(TITLE, ResourceKind::Title), Ok(expected));
+        assert_eq!(
+            parse_resource_id(
+                &format!("https://mangadex.org/title/{}/tomo-chan-wa-onna-no-ko", TITLE),
+                ResourceKind::Title
+            ),
+            Ok(expected)
+        );
+        let wrong_kind = parse_resource_id(&format!("https://mangadex.org/title/{}", TITLE), ResourceKind::Chapter);
+        assert!(wrong_kind.unwrap_err().contains("use --title"));
+        assert!(parse_resource_id("12345", ResourceKind::Title)
+            .unwrap_err()
+            .contains("legacy numeric id"));
+        assert!(parse_resource_id("tomo-chan", ResourceKind::Title).is_err());
+    }
+
+    #[test]
+    fn test_parse_resource_list() {
+        let list = format!("# My titles\n{}\n\n12345\nnot an id\n", TITLE);
+        let errors = parse_resource_list(&list, ResourceKind::Title).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("line 4: "));
+        assert!(errors[1].starts_with("line 5: "));
+        assert_eq!(
+            parse_resource_list(&format!("{}\n", TITLE), ResourceKind::Title)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,348 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display};
+
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::api::chapter::ChapterData;
+use crate::api::manga::{MangaData, PublicationStatus};
+use crate::api::statistics::MangaStatistics;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ChapterReport {
+    pub id: Uuid,
+    pub volume: Option<String>,
+    pub chapter: Option<String>,
+    pub title: Option<String>,
+    pub language: String,
+    pub pages: usize,
+    pub groups: Vec<String>,
+    pub external_url: Option<String>,
+}
+
+impl ChapterReport {
+    pub fn from_chapter(data: &ChapterData) -> Self {
+        ChapterReport {
+            id: data.id,
+            volume: data.attributes.volume.clone(),
+            chapter: data.attributes.chapter.clone(),
+            title: data.attributes.title.clone(),
+            language: data.attributes.translated_language.clone(),
+            pages: data.attributes.pages,
+            groups: data.scanlation_group_names(),
+            external_url: data.attributes.external_url.clone(),
+        }
+    }
+}
+
+impl Display for ChapterReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(ref volume) = self.volume {
+            write!(f, "Vol. {} ", volume)?;
+        }
+        write!(f, "Ch. {}", self.chapter.as_deref().unwrap_or("-"))?;
+        if let Some(ref title) = self.title {
+            write!(f, " - {}", title)?;
+        }
+        write!(f, " [{}, {} pages]", self.language, self.pages)?;
+        if !self.groups.is_empty() {
+            write!(f, " by {}", self.groups.join(", "))?;
+        }
+        if let Some(ref url) = self.external_url {
+            write!(f, " (external: {})", url)?;
+        }
+        write!(f, " {}", self.id)
+    }
+}
+
+/// Chapter numbers that look wrong in one language of a title: whole numbers up to the
+/// last chapter that no chapter has, and numbers that more than one chapter has
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct NumberingGaps {
+    pub language: String,
+    /// Missing chapters, as single numbers and ranges like 40-45
+    pub missing: Vec<String>,
+    pub duplicates: Vec<String>,
+}
+
+impl NumberingGaps {
+    /// Look for gaps and duplicates in the numbering of each language, leaving out
+    /// languages that have neither. Chapters without a number don't count.
+    pub fn find(chapters: &[ChapterData]) -> Vec<Self> {
+        let mut numbers: BTreeMap<&str, Vec<f64>> = BTreeMap::new();
+        for chapter in chapters {
+            if let Some(number) = chapter
+                .attributes
+                .chapter
+                .as_deref()
+                .and_then(|n| n.trim().parse().ok())
+            {
+                numbers
+                    .entry(chapter.attributes.translated_language.as_str())
+                    .or_default()
+                    .push(number);
+            }
+        }
+        numbers
+            .into_iter()
+            .filter_map(|(language, mut numbers)| {
+                numbers.sort_by(f64::total_cmp);
+                let mut duplicates: Vec<String> = numbers
+                    .windows(2)
+                    .filter(|pair| pair[0] == pair[1])
+                    .map(|pair| pair[0].to_string())
+                    .collect();
+                duplicates.dedup();
+                // Chapters like 12.5 count for 12, so extras don't leave gaps
+                let whole: BTreeSet<u64> = numbers.iter().filter(|n| **n >= 0.0).map(|n| *n as u64).collect();
+                let last = whole.last().copied().unwrap_or_default();
+                let first = whole.first().copied().unwrap_or_default().min(1);
+                let mut missing = Vec::new();
+                let mut n = first;
+                while n <= last {
+                    if whole.contains(&n) {
+                        n += 1;
+                        continue;
+                    }
+                    // The last chapter ends every gap
+                    let start = n;
+                    while !whole.contains(&n) {
+                        n += 1;
+                    }
+                    missing.push(match n - 1 {
+                        end if end == start => start.to_string(),
+                        end => format!("{}-{}", start, end),
+                    });
+                }
+                (!missing.is_empty() || !duplicates.is_empty()).then(|| NumberingGaps {
+                    language: language.to_owned(),
+                    missing,
+                    duplicates,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Display for NumberingGaps {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.language)?;
+        if !self.missing.is_empty() {
+            write!(f, " missing {}", self.missing.join(", "))?;
+        }
+        if !self.missing.is_empty() && !self.duplicates.is_empty() {
+            write!(f, ";")?;
+        }
+        if !self.duplicates.is_empty() {
+            write!(f, " more than one chapter {}", self.duplicates.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The chapters of a title that one language has, next to all the chapters there are in
+/// any language
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct LanguageAvailability {
+    pub language: String,
+    pub chapters: usize,
+    /// Chapters that other languages have and this one doesn't, as single numbers and
+    /// runs like 40-45 of the chapters there are
+    pub missing: Vec<String>,
+}
+
+impl Display for LanguageAvailability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} chapters", self.language, self.chapters)?;
+        if !self.missing.is_empty() {
+            write!(f, ", missing {}", self.missing.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Everything `--info` prints about a title, built from api responses we already
+/// fetch for a download
+#[derive(Clone, Debug, Serialize)]
+pub struct TitleReport {
+    pub id: Uuid,
+    pub title: Option<String>,
+    /// Other titles as `language: title`
+    pub alt_titles: Vec<String>,
+    pub description: Option<String>,
+    pub authors: Vec<String>,
+    pub artists: Vec<String>,
+    pub status: Option<PublicationStatus>,
+    /// Rating, follows and comments, filled in for `--info` only
+    pub statistics: Option<MangaStatistics>,
+    pub tags: Vec<String>,
+    pub available_languages: Vec<String>,
+    /// How much of the title each language has, filled in for `--info` only since it
+    /// takes reading the feed in every language
+    pub availability: Vec<LanguageAvailability>,
+    pub gaps: Vec<NumberingGaps>,
+    pub chapters: Vec<ChapterReport>,
+}
+
+impl TitleReport {
+    pub fn new(manga: &MangaData, chapters: &[ChapterData]) -> Self {
+        let tags = manga
+            .attributes
+            .tags()
+            .iter()
+            .filter_map(|tag| tag.display_name().map(str::to_owned))
+            .collect();
+        TitleReport {
+            id: manga.id,
+            title: manga.attributes.display_title().map(str::to_owned),
+            alt_titles: manga
+                .attributes
+                .alt_titles()
+                .into_iter()
+                .map(|(language, title)| format!("{}: {}", language, title))
+                .collect(),
+            description: manga.attributes.description().map(str::to_owned),
+            authors: manga.creator_names("author"),
+            artists: manga.creator_names("artist"),
+            status: manga.attributes.status,
+            statistics: None,
+            tags,
+            available_languages: manga.attributes.available_languages(),
+            availability: Vec::new(),
+            gaps: NumberingGaps::find(chapters),
+            chapters: chapters.iter().map(ChapterReport::from_chapter).collect(),
+        }
+    }
+}
+
+impl Display for TitleReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let or_unknown = |values: &[String]| {
+            if values.is_empty() {
+                "unknown".to_owned()
+            } else {
+                values.join(", ")
+            }
+        };
+        writeln!(f, "{} ({})", self.title.as_deref().unwrap_or("Untitled"), self.id)?;
+        if !self.alt_titles.is_empty() {
+            writeln!(f, "Also:      {}", self.alt_titles.join(", "))?;
+        }
+        writeln!(f, "Authors:   {}", or_unknown(&self.authors))?;
+        writeln!(f, "Artists:   {}", or_unknown(&self.artists))?;
+        match self.status {
+            Some(status) => writeln!(f, "Status:    {}", status)?,
+            None => writeln!(f, "Status:    unknown")?,
+        }
+        if let Some(ref statistics) = self.statistics {
+            match statistics.rating.average {
+                Some(average) => write!(f, "Rating:    {:.2}", average)?,
+                None => write!(f, "Rating:    none")?,
+            }
+            if let Some(bayesian) = statistics.rating.bayesian {
+                write!(f, " (bayesian {:.2})", bayesian)?;
+            }
+            let comments = statistics
+                .comments
+                .as_ref()
+                .map_or(0, |comments| comments.replies_count);
+            writeln!(f, ", {} follows, {} comments", statistics.follows, comments)?;
+        }
+        writeln!(f, "Tags:      {}", or_unknown(&self.tags))?;
+        writeln!(f, "Languages: {}", or_unknown(&self.available_languages))?;
+        for language in &self.availability {
+            writeln!(f, "  {}", language)?;
+        }
+        if let Some(ref description) = self.description {
+            // Only the first paragraph, descriptions often go on with links and credits
+            let summary = description.lines().next().unwrap_or_default();
+            writeln!(f, "About:     {}", summary)?;
+        }
+        for gaps in &self.gaps {
+            writeln!(f, "Gaps:      {}", gaps)?;
+        }
+        writeln!(f, "Chapters:  {}", self.chapters.len())?;
+        for chapter in &self.chapters {
+            writeln!(f, "  {}", chapter)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_title_report() {
+        let manga: MangaData = serde_json::from_value(serde_json::json!({
+            "id": "76ee7069-23b4-493c-bc44-34ccbf3051a8",
+            "type": "manga",
+            "attributes": {
+                "title": {"en": "Tomo-chan wa Onna no ko!"},
+                "status": "completed",
+                "availableTranslatedLanguages": ["en", "es-la"],
+                "altTitles": [{"ja": "トモちゃんは女の子!"}, {"ja-ro": "Tomo-chan wa Onna no Ko!"}],
+                "description": {"en": "Tomo is a tomboy.\n\n---\nLinks"},
+                "tags": [{"id": "423e2eae-a7a2-4a8b-ac03-a8351462d71d", "type": "tag",
+                          "attributes": {"name": {"en": "Romance"}}}]
+            },
+            "relationships": [
+                {"id": "a", "type": "author", "attributes": {"name": "Yanagida Fumita"}},
+                {"id": "b", "type": "cover_art"}
+            ]
+        }))
+        .unwrap();
+        let mut report = TitleReport::new(&manga, &[]);
+        report.statistics = Some(
+            serde_json::from_value(serde_json::json!({
+                "comments": {"threadId": 1, "repliesCount": 45},
+                "rating": {"average": 8.4567, "bayesian": 8.31, "distribution": {"10": 3}},
+                "follows": 12345
+            }))
+            .unwrap(),
+        );
+        assert_eq!(report.authors, vec!["Yanagida Fumita"]);
+        assert!(report.artists.is_empty());
+        assert_eq!(report.tags, vec!["Romance"]);
+        assert_eq!(report.available_languages, vec!["en", "es-la"]);
+        let text = report.to_string();
+        assert!(text.starts_with("Tomo-chan wa Onna no ko! (76ee7069-23b4-493c-bc44-34ccbf3051a8)\n"));
+        assert!(text.contains("Status:    completed\n"));
+        assert!(text.contains("Artists:   unknown\n"));
+        assert!(text.contains("Rating:    8.46 (bayesian 8.31), 12345 follows, 45 comments\n"));
+        assert!(text.contains("Also:      ja: トモちゃんは女の子!, ja-ro: Tomo-chan wa Onna no Ko!\n"));
+        assert!(text.contains("About:     Tomo is a tomboy.\n"));
+    }
+
+    #[test]
+    fn test_numbering_gaps() {
+        let chapters: Vec<ChapterData> = [
+            ("en", "1"),
+            ("en", "2"),
+            ("en", "2"),
+            ("en", "4"),
+            ("en", "4.5"),
+            ("en", "8"),
+            ("es-la", "3"),
+            ("es-la", "Extra"),
+            ("fr", "0"),
+            ("fr", "1"),
+        ]
+        .iter()
+        .map(|(language, chapter)| {
+            serde_json::from_value(serde_json::json!({
+                "id": Uuid::from_u128(rand::random()),
+                "type": "chapter",
+                "attributes": {"chapter": chapter, "translatedLanguage": language, "pages": 1},
+                "relationships": []
+            }))
+            .unwrap()
+        })
+        .collect();
+        let gaps = NumberingGaps::find(&chapters);
+        assert_eq!(gaps.len(), 2);
+        assert_eq!(gaps[0].to_string(), "en: missing 3, 5-7; more than one chapter 2");
+        assert_eq!(gaps[1].to_string(), "es-la: missing 1-2");
+    }
+}
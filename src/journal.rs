@@ -0,0 +1,143 @@
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::retry::Result;
+use crate::title::TitleData;
+
+pub const JOURNAL_FILE_NAME: &str = ".mdscrape-journal";
+
+/// The work of a title download, kept next to it while it runs so that `--resume` can
+/// finish it after a crash or a power loss without asking the API for the title again.
+/// The file starts with this as a line of json, and every chapter that is done is
+/// appended by its id on a line of its own.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Journal {
+    /// The title with its whole chapter list, like `--snapshot-feed` saves it
+    pub title: TitleData,
+    pub path_template: String,
+    pub title_langs: Vec<String>,
+    /// The chapters the download selected
+    pub chapters: Vec<Uuid>,
+}
+
+impl Journal {
+    /// Read the journal of the download into dir, leaving out the chapters that are
+    /// done. None if there is nothing to resume.
+    pub fn read(dir: &Path) -> Result<Option<Self>> {
+        let contents = match fs::read_to_string(dir.join(JOURNAL_FILE_NAME)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut lines = contents.lines();
+        let mut journal: Journal = serde_json::from_str(lines.next().unwrap_or_default())?;
+        // The last line may have been cut short by the crash
+        let done: HashSet<Uuid> = lines.filter_map(|line| line.trim().parse().ok()).collect();
+        journal.chapters.retain(|id| !done.contains(id));
+        Ok(Some(journal))
+    }
+
+    /// Write the journal into dir, replacing any earlier one, to record chapters in as
+    /// they are done
+    pub fn start(&self, dir: &Path) -> Result<JournalFile> {
+        let path = dir.join(JOURNAL_FILE_NAME);
+        let part_path = dir.join(format!("{}.part", JOURNAL_FILE_NAME));
+        let mut header = serde_json::to_vec(self)?;
+        header.push(b'\n');
+        fs::write(&part_path, header)?;
+        fs::rename(&part_path, &path)?;
+        debug!("Journaling {} chapters in {:?}", self.chapters.len(), path);
+        Ok(JournalFile {
+            file: Mutex::new(OpenOptions::new().append(true).open(&path)?),
+            path,
+            pending: Mutex::new(self.chapters.iter().copied().collect()),
+        })
+    }
+}
+
+/// An open journal of a download that is running
+#[derive(Debug)]
+pub struct JournalFile {
+    path: PathBuf,
+    file: Mutex<File>,
+    pending: Mutex<HashSet<Uuid>>,
+}
+
+impl JournalFile {
+    /// Record that a chapter is downloaded, or was complete already
+    pub fn chapter_done(&self, chapter_id: Uuid) -> Result<()> {
+        if !self.pending.lock().unwrap().remove(&chapter_id) {
+            return Ok(());
+        }
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{}", chapter_id)?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    /// Remove the journal if every chapter is done. Otherwise it stays, for `--resume`
+    /// to pick up the rest.
+    pub fn finish(self) {
+        let pending = self.pending.lock().unwrap().len();
+        if pending > 0 {
+            debug!("Keeping {:?}, {} chapters aren't done", self.path, pending);
+            return;
+        }
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Failed to remove {:?}: {}", self.path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_journal() {
+        let dir = std::env::temp_dir().join(format!("mdscrape-test-{}", Uuid::from_u128(rand::random())));
+        fs::create_dir_all(&dir).unwrap();
+        assert!(Journal::read(&dir).unwrap().is_none());
+        let title: TitleData = serde_json::from_value(serde_json::json!({
+            "id": "76ee7069-23b4-493c-bc44-34ccbf3051a8",
+            "manga": {
+                "id": "76ee7069-23b4-493c-bc44-34ccbf3051a8",
+                "type": "manga",
+                "attributes": {"title": {"en": "Tomo-chan wa Onna no ko!"}},
+                "relationships": []
+            },
+            "chapters": []
+        }))
+        .unwrap();
+        let journal = Journal {
+            title,
+            path_template: "{chapter}".to_owned(),
+            title_langs: Vec::new(),
+            chapters: (1..=3).map(Uuid::from_u128).collect(),
+        };
+
+        let file = journal.start(&dir).unwrap();
+        file.chapter_done(Uuid::from_u128(2)).unwrap();
+        // A chapter that was already done, or isn't part of the download, isn't recorded
+        file.chapter_done(Uuid::from_u128(2)).unwrap();
+        file.chapter_done(Uuid::from_u128(4)).unwrap();
+        file.finish();
+        let resumed = Journal::read(&dir).unwrap().unwrap();
+        assert_eq!(resumed.chapters, vec![Uuid::from_u128(1), Uuid::from_u128(3)]);
+        assert_eq!(resumed.path_template, "{chapter}");
+
+        let file = resumed.start(&dir).unwrap();
+        file.chapter_done(Uuid::from_u128(1)).unwrap();
+        file.chapter_done(Uuid::from_u128(3)).unwrap();
+        file.finish();
+        assert!(Journal::read(&dir).unwrap().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
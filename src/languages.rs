@@ -0,0 +1,170 @@
+/// Languages MangaDex has chapters in, as (code, English name, other names people use)
+const LANGUAGES: &[(&str, &str, &[&str])] = &[
+    ("en", "english", &["eng"]),
+    ("ja", "japanese", &["jp", "jpn"]),
+    ("ja-ro", "romanized japanese", &["romaji"]),
+    ("ko", "korean", &["kr", "kor"]),
+    ("ko-ro", "romanized korean", &[]),
+    ("zh", "simplified chinese", &["chinese", "cn", "zh-cn", "zh-hans"]),
+    ("zh-hk", "traditional chinese", &["zh-tw", "zh-hant"]),
+    ("zh-ro", "romanized chinese", &["pinyin"]),
+    ("es", "spanish", &["castilian", "spa"]),
+    (
+        "es-la",
+        "latin american spanish",
+        &["latam", "es-mx", "mexican spanish"],
+    ),
+    ("pt", "portuguese", &["pt-pt", "por"]),
+    ("pt-br", "brazilian portuguese", &["br", "brazilian"]),
+    ("fr", "french", &["fra"]),
+    ("de", "german", &["deu", "ger"]),
+    ("it", "italian", &["ita"]),
+    ("ru", "russian", &["rus"]),
+    ("uk", "ukrainian", &["ua"]),
+    ("pl", "polish", &["pol"]),
+    ("cs", "czech", &["cz"]),
+    ("sk", "slovak", &[]),
+    ("hu", "hungarian", &[]),
+    ("ro", "romanian", &[]),
+    ("bg", "bulgarian", &[]),
+    ("sr", "serbian", &[]),
+    ("hr", "croatian", &[]),
+    ("lt", "lithuanian", &[]),
+    ("lv", "latvian", &[]),
+    ("et", "estonian", &[]),
+    ("el", "greek", &["gr"]),
+    ("tr", "turkish", &["tur"]),
+    ("ar", "arabic", &["ara"]),
+    ("he", "hebrew", &["iw"]),
+    ("fa", "persian", &["farsi"]),
+    ("hi", "hindi", &[]),
+    ("bn", "bengali", &["bangla"]),
+    ("ta", "tamil", &[]),
+    ("te", "telugu", &[]),
+    ("ne", "nepali", &[]),
+    ("ur", "urdu", &[]),
+    ("th", "thai", &[]),
+    ("vi", "vietnamese", &["vn"]),
+    ("id", "indonesian", &["bahasa", "in"]),
+    ("ms", "malay", &[]),
+    ("tl", "tagalog", &["filipino", "fil"]),
+    ("my", "burmese", &[]),
+    ("km", "khmer", &[]),
+    ("mn", "mongolian", &[]),
+    ("kk", "kazakh", &[]),
+    ("uz", "uzbek", &[]),
+    ("az", "azerbaijani", &[]),
+    ("ka", "georgian", &[]),
+    ("hy", "armenian", &[]),
+    ("be", "belarusian", &[]),
+    ("nl", "dutch", &["flemish"]),
+    ("sv", "swedish", &[]),
+    ("no", "norwegian", &["nb", "nn"]),
+    ("da", "danish", &[]),
+    ("fi", "finnish", &[]),
+    ("ca", "catalan", &[]),
+    ("eu", "basque", &[]),
+    ("gl", "galician", &[]),
+    ("sq", "albanian", &[]),
+    ("la", "latin", &[]),
+    ("eo", "esperanto", &[]),
+];
+
+/// Lower case, with `_` and spaces written as `-`, so `PT_BR` and `pt br` match `pt-br`
+fn normalize(value: &str) -> String {
+    value
+        .trim()
+        .to_lowercase()
+        .split(|c: char| c == '-' || c == '_' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Every name a language can be given by
+fn names(&(code, name, aliases): &(&'static str, &'static str, &'static [&'static str])) -> Vec<String> {
+    let mut names = vec![code.to_owned(), name.to_owned()];
+    names.extend(aliases.iter().map(|alias| (*alias).to_owned()));
+    names
+}
+
+/// Find the MangaDex language code for a code, a language name or a common alias,
+/// e.g. `english`, `PT_BR`, `ptbr` or `japanese`. Unknown languages are an error
+/// suggesting the closest matches, since MangaDex just returns an empty feed for them.
+pub fn resolve_language(value: &str) -> Result<&'static str, String> {
+    let wanted = normalize(value);
+    let squashed = wanted.replace('-', "");
+    let found = LANGUAGES.iter().find(|language| {
+        names(language)
+            .iter()
+            .map(|name| normalize(name))
+            .any(|name| name == wanted || name.replace('-', "") == squashed)
+    });
+    if let Some((code, _, _)) = found {
+        return Ok(code);
+    }
+    let mut suggestions: Vec<(usize, &str, &str)> = LANGUAGES
+        .iter()
+        .filter_map(|language| {
+            let distance = names(language)
+                .iter()
+                .map(|name| edit_distance(&wanted, &normalize(name)))
+                .min()?;
+            // Short codes are only a letter or two apart from lots of others
+            (distance <= 2 && distance < wanted.len()).then_some((distance, language.0, language.1))
+        })
+        .collect();
+    suggestions.sort();
+    let mut message = format!("Unknown language \"{}\"", value.trim());
+    if !suggestions.is_empty() {
+        let suggestions: Vec<String> = suggestions
+            .iter()
+            .take(3)
+            .map(|(_, code, name)| format!("{} ({})", code, name))
+            .collect();
+        message.push_str(&format!(", did you mean {}?", suggestions.join(" or ")));
+    }
+    Err(message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resolve_language() {
+        assert_eq!(resolve_language("en"), Ok("en"));
+        assert_eq!(resolve_language("English"), Ok("en"));
+        assert_eq!(resolve_language("japanese"), Ok("ja"));
+        assert_eq!(resolve_language("pt-br"), Ok("pt-br"));
+        assert_eq!(resolve_language("PT_BR"), Ok("pt-br"));
+        assert_eq!(resolve_language("ptbr"), Ok("pt-br"));
+        assert_eq!(resolve_language("latin american spanish"), Ok("es-la"));
+        assert_eq!(resolve_language("es-la"), Ok("es-la"));
+    }
+
+    #[test]
+    fn test_language_suggestions() {
+        assert_eq!(
+            resolve_language("englsh"),
+            Err("Unknown language \"englsh\", did you mean en (english)?".to_owned())
+        );
+        let error = resolve_language("japanse").unwrap_err();
+        assert!(error.contains("ja (japanese)"), "{}", error);
+        assert_eq!(resolve_language("xx"), Err("Unknown language \"xx\"".to_owned()));
+    }
+}
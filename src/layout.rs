@@ -0,0 +1,104 @@
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use log::debug;
+
+use crate::naming::PathTemplate;
+use crate::retry::Result;
+
+pub const KAVITA_IGNORE_FILE_NAME: &str = ".kavitaignore";
+
+/// Kavita reads loose images as chapters too, which would list every chapter twice
+const KAVITA_IGNORE: &str = "\
+# Written by mdscrape. Chapters are read from their .cbz archives, the pages next to
+# them are kept so that later runs know what is already downloaded.
+*.png
+*.jpg
+*.jpeg
+*.gif
+*.webp
+";
+
+/// How output is arranged for a self-hosted reader to use as its library, set with
+/// `--layout`. Every chapter is packaged as a .cbz with a ComicInfo.xml, which both
+/// readers take the series, volume and chapter number from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LibraryLayout {
+    /// Komga takes every directory with books in it as a series, so the chapters of a
+    /// title go straight into its directory
+    Komga,
+    /// Kavita groups the chapters of a series by volume directories
+    Kavita,
+}
+
+impl FromStr for LibraryLayout {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "komga" => Ok(LibraryLayout::Komga),
+            "kavita" => Ok(LibraryLayout::Kavita),
+            _ => Err(format!("Invalid layout \"{}\", expected komga or kavita", value)),
+        }
+    }
+}
+
+impl LibraryLayout {
+    /// Where chapters go, unless `--path-template` says otherwise. The group is part of
+    /// the name so that chapters translated by several groups don't end up together.
+    pub fn path_template(&self) -> PathTemplate {
+        let template = match self {
+            LibraryLayout::Komga => "{manga}/{manga} Ch. {chapter:04} [{group}]",
+            LibraryLayout::Kavita => "{manga}/{volume_dir}/{manga} Ch. {chapter:04} [{group}]",
+        };
+        PathTemplate::parse(template).expect("Layout templates are valid")
+    }
+
+    /// Write the files the reader needs in the directory titles are downloaded to. An
+    /// existing file is left alone, in case it was edited.
+    pub fn prepare(&self, root: &Path) -> Result<()> {
+        if *self == LibraryLayout::Kavita {
+            let path = root.join(KAVITA_IGNORE_FILE_NAME);
+            if !path.exists() {
+                debug!("Writing {:?}", path);
+                fs::create_dir_all(root)?;
+                fs::write(path, KAVITA_IGNORE)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::naming::NamingFields;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_layout_paths() {
+        let fields = NamingFields {
+            chapter: Some("12.5".to_owned()),
+            volume: Some("3".to_owned()),
+            manga: Some("Tomo-chan".to_owned()),
+            group: Some("Some Scans".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            LibraryLayout::Kavita.path_template().render(&fields),
+            PathBuf::from("Tomo-chan")
+                .join("Volume 03")
+                .join("Tomo-chan Ch. 0012.5 [Some Scans]")
+        );
+        assert_eq!(
+            "komga"
+                .parse::<LibraryLayout>()
+                .unwrap()
+                .path_template()
+                .render(&fields),
+            PathBuf::from("Tomo-chan").join("Tomo-chan Ch. 0012.5 [Some Scans]")
+        );
+        assert!("calibre".parse::<LibraryLayout>().is_err());
+    }
+}
@@ -0,0 +1,91 @@
+#![forbid(unsafe_code)]
+
+//! A scraper for mangadex.org, as a library. The `mdscrape` binary is a thin command
+//! line frontend to it.
+//!
+//! Everything a run does goes through a [`ScrapeContext`], which holds the options, the
+//! throttle and the statistics of the run. The command line builds one with
+//! [`ScrapeContext::from_args`], other programs with [`ScrapeContext::builder`]:
+//!
+//! ```no_run
+//! use mdscrape::context::{Command, DownloadType, ScrapeContext};
+//! use mdscrape::throttle::TicketPolicy;
+//!
+//! # async fn example() -> mdscrape::common::OpaqueResult<()> {
+//! let title = "76ee7069-23b4-493c-bc44-34ccbf3051a8".parse()?;
+//! let context = ScrapeContext::builder(Command::Download(vec![DownloadType::Title(title)]))
+//!     .lang_codes(vec!["en".to_owned()])
+//!     .ticket_policy(TicketPolicy {
+//!         max_global: 4,
+//!         max_per_site: 2,
+//!         ..Default::default()
+//!     })
+//!     .build();
+//! mdscrape::run::run_command(&context, chrono::Utc::now()).await?;
+//! println!("Downloaded {} pages", context.stats.pages());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Titles and chapters can also be downloaded one at a time with
+//! [`TitleData::download_to_directory`] and [`ChapterInfo::download_to_directory`].
+//! Progress is reported through [`ProgressDisplay`], either as bars or as
+//! [`ProgressEvent`]s, and log messages through the `log` crate.
+//!
+//! [`ScrapeContext`]: context::ScrapeContext
+//! [`ScrapeContext::from_args`]: context::ScrapeContext::from_args
+//! [`ScrapeContext::builder`]: context::ScrapeContext::builder
+//! [`TitleData::download_to_directory`]: title::TitleData::download_to_directory
+//! [`ChapterInfo::download_to_directory`]: chapter::ChapterInfo::download_to_directory
+//! [`ProgressDisplay`]: progress::ProgressDisplay
+//! [`ProgressEvent`]: progress::ProgressEvent
+
+pub mod api;
+pub mod attestation;
+pub mod cache;
+pub mod cbz;
+pub mod chapter;
+pub mod client;
+pub mod common;
+pub mod completion;
+pub mod config;
+pub mod context;
+pub mod convert;
+pub mod cover;
+pub mod epub;
+pub mod existing;
+pub mod exit;
+pub mod export;
+pub mod feed;
+pub mod filter;
+pub mod hooks;
+pub mod ids;
+pub mod info;
+pub mod journal;
+pub mod languages;
+pub mod layout;
+pub mod library;
+pub mod lock;
+pub mod logging;
+pub mod manifest;
+pub mod metadata;
+pub mod names;
+pub mod naming;
+pub mod nodes;
+pub mod notify;
+pub mod pdf;
+pub mod plan;
+pub mod progress;
+pub mod retry;
+pub mod run;
+pub mod selection;
+pub mod source;
+pub mod stats;
+pub mod store;
+pub mod summary;
+pub mod throttle;
+pub mod title;
+pub mod tui;
+pub mod units;
+pub mod validate;
+pub mod workspace;
@@ -0,0 +1,405 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Utc};
+use log::{debug, error, info, warn};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::api::manga::{MangaFeedResponse, PublicationStatus};
+use crate::client::Session;
+use crate::context::ScrapeContext;
+use crate::naming::{sanitize_component, PathTemplate};
+use crate::retry::Result;
+use crate::title::TitleData;
+use crate::units;
+use crate::workspace::{Workspace, WORKSPACE_DIR_NAME};
+
+pub const TITLE_RECORD_FILE_NAME: &str = ".mdscrape-title.json";
+
+/// What we know about a title downloaded into a directory, kept so that later runs
+/// can tell what is there and decide whether it's worth checking for updates
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TitleRecord {
+    pub title_id: Uuid,
+    /// Comma separated, as given to --lang-code
+    pub lang_code: String,
+    pub status: Option<PublicationStatus>,
+    pub last_checked: DateTime<Utc>,
+    /// Template the chapters were saved with, so that syncing puts new chapters next
+    /// to the old ones. Missing for titles downloaded by older versions.
+    #[serde(default)]
+    pub path_template: Option<String>,
+    /// Name and authors the chapters were saved with, so that `--export` can rename
+    /// them without asking MangaDex. Missing for titles downloaded by older versions.
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    #[serde(default)]
+    pub original_language: Option<String>,
+}
+
+impl TitleRecord {
+    pub fn read(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(TITLE_RECORD_FILE_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&fs::read(path)?)?))
+    }
+
+    pub fn write(&self, dir: &Path) -> Result<()> {
+        fs::write(dir.join(TITLE_RECORD_FILE_NAME), serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// How often titles should be checked for new chapters, depending on their
+/// publication status. Completed series rarely get new chapters, so there's no point
+/// hitting the API for them as often as for ongoing ones.
+#[derive(Clone, Debug)]
+pub struct SyncPolicy {
+    intervals: HashMap<PublicationStatus, Duration>,
+    /// Used for titles whose status is unknown
+    default_interval: Duration,
+}
+
+impl Default for SyncPolicy {
+    fn default() -> Self {
+        let mut intervals = HashMap::new();
+        intervals.insert(PublicationStatus::Ongoing, Duration::hours(24));
+        intervals.insert(PublicationStatus::Hiatus, Duration::days(7));
+        intervals.insert(PublicationStatus::Completed, Duration::days(30));
+        intervals.insert(PublicationStatus::Cancelled, Duration::days(30));
+        SyncPolicy {
+            intervals,
+            default_interval: Duration::hours(24),
+        }
+    }
+}
+
+impl SyncPolicy {
+    /// Parse overrides of the default policy, e.g. `ongoing=12h,completed=30d`. Plain
+    /// numbers are taken to be hours.
+    pub fn parse(overrides: &str) -> std::result::Result<Self, String> {
+        let mut policy = Self::default();
+        for entry in overrides.split(',') {
+            let (status, interval) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Expected STATUS=INTERVAL, got \"{}\"", entry))?;
+            let interval = units::parse_duration(interval, std::time::Duration::from_secs(60 * 60))?;
+            let interval = Duration::from_std(interval).map_err(|e| e.to_string())?;
+            policy.intervals.insert(status.trim().parse()?, interval);
+        }
+        Ok(policy)
+    }
+
+    pub fn interval(&self, status: Option<PublicationStatus>) -> Duration {
+        status
+            .and_then(|status| self.intervals.get(&status).copied())
+            .unwrap_or(self.default_interval)
+    }
+
+    pub fn is_due(&self, record: &TitleRecord, now: DateTime<Utc>) -> bool {
+        now - record.last_checked >= self.interval(record.status)
+    }
+}
+
+/// Find every title directory under root, i.e. every directory with a title record
+pub fn find_title_dirs(root: &Path) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = walkdir::WalkDir::new(root)
+        .into_iter()
+        .filter_entry(|entry| entry.file_name() != WORKSPACE_DIR_NAME)
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file() && entry.file_name() == TITLE_RECORD_FILE_NAME)
+        .filter_map(|entry| entry.path().parent().map(Path::to_owned))
+        .collect();
+    dirs.sort();
+    debug!("Found {} titles under {:?}", dirs.len(), root);
+    dirs
+}
+
+async fn sync_title(dir: &Path, record: &TitleRecord, workspace: &Workspace, context: &ScrapeContext) -> Result<()> {
+    let lang_codes: Vec<String> = record.lang_code.split(',').map(str::to_owned).collect();
+    let mut title = TitleData::download_for_title(record.title_id, &lang_codes, context).await?;
+    match record.path_template.as_deref().map(PathTemplate::parse) {
+        Some(Ok(template)) => title.set_path_template(template),
+        Some(Err(e)) => warn!("Ignoring stored path template for {:?}: {}", dir, e),
+        None => title.set_path_template(PathTemplate::default()),
+    }
+    title.download_to_directory(&dir, workspace, context).await?;
+    // Downloading only rewrites the record if something changed, but this still counts
+    // as a check as far as the sync policy is concerned
+    let mut record = TitleRecord::read(dir)?.unwrap_or_else(|| record.clone());
+    record.last_checked = Utc::now();
+    record.write(dir)
+}
+
+/// Download new chapters of every title under root that is due for a check according
+/// to the sync policy. A title that fails doesn't stop the others from being synced,
+/// the number of failed titles is returned.
+pub async fn sync_library(root: &Path, context: &ScrapeContext) -> Result<usize> {
+    let workspace = Workspace::new(&root);
+    let now = Utc::now();
+    let mut failed = 0;
+    for dir in find_title_dirs(root) {
+        let record = match TitleRecord::read(&dir)? {
+            Some(record) => record,
+            None => continue,
+        };
+        if !context.sync_policy.is_due(&record, now) {
+            info!("Skipping {:?}, last checked {}", dir, record.last_checked);
+            continue;
+        }
+        info!("Syncing title {} in {:?}", record.title_id, dir);
+        if let Err(e) = sync_title(&dir, &record, &workspace, context).await {
+            error!("Failed to sync {:?}: {}", dir, e);
+            context.stats.add_failure(dir.display().to_string(), &e);
+            failed += 1;
+        }
+    }
+    Ok(failed)
+}
+
+/// Most recent chapters of the follows feed to look at, anything older should have
+/// been picked up by an earlier run
+const FOLLOWS_FEED_LIMIT: usize = 500;
+
+/// Ids of the followed manga with recent chapters in the given languages
+async fn recently_updated_follows(session: &Session, context: &ScrapeContext) -> Result<Vec<Uuid>> {
+    let languages: String = context
+        .lang_codes
+        .iter()
+        .map(|lang_code| format!("&translatedLanguage[]={}", lang_code))
+        .collect();
+    let url = Url::parse(&format!(
+        "https://api.mangadex.org/user/follows/manga/feed?limit={}{}{}&order[readableAt]=desc",
+        FOLLOWS_FEED_LIMIT,
+        languages,
+        context.title_filter.feed_query()
+    ))?;
+    debug!("Going to download follows feed from {}", url);
+    let feed: MangaFeedResponse = context.http().get_json_as(&url, session).await?;
+    let mut manga_ids = Vec::new();
+    for chapter in &feed.data {
+        let manga_id = chapter
+            .relationships
+            .iter()
+            .find(|relationship| relationship.relationship_type == "manga")
+            .and_then(|relationship| Uuid::parse_str(&relationship.id).ok());
+        if let Some(manga_id) = manga_id {
+            if !manga_ids.contains(&manga_id) {
+                manga_ids.push(manga_id);
+            }
+        }
+    }
+    context.title_filter.filter_titles(manga_ids, context).await
+}
+
+/// The title directories below root, by the title they hold. Root itself is left out,
+/// since the titles put under it mustn't end up inside another title.
+pub fn existing_title_dirs(root: &Path) -> Result<HashMap<Uuid, PathBuf>> {
+    let mut existing = HashMap::new();
+    for dir in find_title_dirs(root) {
+        if dir == root {
+            continue;
+        }
+        if let Some(record) = TitleRecord::read(&dir)? {
+            existing.insert(record.title_id, dir);
+        }
+    }
+    Ok(existing)
+}
+
+/// Where a title goes among others under root: where it already is, or a new directory
+/// named after it. Each title keeps its own record, journal and attestation there, so a
+/// name another title already has gets the id added to it. The choice goes into
+/// existing, for the titles after it.
+pub fn title_dir(root: &Path, title: &TitleData, existing: &mut HashMap<Uuid, PathBuf>) -> PathBuf {
+    let id = title.id();
+    if let Some(dir) = existing.get(&id) {
+        return dir.clone();
+    }
+    let id_string = id.to_string();
+    let mut name = sanitize_component(title.display_title().unwrap_or(&id_string));
+    if name.is_empty() {
+        name = id_string.clone();
+    }
+    let taken = |dir: &Path| {
+        existing.values().any(|used| used == dir)
+            || TitleRecord::read(dir)
+                .ok()
+                .flatten()
+                .is_some_and(|record| record.title_id != id)
+    };
+    let mut dir = root.join(&name);
+    if taken(&dir) {
+        dir = root.join(format!("{} [{}]", name, &id_string[..8]));
+    }
+    existing.insert(id, dir.clone());
+    dir
+}
+
+/// Download new chapters of every followed manga with recent updates. Titles that are
+/// already in the library under root are updated in place, new ones get a directory
+/// named after the title. Returns the number of titles that failed.
+pub async fn download_follows(root: &Path, context: &ScrapeContext) -> Result<usize> {
+    let session = context.credentials.session().await?;
+    let manga_ids = recently_updated_follows(&session, context).await?;
+    info!("{} followed titles have recent chapters", manga_ids.len());
+    let mut existing = existing_title_dirs(root)?;
+    let workspace = Workspace::new(&root);
+    let mut failed = 0;
+    for manga_id in manga_ids {
+        let result = async {
+            let title = TitleData::download_for_title(manga_id, &context.lang_codes, context).await?;
+            let dir = title_dir(root, &title, &mut existing);
+            std::fs::create_dir_all(&dir)?;
+            title.download_to_directory(&dir, &workspace, context).await
+        }
+        .await;
+        if let Err(e) = result {
+            error!("Failed to download followed title {}: {}", manga_id, e);
+            context.stats.add_failure(manga_id.to_string(), &e);
+            failed += 1;
+        }
+    }
+    Ok(failed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sync_policy() {
+        let policy = SyncPolicy::parse("ongoing=12,hiatus=2d").unwrap();
+        assert_eq!(policy.interval(Some(PublicationStatus::Hiatus)), Duration::days(2));
+        let now = Utc::now();
+        let mut record = TitleRecord {
+            title_id: Uuid::from_u128(1),
+            lang_code: "en".to_owned(),
+            status: Some(PublicationStatus::Ongoing),
+            last_checked: now - Duration::hours(13),
+            path_template: None,
+            title: None,
+            authors: Vec::new(),
+            original_language: None,
+        };
+        assert!(policy.is_due(&record, now));
+        record.status = Some(PublicationStatus::Completed);
+        assert!(!policy.is_due(&record, now));
+        record.status = None;
+        assert!(!policy.is_due(&record, now));
+
+        assert!(SyncPolicy::parse("ongoing").is_err());
+        assert!(SyncPolicy::parse("finished=3").is_err());
+        assert!(SyncPolicy::parse("ongoing=x").is_err());
+    }
+
+    #[test]
+    fn test_find_title_dirs() {
+        let root = std::env::temp_dir().join(format!("mdscrape-test-{}", Uuid::from_u128(rand::random())));
+        let title = root.join("Tomo-chan");
+        fs::create_dir_all(title.join("md00001")).unwrap();
+        fs::create_dir_all(root.join("empty")).unwrap();
+        let record = TitleRecord {
+            title_id: Uuid::from_u128(1),
+            lang_code: "en,es-la".to_owned(),
+            status: None,
+            last_checked: Utc::now(),
+            path_template: Some("{chapter}".to_owned()),
+            title: Some("Tomo-chan wa Onna no ko!".to_owned()),
+            authors: vec!["Yanagida Fumita".to_owned()],
+            original_language: Some("ja".to_owned()),
+        };
+        record.write(&title).unwrap();
+        assert_eq!(find_title_dirs(&root), vec![title.clone()]);
+        let read = TitleRecord::read(&title).unwrap().unwrap();
+        assert_eq!(read.path_template.as_deref(), Some("{chapter}"));
+        assert!(TitleRecord::read(&root).unwrap().is_none());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_batch_title_dirs() {
+        use crate::journal::Journal;
+        let root = std::env::temp_dir().join(format!("mdscrape-test-{}", Uuid::from_u128(rand::random())));
+        fs::create_dir_all(&root).unwrap();
+        let title = |id: u128, name: &str| -> TitleData {
+            serde_json::from_value(serde_json::json!({
+                "id": Uuid::from_u128(id),
+                "manga": {
+                    "id": Uuid::from_u128(id),
+                    "type": "manga",
+                    "attributes": {"title": {"en": name}},
+                    "relationships": []
+                },
+                "chapters": []
+            }))
+            .unwrap()
+        };
+        let titles = [title(1, "Tomo-chan"), title(2, "Komi-san")];
+        let record = |title_id| TitleRecord {
+            title_id,
+            lang_code: "en".to_owned(),
+            status: None,
+            last_checked: Utc::now(),
+            path_template: None,
+            title: None,
+            authors: Vec::new(),
+            original_language: None,
+        };
+        // A title downloaded into root on its own before doesn't take in the others
+        record(Uuid::from_u128(1)).write(&root).unwrap();
+        let mut existing = existing_title_dirs(&root).unwrap();
+        assert!(existing.is_empty());
+
+        let mut dirs = Vec::new();
+        for title in &titles {
+            let dir = title_dir(&root, title, &mut existing);
+            fs::create_dir_all(&dir).unwrap();
+            record(title.id()).write(&dir).unwrap();
+            let journal = Journal {
+                title: title.clone(),
+                path_template: "{chapter}".to_owned(),
+                title_langs: Vec::new(),
+                chapters: vec![Uuid::from_u128(10)],
+            };
+            // Left behind as if the download had failed
+            drop(journal.start(&dir).unwrap());
+            dirs.push(dir);
+        }
+        assert_eq!(dirs, vec![root.join("Tomo-chan"), root.join("Komi-san")]);
+        for (title, dir) in titles.iter().zip(&dirs) {
+            assert_eq!(Journal::read(dir).unwrap().unwrap().title.id(), title.id());
+        }
+        // The next batch finds them where they are
+        let mut existing = existing_title_dirs(&root).unwrap();
+        assert_eq!(title_dir(&root, &titles[1], &mut existing), dirs[1]);
+
+        // Titles that share a name, or have none left once sanitized, keep apart
+        let same_name = [
+            title(3 << 100, "Komi-san"),
+            title(4 << 100, "Komi-san"),
+            title(5, "..."),
+        ];
+        let dirs: Vec<PathBuf> = same_name
+            .iter()
+            .map(|title| title_dir(&root, title, &mut existing))
+            .collect();
+        let prefix = |id: u128| Uuid::from_u128(id).to_string()[..8].to_owned();
+        assert_eq!(
+            dirs,
+            vec![
+                root.join(format!("Komi-san [{}]", prefix(3 << 100))),
+                root.join(format!("Komi-san [{}]", prefix(4 << 100))),
+                root.join(Uuid::from_u128(5).to_string()),
+            ]
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
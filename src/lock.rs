@@ -0,0 +1,206 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::retry::{DownloadError, Result};
+
+pub const LOCK_FILE_NAME: &str = ".mdscrape.lock";
+
+/// Who holds the lock of a directory, as written into its lock file
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+struct LockOwner {
+    pid: u32,
+    started_at: DateTime<Utc>,
+}
+
+impl LockOwner {
+    /// Whether the run that took the lock has certainly ended. Process ids are reused,
+    /// so a process that started after the lock was taken isn't the one that took it.
+    fn is_gone(&self) -> bool {
+        match process_started_at(self.pid) {
+            // Start times are only known to the second or so
+            Some(Some(started_at)) => started_at > self.started_at + chrono::Duration::seconds(5),
+            Some(None) => true,
+            // Without a way to look at processes, only --force takes the lock
+            None => false,
+        }
+    }
+}
+
+/// When process pid started: None if we can't tell, Some(None) if there is no such
+/// process
+#[cfg(target_os = "linux")]
+fn process_started_at(pid: u32) -> Option<Option<DateTime<Utc>>> {
+    let stat = match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        Ok(stat) => stat,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Some(None),
+        Err(_) => return None,
+    };
+    // The 22nd field is the start time in clock ticks since boot, which are 1/100s
+    // everywhere but on exotic kernels. The name before it may contain spaces.
+    let ticks: i64 = stat.rsplit_once(')')?.1.split_whitespace().nth(19)?.parse().ok()?;
+    let boot_time: i64 = fs::read_to_string("/proc/stat")
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("btime "))?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(DateTime::from_timestamp(boot_time + ticks / 100, 0))
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn process_started_at(pid: u32) -> Option<Option<DateTime<Utc>>> {
+    // Not kill -0, which also fails with EPERM for a running process of another user.
+    // ps finds processes whoever they belong to.
+    let status = std::process::Command::new("ps")
+        .args(["-p", &pid.to_string()])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .ok()?;
+    Some(if status.success() {
+        Some(DateTime::<Utc>::MIN_UTC)
+    } else {
+        None
+    })
+}
+
+#[cfg(not(unix))]
+fn process_started_at(_: u32) -> Option<Option<DateTime<Utc>>> {
+    None
+}
+
+/// Whether the file at path was written in the last minute
+fn is_recent(path: &Path) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age < std::time::Duration::from_secs(60))
+}
+
+/// Keeps other mdscrape runs out of an output directory while this one writes to it,
+/// so that they don't stage, commit and clean up the same chapters at once. The lock
+/// file is removed again when this is dropped.
+#[derive(Debug)]
+pub struct DirLock {
+    path: PathBuf,
+    owner: LockOwner,
+}
+
+impl DirLock {
+    /// Lock dir for this run. A lock left behind by a run that has ended is taken over,
+    /// and with force any other lock too.
+    pub fn acquire(dir: &Path, force: bool) -> Result<Self> {
+        let path = dir.join(LOCK_FILE_NAME);
+        let owner = LockOwner {
+            pid: std::process::id(),
+            started_at: Utc::now(),
+        };
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    file.write_all(&serde_json::to_vec(&owner)?)?;
+                    debug!("Locked {:?}", dir);
+                    return Ok(DirLock { path, owner });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e.into()),
+            }
+            let holder: Option<LockOwner> = fs::read(&path)
+                .ok()
+                .and_then(|contents| serde_json::from_slice(&contents).ok());
+            match holder {
+                Some(ref holder) if !force && !holder.is_gone() => {
+                    return Err(DownloadError::DirectoryLocked(format!(
+                        "{:?} is in use by mdscrape process {} since {}, use --force if it isn't running any more",
+                        dir,
+                        holder.pid,
+                        holder.started_at.format("%Y-%m-%d %H:%M:%S UTC")
+                    )));
+                }
+                Some(holder) => warn!(
+                    "Taking over the lock of {:?} from mdscrape process {}, which started at {}",
+                    dir, holder.pid, holder.started_at
+                ),
+                // Being written by another run right now, or by one that crashed while
+                // writing it
+                None if !force && is_recent(&path) => {
+                    return Err(DownloadError::DirectoryLocked(format!(
+                        "{:?} is being locked by another mdscrape run",
+                        dir
+                    )));
+                }
+                None => warn!("Removing the unreadable lock file {:?}", path),
+            }
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Err(DownloadError::DirectoryLocked(format!(
+            "{:?} is in use by another mdscrape run",
+            dir
+        )))
+    }
+}
+
+impl Drop for DirLock {
+    fn drop(&mut self) {
+        // Unless --force gave it to another run in the meantime
+        let ours = fs::read(&self.path)
+            .ok()
+            .and_then(|contents| serde_json::from_slice::<LockOwner>(&contents).ok())
+            .is_some_and(|owner| owner == self.owner);
+        if ours {
+            if let Err(e) = fs::remove_file(&self.path) {
+                warn!("Failed to remove {:?}: {}", self.path, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_dir_lock() {
+        let dir = std::env::temp_dir().join(format!("mdscrape-test-{}", Uuid::from_u128(rand::random())));
+        fs::create_dir_all(&dir).unwrap();
+        let lock = DirLock::acquire(&dir, false).unwrap();
+        assert!(matches!(
+            DirLock::acquire(&dir, false),
+            Err(DownloadError::DirectoryLocked(_))
+        ));
+        drop(lock);
+        assert!(!dir.join(LOCK_FILE_NAME).exists());
+
+        // A lock from a process that has ended is taken over
+        let stale = LockOwner {
+            pid: u32::MAX,
+            started_at: Utc::now(),
+        };
+        fs::write(dir.join(LOCK_FILE_NAME), serde_json::to_vec(&stale).unwrap()).unwrap();
+        if cfg!(unix) {
+            drop(DirLock::acquire(&dir, false).unwrap());
+        } else {
+            fs::remove_file(dir.join(LOCK_FILE_NAME)).unwrap();
+        }
+
+        // And any lock with --force, whose old holder then leaves it alone
+        let lock = DirLock::acquire(&dir, false).unwrap();
+        let forced = DirLock::acquire(&dir, true).unwrap();
+        drop(lock);
+        assert!(dir.join(LOCK_FILE_NAME).exists());
+        drop(forced);
+        assert!(!dir.join(LOCK_FILE_NAME).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,141 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+use simple_logger::SimpleLogger;
+
+use crate::context::ScrapeContext;
+use crate::tui::DashboardLogger;
+
+/// A line of `--log-file`
+#[derive(Debug, Serialize)]
+struct LogLine<'a> {
+    time: DateTime<Utc>,
+    level: &'a str,
+    target: &'a str,
+    message: String,
+}
+
+/// `--log-file`, one json object per message so that the log can be searched and parsed
+struct LogFile {
+    file: Mutex<File>,
+    level: LevelFilter,
+}
+
+impl LogFile {
+    fn open(path: &Path, level: LevelFilter) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(LogFile {
+            file: Mutex::new(file),
+            level,
+        })
+    }
+
+    fn write(&self, record: &Record) {
+        let line = LogLine {
+            time: Utc::now(),
+            level: record.level().as_str(),
+            target: record.target(),
+            message: record.args().to_string(),
+        };
+        if let Ok(mut json) = serde_json::to_vec(&line) {
+            json.push(b'\n');
+            // Written in one go, so lines of concurrent messages don't mix. There is
+            // nowhere left to report a failure to.
+            let _ = self.file.lock().unwrap().write_all(&json);
+        }
+    }
+}
+
+/// Sends log messages to the terminal, or the dashboard with `--tui`, and to `--log-file`
+pub struct Logger {
+    terminal: Box<dyn Log>,
+    terminal_level: LevelFilter,
+    file: Option<LogFile>,
+}
+
+impl Logger {
+    /// Install the logger for this run. With `--quiet` only errors reach the terminal.
+    pub fn init(context: &ScrapeContext) -> Result<(), String> {
+        let terminal_level = if context.quiet {
+            LevelFilter::Error
+        } else {
+            context.log_level
+        };
+        let terminal: Box<dyn Log> = match context.dashboard {
+            Some(ref dashboard) => Box::new(DashboardLogger::new(dashboard.clone(), terminal_level)),
+            None => Box::new(SimpleLogger::new().with_level(terminal_level)),
+        };
+        let file = match context.log_file {
+            Some(ref path) => Some(
+                LogFile::open(path, context.log_level)
+                    .map_err(|e| format!("Failed to open the log file {:?}: {}", path, e))?,
+            ),
+            None => None,
+        };
+        let max_level = terminal_level.max(file.as_ref().map_or(LevelFilter::Off, |file| file.level));
+        let logger = Logger {
+            terminal,
+            terminal_level,
+            file,
+        };
+        log::set_boxed_logger(Box::new(logger)).map_err(|e| e.to_string())?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.terminal_level || self.file.as_ref().is_some_and(|file| metadata.level() <= file.level)
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() <= self.terminal_level {
+            self.terminal.log(record);
+        }
+        if let Some(ref file) = self.file {
+            if record.level() <= file.level {
+                file.write(record);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.terminal.flush();
+        if let Some(ref file) = self.file {
+            let _ = file.file.lock().unwrap().flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_log_file() {
+        let path = std::env::temp_dir().join(format!("mdscrape-test-{}.log", Uuid::from_u128(rand::random())));
+        let file = LogFile::open(&path, LevelFilter::Info).unwrap();
+        file.write(
+            &Record::builder()
+                .args(format_args!("Downloading title: {}", 1))
+                .level(log::Level::Info)
+                .target("mdscrape")
+                .build(),
+        );
+        drop(file);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let line: serde_json::Value = serde_json::from_str(contents.lines().next().unwrap()).unwrap();
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["target"], "mdscrape");
+        assert_eq!(line["message"], "Downloading title: 1");
+        assert!(line["time"].is_string());
+    }
+}
@@ -1,76 +1,130 @@
 #![forbid(unsafe_code)]
 
-mod api;
-mod chapter;
-mod client;
-mod common;
-mod context;
-mod retry;
-mod throttle;
-mod title;
-mod tui;
-
 use tokio::task;
 
-use log::{info, LevelFilter};
-
-use simple_logger::SimpleLogger;
+use log::{error, info};
 
-use chapter::ChapterInfo;
-use common::*;
-use context::ScrapeContext;
-use title::TitleData;
+use mdscrape::common::*;
+use mdscrape::context::ScrapeContext;
+use mdscrape::retry::DownloadError;
+use mdscrape::{exit, logging, progress, run, summary, tui};
 
 #[global_allocator]
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
-#[tokio::main(worker_threads = 1)]
+fn report_error(result: &OpaqueResult<()>) {
+    if let Err(e) = result {
+        error!("{}", e);
+    }
+}
+
+/// Exit with the code for the kind of error the run failed with, if it failed
+fn exit_on_error(result: &OpaqueResult<()>) {
+    if let Err(e) = result {
+        std::process::exit(exit::code_for(e.as_ref()));
+    }
+}
+
+/// Keep what this run learned about nodes and download speed for the next one
+fn save_history(context: &ScrapeContext, elapsed: std::time::Duration) {
+    context.node_scores.save();
+    context.group_names.save();
+    context.author_names.save();
+    if context.stats.pages() > 0 {
+        let mut history = context.history.clone();
+        history.record(&context.stats, elapsed);
+        history.save();
+    }
+}
+
+/// Run the scrape until it is done or the user presses Ctrl-C. Cancelling drops every
+/// download in flight, and staged chapters and partial images clean up after themselves.
+async fn run_until_interrupted(scrape: impl std::future::Future<Output = OpaqueResult<()>>) -> OpaqueResult<()> {
+    tokio::select! {
+        result = scrape => result,
+        // If the handler can't be installed we just run without it
+        Ok(()) = tokio::signal::ctrl_c() => Err(DownloadError::Interrupted.into()),
+    }
+}
+
+/// Tell the user which chapters an interrupted run managed to finish, the totals
+/// follow in the run summary
+fn report_interrupted(context: &ScrapeContext) {
+    let downloaded_chapters = context.downloaded_chapters.lock().unwrap();
+    println!("Interrupted, {} chapters are complete:", downloaded_chapters.len());
+    for chapter in downloaded_chapters.iter() {
+        println!("  {}", chapter);
+    }
+}
+
+/// Print what a download did, and write it to `--report` if asked to
+fn report_run(context: &ScrapeContext, started_at: chrono::DateTime<chrono::Utc>) {
+    if !context.command.downloads_chapters() {
+        return;
+    }
+    let summary = summary::RunSummary::from_context(context, started_at);
+    if context.progress.is_json() {
+        context.progress.emit(progress::ProgressEvent::Summary(&summary));
+    } else if context.quiet {
+        info!("{}", summary);
+    } else {
+        println!("{}", summary);
+    }
+    if let Some(ref path) = context.report {
+        let result = serde_json::to_vec_pretty(&summary)
+            .map_err(DownloadError::from)
+            .and_then(|json| std::fs::write(path, json).map_err(DownloadError::from));
+        if let Err(e) = result {
+            error!("Failed to write the run report to {:?}: {}", path, e);
+        }
+    }
+}
+
+#[tokio::main]
 async fn main() -> OpaqueResult<()> {
-    // let tui = TUI::new()?;
     let context = ScrapeContext::from_args();
-    let level = if context.verbose {
-        LevelFilter::Info
-    } else {
-        LevelFilter::Warn
-    };
-    SimpleLogger::new().with_level(level).init().unwrap();
-    // Setup progress bar
+    if let Err(e) = logging::Logger::init(&context) {
+        eprintln!("mdscrape: {}", e);
+        std::process::exit(exit::USAGE);
+    }
     let progress = context.progress.clone();
-    let invis_bar = progress.add(indicatif::ProgressBar::hidden());
-    let invis_bar_style = indicatif::ProgressStyle::default_bar().template("[MDScrape]");
-    invis_bar.set_style(invis_bar_style);
 
+    let started_at = chrono::Utc::now();
+    let scrape_task = run::run_scheduled(&context, started_at);
+    let started = std::time::Instant::now();
     let scrape_task = async {
-        let current_dir = std::env::current_dir()?;
-        match context.download_type {
-            context::DownloadType::Chapter(ref uuid) => {
-                info!("Going to download chapter {:?}", uuid);
-                let chapter = ChapterInfo::download_for_chapter(*uuid, &context).await?;
-                if context.verbose {
-                    info!("Got chapter information: {:#?}", chapter);
-                }
-                chapter.download_to_directory(&current_dir, &context).await?;
-            }
-            context::DownloadType::Title(ref uuid) => {
-                info!("Downloading title: {}", uuid);
-                let title = TitleData::download_for_title(*uuid, &context).await?;
-                if context.verbose {
-                    info!("Title API response: {:#?}", title);
-                }
-                title.download_to_directory(&current_dir, &context).await?;
-            }
+        match context.dashboard {
+            Some(ref dashboard) => tui::run_with_dashboard(dashboard, &context, scrape_task).await,
+            None => scrape_task.await,
         }
-        invis_bar.finish_and_clear();
-        Ok(())
     };
     if context.show_progress {
         let progress_res = task::spawn_blocking(move || progress.join());
-        let scrape_res: OpaqueResult<_> = scrape_task.await;
-        scrape_res?;
+        let scrape_res = run_until_interrupted(scrape_task).await;
+        save_history(&context, started.elapsed());
+        if scrape_res
+            .as_ref()
+            .is_err_and(|e| DownloadError::is_interrupted(e.as_ref()))
+        {
+            context.progress.finish();
+            report_interrupted(&context);
+        }
+        report_run(&context, started_at);
+        report_error(&scrape_res);
+        exit_on_error(&scrape_res);
         progress_res.await??;
     } else {
-        let scrape_res: OpaqueResult<_> = scrape_task.await;
-        scrape_res?;
+        let scrape_res = run_until_interrupted(scrape_task).await;
+        save_history(&context, started.elapsed());
+        if scrape_res
+            .as_ref()
+            .is_err_and(|e| DownloadError::is_interrupted(e.as_ref()))
+        {
+            report_interrupted(&context);
+        }
+        report_run(&context, started_at);
+        report_error(&scrape_res);
+        exit_on_error(&scrape_res);
     }
     Ok(())
 }
@@ -0,0 +1,319 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::chapter::ChapterInfo;
+use crate::common::run_blocking;
+use crate::completion::CompletionPolicy;
+use crate::context::ScrapeContext;
+use crate::retry::Result;
+use crate::workspace::{Workspace, WORKSPACE_DIR_NAME};
+
+pub const MANIFEST_FILE_NAME: &str = "checksums.sha256";
+const CHAPTER_ID_PREFIX: &str = "# mdscrape chapter ";
+const DATA_SAVER_PREFIX: &str = "# mdscrape data-saver ";
+const MISSING_PREFIX: &str = "# mdscrape missing ";
+
+/// Per-chapter list of page checksums, in the format used by `sha256sum`. The first
+/// line is a comment recording which chapter the directory holds, so that broken
+/// pages can be fetched again later, and further comments flag pages that are only
+/// the data saver version or that couldn't be downloaded at all.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    pub chapter_id: Option<Uuid>,
+    /// (hex digest, file name) pairs
+    pub entries: Vec<(String, String)>,
+    /// File names of pages that were downloaded in data saver quality, because the
+    /// original was missing
+    pub data_saver: Vec<String>,
+    /// File names of pages that are missing from a chapter that was kept anyway,
+    /// because of `--complete-when`
+    pub missing: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChecksumMismatch {
+    Missing,
+    Changed,
+}
+
+impl std::fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChecksumMismatch::Missing => write!(f, "missing"),
+            ChecksumMismatch::Changed => write!(f, "checksum mismatch"),
+        }
+    }
+}
+
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+impl Manifest {
+    /// Hash the given files, which may live in different directories (e.g. some pages
+    /// are still staged while others were downloaded by a previous run)
+    pub fn from_files(chapter_id: Uuid, files: &[PathBuf]) -> io::Result<Self> {
+        let mut entries = Vec::new();
+        for path in files {
+            if let Some(file_name) = path.file_name() {
+                entries.push((hash_file(path)?, file_name.to_string_lossy().into_owned()));
+            }
+        }
+        entries.sort_by(|a, b| a.1.cmp(&b.1));
+        Ok(Manifest {
+            chapter_id: Some(chapter_id),
+            entries,
+            data_saver: Vec::new(),
+            missing: Vec::new(),
+        })
+    }
+
+    pub fn parse(contents: &str) -> Self {
+        let mut chapter_id = None;
+        let mut entries = Vec::new();
+        let mut data_saver = Vec::new();
+        let mut missing = Vec::new();
+        for line in contents.lines() {
+            if let Some(id) = line.strip_prefix(CHAPTER_ID_PREFIX) {
+                chapter_id = Uuid::parse_str(id.trim()).ok();
+            } else if let Some(file_name) = line.strip_prefix(DATA_SAVER_PREFIX) {
+                data_saver.push(file_name.to_owned());
+            } else if let Some(file_name) = line.strip_prefix(MISSING_PREFIX) {
+                missing.push(file_name.to_owned());
+            } else if let Some((digest, file_name)) = line.split_once("  ") {
+                entries.push((digest.to_owned(), file_name.to_owned()));
+            }
+        }
+        Manifest {
+            chapter_id,
+            entries,
+            data_saver,
+            missing,
+        }
+    }
+
+    pub fn read(dir: &Path) -> Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(dir.join(MANIFEST_FILE_NAME))?))
+    }
+
+    pub fn write(&self, dir: &Path) -> Result<()> {
+        let mut contents = String::new();
+        if let Some(id) = self.chapter_id {
+            contents.push_str(&format!("{}{}\n", CHAPTER_ID_PREFIX, id));
+        }
+        for file_name in &self.data_saver {
+            contents.push_str(&format!("{}{}\n", DATA_SAVER_PREFIX, file_name));
+        }
+        for file_name in &self.missing {
+            contents.push_str(&format!("{}{}\n", MISSING_PREFIX, file_name));
+        }
+        for (digest, file_name) in &self.entries {
+            contents.push_str(&format!("{}  {}\n", digest, file_name));
+        }
+        fs::write(dir.join(MANIFEST_FILE_NAME), contents)?;
+        Ok(())
+    }
+
+    /// Re-hash every file in the manifest, returning the ones that don't match and the
+    /// pages that were never downloaded
+    pub fn verify(&self, dir: &Path) -> Result<Vec<(String, ChecksumMismatch)>> {
+        let mut mismatches: Vec<_> = self
+            .missing
+            .iter()
+            .filter(|file_name| !dir.join(file_name).exists())
+            .map(|file_name| (file_name.clone(), ChecksumMismatch::Missing))
+            .collect();
+        for (digest, file_name) in &self.entries {
+            let path = dir.join(file_name);
+            if !path.exists() {
+                mismatches.push((file_name.clone(), ChecksumMismatch::Missing));
+            } else if hash_file(&path)? != *digest {
+                mismatches.push((file_name.clone(), ChecksumMismatch::Changed));
+            }
+        }
+        Ok(mismatches)
+    }
+}
+
+/// If dir already holds enough pages of the chapter according to its manifest to count
+/// as complete under policy, and the given extra files, return the number of pages it
+/// has. This only checks that the files exist, not their checksums, so it is cheap
+/// enough to run for every chapter.
+pub fn completed_pages(
+    dir: &Path,
+    chapter_id: Uuid,
+    expected_pages: usize,
+    extra_files: &[&str],
+    policy: CompletionPolicy,
+) -> Option<usize> {
+    let manifest = Manifest::read(dir).ok()?;
+    let present = manifest
+        .entries
+        .iter()
+        .filter(|(_, file_name)| dir.join(file_name).exists())
+        .count();
+    let complete = manifest.chapter_id == Some(chapter_id)
+        && expected_pages > 0
+        // Files listed in the manifest that have disappeared since need a download
+        && present == manifest.entries.len()
+        && policy.is_complete(present, expected_pages)
+        && extra_files.iter().all(|file_name| dir.join(file_name).exists());
+    complete.then_some(present)
+}
+
+/// Find every chapter directory under root that has a manifest
+pub fn find_manifest_dirs(root: &Path) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = walkdir::WalkDir::new(root)
+        .into_iter()
+        // Anything still staged is incomplete by definition
+        .filter_entry(|entry| entry.file_name() != WORKSPACE_DIR_NAME)
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file() && entry.file_name() == MANIFEST_FILE_NAME)
+        .filter_map(|entry| entry.path().parent().map(Path::to_owned))
+        .collect();
+    dirs.sort();
+    debug!("Found {} manifests under {:?}", dirs.len(), root);
+    dirs
+}
+
+/// Check every chapter under root against its manifest, optionally downloading broken
+/// pages again. Returns the number of files that are still broken.
+pub async fn verify_library(root: &Path, context: &ScrapeContext) -> Result<usize> {
+    let workspace = Workspace::new(&root);
+    let mut broken = 0;
+    for dir in find_manifest_dirs(root) {
+        let manifest = Manifest::read(&dir)?;
+        let mismatches = {
+            let (manifest, dir) = (manifest.clone(), dir.clone());
+            run_blocking(move || manifest.verify(&dir)).await?
+        };
+        if mismatches.is_empty() {
+            debug!("{:?} is intact", dir);
+            continue;
+        }
+        for (file_name, mismatch) in &mismatches {
+            println!("{}: {}", dir.join(file_name).display(), mismatch);
+        }
+        match manifest.chapter_id {
+            Some(chapter_id) if context.refetch => {
+                for (file_name, _) in &mismatches {
+                    context.existing.set_aside(&dir.join(file_name))?;
+                }
+                let chapter = ChapterInfo::download_for_chapter(chapter_id, context).await?;
+                chapter.download_to_directory(&dir, &workspace, context).await?;
+                broken += Manifest::read(&dir)?.verify(&dir)?.len();
+            }
+            _ => broken += mismatches.len(),
+        }
+    }
+    Ok(broken)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_manifest_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("mdscrape-test-{}", Uuid::from_u128(rand::random())));
+        fs::create_dir_all(&dir).unwrap();
+        let page = dir.join("0001.png");
+        fs::write(&page, b"page").unwrap();
+        let chapter_id = Uuid::from_u128(7);
+
+        let mut manifest = Manifest::from_files(chapter_id, std::slice::from_ref(&page)).unwrap();
+        manifest.data_saver.push("0001.png".to_owned());
+        manifest.write(&dir).unwrap();
+        let read = Manifest::read(&dir).unwrap();
+        assert_eq!(read, manifest);
+        assert!(read.verify(&dir).unwrap().is_empty());
+        assert_eq!(find_manifest_dirs(&dir), vec![dir.clone()]);
+
+        fs::write(&page, b"broken").unwrap();
+        assert_eq!(
+            read.verify(&dir).unwrap(),
+            vec![("0001.png".to_owned(), ChecksumMismatch::Changed)]
+        );
+        fs::remove_file(&page).unwrap();
+        assert_eq!(
+            read.verify(&dir).unwrap(),
+            vec![("0001.png".to_owned(), ChecksumMismatch::Missing)]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_completed_pages() {
+        let dir = std::env::temp_dir().join(format!("mdscrape-test-{}", Uuid::from_u128(rand::random())));
+        fs::create_dir_all(&dir).unwrap();
+        let chapter_id = Uuid::from_u128(7);
+        assert_eq!(
+            completed_pages(&dir, chapter_id, 2, &[], CompletionPolicy::AllPages),
+            None
+        );
+
+        let pages = [dir.join("0001.png"), dir.join("0002.png")];
+        for page in &pages {
+            fs::write(page, b"page").unwrap();
+        }
+        Manifest::from_files(chapter_id, &pages).unwrap().write(&dir).unwrap();
+        assert_eq!(
+            completed_pages(&dir, chapter_id, 2, &[], CompletionPolicy::AllPages),
+            Some(2)
+        );
+        // A different chapter, more pages upstream or a newly requested file all mean
+        // there is still work to do
+        assert_eq!(
+            completed_pages(&dir, Uuid::from_u128(8), 2, &[], CompletionPolicy::AllPages),
+            None
+        );
+        assert_eq!(
+            completed_pages(&dir, chapter_id, 3, &[], CompletionPolicy::AllPages),
+            None
+        );
+        assert_eq!(
+            completed_pages(&dir, chapter_id, 2, &["ComicInfo.xml"], CompletionPolicy::AllPages),
+            None
+        );
+
+        // A chapter that was kept with a page missing is complete if the policy allows it
+        let mut manifest = Manifest::from_files(chapter_id, &pages[..1]).unwrap();
+        manifest.missing.push("0002.png".to_owned());
+        manifest.write(&dir).unwrap();
+        assert_eq!(
+            completed_pages(&dir, chapter_id, 2, &[], CompletionPolicy::AllPages),
+            None
+        );
+        assert_eq!(
+            completed_pages(&dir, chapter_id, 2, &[], CompletionPolicy::Percent(50)),
+            Some(1)
+        );
+        fs::remove_file(&pages[1]).unwrap();
+        assert_eq!(
+            Manifest::read(&dir).unwrap().verify(&dir).unwrap(),
+            vec![("0002.png".to_owned(), ChecksumMismatch::Missing)]
+        );
+
+        fs::remove_file(&pages[0]).unwrap();
+        assert_eq!(
+            completed_pages(&dir, chapter_id, 2, &[], CompletionPolicy::MetadataOnly),
+            None
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
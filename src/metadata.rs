@@ -0,0 +1,126 @@
+use crate::api::chapter::ChapterData;
+use crate::common::escape_xml;
+
+pub const COMIC_INFO_FILE_NAME: &str = "ComicInfo.xml";
+
+/// The subset of the ComicRack `ComicInfo.xml` schema that readers like Kavita and
+/// Komga use to identify a chapter
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ComicInfo {
+    pub series: Option<String>,
+    pub title: Option<String>,
+    pub number: Option<String>,
+    pub volume: Option<String>,
+    /// Description of the series
+    pub summary: Option<String>,
+    pub language_iso: String,
+    pub translators: Vec<String>,
+    pub writers: Vec<String>,
+    pub pencillers: Vec<String>,
+    pub genres: Vec<String>,
+    pub tags: Vec<String>,
+    pub page_count: usize,
+    pub web: String,
+}
+
+impl ComicInfo {
+    pub fn from_chapter(data: &ChapterData, series: Option<&str>, page_count: usize) -> Self {
+        ComicInfo {
+            series: series.map(str::to_owned),
+            title: data.attributes.title.clone(),
+            number: data.attributes.chapter.clone(),
+            volume: data.attributes.volume.clone(),
+            language_iso: data.attributes.translated_language.clone(),
+            translators: data.scanlation_group_names(),
+            writers: Vec::new(),
+            pencillers: Vec::new(),
+            genres: Vec::new(),
+            tags: Vec::new(),
+            summary: None,
+            page_count,
+            web: format!("https://mangadex.org/chapter/{}", data.id),
+        }
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        xml.push_str(
+            "<ComicInfo xmlns:xsd=\"http://www.w3.org/2001/XMLSchema\" \
+             xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\">\n",
+        );
+        let mut push_element = |name: &str, value: &str| {
+            xml.push_str(&format!("  <{}>{}</{}>\n", name, escape_xml(value), name));
+        };
+        if let Some(ref series) = self.series {
+            push_element("Series", series);
+        }
+        if let Some(ref title) = self.title {
+            push_element("Title", title);
+        }
+        if let Some(ref number) = self.number {
+            push_element("Number", number);
+        }
+        // ComicInfo volumes are integers, so decimal or named volumes are left out
+        if let Some(volume) = self.volume.as_ref().filter(|v| v.parse::<u32>().is_ok()) {
+            push_element("Volume", volume);
+        }
+        if let Some(ref summary) = self.summary {
+            push_element("Summary", summary);
+        }
+        if !self.writers.is_empty() {
+            push_element("Writer", &self.writers.join(", "));
+        }
+        if !self.pencillers.is_empty() {
+            push_element("Penciller", &self.pencillers.join(", "));
+        }
+        if !self.translators.is_empty() {
+            push_element("Translator", &self.translators.join(", "));
+        }
+        if !self.genres.is_empty() {
+            push_element("Genre", &self.genres.join(", "));
+        }
+        if !self.tags.is_empty() {
+            push_element("Tags", &self.tags.join(", "));
+        }
+        push_element("LanguageISO", &self.language_iso);
+        push_element("PageCount", &self.page_count.to_string());
+        push_element("Web", &self.web);
+        push_element("Manga", "YesAndRightToLeft");
+        xml.push_str("</ComicInfo>\n");
+        xml
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_comic_info_xml() {
+        let info = ComicInfo {
+            series: Some("Tomo-chan wa Onna no ko!".to_owned()),
+            number: Some("953.5".to_owned()),
+            volume: Some("3.5".to_owned()),
+            language_iso: "en".to_owned(),
+            translators: vec!["Group A".to_owned(), "B & C".to_owned()],
+            writers: vec!["Yanagida Fumita".to_owned()],
+            page_count: 2,
+            summary: Some("Tomo is a <tomboy>".to_owned()),
+            genres: vec!["Comedy".to_owned(), "Romance".to_owned()],
+            ..Default::default()
+        };
+        let xml = info.to_xml();
+        assert!(xml.contains("<Series>Tomo-chan wa Onna no ko!</Series>"));
+        assert!(xml.contains("<Number>953.5</Number>"));
+        assert!(!xml.contains("<Volume>"));
+        assert!(!xml.contains("<Title>"));
+        assert!(xml.contains("<Translator>Group A, B &amp; C</Translator>"));
+        assert!(xml.contains("<PageCount>2</PageCount>"));
+        assert!(xml.contains("<Writer>Yanagida Fumita</Writer>"));
+        assert!(!xml.contains("<Penciller>"));
+        assert!(xml.contains("<Summary>Tomo is a &lt;tomboy&gt;</Summary>"));
+        assert!(xml.contains("<Genre>Comedy, Romance</Genre>"));
+        assert!(!xml.contains("<Tags>"));
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use log::{debug, warn};
+use reqwest::Url;
+use uuid::Uuid;
+
+use crate::api::author::AuthorListResponse;
+use crate::api::chapter::ChapterData;
+use crate::api::group::GroupListResponse;
+use crate::api::manga::{MangaData, MangaRelationShip};
+use crate::context::ScrapeContext;
+use crate::retry::Result;
+
+pub const GROUP_NAMES_FILE_NAME: &str = "group-names.json";
+pub const AUTHOR_NAMES_FILE_NAME: &str = "author-names.json";
+/// Most entities the API returns for one request
+const IDS_PER_REQUEST: usize = 100;
+
+/// Names of scanlation groups or authors by id. Responses normally include the names,
+/// the rest are looked up with `GET /group` or `GET /author`. Names are kept across
+/// runs in the cache directory, since they hardly ever change.
+#[derive(Debug, Default)]
+pub struct NameCache {
+    path: Option<PathBuf>,
+    names: Mutex<HashMap<Uuid, String>>,
+}
+
+impl NameCache {
+    /// Load the names seen by earlier runs, starting from scratch if they can't be read
+    pub fn load(file_name: &str) -> Self {
+        let path = dirs::cache_dir().map(|dir| dir.join("mdscrape").join(file_name));
+        let names = path
+            .as_deref()
+            .and_then(|path| Self::read(path).ok())
+            .unwrap_or_default();
+        NameCache {
+            path,
+            names: Mutex::new(names),
+        }
+    }
+
+    fn read(path: &Path) -> Result<HashMap<Uuid, String>> {
+        let names: HashMap<Uuid, String> = serde_json::from_slice(&fs::read(path)?)?;
+        debug!("Loaded {} names from {:?}", names.len(), path);
+        Ok(names)
+    }
+
+    pub fn save(&self) {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return,
+        };
+        let result = (|| -> Result<()> {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, serde_json::to_vec_pretty(&*self.names.lock().unwrap())?)?;
+            Ok(())
+        })();
+        if let Err(e) = result {
+            warn!("Failed to save names to {:?}: {}", path, e);
+        }
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<String> {
+        self.names.lock().unwrap().get(&id).cloned()
+    }
+
+    /// Remember the names a response came with, and return the ids that have none yet
+    fn missing(&self, entities: impl Iterator<Item = (Uuid, Option<String>)>) -> Vec<Uuid> {
+        let mut names = self.names.lock().unwrap();
+        let mut missing = HashSet::new();
+        for (id, name) in entities {
+            match name {
+                Some(name) => {
+                    names.insert(id, name);
+                }
+                None if !names.contains_key(&id) => {
+                    missing.insert(id);
+                }
+                None => {}
+            }
+        }
+        missing.into_iter().collect()
+    }
+
+    fn insert(&self, id: Uuid, name: String) {
+        self.names.lock().unwrap().insert(id, name);
+    }
+}
+
+/// The url listing the entities of `endpoint` with these ids
+fn list_url(endpoint: &str, ids: &[Uuid]) -> Result<Url> {
+    let query: String = ids.iter().map(|id| format!("&ids[]={}", id)).collect();
+    Ok(Url::parse(&format!(
+        "https://api.mangadex.org/{}?limit={}{}",
+        endpoint, IDS_PER_REQUEST, query
+    ))?)
+}
+
+/// The `name` attribute of a related entity, if the response included it
+fn related_name(attributes: Option<&serde_json::Map<String, serde_json::Value>>) -> Option<String> {
+    attributes?.get("name")?.as_str().map(str::to_owned)
+}
+
+fn manga_attributes(relationship: &MangaRelationShip) -> Option<&serde_json::Map<String, serde_json::Value>> {
+    relationship.other.get("attributes")?.as_object()
+}
+
+fn name_attributes(name: String) -> serde_json::Map<String, serde_json::Value> {
+    let mut attributes = serde_json::Map::new();
+    attributes.insert("name".to_owned(), name.into());
+    attributes
+}
+
+/// The groups of a chapter, with their names if the response included them
+fn group_relationships(chapter: &ChapterData) -> impl Iterator<Item = (Uuid, Option<String>)> + '_ {
+    chapter
+        .relationships
+        .iter()
+        .filter(|r| r.relationship_type == "scanlation_group")
+        .filter_map(|r| Some((Uuid::parse_str(&r.id).ok()?, related_name(r.attributes.as_ref()))))
+}
+
+/// Fill in the group names the chapters are missing, so that
+/// [`ChapterData::scanlation_group_names`] has all of them
+pub async fn resolve_group_names(chapters: &mut [ChapterData], context: &ScrapeContext) -> Result<()> {
+    let cache = &context.group_names;
+    let missing = cache.missing(chapters.iter().flat_map(group_relationships));
+    for ids in missing.chunks(IDS_PER_REQUEST) {
+        debug!("Looking up the names of {} groups", ids.len());
+        let response: GroupListResponse = context.http().get_json(&list_url("group", ids)?).await?;
+        for group in response.data {
+            cache.insert(group.id, group.attributes.name);
+        }
+    }
+    for relationship in chapters
+        .iter_mut()
+        .flat_map(|chapter| chapter.relationships.iter_mut())
+        .filter(|r| r.relationship_type == "scanlation_group" && r.attributes.is_none())
+    {
+        if let Some(name) = Uuid::parse_str(&relationship.id).ok().and_then(|id| cache.get(id)) {
+            relationship.attributes = Some(name_attributes(name));
+        }
+    }
+    Ok(())
+}
+
+/// Fill in the names of the authors and artists of a title that the response is
+/// missing, so that [`MangaData::creator_names`] has all of them
+pub async fn resolve_creator_names(manga: &mut MangaData, context: &ScrapeContext) -> Result<()> {
+    let cache = &context.author_names;
+    let is_creator = |relationship_type: &str| matches!(relationship_type, "author" | "artist");
+    let missing = cache.missing(
+        manga
+            .relationships
+            .iter()
+            .filter(|r| is_creator(&r.relationship_type))
+            .filter_map(|r| Some((Uuid::parse_str(&r.id).ok()?, related_name(manga_attributes(r))))),
+    );
+    for ids in missing.chunks(IDS_PER_REQUEST) {
+        debug!("Looking up the names of {} authors and artists", ids.len());
+        let response: AuthorListResponse = context.http().get_json(&list_url("author", ids)?).await?;
+        for author in response.data {
+            cache.insert(author.id, author.attributes.name);
+        }
+    }
+    for relationship in manga
+        .relationships
+        .iter_mut()
+        .filter(|r| is_creator(&r.relationship_type) && related_name(manga_attributes(r)).is_none())
+    {
+        if let Some(name) = Uuid::parse_str(&relationship.id).ok().and_then(|id| cache.get(id)) {
+            relationship
+                .other
+                .insert("attributes".to_owned(), name_attributes(name).into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::context::Command;
+
+    #[tokio::test]
+    async fn test_resolve_from_cache() {
+        let chapter: ChapterData = serde_json::from_str(
+            r#"{
+                "id": "417d64e1-6c88-48f8-b507-ad43e9636888",
+                "type": "chapter",
+                "attributes": {"title": null, "volume": null, "chapter": "1", "pages": 3, "translatedLanguage": "en",
+                               "externalUrl": null, "publishAt": null, "readableAt": null, "createdAt": null,
+                               "updatedAt": null},
+                "relationships": [
+                    {"id": "a5ee1f4b-7a28-4e23-9e5c-94ff5e3a7e2f", "type": "scanlation_group",
+                     "attributes": {"name": "Some Scans"}},
+                    {"id": "b5ee1f4b-7a28-4e23-9e5c-94ff5e3a7e2f", "type": "scanlation_group"},
+                    {"id": "c5ee1f4b-7a28-4e23-9e5c-94ff5e3a7e2f", "type": "user"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let first = Uuid::parse_str("a5ee1f4b-7a28-4e23-9e5c-94ff5e3a7e2f").unwrap();
+        let second = Uuid::parse_str("b5ee1f4b-7a28-4e23-9e5c-94ff5e3a7e2f").unwrap();
+        let groups: Vec<_> = group_relationships(&chapter).collect();
+        assert_eq!(groups, vec![(first, Some("Some Scans".to_owned())), (second, None)]);
+
+        // Every name is known, so nothing has to be requested
+        let mut context = ScrapeContext::builder(Command::Follows).build();
+        context.group_names = NameCache::default();
+        context.group_names.insert(second, "Other Scans".to_owned());
+        let mut chapters = vec![chapter];
+        resolve_group_names(&mut chapters, &context).await.unwrap();
+        assert_eq!(chapters[0].scanlation_group_names(), vec!["Some Scans", "Other Scans"]);
+        assert_eq!(context.group_names.get(first).as_deref(), Some("Some Scans"));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_creators_from_cache() {
+        let mut manga: MangaData = serde_json::from_str(
+            r#"{
+                "id": "76ee7069-23b4-493c-bc44-34ccbf3051a8",
+                "type": "manga",
+                "attributes": {"title": {"en": "Tomo-chan wa Onna no ko!"}, "status": "completed"},
+                "relationships": [
+                    {"id": "a5ee1f4b-7a28-4e23-9e5c-94ff5e3a7e2f", "type": "author"},
+                    {"id": "a5ee1f4b-7a28-4e23-9e5c-94ff5e3a7e2f", "type": "artist"},
+                    {"id": "c5ee1f4b-7a28-4e23-9e5c-94ff5e3a7e2f", "type": "cover_art"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let mut context = ScrapeContext::builder(Command::Follows).build();
+        context.author_names = NameCache::default();
+        context.author_names.insert(
+            Uuid::parse_str("a5ee1f4b-7a28-4e23-9e5c-94ff5e3a7e2f").unwrap(),
+            "Yanagida Fumita".to_owned(),
+        );
+        resolve_creator_names(&mut manga, &context).await.unwrap();
+        assert_eq!(manga.creator_names("author"), vec!["Yanagida Fumita"]);
+        assert_eq!(manga.creator_names("artist"), vec!["Yanagida Fumita"]);
+    }
+}
@@ -0,0 +1,347 @@
+use std::path::{Component, Path, PathBuf};
+
+use crate::api::chapter::ChapterData;
+
+/// Directory naming used before templates existed
+pub const DEFAULT_PATH_TEMPLATE: &str = "md{index:05} - {id} - {title}";
+
+/// Placeholders that can be used in a path template:
+///
+/// - `{index}`: position of the chapter in the title feed among chapters of the same
+///   language, starting at 1
+/// - `{id}`: chapter uuid
+/// - `{title}`: chapter title
+/// - `{chapter}`: chapter number
+/// - `{volume}`: volume number
+/// - `{volume_dir}`: `Volume 01` and so on, or `No Volume` for chapters without one
+/// - `{manga}`: manga title
+/// - `{lang}`: language code of the translation
+/// - `{group}`: scanlation group names
+/// - `{author}`: author names of the manga
+///
+/// Any placeholder can be given a width, e.g. `{chapter:04}`, in which case the number
+/// is padded with zeros. A `/` in the template separates directories, while values
+/// substituted into it are sanitized so they always stay within one path component.
+pub const PLACEHOLDERS: &[&str] = &[
+    "index",
+    "id",
+    "title",
+    "chapter",
+    "volume",
+    "volume_dir",
+    "manga",
+    "lang",
+    "group",
+    "author",
+];
+
+#[derive(Clone, Debug, Default)]
+pub struct NamingFields {
+    pub index: usize,
+    pub id: String,
+    pub title: Option<String>,
+    pub chapter: Option<String>,
+    pub volume: Option<String>,
+    pub manga: Option<String>,
+    pub lang: String,
+    pub group: Option<String>,
+    pub author: Option<String>,
+}
+
+impl NamingFields {
+    pub fn from_chapter(index: usize, data: &ChapterData, manga: Option<&str>, authors: &[String]) -> Self {
+        let groups = data.scanlation_group_names();
+        NamingFields {
+            index,
+            id: data.id.to_string(),
+            title: data.attributes.title.clone(),
+            chapter: data.attributes.chapter.clone(),
+            volume: data.attributes.volume.clone(),
+            manga: manga.map(str::to_owned),
+            lang: data.attributes.translated_language.clone(),
+            group: if groups.is_empty() {
+                None
+            } else {
+                Some(groups.join(", "))
+            },
+            author: if authors.is_empty() {
+                None
+            } else {
+                Some(authors.join(", "))
+            },
+        }
+    }
+
+    fn get(&self, name: &str) -> String {
+        match name {
+            "index" => self.index.to_string(),
+            "id" => self.id.clone(),
+            "title" => self.title.clone().unwrap_or_default(),
+            "chapter" => self.chapter.clone().unwrap_or_default(),
+            "volume" => self.volume.clone().unwrap_or_default(),
+            "volume_dir" => match self.volume.as_deref().map(str::trim) {
+                Some(volume) if !volume.is_empty() => format!("Volume {}", pad_number(volume, 2)),
+                _ => "No Volume".to_owned(),
+            },
+            "manga" => self.manga.clone().unwrap_or_default(),
+            "lang" => self.lang.clone(),
+            "group" => self.group.clone().unwrap_or_default(),
+            "author" => self.author.clone().unwrap_or_default(),
+            _ => unreachable!("Placeholders are validated when parsing"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Placeholder { name: String, width: usize },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathTemplate {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+/// Make a value safe to use as (part of) a single path component on any platform
+pub fn sanitize_component(value: &str) -> String {
+    let sanitized: String = value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    // Windows doesn't allow trailing dots or spaces
+    let sanitized = sanitized.trim_end_matches(['.', ' ']).to_owned();
+    const RESERVED: &[&str] = &[
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1",
+        "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+    let stem = sanitized.split('.').next().unwrap_or("");
+    if RESERVED.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        format!("_{}", sanitized)
+    } else {
+        sanitized
+    }
+}
+
+/// Whether a path stays inside the directory it is joined onto, i.e. it is relative
+/// and has no `..` (or root/prefix) components
+pub fn is_contained(path: &Path) -> bool {
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// Zero pad the integer part of a number, e.g. `12.5` with width 4 becomes `0012.5`
+fn pad_number(value: &str, width: usize) -> String {
+    let integer_len = value.chars().take_while(|c| c.is_ascii_digit()).count();
+    if integer_len == 0 || integer_len >= width {
+        value.to_owned()
+    } else {
+        format!("{}{}", "0".repeat(width - integer_len), value)
+    }
+}
+
+impl PathTemplate {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_owned()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("Unclosed placeholder in path template \"{}\"", template))?
+                + start;
+            let placeholder = &rest[start + 1..end];
+            let (name, width) = match placeholder.split_once(':') {
+                Some((name, width)) => (
+                    name,
+                    width
+                        .parse()
+                        .map_err(|_| format!("Invalid width in placeholder {{{}}}", placeholder))?,
+                ),
+                None => (placeholder, 0),
+            };
+            if !PLACEHOLDERS.contains(&name) {
+                return Err(format!(
+                    "Unknown placeholder {{{}}} in path template, expected one of: {}",
+                    name,
+                    PLACEHOLDERS.join(", ")
+                ));
+            }
+            segments.push(Segment::Placeholder {
+                name: name.to_owned(),
+                width,
+            });
+            rest = &rest[end + 1..];
+        }
+        if rest.contains('}') {
+            return Err(format!("Unmatched '}}' in path template \"{}\"", template));
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_owned()));
+        }
+        let escapes = segments.iter().any(|segment| match segment {
+            Segment::Literal(literal) => literal.split('/').any(|component| component.trim() == ".."),
+            Segment::Placeholder { .. } => false,
+        });
+        if template.starts_with('/') || escapes {
+            return Err(format!(
+                "Path template \"{}\" must stay within the output directory",
+                template
+            ));
+        }
+        Ok(PathTemplate {
+            source: template.to_owned(),
+            segments,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Whether the template contains the given placeholder
+    pub fn uses(&self, placeholder: &str) -> bool {
+        self.segments
+            .iter()
+            .any(|segment| matches!(segment, Segment::Placeholder { name, .. } if name == placeholder))
+    }
+
+    /// The template with every chapter directory grouped under its volume's, for
+    /// `--group-by-volume`
+    pub fn grouped_by_volume(self) -> Self {
+        if self.uses("volume_dir") {
+            return self;
+        }
+        Self::parse(&format!("{{volume_dir}}/{}", self.source)).expect("Prefixing a valid template keeps it valid")
+    }
+
+    /// Render the template into a relative path. Components that end up empty (e.g.
+    /// `{volume}` for a chapter without one) are dropped, as are `.` and `..`, so the
+    /// result can never point outside the directory it is joined onto.
+    pub fn render(&self, fields: &NamingFields) -> PathBuf {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => rendered.push_str(literal),
+                Segment::Placeholder { name, width } => {
+                    let value = pad_number(&fields.get(name), *width);
+                    rendered.push_str(&sanitize_component(&value));
+                }
+            }
+        }
+        rendered
+            .split('/')
+            .map(|component| component.trim())
+            .map(sanitize_component)
+            .filter(|component| !matches!(component.as_str(), "" | "." | ".."))
+            .collect()
+    }
+}
+
+impl Default for PathTemplate {
+    fn default() -> Self {
+        Self::parse(DEFAULT_PATH_TEMPLATE).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fields() -> NamingFields {
+        NamingFields {
+            index: 3,
+            id: "417d64e1-6c88-48f8-b507-ad43e9636888".to_owned(),
+            title: Some("AC/DC: Live?".to_owned()),
+            chapter: Some("12.5".to_owned()),
+            manga: Some("Tomo-chan".to_owned()),
+            lang: "en".to_owned(),
+            author: Some("Yanagida Fumita".to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_author_template() {
+        let template = PathTemplate::parse("{author}/{manga}/{chapter}").unwrap();
+        assert_eq!(
+            template.render(&fields()),
+            PathBuf::from("Yanagida Fumita").join("Tomo-chan").join("12.5")
+        );
+    }
+
+    #[test]
+    fn test_default_template() {
+        assert_eq!(
+            PathTemplate::default().render(&fields()),
+            PathBuf::from("md00003 - 417d64e1-6c88-48f8-b507-ad43e9636888 - AC_DC_ Live_")
+        );
+    }
+
+    #[test]
+    fn test_nested_template() {
+        let template = PathTemplate::parse("{manga}/{volume}/{chapter:04} - {title}").unwrap();
+        assert_eq!(
+            template.render(&fields()),
+            PathBuf::from("Tomo-chan").join("0012.5 - AC_DC_ Live_")
+        );
+    }
+
+    #[test]
+    fn test_template_errors() {
+        assert!(PathTemplate::default().uses("title"));
+        assert!(!PathTemplate::default().uses("lang"));
+        assert!(PathTemplate::parse("{chapter").is_err());
+        assert!(PathTemplate::parse("{uploader}").is_err());
+        assert!(PathTemplate::parse("{chapter:x}").is_err());
+        assert!(PathTemplate::parse("chapter}").is_err());
+    }
+
+    #[test]
+    fn test_grouped_by_volume() {
+        let template = PathTemplate::parse("{chapter:04}").unwrap().grouped_by_volume();
+        assert_eq!(template.as_str(), "{volume_dir}/{chapter:04}");
+        assert_eq!(template.render(&fields()), PathBuf::from("No Volume").join("0012.5"));
+        let fields = NamingFields {
+            volume: Some("3".to_owned()),
+            ..fields()
+        };
+        assert_eq!(template.render(&fields), PathBuf::from("Volume 03").join("0012.5"));
+        // Grouping twice doesn't nest the volumes
+        assert_eq!(template.clone().grouped_by_volume(), template);
+    }
+
+    #[test]
+    fn test_paths_stay_contained() {
+        let hostile = NamingFields {
+            title: Some("../../../etc".to_owned()),
+            chapter: Some("..".to_owned()),
+            volume: Some("/root".to_owned()),
+            ..fields()
+        };
+        let template = PathTemplate::parse("{volume}/{chapter}/{title}").unwrap();
+        let rendered = template.render(&hostile);
+        assert!(is_contained(&rendered));
+        assert_eq!(rendered, PathBuf::from("_root").join(".._.._.._etc"));
+
+        assert!(PathTemplate::parse("../{title}").is_err());
+        assert!(PathTemplate::parse("{manga}/ .. /{title}").is_err());
+        assert!(PathTemplate::parse("/srv/{title}").is_err());
+        assert!(!is_contained(Path::new("/etc")));
+        assert!(!is_contained(Path::new("a/../../b")));
+    }
+
+    #[test]
+    fn test_sanitize_component() {
+        assert_eq!(sanitize_component("a\0b\nc"), "a_b_c");
+        assert_eq!(sanitize_component("con.txt"), "_con.txt");
+        assert_eq!(sanitize_component("trailing. "), "trailing");
+    }
+}
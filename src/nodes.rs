@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::retry::Result;
+
+const NODE_SCORES_FILE_NAME: &str = "node-scores.json";
+/// Weight of the newest sample in the rolling averages
+const SMOOTHING: f64 = 0.2;
+/// Don't judge a node until we've seen this many downloads from it
+const MIN_SAMPLES: u64 = 5;
+/// Nodes that haven't been seen for this long are forgotten, they may well have moved
+/// or been fixed since
+const MAX_AGE_DAYS: i64 = 7;
+
+/// Nodes are identified by their origin, e.g. `https://abc.mangadex.network:443`
+pub fn node_key(url: &str) -> String {
+    url::Url::parse(url)
+        .map(|url| url.origin().ascii_serialization())
+        .unwrap_or_else(|_| url.to_owned())
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct NodeScore {
+    /// Rolling average time per image, in milliseconds
+    pub latency_ms: f64,
+    /// Rolling fraction of failed downloads
+    pub error_rate: f64,
+    pub samples: u64,
+    pub last_seen: DateTime<Utc>,
+}
+
+impl NodeScore {
+    /// Expected cost of fetching an image from this node, lower is better. Failures
+    /// are weighted heavily since they also cost a retry.
+    pub fn cost(&self) -> f64 {
+        self.latency_ms * (1.0 + 4.0 * self.error_rate)
+    }
+}
+
+/// Rolling latency and error scores for MD@Home nodes, keyed by base url. Scores are
+/// kept across runs in the cache directory, so that nodes that were slow before can
+/// be avoided from the start.
+#[derive(Debug, Default)]
+pub struct NodeScores {
+    path: Option<PathBuf>,
+    scores: Mutex<HashMap<String, NodeScore>>,
+}
+
+impl NodeScores {
+    fn default_path() -> Option<PathBuf> {
+        dirs::cache_dir().map(|dir| dir.join("mdscrape").join(NODE_SCORES_FILE_NAME))
+    }
+
+    /// Load persisted scores, starting from scratch if there are none or they can't be
+    /// read. Scores are only an optimisation, so problems are never fatal.
+    pub fn load() -> Self {
+        let path = Self::default_path();
+        let scores = path
+            .as_deref()
+            .and_then(|path| Self::read(path).ok())
+            .unwrap_or_default();
+        NodeScores {
+            path,
+            scores: Mutex::new(scores),
+        }
+    }
+
+    fn read(path: &Path) -> Result<HashMap<String, NodeScore>> {
+        let mut scores: HashMap<String, NodeScore> = serde_json::from_slice(&fs::read(path)?)?;
+        let cutoff = Utc::now() - chrono::Duration::days(MAX_AGE_DAYS);
+        scores.retain(|_, score| score.last_seen > cutoff);
+        debug!("Loaded scores for {} nodes from {:?}", scores.len(), path);
+        Ok(scores)
+    }
+
+    pub fn save(&self) {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return,
+        };
+        let result = (|| -> Result<()> {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, serde_json::to_vec_pretty(&*self.scores.lock().unwrap())?)?;
+            Ok(())
+        })();
+        if let Err(e) = result {
+            warn!("Failed to save node scores to {:?}: {}", path, e);
+        }
+    }
+
+    pub fn record(&self, node: &str, elapsed: Duration, success: bool) {
+        let latency_ms = elapsed.as_secs_f64() * 1000.0;
+        let error = if success { 0.0 } else { 1.0 };
+        let mut scores = self.scores.lock().unwrap();
+        let score = scores.entry(node.to_owned()).or_insert_with(|| NodeScore {
+            latency_ms,
+            error_rate: error,
+            samples: 0,
+            last_seen: Utc::now(),
+        });
+        // Failed requests often time out, which says nothing useful about latency
+        if success {
+            score.latency_ms += SMOOTHING * (latency_ms - score.latency_ms);
+        }
+        score.error_rate += SMOOTHING * (error - score.error_rate);
+        score.samples += 1;
+        score.last_seen = Utc::now();
+    }
+
+    pub fn get(&self, node: &str) -> Option<NodeScore> {
+        self.scores.lock().unwrap().get(node).cloned()
+    }
+
+    /// Whether a node has been noticeably worse than the typical node, in which case
+    /// it's worth asking for a different one
+    pub fn is_poor(&self, node: &str) -> bool {
+        let scores = self.scores.lock().unwrap();
+        let score = match scores.get(node) {
+            Some(score) if score.samples >= MIN_SAMPLES => score,
+            _ => return false,
+        };
+        if score.error_rate > 0.5 {
+            return true;
+        }
+        let mut costs: Vec<f64> = scores
+            .values()
+            .filter(|score| score.samples >= MIN_SAMPLES)
+            .map(NodeScore::cost)
+            .collect();
+        costs.sort_by(f64::total_cmp);
+        let median = costs[(costs.len() - 1) / 2];
+        score.cost() > 2.0 * median
+    }
+
+    /// Pick the node with the best history, unknown nodes are given the benefit of the
+    /// doubt over ones that are known to be poor
+    pub fn prefer<'a>(&self, first: &'a str, second: &'a str) -> &'a str {
+        match (self.get(first), self.get(second)) {
+            (Some(a), Some(b)) if a.samples >= MIN_SAMPLES && b.samples >= MIN_SAMPLES => {
+                if b.cost() < a.cost() {
+                    second
+                } else {
+                    first
+                }
+            }
+            _ if self.is_poor(first) && !self.is_poor(second) => second,
+            _ => first,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn scores_with(nodes: &[(&str, u64, bool)]) -> NodeScores {
+        let scores = NodeScores::default();
+        for &(node, latency_ms, success) in nodes {
+            for _ in 0..MIN_SAMPLES {
+                scores.record(node, Duration::from_millis(latency_ms), success);
+            }
+        }
+        scores
+    }
+
+    #[test]
+    fn test_poor_nodes() {
+        let scores = scores_with(&[
+            ("https://a.mangadex.network", 100, true),
+            ("https://b.mangadex.network", 120, true),
+            ("https://c.mangadex.network", 900, true),
+            ("https://d.mangadex.network", 100, false),
+        ]);
+        assert!(!scores.is_poor("https://a.mangadex.network"));
+        assert!(scores.is_poor("https://c.mangadex.network"));
+        assert!(scores.is_poor("https://d.mangadex.network"));
+        assert!(!scores.is_poor("https://unknown.mangadex.network"));
+
+        assert_eq!(
+            scores.prefer("https://b.mangadex.network", "https://a.mangadex.network"),
+            "https://a.mangadex.network"
+        );
+        assert_eq!(
+            scores.prefer("https://c.mangadex.network", "https://unknown.mangadex.network"),
+            "https://unknown.mangadex.network"
+        );
+    }
+
+    #[test]
+    fn test_node_key() {
+        assert_eq!(
+            node_key("https://abc.mangadex.network:44300/data/hash/1.png"),
+            "https://abc.mangadex.network:44300"
+        );
+        assert_eq!(node_key("https://abc.mangadex.network"), "https://abc.mangadex.network");
+    }
+
+    #[test]
+    fn test_rolling_average() {
+        let scores = NodeScores::default();
+        scores.record("node", Duration::from_millis(100), true);
+        scores.record("node", Duration::from_millis(600), true);
+        scores.record("node", Duration::from_secs(30), false);
+        let score = scores.get("node").unwrap();
+        assert_eq!(score.samples, 3);
+        assert!((score.latency_ms - 200.0).abs() < 1e-9);
+        assert!((score.error_rate - 0.2).abs() < 1e-9);
+    }
+}
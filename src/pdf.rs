@@ -0,0 +1,333 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+
+use crate::cbz;
+use crate::retry::{DownloadError, Result};
+use crate::validate::{self, ImageFormat};
+
+/// Where `--export-format pdf` puts the PDF of the chapter in dir, next to the directory
+pub fn pdf_path(dir: &Path) -> PathBuf {
+    cbz::sibling_path(dir, "pdf")
+}
+
+/// A page as a PDF image. JPEGs and most PNGs can be embedded as they are, without
+/// decoding them.
+struct PdfImage {
+    width: u32,
+    height: u32,
+    /// The image dictionary entries besides its size and length
+    entries: String,
+    data: Vec<u8>,
+}
+
+impl PdfImage {
+    fn read(path: &Path) -> Result<Self> {
+        let data = fs::read(path)?;
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let unsupported = |why: &str| DownloadError::ConversionFailed(format!("{} to PDF: {}", file_name, why));
+        match validate::detect_format(&data) {
+            Some(ImageFormat::Jpeg) => {
+                let (width, height) = validate::image_dimensions(&data, ImageFormat::Jpeg)
+                    .ok_or_else(|| unsupported("not a valid JPEG"))?;
+                let colour_space = match validate::jpeg_components(&data) {
+                    Some(1) => "/DeviceGray",
+                    Some(3) => "/DeviceRGB",
+                    // Adobe's CMYK JPEGs are stored inverted
+                    Some(4) => "/DeviceCMYK /Decode [1 0 1 0 1 0 1 0]",
+                    _ => return Err(unsupported("unknown JPEG colour components")),
+                };
+                Ok(PdfImage {
+                    width,
+                    height,
+                    entries: format!("/ColorSpace {} /BitsPerComponent 8 /Filter /DCTDecode", colour_space),
+                    data,
+                })
+            }
+            Some(ImageFormat::Png) => Self::from_png(&data).map_err(unsupported),
+            Some(format) => Err(unsupported(&format!(
+                "{} pages can't be put into a PDF, convert them with --convert jpeg first",
+                format
+            ))),
+            None => Err(unsupported("not an image")),
+        }
+    }
+
+    /// PDF's Flate filter with PNG predictors reads the compressed data of a PNG as is,
+    /// as long as it has no transparency and isn't interlaced
+    fn from_png(data: &[u8]) -> std::result::Result<Self, &'static str> {
+        let mut header = None;
+        let mut palette = None;
+        let mut compressed = Vec::new();
+        let mut i = 8;
+        while let Some(length) = data.get(i..i + 4) {
+            let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+            let kind = data.get(i + 4..i + 8).ok_or("truncated PNG")?;
+            // A corrupt length may point anywhere
+            let end = (i + 8)
+                .checked_add(length)
+                .filter(|end| *end <= data.len())
+                .ok_or("truncated PNG")?;
+            let chunk = &data[i + 8..end];
+            match kind {
+                b"IHDR" if chunk.len() >= 13 => header = Some(chunk),
+                b"PLTE" => palette = Some(chunk),
+                b"IDAT" => compressed.extend_from_slice(chunk),
+                b"IEND" => break,
+                _ => {}
+            }
+            // Length, type, data and CRC
+            i = end + 4;
+        }
+        let header = header.ok_or("not a valid PNG")?;
+        let width = u32::from_be_bytes(header[0..4].try_into().unwrap());
+        let height = u32::from_be_bytes(header[4..8].try_into().unwrap());
+        let (bit_depth, colour_type, interlace) = (header[8], header[9], header[12]);
+        if interlace != 0 {
+            return Err("interlaced PNGs can't be put into a PDF, convert them with --convert jpeg first");
+        }
+        let (colours, colour_space) = match colour_type {
+            0 => (1, "/DeviceGray".to_owned()),
+            2 => (3, "/DeviceRGB".to_owned()),
+            3 => {
+                let palette = palette
+                    .filter(|palette| palette.len() >= 3 && palette.len() % 3 == 0)
+                    .ok_or("PNG without a valid palette")?;
+                let hex: String = palette.iter().map(|byte| format!("{:02x}", byte)).collect();
+                (1, format!("[/Indexed /DeviceRGB {} <{}>]", palette.len() / 3 - 1, hex))
+            }
+            _ => return Err("PNGs with transparency can't be put into a PDF, convert them with --convert jpeg first"),
+        };
+        Ok(PdfImage {
+            width,
+            height,
+            entries: format!(
+                "/ColorSpace {} /BitsPerComponent {} /Filter /FlateDecode \
+                 /DecodeParms << /Predictor 15 /Colors {} /BitsPerComponent {} /Columns {} >>",
+                colour_space, bit_depth, colours, bit_depth, width
+            ),
+            data: compressed,
+        })
+    }
+}
+
+/// A PDF text string, as UTF-16 if it isn't plain ASCII
+fn pdf_string(value: &str) -> String {
+    if value.chars().all(|c| c.is_ascii() && !c.is_ascii_control()) {
+        let escaped = value.replace('\\', "\\\\").replace('(', "\\(").replace(')', "\\)");
+        return format!("({})", escaped);
+    }
+    let hex: String = value.encode_utf16().map(|unit| format!("{:04x}", unit)).collect();
+    format!("<feff{}>", hex)
+}
+
+/// Lay out the pages as a PDF with a page per image, each page the size of its image
+fn build_pdf(title: &str, images: &[PdfImage]) -> Vec<u8> {
+    let mut pdf = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+    let mut offsets = Vec::new();
+    let mut add_object = |pdf: &mut Vec<u8>, dictionary: String, stream: Option<&[u8]>| {
+        offsets.push(pdf.len());
+        write!(pdf, "{} 0 obj\n{}\n", offsets.len(), dictionary).unwrap();
+        if let Some(stream) = stream {
+            pdf.extend_from_slice(b"stream\n");
+            pdf.extend_from_slice(stream);
+            pdf.extend_from_slice(b"\nendstream\n");
+        }
+        pdf.extend_from_slice(b"endobj\n");
+    };
+    // The catalog, the page tree and the document info come first, then an image, its
+    // drawing instructions and its page for every page
+    let page_ids: Vec<String> = (0..images.len()).map(|i| format!("{} 0 R", 6 + 3 * i)).collect();
+    add_object(&mut pdf, "<< /Type /Catalog /Pages 2 0 R >>".to_owned(), None);
+    add_object(
+        &mut pdf,
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            page_ids.join(" "),
+            images.len()
+        ),
+        None,
+    );
+    add_object(
+        &mut pdf,
+        format!("<< /Title {} /Producer (mdscrape) >>", pdf_string(title)),
+        None,
+    );
+    for (i, image) in images.iter().enumerate() {
+        let image_id = 4 + 3 * i;
+        add_object(
+            &mut pdf,
+            format!(
+                "<< /Type /XObject /Subtype /Image /Width {} /Height {} {} /Length {} >>",
+                image.width,
+                image.height,
+                image.entries,
+                image.data.len()
+            ),
+            Some(&image.data),
+        );
+        let content = format!("q {} 0 0 {} 0 0 cm /Page Do Q", image.width, image.height);
+        add_object(
+            &mut pdf,
+            format!("<< /Length {} >>", content.len()),
+            Some(content.as_bytes()),
+        );
+        add_object(
+            &mut pdf,
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources << /XObject << /Page {} 0 R >> >> \
+                 /Contents {} 0 R >>",
+                image.width,
+                image.height,
+                image_id,
+                image_id + 1
+            ),
+            None,
+        );
+    }
+    let xref = pdf.len();
+    write!(pdf, "xref\n0 {}\n0000000000 65535 f \n", offsets.len() + 1).unwrap();
+    for offset in &offsets {
+        writeln!(pdf, "{:010} 00000 n ", offset).unwrap();
+    }
+    write!(
+        pdf,
+        "trailer\n<< /Size {} /Root 1 0 R /Info 3 0 R >>\nstartxref\n{}\n%%EOF\n",
+        offsets.len() + 1,
+        xref
+    )
+    .unwrap();
+    pdf
+}
+
+/// Package the pages of the chapter in dir as a PDF next to it, with a page per image.
+/// Returns the path of the PDF.
+pub fn write_chapter_pdf(dir: &Path, title: &str) -> Result<PathBuf> {
+    let path = pdf_path(dir);
+    let mut pages = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let file_name = entry?.file_name().to_string_lossy().into_owned();
+        if cbz::is_page_file(&file_name) {
+            pages.push(file_name);
+        }
+    }
+    pages.sort();
+    debug!("Packaging {} pages of {:?} into {:?}", pages.len(), dir, path);
+    let images = pages
+        .iter()
+        .map(|page| PdfImage::read(&dir.join(page)))
+        .collect::<Result<Vec<_>>>()?;
+    // Like archives, written next to it first so that it is never left truncated
+    let part_path = cbz::sibling_path(&path, "part");
+    fs::write(&part_path, build_pdf(title, &images))?;
+    fs::rename(&part_path, &path)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use uuid::Uuid;
+
+    fn jpeg(width: u16, height: u16) -> Vec<u8> {
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xc0, 0, 17, 8];
+        jpeg.extend_from_slice(&height.to_be_bytes());
+        jpeg.extend_from_slice(&width.to_be_bytes());
+        jpeg.extend_from_slice(&[3, 1, 0x22, 0, 2, 0x11, 1, 3, 0x11, 1, 0xff, 0xd9]);
+        jpeg
+    }
+
+    fn png_chunk(png: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+        png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        png.extend_from_slice(kind);
+        png.extend_from_slice(data);
+        // Readers of the PDF don't check the CRC, and neither do we
+        png.extend_from_slice(&[0; 4]);
+    }
+
+    fn png(colour_type: u8) -> Vec<u8> {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        let mut header = Vec::new();
+        header.extend_from_slice(&4u32.to_be_bytes());
+        header.extend_from_slice(&2u32.to_be_bytes());
+        header.extend_from_slice(&[8, colour_type, 0, 0, 0]);
+        png_chunk(&mut png, b"IHDR", &header);
+        png_chunk(&mut png, b"IDAT", b"first");
+        png_chunk(&mut png, b"IDAT", b"second");
+        png_chunk(&mut png, b"IEND", b"");
+        png
+    }
+
+    #[test]
+    fn test_chapter_pdf() {
+        let dir = std::env::temp_dir().join(format!("mdscrape-test-{}", Uuid::from_u128(rand::random())));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("0001.jpg"), jpeg(800, 1200)).unwrap();
+        fs::write(dir.join("0002.png"), png(2)).unwrap();
+        fs::write(dir.join("ComicInfo.xml"), "<ComicInfo/>").unwrap();
+        let path = write_chapter_pdf(&dir, "Tomo-chan (Ch. 1) – Tomo").unwrap();
+        assert_eq!(path, pdf_path(&dir));
+
+        let pdf = fs::read(&path).unwrap();
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(text.starts_with("%PDF-1.4"));
+        assert!(text.contains("/Count 2"));
+        assert!(text.contains("/MediaBox [0 0 800 1200]"));
+        assert!(text.contains("/Filter /DCTDecode"));
+        assert!(text.contains("/Columns 4 >>"));
+        // The IDAT chunks are embedded back to back
+        assert!(text.contains("stream\nfirstsecond\nendstream"));
+        assert!(text.contains("/Title <feff"));
+        // Every object is where the cross reference table says
+        let xref = text.rfind("xref\n").unwrap();
+        for (id, line) in text[xref..].lines().skip(3).take(9).enumerate() {
+            let offset: usize = line[..10].parse().unwrap();
+            assert!(text[offset..].starts_with(&format!("{} 0 obj", id + 1)), "{}", line);
+        }
+
+        fs::write(dir.join("0003.png"), png(6)).unwrap();
+        assert!(matches!(
+            write_chapter_pdf(&dir, "Tomo-chan"),
+            Err(DownloadError::ConversionFailed(_))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_malformed_png() {
+        let mut header = Vec::new();
+        header.extend_from_slice(&4u32.to_be_bytes());
+        header.extend_from_slice(&2u32.to_be_bytes());
+        header.extend_from_slice(&[8, 3, 0, 0, 0]);
+        let mut indexed = b"\x89PNG\r\n\x1a\n".to_vec();
+        png_chunk(&mut indexed, b"IHDR", &header);
+
+        let mut empty_palette = indexed.clone();
+        png_chunk(&mut empty_palette, b"PLTE", b"");
+        png_chunk(&mut empty_palette, b"IEND", b"");
+        assert!(PdfImage::from_png(&empty_palette).is_err());
+        let mut palette = indexed.clone();
+        png_chunk(&mut palette, b"PLTE", &[0, 0, 0, 255, 255, 255]);
+        png_chunk(&mut palette, b"IEND", b"");
+        assert!(PdfImage::from_png(&palette).is_ok());
+
+        // A chunk longer than what is left of the file, or than memory could hold
+        let mut truncated = indexed.clone();
+        png_chunk(&mut truncated, b"IDAT", b"data");
+        truncated.truncate(truncated.len() - 6);
+        assert!(PdfImage::from_png(&truncated).is_err());
+        let mut overflowing = indexed;
+        overflowing.extend_from_slice(&u32::MAX.to_be_bytes());
+        overflowing.extend_from_slice(b"IDAT");
+        assert!(PdfImage::from_png(&overflowing).is_err());
+    }
+
+    #[test]
+    fn test_pdf_string() {
+        assert_eq!(pdf_string("Ch. 1 (end)"), "(Ch. 1 \\(end\\))");
+        assert_eq!(pdf_string("é"), "<feff00e9>");
+    }
+}
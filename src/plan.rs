@@ -0,0 +1,277 @@
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::context::ScrapeContext;
+use crate::retry::Result;
+use crate::summary::RunStats;
+use crate::title::TitleData;
+use crate::units::{format_duration, format_size};
+
+const HISTORY_FILE_NAME: &str = "download-history.json";
+/// Rough size of a full quality page, at-home doesn't tell us sizes before fetching
+pub const ESTIMATED_PAGE_BYTES: u64 = 400 * 1024;
+/// Rough time per page until we've downloaded something, rate limits included
+const ESTIMATED_PAGE_SECS: f64 = 1.0;
+/// Once the history covers this many pages older runs start to fade out, so that
+/// estimates follow changes in connection speed or image sizes
+const MAX_HISTORY_PAGES: u64 = 20_000;
+
+/// Totals over previous runs, kept in the cache directory and used to estimate how
+/// long a download will take and how much it will fetch
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct DownloadHistory {
+    pub pages: u64,
+    pub bytes: u64,
+    pub seconds: f64,
+}
+
+impl DownloadHistory {
+    fn default_path() -> Option<PathBuf> {
+        dirs::cache_dir().map(|dir| dir.join("mdscrape").join(HISTORY_FILE_NAME))
+    }
+
+    /// Load the history, starting from scratch if there is none or it can't be read.
+    /// It only feeds estimates, so problems are never fatal.
+    pub fn load() -> Self {
+        Self::default_path()
+            .and_then(|path| Self::read(&path).ok())
+            .unwrap_or_default()
+    }
+
+    fn read(path: &Path) -> Result<Self> {
+        let history = serde_json::from_slice(&fs::read(path)?)?;
+        debug!("Loaded download history from {:?}: {:?}", path, history);
+        Ok(history)
+    }
+
+    pub fn save(&self) {
+        let path = match Self::default_path() {
+            Some(path) => path,
+            None => return,
+        };
+        let result = (|| -> Result<()> {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, serde_json::to_vec_pretty(self)?)?;
+            Ok(())
+        })();
+        if let Err(e) = result {
+            warn!("Failed to save download history to {:?}: {}", path, e);
+        }
+    }
+
+    /// Add a run that downloaded the pages counted in stats in the given time
+    pub fn record(&mut self, stats: &RunStats, elapsed: Duration) {
+        if stats.pages() == 0 {
+            return;
+        }
+        if self.pages > MAX_HISTORY_PAGES {
+            let scale = MAX_HISTORY_PAGES as f64 / self.pages as f64;
+            self.pages = MAX_HISTORY_PAGES;
+            self.bytes = (self.bytes as f64 * scale) as u64;
+            self.seconds *= scale;
+        }
+        self.pages += stats.pages() as u64;
+        self.bytes += stats.bytes();
+        self.seconds += elapsed.as_secs_f64();
+    }
+
+    pub fn bytes_per_page(&self) -> u64 {
+        self.bytes.checked_div(self.pages).unwrap_or(ESTIMATED_PAGE_BYTES)
+    }
+
+    pub fn seconds_per_page(&self) -> f64 {
+        if self.pages == 0 {
+            ESTIMATED_PAGE_SECS
+        } else {
+            self.seconds / self.pages as f64
+        }
+    }
+}
+
+/// How much work a download is, without downloading anything. This is what every
+/// frontend should show, so that they all agree.
+#[derive(Clone, Debug, Default, Serialize, PartialEq)]
+pub struct WorkEstimate {
+    /// Chapters that still need pages
+    pub chapters: usize,
+    /// Chapters that are already complete on disk and would be skipped
+    pub complete_chapters: usize,
+    pub pages: usize,
+    pub bytes: u64,
+    pub seconds: f64,
+}
+
+impl WorkEstimate {
+    pub fn new(chapters: usize, complete_chapters: usize, pages: usize, history: &DownloadHistory) -> Self {
+        WorkEstimate {
+            chapters,
+            complete_chapters,
+            pages,
+            bytes: pages as u64 * history.bytes_per_page(),
+            seconds: pages as f64 * history.seconds_per_page(),
+        }
+    }
+}
+
+impl Display for WorkEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} pages in {} chapters, about {} in {}",
+            self.pages,
+            self.chapters,
+            format_size(self.bytes),
+            format_duration(Duration::from_secs_f64(self.seconds))
+        )?;
+        if self.complete_chapters > 0 {
+            write!(f, " ({} chapters already complete)", self.complete_chapters)?;
+        }
+        Ok(())
+    }
+}
+
+/// The work needed to bring a title in a directory up to date
+#[derive(Clone, Debug, Serialize)]
+pub struct TitleEstimate {
+    pub id: Uuid,
+    pub title: Option<String>,
+    #[serde(flatten)]
+    pub work: WorkEstimate,
+}
+
+impl Display for TitleEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}): {}",
+            self.title.as_deref().unwrap_or("Untitled"),
+            self.id,
+            self.work
+        )
+    }
+}
+
+/// Estimate the work for downloading a title into destination with the given
+/// languages, using only the title feed (or `--from-snapshot`). Chapters are counted
+/// by the pages the API reports, so unlike a dry run this doesn't ask at-home about
+/// every chapter.
+pub async fn estimate_title(
+    title_id: Uuid,
+    lang_codes: &[String],
+    destination: &Path,
+    context: &ScrapeContext,
+) -> Result<TitleEstimate> {
+    let title = TitleData::resolve(title_id, lang_codes, context).await?;
+    Ok(TitleEstimate {
+        id: title_id,
+        title: title.display_title().map(str::to_owned),
+        work: title.estimate_work(&destination, context)?,
+    })
+}
+
+/// What downloading a chapter would do
+#[derive(Clone, Debug, Serialize)]
+pub struct ChapterPlan {
+    pub id: Uuid,
+    pub name: String,
+    /// Number of pages that aren't on disk yet
+    pub pages: usize,
+    /// Every file that would be created, pages first
+    pub files: Vec<PathBuf>,
+}
+
+/// What a download would do, worked out without fetching any images
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DownloadPlan {
+    pub chapters: Vec<ChapterPlan>,
+    /// Chapters that are already complete on disk and would be skipped
+    pub complete_chapters: usize,
+}
+
+impl DownloadPlan {
+    pub fn add_chapter(&mut self, chapter: ChapterPlan) {
+        self.chapters.push(chapter);
+    }
+
+    pub fn add_complete_chapter(&mut self) {
+        self.complete_chapters += 1;
+    }
+
+    pub fn estimate(&self, history: &DownloadHistory) -> WorkEstimate {
+        let pages = self.chapters.iter().map(|chapter| chapter.pages).sum();
+        WorkEstimate::new(self.chapters.len(), self.complete_chapters, pages, history)
+    }
+}
+
+/// Lists every file, the totals are left to the estimate
+impl Display for DownloadPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for chapter in &self.chapters {
+            writeln!(f, "{} ({})", chapter.name, chapter.id)?;
+            for file in &chapter.files {
+                writeln!(f, "  {}", file.display())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_plan_totals() {
+        let mut plan = DownloadPlan::default();
+        plan.add_complete_chapter();
+        plan.add_chapter(ChapterPlan {
+            id: Uuid::from_u128(1),
+            name: "Ch. 1".to_owned(),
+            pages: 2,
+            files: vec![PathBuf::from("ch1/0001.png"), PathBuf::from("ch1/0002.png")],
+        });
+        plan.add_chapter(ChapterPlan {
+            id: Uuid::from_u128(2),
+            name: "Ch. 2".to_owned(),
+            pages: 3,
+            files: Vec::new(),
+        });
+        assert!(plan.to_string().contains("  ch1/0002.png\n"));
+        let estimate = plan.estimate(&DownloadHistory::default());
+        assert_eq!(estimate.pages, 5);
+        assert_eq!(estimate.bytes, 5 * ESTIMATED_PAGE_BYTES);
+        assert_eq!(
+            estimate.to_string(),
+            "5 pages in 2 chapters, about 2.0 MiB in 5s (1 chapters already complete)"
+        );
+    }
+
+    #[test]
+    fn test_history_estimates() {
+        let stats = RunStats::default();
+        for _ in 0..10 {
+            stats.add_page(200_000);
+        }
+        let mut history = DownloadHistory::default();
+        history.record(&stats, Duration::from_secs(20));
+        assert_eq!(history.bytes_per_page(), 200_000);
+        assert_eq!(history.seconds_per_page(), 2.0);
+        assert_eq!(WorkEstimate::new(1, 0, 30, &history).seconds, 60.0);
+
+        // Old runs fade out once the history is large
+        history.pages = 2 * MAX_HISTORY_PAGES;
+        history.bytes = 2 * MAX_HISTORY_PAGES * 100_000;
+        history.seconds = 2.0 * MAX_HISTORY_PAGES as f64;
+        history.record(&stats, Duration::from_secs(20));
+        assert_eq!(history.pages, MAX_HISTORY_PAGES + 10);
+        assert_eq!(history.bytes, MAX_HISTORY_PAGES * 100_000 + 2_000_000);
+    }
+}
@@ -0,0 +1,277 @@
+use std::sync::{Arc, Mutex};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::api::chapter::ChapterData;
+use crate::retry::Result;
+use crate::stats::SpeedEstimator;
+use crate::summary::RunSummary;
+use crate::units::{format_duration, format_size};
+
+/// How progress is reported, set with `--progress-format`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProgressFormat {
+    /// Progress bars on the terminal
+    #[default]
+    Bars,
+    /// A json object per line on stdout, for other programs to follow
+    Json,
+}
+
+impl std::str::FromStr for ProgressFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim() {
+            "bars" => Ok(ProgressFormat::Bars),
+            "json" => Ok(ProgressFormat::Json),
+            v => Err(format!("Unknown progress format \"{}\", expected bars or json", v)),
+        }
+    }
+}
+
+/// What `--progress-format json` writes, one event per line
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent<'a> {
+    ChapterStarted {
+        chapter_id: Uuid,
+        name: &'a str,
+        /// Pages to download, not counting those already on disk
+        pages: usize,
+        already_done: usize,
+    },
+    PageDone {
+        chapter_id: Uuid,
+        /// Numbered from 1
+        page: usize,
+        bytes: u64,
+    },
+    /// A request is being tried again
+    Retry { origin: &'a str, attempt: usize },
+    /// A server asked us to slow down, requests to it wait for a while
+    RateLimited { origin: &'a str },
+    ChapterDone {
+        chapter_id: Uuid,
+        /// Why the chapter failed, if it did
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// The run summary, the last event of a run
+    Summary(&'a RunSummary),
+}
+
+/// How far a chapter moves the bar of its title: its page count as the feed reports it,
+/// so that a three page extra doesn't count as much as a sixty page chapter. Chapters
+/// the feed has no pages for still count for one.
+pub fn chapter_weight(chapter: &ChapterData) -> u64 {
+    chapter.attributes.pages.max(1) as u64
+}
+
+/// The progress display. A line of totals stays at the top, with the pages downloaded
+/// out of those known so far and their size. Below it every title being downloaded gets
+/// a bar counting the pages of its chapters as they finish, and every chapter in flight
+/// one counting its own pages. Chapter bars are cleared as soon as the chapter is done,
+/// so the display only grows with the number of concurrent downloads, not with their
+/// length. A footer line at the bottom shows how fast the run is going and when it will
+/// be done.
+#[derive(Clone, Debug)]
+pub struct ProgressDisplay {
+    multi: Arc<MultiProgress>,
+    totals: ProgressBar,
+    footer: ProgressBar,
+    /// How many bars there are above the footer. Cleared bars keep their place.
+    bars: Arc<Mutex<usize>>,
+    speed: Arc<SpeedEstimator>,
+    json: bool,
+}
+
+impl ProgressDisplay {
+    /// Bars are only drawn when `visible` and the format asks for them
+    pub fn new(visible: bool, format: ProgressFormat) -> Self {
+        let json = format == ProgressFormat::Json;
+        let multi = if visible && !json {
+            MultiProgress::new()
+        } else {
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+        };
+        let totals = multi.add(ProgressBar::new(0));
+        totals
+            .set_style(ProgressStyle::default_bar().template("[MDScrape] {pos}/{len} pages, {msg}, {elapsed_precise}"));
+        totals.set_message("0 B");
+        let footer = multi.add(ProgressBar::new(0));
+        footer.set_style(ProgressStyle::default_bar().template("{msg}"));
+        footer.set_message("Waiting for the first page");
+        ProgressDisplay {
+            multi: Arc::new(multi),
+            totals,
+            footer,
+            bars: Arc::new(Mutex::new(1)),
+            speed: Arc::new(SpeedEstimator::default()),
+            json,
+        }
+    }
+
+    /// Add a bar below the others, above the footer
+    fn add_bar(&self, bar: ProgressBar) -> ProgressBar {
+        let mut bars = self.bars.lock().unwrap();
+        let bar = self.multi.insert(*bars, bar);
+        *bars += 1;
+        bar
+    }
+
+    /// Draw the display until [`ProgressDisplay::finish`] is called. This blocks, so it
+    /// has to run on a thread of its own.
+    pub fn join(&self) -> std::io::Result<()> {
+        self.multi.join()
+    }
+
+    /// Add a bar for a title, which goes forward by [`chapter_weight`] with each chapter
+    /// that is done
+    pub fn title_bar(&self, name: &str, pages: u64) -> ProgressBar {
+        let bar = self.add_bar(ProgressBar::new(pages));
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("{prefix} [{bar:40.yellow/red}] {pos}/{len} pages (ETA {eta})")
+                .progress_chars("=>-"),
+        );
+        bar.set_prefix(name);
+        bar.tick();
+        bar
+    }
+
+    /// Add a bar counting the pages of a chapter. Its length is set once the pages
+    /// are known.
+    pub fn chapter_bar(&self, name: &str) -> ProgressBar {
+        let bar = self.add_bar(ProgressBar::new(0));
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("  {prefix} [{bar:40.cyan/blue}] {pos}/{len} pages")
+                .progress_chars("=>-"),
+        );
+        bar.set_prefix(name);
+        bar
+    }
+
+    /// Pages that are going to be downloaded, counted towards the ETA
+    pub fn add_pages(&self, pages: usize) {
+        self.totals.inc_length(pages as u64);
+    }
+
+    /// A page was downloaded, bringing the run's total to `total_bytes`
+    pub fn page_done(&self, total_bytes: u64) {
+        self.speed.page_done(total_bytes);
+        self.totals.set_message(&format_size(total_bytes));
+        self.totals.inc(1);
+        self.footer.set_message(&self.footer_message());
+    }
+
+    /// The current and average speed, and the time left for every page known so far
+    fn footer_message(&self) -> String {
+        let speeds = self.speed.speeds();
+        let remaining = self.totals.length().saturating_sub(self.totals.position());
+        let eta = match speeds.eta(remaining) {
+            _ if remaining == 0 => "nothing left".to_owned(),
+            Some(eta) => format!("{} left", format_duration(eta)),
+            None => "stalled".to_owned(),
+        };
+        format!(
+            "Now {}/s, average {}/s, {}",
+            format_size(speeds.current as u64),
+            format_size(speeds.average as u64),
+            eta
+        )
+    }
+
+    /// Write an event for `--progress-format json`
+    pub fn emit(&self, event: ProgressEvent) {
+        if self.json {
+            match serde_json::to_string(&event) {
+                Ok(line) => println!("{}", line),
+                Err(e) => log::error!("Failed to write progress event {:?}: {}", event, e),
+            }
+        }
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.json
+    }
+
+    /// A chapter finished downloading, or gave up
+    pub fn chapter_done(&self, chapter_id: Uuid, result: &Result<()>) {
+        self.emit(ProgressEvent::ChapterDone {
+            chapter_id,
+            error: result.as_ref().err().map(ToString::to_string),
+        });
+    }
+
+    /// Clear the display, which also ends [`ProgressDisplay::join`]
+    pub fn finish(&self) {
+        self.totals.finish_and_clear();
+        self.footer.finish_and_clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_totals() {
+        let progress = ProgressDisplay::new(false, ProgressFormat::Bars);
+        progress.add_pages(3);
+        progress.page_done(1024);
+        progress.add_pages(2);
+        assert_eq!(progress.totals.position(), 1);
+        assert_eq!(progress.totals.length(), 5);
+        assert!(progress.footer_message().ends_with(" left"));
+        // Bars go between the totals and the footer
+        progress.title_bar("Tomo-chan", 5);
+        progress.chapter_bar("Ch. 1");
+        assert_eq!(*progress.bars.lock().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_chapter_weight() {
+        let chapter = |pages: usize| -> ChapterData {
+            serde_json::from_value(serde_json::json!({
+                "id": Uuid::from_u128(1),
+                "type": "chapter",
+                "attributes": {"translatedLanguage": "en", "pages": pages},
+                "relationships": []
+            }))
+            .unwrap()
+        };
+        let progress = ProgressDisplay::new(false, ProgressFormat::Bars);
+        let chapters = [chapter(3), chapter(60), chapter(0)];
+        let bar = progress.title_bar("Tomo-chan", chapters.iter().map(chapter_weight).sum());
+        assert_eq!(bar.length(), 64);
+        bar.inc(chapter_weight(&chapters[1]));
+        assert_eq!(bar.position(), 60);
+    }
+
+    #[test]
+    fn test_events() {
+        assert_eq!("json".parse(), Ok(ProgressFormat::Json));
+        assert!("xml".parse::<ProgressFormat>().is_err());
+        let event = ProgressEvent::PageDone {
+            chapter_id: Uuid::from_u128(1),
+            page: 3,
+            bytes: 2048,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"page_done","chapter_id":"00000000-0000-0000-0000-000000000001","page":3,"bytes":2048}"#
+        );
+        let event = ProgressEvent::ChapterDone {
+            chapter_id: Uuid::from_u128(1),
+            error: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"chapter_done","chapter_id":"00000000-0000-0000-0000-000000000001"}"#
+        );
+    }
+}
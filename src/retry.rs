@@ -3,22 +3,78 @@ use std::convert::From;
 use tokio::time::Duration;
 
 const MANGADEX_RATE_LIMIT_CODE: u16 = 429;
+const API_HOST: &str = "api.mangadex.org";
 
 #[derive(Debug)]
 pub enum DownloadError {
     IOError(std::io::Error),
+    ReadOnlyFilesystem(std::io::Error),
+    PermissionDenied(std::io::Error),
+    DiskFull(std::io::Error),
     NoSuchChapter(usize),
+    NoSuchPage(usize),
+    CorruptPage(usize),
+    VerificationFailed(usize),
+    SyncFailed(usize),
+    CoversFailed(usize),
+    DownloadsFailed(usize),
+    Interrupted,
+    /// The user skipped the chapter from the dashboard
+    Skipped,
+    UnsafePath(std::path::PathBuf),
+    /// An id that the source it was given to can't make sense of
+    InvalidId(String),
+    /// A tag name given to `--exclude-tag` that MangaDex doesn't have
+    UnknownTag(String),
+    AttestationError(String),
+    /// A title directory can't be exported as `--export` asked
+    ExportFailed(String),
+    /// A download can't be resumed from its journal
+    JournalError(String),
+    /// Another run is using the output directory
+    DirectoryLocked(String),
+    /// A chapter list has more chapters than the API pages through
+    FeedTooLong(usize),
+    /// Something had to be fetched with `--offline`, e.g. a response that isn't cached
+    Offline(String),
+    /// A page couldn't be converted as `--convert` asked
+    ConversionFailed(String),
+    AuthError(String),
+    NotificationError(String),
     ChapterIsWrongLanguage(usize),
     ParseError(url::ParseError),
+    JsonError(serde_json::Error),
     ReqwestError(reqwest::Error),
+    /// MangaDex refused an API request and said why
+    Api(crate::api::error::ApiError),
     RateLimitError(reqwest::Error),
+    /// A server stopped sending data for this long
+    Timeout(Duration),
+    RetryExhausted {
+        attempts: Vec<FailedAttempt>,
+        last: Box<DownloadError>,
+    },
+}
+
+/// A try that failed with a transient error, kept so that giving up can explain why
+#[derive(Clone, Debug, PartialEq)]
+pub struct FailedAttempt {
+    /// Time since the first try started
+    pub after: Duration,
+    pub error: String,
 }
 
 pub type Result<T> = std::result::Result<T, DownloadError>;
 
 impl From<std::io::Error> for DownloadError {
     fn from(e: std::io::Error) -> Self {
-        DownloadError::IOError(e)
+        use std::io::ErrorKind;
+        match e.kind() {
+            ErrorKind::ReadOnlyFilesystem => DownloadError::ReadOnlyFilesystem(e),
+            ErrorKind::PermissionDenied => DownloadError::PermissionDenied(e),
+            ErrorKind::StorageFull => DownloadError::DiskFull(e),
+            _ => DownloadError::IOError(e),
+        }
     }
 }
 
@@ -28,6 +84,12 @@ impl From<url::ParseError> for DownloadError {
     }
 }
 
+impl From<serde_json::Error> for DownloadError {
+    fn from(e: serde_json::Error) -> Self {
+        DownloadError::JsonError(e)
+    }
+}
+
 impl From<reqwest::Error> for DownloadError {
     fn from(e: reqwest::Error) -> Self {
         if e.status().map(|c| c.as_u16()) == Some(MANGADEX_RATE_LIMIT_CODE) {
@@ -42,19 +104,83 @@ impl std::fmt::Display for DownloadError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             DownloadError::IOError(e) => write!(f, "IO error: {}", e),
+            DownloadError::ReadOnlyFilesystem(e) => write!(
+                f,
+                "The output directory is on a read-only filesystem, remount it or choose another directory: {}",
+                e
+            ),
+            DownloadError::PermissionDenied(e) => write!(
+                f,
+                "Permission denied, check that you can write to the output directory: {}",
+                e
+            ),
+            DownloadError::DiskFull(e) => write!(f, "The disk is full, free up some space and try again: {}", e),
             DownloadError::ParseError(e) => write!(f, "Url parsing error: {}", e),
+            DownloadError::JsonError(e) => write!(f, "Json error: {}", e),
             DownloadError::NoSuchChapter(chapter_id) => write!(f, "Chapter not found: {}", chapter_id),
+            DownloadError::NoSuchPage(page) => write!(f, "Page not found: {}", page),
+            DownloadError::NotificationError(e) => write!(f, "Failed to send notification: {}", e),
+            DownloadError::VerificationFailed(count) => write!(f, "{} files failed verification", count),
+            DownloadError::SyncFailed(count) => write!(f, "{} titles failed to sync", count),
+            DownloadError::CoversFailed(count) => write!(f, "Failed to download covers of {} titles", count),
+            DownloadError::DownloadsFailed(count) => write!(f, "{} chapters or titles failed to download", count),
+            DownloadError::Interrupted => write!(f, "Interrupted, run the same command again to resume"),
+            DownloadError::Skipped => write!(f, "Skipped from the dashboard"),
+            DownloadError::UnsafePath(path) => write!(f, "Refusing to write outside the output directory: {:?}", path),
+            DownloadError::InvalidId(id) => write!(f, "Not a valid id: {}", id),
+            DownloadError::UnknownTag(tag) => write!(f, "Unknown tag: {}", tag),
+            DownloadError::AttestationError(e) => write!(f, "Attestation check failed: {}", e),
+            DownloadError::ExportFailed(e) => write!(f, "Can't export: {}", e),
+            DownloadError::JournalError(e) => write!(f, "Can't resume: {}", e),
+            DownloadError::DirectoryLocked(e) => write!(f, "Output directory is locked: {}", e),
+            DownloadError::FeedTooLong(total) => write!(
+                f,
+                "The chapter list has {} chapters, more than MangaDex can list at once, select fewer languages",
+                total
+            ),
+            DownloadError::Offline(e) => write!(f, "Can't work offline, {} would have to be downloaded", e),
+            DownloadError::ConversionFailed(e) => write!(f, "Failed to convert page {}", e),
+            DownloadError::AuthError(e) => write!(f, "Failed to log in to MangaDex: {}", e),
+            DownloadError::CorruptPage(page) => write!(f, "Page is still broken after re-downloading: {}", page),
             DownloadError::ChapterIsWrongLanguage(chapter_id) => {
                 write!(f, "Chapter has wrong lang code: {}", chapter_id)
             }
             DownloadError::ReqwestError(e) => write!(f, "Download error: {}", e),
+            DownloadError::Api(e) => write!(f, "MangaDex: {}", e),
             DownloadError::RateLimitError(e) => write!(f, "Downloads exceeded rate limit: {}", e),
+            DownloadError::Timeout(limit) => write!(
+                f,
+                "No data from the server for {}",
+                crate::units::format_duration(*limit)
+            ),
+            DownloadError::RetryExhausted { attempts, last } => write!(
+                f,
+                "Gave up after {} attempts in {}: {}",
+                attempts.len(),
+                crate::units::format_duration(attempts.last().map_or(Duration::ZERO, |attempt| attempt.after)),
+                last
+            ),
         }
     }
 }
 
 impl std::error::Error for DownloadError {}
 
+impl DownloadError {
+    pub fn is_interrupted(error: &(dyn std::error::Error + 'static)) -> bool {
+        matches!(error.downcast_ref::<DownloadError>(), Some(DownloadError::Interrupted))
+    }
+
+    /// Whether the server said there is no such file
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            DownloadError::ReqwestError(e) => e.status() == Some(reqwest::StatusCode::NOT_FOUND),
+            DownloadError::Api(e) => e.is_not_found(),
+            _ => false,
+        }
+    }
+}
+
 trait MaybePermanentError {
     fn is_permanent(&self) -> bool;
 }
@@ -63,38 +189,316 @@ impl MaybePermanentError for DownloadError {
     fn is_permanent(&self) -> bool {
         match self {
             DownloadError::IOError(_) => true,
+            DownloadError::ReadOnlyFilesystem(_) => true,
+            DownloadError::PermissionDenied(_) => true,
+            DownloadError::DiskFull(_) => true,
             DownloadError::ParseError(_) => true,
+            DownloadError::JsonError(_) => true,
             DownloadError::NoSuchChapter(_) => true,
+            DownloadError::NoSuchPage(_) => true,
+            DownloadError::CorruptPage(_) => true,
+            DownloadError::VerificationFailed(_) => true,
+            DownloadError::SyncFailed(_) => true,
+            DownloadError::CoversFailed(_) => true,
+            DownloadError::DownloadsFailed(_) => true,
+            DownloadError::Interrupted => true,
+            DownloadError::Skipped => true,
+            DownloadError::UnsafePath(_) => true,
+            DownloadError::InvalidId(_) => true,
+            DownloadError::UnknownTag(_) => true,
+            DownloadError::AttestationError(_) => true,
+            DownloadError::ExportFailed(_) => true,
+            DownloadError::JournalError(_) => true,
+            DownloadError::DirectoryLocked(_) => true,
+            DownloadError::FeedTooLong(_) => true,
+            DownloadError::Offline(_) => true,
+            DownloadError::ConversionFailed(_) => true,
+            DownloadError::AuthError(_) => true,
+            DownloadError::NotificationError(_) => true,
             DownloadError::ChapterIsWrongLanguage(_) => true,
             DownloadError::RateLimitError(_) => false,
+            DownloadError::Timeout(_) => false,
+            DownloadError::RetryExhausted { .. } => true,
             DownloadError::ReqwestError(e) => e.is_builder() || e.is_status(),
+            DownloadError::Api(e) => e.status < 500,
+        }
+    }
+}
+
+/// How much of each backoff delay is randomised, so that downloads that failed together
+/// don't all retry at the same moment
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Jitter {
+    /// Wait anywhere between no time and the full delay
+    #[default]
+    Full,
+    /// Wait at least half the delay
+    Equal,
+    /// Always wait the full delay
+    None,
+}
+
+impl std::str::FromStr for Jitter {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim() {
+            "full" => Ok(Jitter::Full),
+            "equal" => Ok(Jitter::Equal),
+            "none" => Ok(Jitter::None),
+            v => Err(format!("Unknown jitter \"{}\", expected full, equal or none", v)),
+        }
+    }
+}
+
+impl Jitter {
+    /// The wait for a delay, given a random number between 0 and 1
+    fn apply(self, delay: Duration, random: f64) -> Duration {
+        match self {
+            Jitter::Full => delay.mul_f64(random),
+            Jitter::Equal => delay / 2 + (delay / 2).mul_f64(random),
+            Jitter::None => delay,
+        }
+    }
+}
+
+/// How to back off from transient errors: wait up to `initial` (with jitter) after the
+/// first failure, multiply the wait by `factor` after every further one, and give up
+/// after `attempts` tries in total, or once retrying would take longer than `max_time`.
+/// Rate limits are waited out separately and don't count as attempts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BackoffPolicy {
+    pub initial: Duration,
+    pub factor: f64,
+    pub attempts: usize,
+    pub max_time: Option<Duration>,
+    pub jitter: Jitter,
+}
+
+impl BackoffPolicy {
+    /// Parse `INITIAL,FACTOR,ATTEMPTS`, e.g. `500ms,4,4`
+    pub fn parse(value: &str) -> std::result::Result<Self, String> {
+        let parts: Vec<&str> = value.split(',').map(str::trim).collect();
+        let [initial, factor, attempts] = parts[..] else {
+            return Err(format!(
+                "Expected a backoff as INITIAL,FACTOR,ATTEMPTS (e.g. 500ms,4,4), got \"{}\"",
+                value
+            ));
+        };
+        let policy = BackoffPolicy {
+            initial: crate::units::parse_duration(initial, Duration::from_millis(1))?,
+            factor: factor
+                .parse()
+                .map_err(|_| format!("Invalid backoff factor \"{}\"", factor))?,
+            attempts: attempts
+                .parse()
+                .map_err(|_| format!("Invalid number of attempts \"{}\"", attempts))?,
+            max_time: None,
+            jitter: Jitter::default(),
+        };
+        if policy.factor < 1.0 || policy.attempts == 0 {
+            return Err(format!(
+                "Backoff \"{}\" needs a factor of at least 1 and at least one attempt",
+                value
+            ));
+        }
+        Ok(policy)
+    }
+
+    /// Longest wait before the given retry, counting from 0
+    fn delay(&self, retry: usize) -> Duration {
+        self.initial.mul_f64(self.factor.powi(retry as i32))
+    }
+}
+
+/// Backoff for each kind of origin we talk to. The API is easily overloaded and
+/// quick to rate limit, so it is given more time to recover, while a failing image
+/// node is usually just flaky and worth trying again soon.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    pub api: BackoffPolicy,
+    pub images: BackoffPolicy,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            api: BackoffPolicy {
+                initial: Duration::from_millis(500),
+                factor: 4.0,
+                attempts: 4,
+                max_time: None,
+                jitter: Jitter::Full,
+            },
+            images: BackoffPolicy {
+                initial: Duration::from_millis(100),
+                factor: 2.0,
+                attempts: 6,
+                max_time: None,
+                jitter: Jitter::Full,
+            },
         }
     }
 }
 
-pub async fn with_retry<T, F, G>(f: impl Fn() -> F, wait: impl Fn() -> G) -> Result<T>
+impl RetryPolicy {
+    /// Apply a limit on the total time spent retrying to every origin
+    pub fn set_max_time(&mut self, max_time: Duration) {
+        self.api.max_time = Some(max_time);
+        self.images.max_time = Some(max_time);
+    }
+
+    pub fn set_jitter(&mut self, jitter: Jitter) {
+        self.api.jitter = jitter;
+        self.images.jitter = jitter;
+    }
+
+    pub fn for_url(&self, url: &url::Url) -> &BackoffPolicy {
+        if url.host_str() == Some(API_HOST) {
+            &self.api
+        } else {
+            &self.images
+        }
+    }
+}
+
+pub async fn with_retry<T, F, G>(backoff: &BackoffPolicy, f: impl Fn() -> F, wait: impl Fn() -> G) -> Result<T>
 where
     F: Future<Output = Result<T>>,
     G: Future<Output = ()>,
 {
     use rand::Rng;
-    let mut rng = rand::thread_rng();
-    let mut duration = Duration::from_millis(200);
-    let mut count = 0;
+    let started = tokio::time::Instant::now();
+    let mut attempts = Vec::new();
     loop {
-        count += 1;
         match f().await {
             v @ Ok(_) => return v,
             Err(DownloadError::RateLimitError(_)) => wait().await,
             Err(e) if e.is_permanent() => return Err(e),
-            e => {
-                if count < 4 {
-                    tokio::time::sleep(duration.mul_f64(rng.gen())).await;
-                    duration *= 3;
-                } else {
-                    return e;
+            Err(e) => {
+                attempts.push(FailedAttempt {
+                    after: started.elapsed(),
+                    error: e.to_string(),
+                });
+                let sleep = backoff
+                    .jitter
+                    .apply(backoff.delay(attempts.len() - 1), rand::thread_rng().gen());
+                let out_of_time = backoff
+                    .max_time
+                    .is_some_and(|max_time| started.elapsed() + sleep > max_time);
+                if attempts.len() >= backoff.attempts || out_of_time {
+                    return Err(DownloadError::RetryExhausted {
+                        attempts,
+                        last: Box::new(e),
+                    });
                 }
+                tokio::time::sleep(sleep).await;
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_backoff() {
+        let backoff = BackoffPolicy::parse("1s, 3, 5").unwrap();
+        assert_eq!(
+            backoff,
+            BackoffPolicy {
+                initial: Duration::from_secs(1),
+                factor: 3.0,
+                attempts: 5,
+                max_time: None,
+                jitter: Jitter::Full,
+            }
+        );
+        assert_eq!(backoff.delay(2), Duration::from_secs(9));
+        assert_eq!(
+            BackoffPolicy::parse("200,2,3").unwrap().initial,
+            Duration::from_millis(200)
+        );
+        assert!(BackoffPolicy::parse("1s,3").is_err());
+        assert!(BackoffPolicy::parse("1s,0.5,3").is_err());
+        assert!(BackoffPolicy::parse("1s,2,0").is_err());
+    }
+
+    #[test]
+    fn test_policy_by_origin() {
+        let policy = RetryPolicy::default();
+        let api = url::Url::parse("https://api.mangadex.org/chapter/1").unwrap();
+        let node = url::Url::parse("https://abc.xyz.mangadex.network:443/data/x/1.png").unwrap();
+        assert_eq!(policy.for_url(&api), &policy.api);
+        assert_eq!(policy.for_url(&node), &policy.images);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_attempts() {
+        use std::cell::Cell;
+        let backoff = BackoffPolicy {
+            initial: Duration::from_millis(1),
+            factor: 1.0,
+            attempts: 3,
+            max_time: None,
+            jitter: Jitter::Full,
+        };
+        let calls = Cell::new(0);
+        let result: Result<()> = with_retry(
+            &backoff,
+            || async {
+                calls.set(calls.get() + 1);
+                // A dns failure or similar, which is worth retrying
+                Err(reqwest::get("http://invalid.invalid/").await.unwrap_err().into())
+            },
+            || async {},
+        )
+        .await;
+        match result {
+            Err(DownloadError::RetryExhausted { attempts, .. }) => assert_eq!(attempts.len(), 3),
+            result => panic!("Expected to give up, got {:?}", result),
+        }
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn test_jitter() {
+        let delay = Duration::from_secs(4);
+        assert_eq!(Jitter::Full.apply(delay, 0.25), Duration::from_secs(1));
+        assert_eq!(Jitter::Equal.apply(delay, 0.0), Duration::from_secs(2));
+        assert_eq!(Jitter::Equal.apply(delay, 1.0), delay);
+        assert_eq!(Jitter::None.apply(delay, 0.0), delay);
+        assert_eq!("equal".parse(), Ok(Jitter::Equal));
+        assert!("some".parse::<Jitter>().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_gives_up_after_max_time() {
+        use std::cell::Cell;
+        let backoff = BackoffPolicy {
+            initial: Duration::from_secs(10),
+            factor: 2.0,
+            attempts: 10,
+            max_time: Some(Duration::from_secs(60)),
+            jitter: Jitter::None,
+        };
+        let calls = Cell::new(0);
+        let result: Result<()> = with_retry(
+            &backoff,
+            || async {
+                calls.set(calls.get() + 1);
+                // Nothing listens on port 1, so the connection is refused
+                Err(reqwest::get("http://127.0.0.1:1/").await.unwrap_err().into())
+            },
+            || async {},
+        )
+        .await;
+        // Waits of 10s and 20s fit in a minute, the next one of 40s doesn't
+        assert_eq!(calls.get(), 3);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("Gave up after 3 attempts in 30s"));
+    }
+}
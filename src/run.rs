@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use rand::Rng;
+use uuid::Uuid;
+
+use crate::chapter::{self, ChapterInfo};
+use crate::common::*;
+use crate::context::{Command, DownloadType, ScrapeContext};
+use crate::journal::Journal;
+use crate::lock::DirLock;
+use crate::retry::DownloadError;
+use crate::title::TitleData;
+use crate::units::format_duration;
+use crate::workspace::Workspace;
+use crate::{attestation, cover, export, info, library, manifest, notify, plan, summary};
+
+/// Download a title or a chapter into `current_dir`
+pub async fn download_resource(
+    download: &DownloadType,
+    current_dir: &Path,
+    title_dirs: Option<&mut HashMap<Uuid, PathBuf>>,
+    workspace: &Workspace,
+    context: &ScrapeContext,
+) -> OpaqueResult<()> {
+    match *download {
+        DownloadType::Chapter(ref uuid) => {
+            info!("Going to download chapter {:?}", uuid);
+            let data = ChapterInfo::download_chapter_data(*uuid, context).await?;
+            if let Some(pages) = chapter::completed_pages(current_dir, &data, context) {
+                context.print_status(format!("Chapter {} already complete ({} pages)", uuid, pages));
+                context.stats.add_skipped_chapter();
+            } else {
+                let chapter = ChapterInfo::from_chapter_data(data, context).await?;
+                if context.verbose {
+                    info!("Got chapter information: {:#?}", chapter);
+                }
+                match chapter.download_to_directory(&current_dir, workspace, context).await {
+                    Err(DownloadError::Skipped) => info!("Skipped chapter {}", uuid),
+                    Err(e) => {
+                        context.stats.add_failed_chapter();
+                        return Err(e.into());
+                    }
+                    Ok(()) => {}
+                }
+            }
+        }
+        DownloadType::Title(ref uuid) => {
+            info!("Downloading title: {}", uuid);
+            let title = TitleData::resolve(*uuid, &context.lang_codes, context).await?;
+            if context.verbose {
+                info!("Title API response: {:#?}", title);
+            }
+            let dir = match title_dirs {
+                Some(existing) => {
+                    let dir = library::title_dir(current_dir, &title, existing);
+                    std::fs::create_dir_all(&dir)?;
+                    dir
+                }
+                None => current_dir.to_owned(),
+            };
+            title.download_to_directory(&dir, workspace, context).await?;
+            if context.verbose {
+                for origin in summary::ThrottleSummary::from_context(context) {
+                    info!("Throttle: {}", origin);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The titles of a batch of more than one each get a directory of their own under
+/// current_dir, like followed titles, so that their records, journals and attestations
+/// don't replace each other. These are the directories the titles already have.
+pub fn batch_title_dirs(
+    downloads: &[DownloadType],
+    current_dir: &Path,
+) -> OpaqueResult<Option<HashMap<Uuid, PathBuf>>> {
+    let titles = downloads
+        .iter()
+        .filter(|download| matches!(download, DownloadType::Title(_)))
+        .count();
+    if titles < 2 {
+        return Ok(None);
+    }
+    Ok(Some(library::existing_title_dirs(current_dir)?))
+}
+
+/// Add what downloading a title or a chapter would take to `plan`
+pub async fn plan_resource(
+    download: &DownloadType,
+    current_dir: &Path,
+    title_dirs: Option<&mut HashMap<Uuid, PathBuf>>,
+    plan: &mut plan::DownloadPlan,
+    context: &ScrapeContext,
+) -> OpaqueResult<()> {
+    match *download {
+        DownloadType::Chapter(uuid) => {
+            let data = ChapterInfo::download_chapter_data(uuid, context).await?;
+            if chapter::completed_pages(current_dir, &data, context).is_some() {
+                plan.add_complete_chapter();
+            } else {
+                let mut chapter = ChapterInfo::from_chapter_data(data, context).await?;
+                plan.add_chapter(chapter.plan(&current_dir, context)?);
+            }
+        }
+        DownloadType::Title(uuid) => {
+            let title = TitleData::resolve(uuid, &context.lang_codes, context).await?;
+            let dir = match title_dirs {
+                Some(existing) => library::title_dir(current_dir, &title, existing),
+                None => current_dir.to_owned(),
+            };
+            title.plan_download(&dir, plan, context).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Print what we know about a title or a chapter
+pub async fn print_info(item: &DownloadType, context: &ScrapeContext) -> OpaqueResult<()> {
+    match *item {
+        DownloadType::Chapter(uuid) => {
+            let data = ChapterInfo::download_chapter_data(uuid, context).await?;
+            let report = info::ChapterReport::from_chapter(&data);
+            if context.json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                println!("{}", report);
+            }
+        }
+        DownloadType::Title(uuid) => {
+            let title = TitleData::resolve(uuid, &context.lang_codes, context).await?;
+            let mut report = title.report();
+            report.statistics = match title.download_statistics(context).await {
+                Err(DownloadError::Offline(e)) => {
+                    warn!("Not showing the rating of the title, {} isn't cached", e);
+                    None
+                }
+                result => result?,
+            };
+            report.availability = match title.language_availability(context).await {
+                Err(DownloadError::Offline(e)) => {
+                    warn!("Not showing the chapters of every language, {} isn't cached", e);
+                    Vec::new()
+                }
+                result => result?,
+            };
+            if context.json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print!("{}", report);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Carry out the command of `context`, which started at `started_at`. This is
+/// everything a run does apart from reporting on it. The progress display is left up,
+/// for [`run_scheduled`] to clear once no more checks follow.
+pub async fn run_command(context: &ScrapeContext, started_at: DateTime<Utc>) -> OpaqueResult<()> {
+    let current_dir = std::env::current_dir()?;
+    let workspace = Workspace::new(&current_dir);
+    // Syncs carry on past titles that fail, and report them once everything is done
+    let mut failed_titles = 0;
+    // Commands that write keep other runs out of the directory until they are done
+    let _lock = match context.command {
+        Command::Download(_) | Command::Follows => {
+            workspace.check_writable()?;
+            Some(DirLock::acquire(&current_dir, context.force)?)
+        }
+        Command::Sync(ref root) | Command::Covers(ref root) | Command::Export(ref root) | Command::Resume(ref root) => {
+            Workspace::new(root).check_writable()?;
+            Some(DirLock::acquire(root, context.force)?)
+        }
+        Command::Verify(ref root) if context.refetch => Some(DirLock::acquire(root, context.force)?),
+        Command::Verify(_) | Command::Info(_) | Command::DryRun(_) | Command::Plan(_) => None,
+    };
+    match context.command {
+        Command::Download(ref downloads) => {
+            let mut title_dirs = batch_title_dirs(downloads, &current_dir)?;
+            for download in downloads {
+                let result = download_resource(download, &current_dir, title_dirs.as_mut(), &workspace, context).await;
+                match result {
+                    Err(e) if context.keep_going => {
+                        // Failed chapters of a title are already on the list
+                        if !matches!(e.downcast_ref(), Some(DownloadError::DownloadsFailed(_))) {
+                            error!("Failed to download {:?}: {}", download, e);
+                            context.stats.add_failure(format!("{:?}", download), &e);
+                        }
+                    }
+                    result => result?,
+                }
+            }
+            let failed = context.stats.failure_count();
+            if failed > 0 {
+                return Err(DownloadError::DownloadsFailed(failed).into());
+            }
+        }
+        Command::Info(ref items) => {
+            for item in items {
+                print_info(item, context).await?;
+            }
+        }
+        Command::DryRun(ref downloads) => {
+            let mut plan = plan::DownloadPlan::default();
+            let mut title_dirs = batch_title_dirs(downloads, &current_dir)?;
+            for download in downloads {
+                plan_resource(download, &current_dir, title_dirs.as_mut(), &mut plan, context).await?;
+            }
+            print!("{}", plan);
+            println!("Would download {}", plan.estimate(&context.history));
+        }
+        Command::Plan(ref titles) => {
+            for title_id in titles {
+                let estimate = plan::estimate_title(*title_id, &context.lang_codes, &current_dir, context).await?;
+                if context.json {
+                    println!("{}", serde_json::to_string_pretty(&estimate)?);
+                } else {
+                    println!("{}", estimate);
+                }
+            }
+        }
+        Command::Verify(ref root) => {
+            info!("Verifying library at {:?}", root);
+            if let Some(ref trusted_key) = context.trusted_key {
+                let attestation = attestation::Attestation::read(root)?;
+                attestation.verify(root, trusted_key)?;
+                println!(
+                    "Attestation for title {} is valid, signed by {}",
+                    attestation.title_id,
+                    attestation.fingerprint()
+                );
+            }
+            let broken = manifest::verify_library(root, context).await?;
+            if broken > 0 {
+                return Err(DownloadError::VerificationFailed(broken).into());
+            }
+        }
+        Command::Sync(ref root) => {
+            info!("Syncing library at {:?}", root);
+            failed_titles = library::sync_library(root, context).await?;
+        }
+        Command::Follows => {
+            info!("Downloading followed titles into {:?}", current_dir);
+            failed_titles = library::download_follows(&current_dir, context).await?;
+        }
+        Command::Covers(ref root) => {
+            info!("Downloading covers for the library at {:?}", root);
+            let selection = context.covers.unwrap_or(cover::CoverSelection::All);
+            let failed = cover::download_library_covers(root, selection, context).await?;
+            if failed > 0 {
+                return Err(DownloadError::CoversFailed(failed).into());
+            }
+        }
+        Command::Export(ref root) => {
+            info!("Exporting the title at {:?}", root);
+            let (dir, format, template) = (root.clone(), context.export_format, context.export_template.clone());
+            let exported = run_blocking(move || export::export_title(&dir, format, template.as_ref())).await?;
+            if !context.quiet {
+                println!("Exported {} chapters of {}", exported, root.display());
+            }
+        }
+        Command::Resume(ref root) => {
+            let journal = Journal::read(root)?.ok_or_else(|| {
+                DownloadError::JournalError(format!("there is no unfinished download in {}", root.display()))
+            })?;
+            info!(
+                "Resuming {} chapters of title {} in {:?}",
+                journal.chapters.len(),
+                journal.title.id(),
+                root
+            );
+            let title = TitleData::from_journal(journal)?;
+            title
+                .download_to_directory(root, &Workspace::new(root), context)
+                .await?;
+        }
+    }
+    let downloaded_chapters = context.downloaded_chapters.lock().unwrap().clone();
+    let mut changes = Vec::new();
+    let mut new_failures = false;
+    if let Command::Sync(ref root) = context.command {
+        let summary = summary::RunSummary::from_context(context, started_at);
+        let previous = summary::RunSummary::read(root)?;
+        if let Some(ref previous) = previous {
+            changes = summary.compare(previous);
+        }
+        new_failures = summary.has_new_failures(previous.as_ref());
+        summary.write(root)?;
+    }
+    if !downloaded_chapters.is_empty() || new_failures {
+        let notification = notify::Notification {
+            title: format!("mdscrape downloaded {} new chapters", downloaded_chapters.len()),
+            new_chapters: downloaded_chapters,
+            changes,
+        };
+        notify::notify_all(&context.notifiers, &notification).await;
+    }
+    if failed_titles > 0 {
+        return Err(DownloadError::SyncFailed(failed_titles).into());
+    }
+    Ok(())
+}
+
+/// How long to wait before the next check of `--watch`. The wait varies by a tenth either
+/// way, so that instances started together drift apart instead of all hitting the API
+/// at the same moment.
+fn watch_delay(interval: Duration) -> Duration {
+    interval.mul_f64(rand::thread_rng().gen_range(0.9..=1.1))
+}
+
+/// Run the command, or with `--watch` keep running it until interrupted. A check that
+/// fails is reported and the next one goes ahead as planned, so the watch survives the
+/// network or the API being down for a while.
+pub async fn run_scheduled(context: &ScrapeContext, started_at: DateTime<Utc>) -> OpaqueResult<()> {
+    let result = match context.watch {
+        Some(interval) => watch(context, interval, started_at).await,
+        None => run_command(context, started_at).await,
+    };
+    // The display can't be joined again once cleared, so this happens once however the
+    // run ends, and a failure doesn't leave the bars on the terminal
+    context.progress.finish();
+    result
+}
+
+async fn watch(context: &ScrapeContext, interval: Duration, started_at: DateTime<Utc>) -> OpaqueResult<()> {
+    let mut started_at = started_at;
+    loop {
+        match run_command(context, started_at).await {
+            Err(e) if DownloadError::is_interrupted(e.as_ref()) => return Err(e),
+            Err(e) => error!("Check failed: {}", e),
+            Ok(()) => {}
+        }
+        info!("{}", summary::RunSummary::from_context(context, started_at));
+        let delay = watch_delay(interval);
+        info!("Checking again in {}", format_duration(delay));
+        tokio::time::sleep(delay).await;
+        started_at = Utc::now();
+        context.stats.reset();
+        context.reset_throttle_stats();
+        context.downloaded_chapters.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_send<T: Send>(_: &T) {}
+
+    #[test]
+    fn test_run_is_send() {
+        let context = ScrapeContext::builder(Command::Follows).build();
+        let shared = std::sync::Arc::new(&context);
+        assert_send(&shared);
+        let started_at = Utc::now();
+        let run = run_scheduled(&context, started_at);
+        assert_send(&run);
+    }
+
+    #[test]
+    fn test_watch_delay() {
+        let interval = Duration::from_secs(30 * 60);
+        for _ in 0..100 {
+            let delay = watch_delay(interval);
+            assert!(delay >= Duration::from_secs(27 * 60) && delay <= Duration::from_secs(33 * 60));
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDate, Utc};
+
+use crate::units;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SelectionParseError(String);
+
+impl Display for SelectionParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid selection: {}", self.0)
+    }
+}
+
+impl std::error::Error for SelectionParseError {}
+
+/// A non-empty set of inclusive ranges parsed from an expression like `1,5-10`. Used
+/// with `f64` for chapter numbers, which can be fractional, e.g. `12,15.5-20`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RangeSet<T> {
+    ranges: Vec<(T, T)>,
+}
+
+impl<T> RangeSet<T>
+where
+    T: FromStr + PartialOrd + Copy + Display,
+{
+    pub fn parse(expression: &str) -> Result<Self, SelectionParseError> {
+        let parse_bound = |s: &str| {
+            s.trim()
+                .parse::<T>()
+                .map_err(|_| SelectionParseError(format!("\"{}\" is not a valid number", s.trim())))
+        };
+        let mut ranges = Vec::new();
+        for part in expression.split(',') {
+            if part.trim().is_empty() {
+                return Err(SelectionParseError(format!("empty entry in \"{}\"", expression)));
+            }
+            let range = match part.split_once('-') {
+                Some((start, end)) => (parse_bound(start)?, parse_bound(end)?),
+                None => {
+                    let v = parse_bound(part)?;
+                    (v, v)
+                }
+            };
+            if range.0 > range.1 {
+                return Err(SelectionParseError(format!(
+                    "range {}-{} ends before it starts",
+                    range.0, range.1
+                )));
+            }
+            ranges.push(range);
+        }
+        Ok(RangeSet { ranges })
+    }
+
+    pub fn contains(&self, value: T) -> bool {
+        self.ranges.iter().any(|&(start, end)| start <= value && value <= end)
+    }
+
+    /// The smallest and largest values covered by the selection
+    pub fn bounds(&self) -> (T, T) {
+        let mut bounds = self.ranges[0];
+        for &(start, end) in &self.ranges[1..] {
+            if start < bounds.0 {
+                bounds.0 = start;
+            }
+            if end > bounds.1 {
+                bounds.1 = end;
+            }
+        }
+        bounds
+    }
+}
+
+impl<T> FromStr for RangeSet<T>
+where
+    T: FromStr + PartialOrd + Copy + Display,
+{
+    type Err = SelectionParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+/// Chapters released within a window of time, from `--published-since` and
+/// `--published-before`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DateRange {
+    pub since: Option<DateTime<Utc>>,
+    pub before: Option<DateTime<Utc>>,
+}
+
+impl DateRange {
+    pub fn is_unbounded(&self) -> bool {
+        self.since.is_none() && self.before.is_none()
+    }
+
+    /// Whether a chapter released at `date` is in the window. Chapters without a date
+    /// are only included when there is no window at all.
+    pub fn contains(&self, date: Option<DateTime<Utc>>) -> bool {
+        if self.is_unbounded() {
+            return true;
+        }
+        date.is_some_and(|date| {
+            self.since.is_none_or(|since| since <= date) && self.before.is_none_or(|before| date < before)
+        })
+    }
+}
+
+/// Parse a point in time given as a date like `2024-01-01` (midnight UTC), an RFC 3339
+/// timestamp, or a duration like `30d` for that long before `now`
+pub fn parse_date(value: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    let value = value.trim();
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+    }
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Ok(date.with_timezone(&Utc));
+    }
+    let ago = units::parse_duration(value, std::time::Duration::from_secs(24 * 60 * 60))
+        .ok()
+        .and_then(|ago| chrono::Duration::from_std(ago).ok())
+        .ok_or_else(|| format!("Invalid date \"{}\", expected e.g. 2024-01-01 or 30d", value))?;
+    Ok(now - ago)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_date_range() {
+        let now = DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let since = parse_date("2024-01-01", now).unwrap();
+        assert_eq!(since.to_rfc3339(), "2024-01-01T00:00:00+00:00");
+        assert_eq!(
+            parse_date("30d", now).unwrap().to_rfc3339(),
+            "2024-04-01T12:00:00+00:00"
+        );
+        assert_eq!(
+            parse_date("2024-03-01T10:00:00+02:00", now).unwrap().to_rfc3339(),
+            "2024-03-01T08:00:00+00:00"
+        );
+        assert!(parse_date("last tuesday", now).is_err());
+
+        let range = DateRange {
+            since: Some(since),
+            before: Some(parse_date("2024-04-01", now).unwrap()),
+        };
+        assert!(range.contains(Some(parse_date("2024-02-10", now).unwrap())));
+        assert!(!range.contains(Some(parse_date("2024-04-01", now).unwrap())));
+        assert!(!range.contains(Some(parse_date("2023-12-31", now).unwrap())));
+        assert!(!range.contains(None));
+        assert!(DateRange::default().contains(None));
+    }
+
+    #[test]
+    fn test_parse_pages() {
+        let pages: RangeSet<usize> = "1,5-10, 12".parse().unwrap();
+        assert!(pages.contains(1));
+        assert!(!pages.contains(2));
+        assert!(pages.contains(5));
+        assert!(pages.contains(10));
+        assert!(!pages.contains(11));
+        assert!(pages.contains(12));
+        assert_eq!(pages.bounds(), (1, 12));
+    }
+
+    #[test]
+    fn test_parse_chapters() {
+        let chapters: RangeSet<f64> = "1-10,12,15.5-20".parse().unwrap();
+        assert!(chapters.contains(1.0));
+        assert!(chapters.contains(10.0));
+        assert!(!chapters.contains(10.5));
+        assert!(chapters.contains(12.0));
+        assert!(!chapters.contains(15.0));
+        assert!(chapters.contains(15.5));
+        assert!(chapters.contains(20.0));
+        assert_eq!(chapters.bounds(), (1.0, 20.0));
+        assert!("20-15.5".parse::<RangeSet<f64>>().is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!("".parse::<RangeSet<usize>>().is_err());
+        assert!("1,,2".parse::<RangeSet<usize>>().is_err());
+        assert!("a-3".parse::<RangeSet<usize>>().is_err());
+        assert!("5-3".parse::<RangeSet<usize>>().is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use reqwest::Url;
+use uuid::Uuid;
+
+use crate::api::chapter::ChapterData;
+use crate::chapter::ChapterInfo;
+use crate::context::ScrapeContext;
+use crate::ids::{parse_resource_id, ResourceKind};
+use crate::retry::{DownloadError, Result};
+use crate::title::TitleData;
+
+/// A chapter as a source lists it, enough to name it and to ask for its pages
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceChapter {
+    /// The source's own id for the chapter
+    pub id: String,
+    pub volume: Option<String>,
+    pub chapter: Option<String>,
+    pub title: Option<String>,
+    pub lang_code: String,
+}
+
+/// A title and the chapters a source has of it in the requested languages
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceTitle {
+    pub id: String,
+    pub name: Option<String>,
+    pub chapters: Vec<SourceChapter>,
+}
+
+/// A site that titles can be downloaded from. A source only has to get from a title
+/// to its chapters and from a chapter to the urls of its pages, the throttle, retries,
+/// staging, manifests and progress reporting are shared. MangaDex is the only source
+/// so far, more go behind a feature flag each.
+#[allow(async_fn_in_trait)]
+pub trait Source {
+    /// Name of the source in messages
+    fn name(&self) -> &'static str;
+
+    /// The source's id of a title or chapter, given either as an id or as a link
+    fn parse_id(&self, value: &str, kind: ResourceKind) -> std::result::Result<String, String>;
+
+    /// The chapters of a title in any of `lang_codes`, in reading order
+    async fn resolve_title(
+        &self,
+        title_id: &str,
+        lang_codes: &[String],
+        context: &ScrapeContext,
+    ) -> Result<SourceTitle>;
+
+    /// The urls of the pages of a chapter, in reading order
+    async fn chapter_pages(&self, chapter_id: &str, context: &ScrapeContext) -> Result<Vec<Url>>;
+}
+
+/// mangadex.org, through its API and the at-home network
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MangaDex;
+
+impl MangaDex {
+    fn parse_uuid(id: &str) -> Result<Uuid> {
+        Uuid::parse_str(id).map_err(|_| DownloadError::InvalidId(id.to_owned()))
+    }
+}
+
+impl From<&ChapterData> for SourceChapter {
+    fn from(data: &ChapterData) -> Self {
+        SourceChapter {
+            id: data.id.to_string(),
+            volume: data.attributes.volume.clone(),
+            chapter: data.attributes.chapter.clone(),
+            title: data.attributes.title.clone(),
+            lang_code: data.attributes.translated_language.clone(),
+        }
+    }
+}
+
+impl Source for MangaDex {
+    fn name(&self) -> &'static str {
+        "MangaDex"
+    }
+
+    fn parse_id(&self, value: &str, kind: ResourceKind) -> std::result::Result<String, String> {
+        parse_resource_id(value, kind).map(|id| id.to_string())
+    }
+
+    async fn resolve_title(
+        &self,
+        title_id: &str,
+        lang_codes: &[String],
+        context: &ScrapeContext,
+    ) -> Result<SourceTitle> {
+        let title = TitleData::resolve(Self::parse_uuid(title_id)?, lang_codes, context).await?;
+        Ok(SourceTitle {
+            id: title.id().to_string(),
+            name: title.display_title().map(str::to_owned),
+            chapters: title.chapters().iter().map(SourceChapter::from).collect(),
+        })
+    }
+
+    async fn chapter_pages(&self, chapter_id: &str, context: &ScrapeContext) -> Result<Vec<Url>> {
+        let data = ChapterInfo::download_chapter_data(Self::parse_uuid(chapter_id)?, context).await?;
+        ChapterInfo::from_chapter_data(data, context).await?.page_urls()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mangadex_ids() {
+        let source = MangaDex;
+        assert_eq!(
+            source.parse_id(
+                "https://mangadex.org/title/76ee7069-23b4-493c-bc44-34ccbf3051a8/some-title",
+                ResourceKind::Title
+            ),
+            Ok("76ee7069-23b4-493c-bc44-34ccbf3051a8".to_owned())
+        );
+        assert!(source
+            .parse_id(
+                "https://mangadex.org/chapter/417d64e1-6c88-48f8-b507-ad43e9636888",
+                ResourceKind::Title
+            )
+            .is_err());
+        assert!(MangaDex::parse_uuid("not-a-uuid").is_err());
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How far back the current speed looks
+pub const SPEED_WINDOW: Duration = Duration::from_secs(10);
+
+/// How fast a run is going, as of some moment
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Speeds {
+    /// Bytes per second over the last [`SPEED_WINDOW`]
+    pub current: f64,
+    /// Bytes per second since the run started
+    pub average: f64,
+    /// Pages per second over the last [`SPEED_WINDOW`]
+    pub pages: f64,
+}
+
+impl Speeds {
+    /// How long the remaining pages will take at the current pace. None while nothing
+    /// is coming in.
+    pub fn eta(&self, remaining_pages: u64) -> Option<Duration> {
+        (self.pages > 0.0).then(|| Duration::from_secs_f64(remaining_pages as f64 / self.pages))
+    }
+}
+
+#[derive(Debug, Default)]
+struct Samples {
+    /// When each recent page finished, with its size
+    recent: VecDeque<(Instant, u64)>,
+    total_bytes: u64,
+}
+
+/// Keeps the transfer speed of a run over a rolling window, which follows throttling,
+/// slow nodes and pauses much more closely than the average since the start does
+#[derive(Debug)]
+pub struct SpeedEstimator {
+    window: Duration,
+    started: Instant,
+    samples: Mutex<Samples>,
+}
+
+impl SpeedEstimator {
+    pub fn new(window: Duration) -> Self {
+        SpeedEstimator {
+            window,
+            started: Instant::now(),
+            samples: Mutex::new(Samples::default()),
+        }
+    }
+
+    /// A page was downloaded, bringing the run's total to `total_bytes`
+    pub fn page_done(&self, total_bytes: u64) {
+        self.page_done_at(Instant::now(), total_bytes);
+    }
+
+    fn page_done_at(&self, now: Instant, total_bytes: u64) {
+        let mut samples = self.samples.lock().unwrap();
+        // Pages finishing at once may report their totals out of order
+        let bytes = total_bytes.saturating_sub(samples.total_bytes);
+        samples.total_bytes = samples.total_bytes.max(total_bytes);
+        samples.recent.push_back((now, bytes));
+        self.forget_before(&mut samples, now);
+    }
+
+    fn forget_before(&self, samples: &mut Samples, now: Instant) {
+        while let Some(&(at, _)) = samples.recent.front() {
+            if now.saturating_duration_since(at) <= self.window {
+                break;
+            }
+            samples.recent.pop_front();
+        }
+    }
+
+    pub fn speeds(&self) -> Speeds {
+        self.speeds_at(Instant::now())
+    }
+
+    fn speeds_at(&self, now: Instant) -> Speeds {
+        let mut samples = self.samples.lock().unwrap();
+        self.forget_before(&mut samples, now);
+        let elapsed = now.saturating_duration_since(self.started).as_secs_f64();
+        // Early in the run the window isn't full yet
+        let span = elapsed.min(self.window.as_secs_f64());
+        if span <= 0.0 {
+            return Speeds {
+                current: 0.0,
+                average: 0.0,
+                pages: 0.0,
+            };
+        }
+        let recent: u64 = samples.recent.iter().map(|&(_, bytes)| bytes).sum();
+        Speeds {
+            current: recent as f64 / span,
+            average: samples.total_bytes as f64 / elapsed,
+            pages: samples.recent.len() as f64 / span,
+        }
+    }
+}
+
+impl Default for SpeedEstimator {
+    fn default() -> Self {
+        Self::new(SPEED_WINDOW)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_speed_estimator() {
+        let estimator = SpeedEstimator::new(Duration::from_secs(10));
+        let start = estimator.started;
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        assert_eq!(estimator.speeds_at(start).eta(10), None);
+        estimator.page_done_at(at(1), 1000);
+        estimator.page_done_at(at(2), 3000);
+        // Reported late, after the total it is part of
+        estimator.page_done_at(at(2), 2000);
+        let speeds = estimator.speeds_at(at(4));
+        assert_eq!(speeds.current, 750.0);
+        assert_eq!(speeds.average, 750.0);
+        assert_eq!(speeds.pages, 0.75);
+        assert_eq!(speeds.eta(3), Some(Duration::from_secs(4)));
+
+        // Once the pages are out of the window only the average remembers them
+        estimator.page_done_at(at(20), 5000);
+        let speeds = estimator.speeds_at(at(20));
+        assert_eq!(speeds.current, 200.0);
+        assert_eq!(speeds.average, 250.0);
+        assert_eq!(speeds.pages, 0.1);
+        assert_eq!(estimator.speeds_at(at(40)).eta(1), None);
+    }
+}
@@ -0,0 +1,188 @@
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use log::debug;
+
+use crate::manifest::Manifest;
+use crate::retry::Result;
+
+/// Where pages are kept by their checksum, under the output root
+pub const OBJECTS_DIR_NAME: &str = ".mdscrape/objects";
+
+/// How chapter directories refer to the pages in the store, set with `--page-store`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StoreLinks {
+    /// Pages stay ordinary files to every program, but the store has to be on the same
+    /// filesystem as the chapters
+    Hardlink,
+    /// Relative links, which keep working when the output directory is moved as a whole
+    Symlink,
+}
+
+impl FromStr for StoreLinks {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "hardlink" => Ok(StoreLinks::Hardlink),
+            "symlink" => Ok(StoreLinks::Symlink),
+            _ => Err(format!(
+                "Invalid page store \"{}\", expected hardlink or symlink",
+                value
+            )),
+        }
+    }
+}
+
+/// Content-addressable storage for pages. Every page is kept once in
+/// `.mdscrape/objects/<first two digits>/<sha256>` and the chapter directories link to
+/// it, so that a title whose group uploaded the same images again takes no more space.
+#[derive(Clone, Debug)]
+pub struct PageStore {
+    objects: PathBuf,
+    links: StoreLinks,
+}
+
+impl PageStore {
+    pub fn new(output_root: &impl AsRef<Path>, links: StoreLinks) -> Self {
+        PageStore {
+            objects: output_root.as_ref().join(OBJECTS_DIR_NAME),
+            links,
+        }
+    }
+
+    fn object_path(&self, hash: &str) -> PathBuf {
+        self.objects.join(&hash[..2.min(hash.len())]).join(hash)
+    }
+
+    /// Replace the page at path by a link to the object with its checksum, adding it to
+    /// the store if it is new. Returns the bytes that were already stored.
+    fn link_page(&self, path: &Path, hash: &str) -> io::Result<u64> {
+        let object = self.object_path(hash);
+        if fs::symlink_metadata(path)?.file_type().is_symlink() || is_same_file(path, &object) {
+            return Ok(0);
+        }
+        let mut saved = 0;
+        if object.exists() {
+            saved = fs::metadata(path)?.len();
+            fs::remove_file(path)?;
+        } else {
+            fs::create_dir_all(object.parent().unwrap())?;
+            fs::rename(path, &object)?;
+        }
+        match self.links {
+            StoreLinks::Hardlink => fs::hard_link(&object, path)?,
+            StoreLinks::Symlink => {
+                let dir = fs::canonicalize(path.parent().unwrap())?;
+                symlink(&relative_path(&fs::canonicalize(&object)?, &dir), path)?
+            }
+        }
+        Ok(saved)
+    }
+
+    /// Move the pages of the chapter in dir into the store, going by the checksums in its
+    /// manifest. Pages that are links already are left alone. Returns the bytes saved.
+    pub fn link_chapter(&self, dir: &Path) -> Result<u64> {
+        let manifest = Manifest::read(dir)?;
+        let mut saved = 0;
+        for (hash, file_name) in &manifest.entries {
+            let path = dir.join(file_name);
+            if path.exists() {
+                saved += self.link_page(&path, hash)?;
+            }
+        }
+        if saved > 0 {
+            debug!(
+                "Linked pages of {:?} to copies already in the store, saving {} bytes",
+                dir, saved
+            );
+        }
+        Ok(saved)
+    }
+}
+
+#[cfg(unix)]
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// Without inode numbers a hardlinked page is simply linked again
+#[cfg(not(unix))]
+fn is_same_file(_: &Path, _: &Path) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, path: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, path)
+}
+
+/// target as seen from dir, both of them absolute
+fn relative_path(target: &Path, dir: &Path) -> PathBuf {
+    let common = target
+        .components()
+        .zip(dir.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative: PathBuf = dir.components().skip(common).map(|_| Component::ParentDir).collect();
+    relative.extend(target.components().skip(common));
+    relative
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::manifest::MANIFEST_FILE_NAME;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(
+            relative_path(
+                Path::new("/manga/.mdscrape/objects/ab/abc"),
+                Path::new("/manga/Tomo/Ch. 1")
+            ),
+            Path::new("../../.mdscrape/objects/ab/abc")
+        );
+    }
+
+    #[test]
+    fn test_link_chapter() {
+        let root = std::env::temp_dir().join(format!("mdscrape-test-{}", Uuid::from_u128(rand::random())));
+        let (first, second) = (root.join("Tomo").join("Ch. 1"), root.join("Tomo").join("Ch. 1 again"));
+        for dir in [&first, &second] {
+            fs::create_dir_all(dir).unwrap();
+            fs::write(dir.join("0001.png"), b"page").unwrap();
+            let manifest = Manifest::from_files(Uuid::nil(), &[dir.join("0001.png")]).unwrap();
+            manifest.write(dir).unwrap();
+        }
+        let hardlinks = PageStore::new(&root, StoreLinks::Hardlink);
+        assert_eq!(hardlinks.link_chapter(&first).unwrap(), 0);
+        assert_eq!(hardlinks.link_chapter(&second).unwrap(), 4);
+        // Linking again is a no-op
+        assert_eq!(hardlinks.link_chapter(&second).unwrap(), 0);
+        assert_eq!(fs::read(second.join("0001.png")).unwrap(), b"page");
+
+        fs::remove_file(second.join("0001.png")).unwrap();
+        fs::write(second.join("0001.png"), b"page").unwrap();
+        let symlinks = PageStore::new(&root, StoreLinks::Symlink);
+        assert_eq!(symlinks.link_chapter(&second).unwrap(), 4);
+        let link = fs::read_link(second.join("0001.png")).unwrap();
+        assert!(link.starts_with("../../.mdscrape/objects"));
+        assert_eq!(fs::read(second.join("0001.png")).unwrap(), b"page");
+        assert!(second.join(MANIFEST_FILE_NAME).exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
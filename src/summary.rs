@@ -0,0 +1,368 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::context::ScrapeContext;
+use crate::info::NumberingGaps;
+use crate::retry::Result;
+use crate::units::{format_duration, format_size};
+
+pub const SUMMARY_FILE_NAME: &str = ".mdscrape-last-run.json";
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Failure {
+    /// What failed, e.g. the directory of a title
+    pub what: String,
+    pub error: String,
+}
+
+/// How the throttle treated one origin during a run, to help with tuning the thresholds
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct ThrottleSummary {
+    pub origin: String,
+    pub requests: usize,
+    /// Total time requests spent waiting for a connection
+    pub waited_secs: f64,
+    /// Times the origin rate limited us
+    pub rate_limited: usize,
+}
+
+impl ThrottleSummary {
+    /// One summary for every origin used so far, sorted by origin
+    pub fn from_context(context: &ScrapeContext) -> Vec<Self> {
+        let mut summaries: Vec<ThrottleSummary> = context
+            .throttle_stats()
+            .into_iter()
+            .map(|(origin, stats)| ThrottleSummary {
+                origin: origin.ascii_serialization(),
+                requests: stats.issued,
+                waited_secs: stats.waited.as_secs_f64(),
+                rate_limited: stats.lockouts,
+            })
+            .collect();
+        summaries.sort_by(|a, b| a.origin.cmp(&b.origin));
+        summaries
+    }
+}
+
+impl fmt::Display for ThrottleSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} requests, waited {} for connections, rate limited {} times",
+            self.origin,
+            self.requests,
+            format_duration(Duration::from_secs_f64(self.waited_secs)),
+            self.rate_limited
+        )
+    }
+}
+
+/// Counters collected while downloading
+#[derive(Debug, Default)]
+pub struct RunStats {
+    pages: AtomicUsize,
+    bytes: AtomicU64,
+    skipped_chapters: AtomicUsize,
+    failed_chapters: AtomicUsize,
+    retries: AtomicUsize,
+    failures: Mutex<Vec<Failure>>,
+    hook_failures: Mutex<Vec<Failure>>,
+    numbering_gaps: Mutex<Vec<String>>,
+}
+
+impl RunStats {
+    pub fn add_page(&self, bytes: usize) {
+        self.pages.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn pages(&self) -> usize {
+        self.pages.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// A chapter that was already complete on disk
+    pub fn add_skipped_chapter(&self) {
+        self.skipped_chapters.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Start counting from scratch, for the next check of `--watch`
+    pub fn reset(&self) {
+        self.pages.store(0, Ordering::Relaxed);
+        self.bytes.store(0, Ordering::Relaxed);
+        self.skipped_chapters.store(0, Ordering::Relaxed);
+        self.failed_chapters.store(0, Ordering::Relaxed);
+        self.retries.store(0, Ordering::Relaxed);
+        self.failures.lock().unwrap().clear();
+        self.hook_failures.lock().unwrap().clear();
+        self.numbering_gaps.lock().unwrap().clear();
+    }
+
+    pub fn add_failed_chapter(&self) {
+        self.failed_chapters.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Requests that had to be sent again, after an error or a rate limit
+    pub fn add_retries(&self, retries: usize) {
+        self.retries.fetch_add(retries, Ordering::Relaxed);
+    }
+
+    pub fn failure_count(&self) -> usize {
+        self.failures.lock().unwrap().len()
+    }
+
+    pub fn add_failure(&self, what: impl Into<String>, error: impl ToString) {
+        self.failures.lock().unwrap().push(Failure {
+            what: what.into(),
+            error: error.to_string(),
+        });
+    }
+
+    /// A `--on-chapter-done` or `--on-title-done` command that failed
+    pub fn add_hook_failure(&self, what: impl Into<String>, error: impl ToString) {
+        self.hook_failures.lock().unwrap().push(Failure {
+            what: what.into(),
+            error: error.to_string(),
+        });
+    }
+
+    pub fn hook_failures(&self) -> Vec<Failure> {
+        self.hook_failures.lock().unwrap().clone()
+    }
+
+    /// Chapters missing from the numbering of a title, or numbered twice
+    pub fn add_numbering_gaps(&self, title: &str, gaps: &NumberingGaps) {
+        self.numbering_gaps.lock().unwrap().push(format!("{} {}", title, gaps));
+    }
+
+    pub fn numbering_gaps(&self) -> Vec<String> {
+        self.numbering_gaps.lock().unwrap().clone()
+    }
+}
+
+/// What a run did, printed at the end and written with `--report`. Syncs also keep it
+/// in the library root so the next run can say what changed.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct RunSummary {
+    pub finished_at: DateTime<Utc>,
+    pub duration_secs: f64,
+    pub new_chapters: Vec<String>,
+    /// Chapters that were already complete
+    #[serde(default)]
+    pub skipped_chapters: usize,
+    #[serde(default)]
+    pub failed_chapters: usize,
+    pub pages: usize,
+    pub bytes: u64,
+    #[serde(default)]
+    pub retries: usize,
+    pub failures: Vec<Failure>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hook_failures: Vec<Failure>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub throttle: Vec<ThrottleSummary>,
+    /// Titles whose chapter numbering has gaps or duplicates, per language
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub numbering_gaps: Vec<String>,
+}
+
+impl RunSummary {
+    pub fn from_context(context: &ScrapeContext, started_at: DateTime<Utc>) -> Self {
+        let finished_at = Utc::now();
+        RunSummary {
+            finished_at,
+            duration_secs: (finished_at - started_at).num_milliseconds() as f64 / 1000.0,
+            new_chapters: context.downloaded_chapters.lock().unwrap().clone(),
+            skipped_chapters: context.stats.skipped_chapters.load(Ordering::Relaxed),
+            failed_chapters: context.stats.failed_chapters.load(Ordering::Relaxed),
+            pages: context.stats.pages(),
+            bytes: context.stats.bytes(),
+            retries: context.stats.retries.load(Ordering::Relaxed),
+            failures: context.stats.failures.lock().unwrap().clone(),
+            hook_failures: context.stats.hook_failures(),
+            throttle: ThrottleSummary::from_context(context),
+            numbering_gaps: context.stats.numbering_gaps(),
+        }
+    }
+
+    pub fn read(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(SUMMARY_FILE_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&fs::read(path)?)?))
+    }
+
+    pub fn write(&self, dir: &Path) -> Result<()> {
+        fs::write(dir.join(SUMMARY_FILE_NAME), serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Bytes per second while downloading, if anything was downloaded
+    pub fn throughput(&self) -> Option<f64> {
+        (self.bytes > 0 && self.duration_secs > 0.0).then(|| self.bytes as f64 / self.duration_secs)
+    }
+
+    /// Describe how this run differs from the previous one, one line per change
+    pub fn compare(&self, previous: &RunSummary) -> Vec<String> {
+        let mut changes = vec![format!(
+            "{} new chapters since the last run on {} (which found {})",
+            self.new_chapters.len(),
+            previous.finished_at.format("%Y-%m-%d %H:%M UTC"),
+            previous.new_chapters.len()
+        )];
+        if let (Some(current), Some(before)) = (self.throughput(), previous.throughput()) {
+            changes.push(format!(
+                "Throughput {:.2} MB/s, {:+.0}% compared to the last run",
+                current / 1e6,
+                (current / before - 1.0) * 100.0
+            ));
+        }
+        let known = |failure: &Failure, failures: &[Failure]| failures.iter().any(|f| f.what == failure.what);
+        for failure in self.failures.iter().filter(|f| !known(f, &previous.failures)) {
+            changes.push(format!("New failure: {}: {}", failure.what, failure.error));
+        }
+        for failure in previous.failures.iter().filter(|f| !known(f, &self.failures)) {
+            changes.push(format!("Fixed since the last run: {}", failure.what));
+        }
+        changes
+    }
+
+    /// Whether there are failures that weren't there last time
+    pub fn has_new_failures(&self, previous: Option<&RunSummary>) -> bool {
+        self.failures
+            .iter()
+            .any(|failure| previous.is_none_or(|previous| previous.failures.iter().all(|f| f.what != failure.what)))
+    }
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Downloaded {} chapters, {} pages ({}) in {}",
+            self.new_chapters.len(),
+            self.pages,
+            format_size(self.bytes),
+            format_duration(Duration::from_secs_f64(self.duration_secs))
+        )?;
+        if let Some(throughput) = self.throughput() {
+            write!(f, " at {}/s", format_size(throughput as u64))?;
+        }
+        write!(
+            f,
+            "\n{} chapters already complete, {} failed, {} requests retried",
+            self.skipped_chapters, self.failed_chapters, self.retries
+        )?;
+        for failure in &self.failures {
+            write!(f, "\nFailed: {}: {}", failure.what, failure.error)?;
+        }
+        for failure in &self.hook_failures {
+            write!(f, "\nHook failed: {}: {}", failure.what, failure.error)?;
+        }
+        for gaps in &self.numbering_gaps {
+            write!(f, "\nGaps: {}", gaps)?;
+        }
+        for origin in &self.throttle {
+            write!(f, "\n{}", origin)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn summary(new_chapters: usize, bytes: u64, failures: &[&str]) -> RunSummary {
+        RunSummary {
+            finished_at: DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z").unwrap().into(),
+            duration_secs: 10.0,
+            new_chapters: (0..new_chapters).map(|i| format!("Ch. {}", i)).collect(),
+            skipped_chapters: 2,
+            failed_chapters: failures.len(),
+            pages: 0,
+            bytes,
+            retries: 4,
+            failures: failures
+                .iter()
+                .map(|what| Failure {
+                    what: what.to_string(),
+                    error: "Download error".to_owned(),
+                })
+                .collect(),
+            hook_failures: Vec::new(),
+            throttle: Vec::new(),
+            numbering_gaps: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_compare_runs() {
+        let previous = summary(1, 20_000_000, &["/library/Old"]);
+        let current = summary(3, 30_000_000, &["/library/New"]);
+        assert_eq!(
+            current.compare(&previous),
+            vec![
+                "3 new chapters since the last run on 2024-05-01 12:00 UTC (which found 1)",
+                "Throughput 3.00 MB/s, +50% compared to the last run",
+                "New failure: /library/New: Download error",
+                "Fixed since the last run: /library/Old",
+            ]
+        );
+        assert!(current.has_new_failures(Some(&previous)));
+        assert!(!previous.has_new_failures(Some(&previous)));
+        assert!(summary(0, 0, &[]).compare(&previous).len() == 2);
+    }
+
+    #[test]
+    fn test_summary_report() {
+        assert_eq!(
+            summary(3, 30_000_000, &["/library/New"]).to_string(),
+            "Downloaded 3 chapters, 0 pages (28.6 MiB) in 10s at 2.9 MiB/s\n\
+             2 chapters already complete, 1 failed, 4 requests retried\n\
+             Failed: /library/New: Download error"
+        );
+        let with_hook = RunSummary {
+            hook_failures: vec![Failure {
+                what: "false".to_owned(),
+                error: "exited with exit status: 1".to_owned(),
+            }],
+            ..summary(0, 0, &[])
+        };
+        assert!(with_hook
+            .to_string()
+            .ends_with("\nHook failed: false: exited with exit status: 1"));
+        let with_throttle = RunSummary {
+            throttle: vec![ThrottleSummary {
+                origin: "https://api.mangadex.org".to_owned(),
+                requests: 120,
+                waited_secs: 95.0,
+                rate_limited: 1,
+            }],
+            ..summary(0, 0, &[])
+        };
+        assert!(with_throttle.to_string().ends_with(
+            "\nhttps://api.mangadex.org: 120 requests, waited 1m 35s for connections, rate limited 1 times"
+        ));
+        let with_gaps = RunSummary {
+            numbering_gaps: vec!["Tomo-chan en: missing 23".to_owned()],
+            ..summary(0, 0, &[])
+        };
+        assert!(with_gaps.to_string().ends_with("\nGaps: Tomo-chan en: missing 23"));
+        // Summaries written before the counters existed still load
+        let old = r#"{"finished_at":"2024-05-01T12:00:00Z","duration_secs":1.0,"new_chapters":[],
+            "pages":0,"bytes":0,"failures":[]}"#;
+        assert_eq!(serde_json::from_str::<RunSummary>(old).unwrap().retries, 0);
+    }
+}
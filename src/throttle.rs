@@ -1,31 +1,335 @@
 use log::info;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     fmt::Debug,
     hash::Hash,
     sync::{Arc, Mutex},
-    time::Duration,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use tokio::{
-    sync::{OwnedSemaphorePermit, Semaphore},
+    sync::{oneshot, OwnedSemaphorePermit, Semaphore},
     time::Instant,
 };
 
-#[derive(Debug, Clone, Copy)]
+/// Length of the sliding window that `max_per_minute` is counted over
+const REQUEST_WINDOW: Duration = Duration::from_secs(60);
+
+/// How many requests may be in flight at once, and how long to back off for when a
+/// server rate limits us
+#[derive(Debug, Clone)]
 pub struct TicketPolicy {
     pub max_global: usize,
     pub max_per_site: usize,
     pub rate_limit_wait_time: Duration,
+    /// Max number of tickets handed out per origin in any minute, if limited
+    pub max_per_minute: Option<usize>,
+    /// Limits for some origins that replace the ones above, the first that matches wins
+    pub per_origin: Vec<OriginLimit>,
+}
+
+impl Default for TicketPolicy {
+    fn default() -> Self {
+        TicketPolicy {
+            max_global: 1,
+            max_per_site: 1,
+            rate_limit_wait_time: Duration::from_secs(150),
+            max_per_minute: None,
+            per_origin: Vec::new(),
+        }
+    }
+}
+
+impl TicketPolicy {
+    /// The connection and per minute limits for origin
+    fn limits_for(&self, origin: &impl OriginHost) -> (usize, Option<usize>) {
+        let host = origin.host();
+        match self.per_origin.iter().find(|limit| limit.matches(host.as_deref())) {
+            Some(limit) => (limit.max_per_site, limit.max_per_minute.or(self.max_per_minute)),
+            None => (self.max_per_site, self.max_per_minute),
+        }
+    }
+}
+
+/// Limits for the origins of one host, or of every subdomain of a domain with
+/// `*.domain`, e.g. `*.mangadex.network` for the MangaDex@Home image servers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OriginLimit {
+    pub host: String,
+    pub max_per_site: usize,
+    /// Replaces the policy's `max_per_minute` if set
+    pub max_per_minute: Option<usize>,
+}
+
+impl OriginLimit {
+    /// Parse `HOST=CONNECTIONS`, optionally followed by `/REQUESTS_PER_MINUTE`, e.g.
+    /// `api.mangadex.org=1/240`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let invalid = || {
+            format!(
+                "Invalid origin limit {:?}, expected HOST=CONNECTIONS[/PER_MINUTE]",
+                value
+            )
+        };
+        let (host, limits) = value.split_once('=').ok_or_else(invalid)?;
+        let (connections, per_minute) = match limits.split_once('/') {
+            Some((connections, per_minute)) => (connections, Some(per_minute)),
+            None => (limits, None),
+        };
+        let host = host.trim().to_ascii_lowercase();
+        let max_per_site = connections.trim().parse::<usize>().map_err(|_| invalid())?;
+        let max_per_minute = per_minute
+            .map(|n| n.trim().parse::<usize>().map_err(|_| invalid()))
+            .transpose()?;
+        if host.is_empty() || max_per_site == 0 || max_per_minute == Some(0) {
+            return Err(invalid());
+        }
+        Ok(OriginLimit {
+            host,
+            max_per_site,
+            max_per_minute,
+        })
+    }
+
+    fn matches(&self, host: Option<&str>) -> bool {
+        let Some(host) = host else {
+            return false;
+        };
+        match self.host.strip_prefix("*.") {
+            Some(domain) => host.strip_suffix(domain).is_some_and(|sub| sub.ends_with('.')),
+            None => host.eq_ignore_ascii_case(&self.host),
+        }
+    }
+}
+
+/// What the ticketer keys its throttles by, with the host the origin is on so that
+/// [`OriginLimit`]s can find it
+pub trait OriginHost {
+    fn host(&self) -> Option<String>;
+}
+
+impl OriginHost for url::Origin {
+    fn host(&self) -> Option<String> {
+        match self {
+            url::Origin::Tuple(_, host, _) => Some(host.to_string()),
+            url::Origin::Opaque(_) => None,
+        }
+    }
+}
+
+impl OriginHost for String {
+    fn host(&self) -> Option<String> {
+        Some(self.clone())
+    }
 }
 
 struct TicketPartition {
     // Needed to be arced to own a permit, apparently
     lock: Arc<tokio::sync::Semaphore>,
+    max_per_site: usize,
+    max_per_minute: Option<usize>,
     locked_till: Option<Instant>,
+    // When the tickets in the current window were handed out, oldest first
+    recent: VecDeque<Instant>,
+    budget: Option<RateBudget>,
+    stats: TicketStats,
+}
+
+/// What the throttle of an origin has done so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TicketStats {
+    /// Tickets handed out
+    pub issued: usize,
+    /// Total time spent waiting for those tickets
+    pub waited: Duration,
+    /// Times the origin rate limited us
+    pub lockouts: usize,
+}
+
+/// The requests a server still allows until its limit resets, as announced in the
+/// `X-RateLimit-*` headers of its responses. Once only a quarter of the budget is
+/// left, the rest is spread out until the reset so that we never get to a 429.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateBudget {
+    pub limit: usize,
+    pub remaining: usize,
+    pub resets_at: Option<Instant>,
+    // No request before this, while pacing
+    paced_till: Option<Instant>,
 }
 
+impl RateBudget {
+    pub fn new(limit: usize, remaining: usize, resets_at: Option<Instant>) -> Self {
+        RateBudget {
+            limit,
+            remaining,
+            resets_at,
+            paced_till: None,
+        }
+    }
+
+    /// The budget announced by response headers, if they announce one.
+    /// `X-RateLimit-Retry-After` is the unix time the limit resets at, a plain
+    /// `Retry-After` the seconds until then.
+    pub fn from_headers(headers: &reqwest::header::HeaderMap) -> Option<Self> {
+        let number = |name: &str| headers.get(name)?.to_str().ok()?.trim().parse::<u64>().ok();
+        let limit = number("x-ratelimit-limit")?;
+        let remaining = number("x-ratelimit-remaining")?;
+        let resets_in = match number("x-ratelimit-retry-after") {
+            Some(timestamp) => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                Some(Duration::from_secs(timestamp).saturating_sub(now))
+            }
+            None => number("retry-after").map(Duration::from_secs),
+        };
+        Some(RateBudget::new(
+            limit as usize,
+            remaining as usize,
+            resets_in.map(|wait| Instant::now() + wait),
+        ))
+    }
+
+    /// Take a request from the budget, or get the time to try again at
+    fn take(&mut self, now: Instant) -> Result<(), Instant> {
+        let resets_at = match self.resets_at {
+            Some(resets_at) if now < resets_at => resets_at,
+            Some(_) => {
+                // The limit has reset since the server told us about it
+                *self = RateBudget::new(self.limit, self.limit, None);
+                return self.take(now);
+            }
+            None => {
+                self.remaining = self.remaining.saturating_sub(1);
+                return Ok(());
+            }
+        };
+        if self.remaining == 0 {
+            return Err(resets_at);
+        }
+        if self.remaining * 4 <= self.limit {
+            if let Some(paced_till) = self.paced_till.filter(|till| now < *till) {
+                return Err(paced_till);
+            }
+            self.paced_till = Some(now + (resets_at - now) / self.remaining as u32);
+        }
+        self.remaining -= 1;
+        Ok(())
+    }
+}
+
+/// Which queue a ticket waits in for one of the global permits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TicketClass {
+    /// New work, e.g. API calls
+    Normal,
+    /// Work that is already under way, e.g. the pages of a chapter being
+    /// downloaded, which is served before anything normal
+    Priority,
+}
+
+struct GlobalQueueState {
+    available: usize,
+    priority: VecDeque<oneshot::Sender<GlobalPermit>>,
+    normal: VecDeque<oneshot::Sender<GlobalPermit>>,
+}
+
+/// Hands out the global permits, like a semaphore with two queues of waiters
+#[derive(Clone)]
+struct GlobalQueue {
+    state: Arc<Mutex<GlobalQueueState>>,
+}
+
+/// A global permit, passed on to the next waiter when dropped
+struct GlobalPermit {
+    queue: GlobalQueue,
+}
+
+impl Drop for GlobalPermit {
+    fn drop(&mut self) {
+        self.queue.release();
+    }
+}
+
+impl GlobalQueue {
+    fn new(permits: usize) -> Self {
+        GlobalQueue {
+            state: Arc::new(Mutex::new(GlobalQueueState {
+                available: permits,
+                priority: VecDeque::new(),
+                normal: VecDeque::new(),
+            })),
+        }
+    }
+
+    async fn acquire(&self, class: TicketClass) -> GlobalPermit {
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            if state.available > 0 {
+                state.available -= 1;
+                return GlobalPermit { queue: self.clone() };
+            }
+            let (sender, receiver) = oneshot::channel();
+            match class {
+                TicketClass::Priority => state.priority.push_back(sender),
+                TicketClass::Normal => state.normal.push_back(sender),
+            }
+            receiver
+        };
+        // The queue never drops a sender without sending, a permit that arrives after
+        // we stop waiting is dropped with the receiver and passed on again
+        receiver.await.unwrap()
+    }
+
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let next = state.priority.pop_front().or_else(|| state.normal.pop_front());
+            match next {
+                // Whoever was waiting here has given up
+                Some(sender) if sender.is_closed() => continue,
+                Some(sender) => {
+                    drop(state);
+                    // If the waiter went away in the meantime the permit comes back
+                    // as the error and is released again when dropped
+                    let _ = sender.send(GlobalPermit { queue: self.clone() });
+                    return;
+                }
+                None => {
+                    state.available += 1;
+                    return;
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    fn available_permits(&self) -> usize {
+        self.state.lock().unwrap().available
+    }
+
+    fn waiting(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.priority.len() + state.normal.len()
+    }
+}
+
+/// What the throttle of one origin is doing, as shown on the dashboard
+#[derive(Debug, Clone, PartialEq)]
+pub struct OriginState<Origin> {
+    pub origin: Origin,
+    /// Tickets currently held for the origin
+    pub in_use: usize,
+    /// Requests sent in the current window, if requests per minute are limited
+    pub recent_requests: Option<usize>,
+    /// How much longer the origin is rate limited for
+    pub locked_for: Option<Duration>,
+    /// Requests the server still allows and its limit, if it announced them
+    pub budget: Option<(usize, usize)>,
+}
+
+/// Hands out tickets to make requests with, following a [`TicketPolicy`]. Requests to
+/// an origin that rate limited us wait until it has had time to recover.
 pub struct Ticketer<Origin: Clone + Hash + Eq> {
-    global_lock: Arc<Semaphore>,
+    global_lock: GlobalQueue,
     // Fine to use a mutex, should be very little contention
     state: Mutex<HashMap<Origin, TicketPartition>>,
     policy: Arc<TicketPolicy>,
@@ -37,39 +341,82 @@ impl<Origin: Clone + Hash + Eq> Debug for Ticketer<Origin> {
     }
 }
 
-impl<Origin: Clone + Hash + Eq> Ticketer<Origin> {
+impl<Origin: Clone + Hash + Eq + OriginHost> Ticketer<Origin> {
     pub fn new(policy: &TicketPolicy) -> Self {
         Ticketer {
-            global_lock: Arc::new(Semaphore::new(policy.max_global)),
+            global_lock: GlobalQueue::new(policy.max_global),
             state: Default::default(),
-            policy: Arc::new(*policy),
+            policy: Arc::new(policy.clone()),
         }
     }
 
-    fn default_origin_details(&self) -> TicketPartition {
+    fn default_origin_details(&self, origin: &Origin) -> TicketPartition {
+        let (max_per_site, max_per_minute) = self.policy.limits_for(origin);
         TicketPartition {
-            lock: Arc::new(Semaphore::new(self.policy.max_per_site)),
+            lock: Arc::new(Semaphore::new(max_per_site)),
+            max_per_site,
+            max_per_minute,
             locked_till: None,
+            recent: VecDeque::new(),
+            budget: None,
+            stats: TicketStats::default(),
         }
     }
 
+    /// Stop handing out tickets for origin until its rate limit resets, or for the
+    /// policy's wait time if the server didn't say when that is
     pub fn mark_origin_locked(&self, origin: &Origin) {
-        let wait_time = self.policy.rate_limit_wait_time;
-        info!("Rate limit exceeded, waiting for {:?}", wait_time);
-        let wait_till = Instant::now() + wait_time;
+        let now = Instant::now();
         let mut lock = self.state.lock().unwrap();
-        lock.get_mut(origin).unwrap().locked_till = Some(wait_till);
+        let partition = lock.get_mut(origin).unwrap();
+        let wait_till = partition
+            .budget
+            .and_then(|budget| budget.resets_at)
+            .filter(|resets_at| now < *resets_at)
+            .unwrap_or(now + self.policy.rate_limit_wait_time);
+        info!("Rate limit exceeded, waiting for {:?}", wait_till - now);
+        partition.locked_till = Some(wait_till);
+        partition.stats.lockouts += 1;
+    }
+
+    /// Remember the request budget origin announced in a response
+    pub fn record_budget(&self, origin: &Origin, mut budget: RateBudget) {
+        let mut guard = self.state.lock().unwrap();
+        let partition = guard
+            .entry(origin.clone())
+            .or_insert_with(|| self.default_origin_details(origin));
+        budget.paced_till = partition.budget.and_then(|old| old.paced_till);
+        partition.budget = Some(budget);
     }
 
-    fn get_origin_locked_till(&self, origin: &Origin) -> Instant {
+    /// Claim a request slot for origin, or get the time to try again at if it is
+    /// rate limited or has used up its requests for the current window
+    fn reserve_request(&self, origin: &Origin) -> Result<(), Instant> {
         // We won't try to deal with lock poisoning
         let mut guard = self.state.lock().unwrap();
         // TODO-OPTIMIZE away the clone
-        guard
+        let partition = guard
             .entry(origin.clone())
-            .or_insert(self.default_origin_details())
-            .locked_till
-            .unwrap_or(Instant::now())
+            .or_insert_with(|| self.default_origin_details(origin));
+        let now = Instant::now();
+        if let Some(locked_till) = partition.locked_till.filter(|till| now < *till) {
+            return Err(locked_till);
+        }
+        if let Some(max_per_minute) = partition.max_per_minute {
+            while partition.recent.front().is_some_and(|at| *at + REQUEST_WINDOW <= now) {
+                partition.recent.pop_front();
+            }
+            if partition.recent.len() >= max_per_minute {
+                return Err(partition.recent[0] + REQUEST_WINDOW);
+            }
+        }
+        if let Some(ref mut budget) = partition.budget {
+            budget.take(now)?;
+        }
+        if partition.max_per_minute.is_some() {
+            partition.recent.push_back(now);
+        }
+        Ok(())
     }
 
     fn get_origin_lock(&self, origin: &Origin) -> Arc<Semaphore> {
@@ -78,17 +425,59 @@ impl<Origin: Clone + Hash + Eq> Ticketer<Origin> {
         // TODO-OPTIMIZE away the clone
         guard
             .entry(origin.clone())
-            .or_insert(self.default_origin_details())
+            .or_insert_with(|| self.default_origin_details(origin))
             .lock
             .clone()
     }
 
+    /// Requests waiting for one of the global permits
+    pub fn waiting(&self) -> usize {
+        self.global_lock.waiting()
+    }
+
+    /// The state of every origin a ticket was asked for so far
+    pub fn origin_states(&self) -> Vec<OriginState<Origin>> {
+        let now = Instant::now();
+        let guard = self.state.lock().unwrap();
+        guard
+            .iter()
+            .map(|(origin, partition)| OriginState {
+                origin: origin.clone(),
+                in_use: partition.max_per_site - partition.lock.available_permits(),
+                recent_requests: partition
+                    .max_per_minute
+                    .map(|_| partition.recent.iter().filter(|at| **at + REQUEST_WINDOW > now).count()),
+                locked_for: partition.locked_till.filter(|till| now < *till).map(|till| till - now),
+                budget: partition.budget.map(|budget| (budget.remaining, budget.limit)),
+            })
+            .collect()
+    }
+
+    /// What the throttle has done for every origin a ticket was asked for so far
+    pub fn stats(&self) -> Vec<(Origin, TicketStats)> {
+        let guard = self.state.lock().unwrap();
+        guard
+            .iter()
+            .map(|(origin, partition)| (origin.clone(), partition.stats))
+            .collect()
+    }
+
+    /// Start counting from scratch
+    pub fn reset_stats(&self) {
+        let mut guard = self.state.lock().unwrap();
+        for partition in guard.values_mut() {
+            partition.stats = TicketStats::default();
+        }
+    }
+
     #[cfg(test)]
     fn ensure_exists(&self, origin: &Origin) {
         // We won't try to deal with lock poisoning
         let mut guard = self.state.lock().unwrap();
         // TODO-OPTIMIZE away the clone
-        guard.entry(origin.clone()).or_insert(self.default_origin_details());
+        guard
+            .entry(origin.clone())
+            .or_insert_with(|| self.default_origin_details(origin));
     }
 
     #[cfg(test)]
@@ -100,20 +489,34 @@ impl<Origin: Clone + Hash + Eq> Ticketer<Origin> {
     }
 
     pub async fn get_ticket(&self, origin: &Origin) -> Ticket {
+        self.get_ticket_in(origin, TicketClass::Normal).await
+    }
+
+    /// Get a ticket ahead of everything waiting with [`Ticketer::get_ticket`]
+    pub async fn get_priority_ticket(&self, origin: &Origin) -> Ticket {
+        self.get_ticket_in(origin, TicketClass::Priority).await
+    }
+
+    async fn get_ticket_in(&self, origin: &Origin, class: TicketClass) -> Ticket {
         use tokio::time::sleep_until;
+        let started = Instant::now();
         // We assume that the ticketer semaphore will never be closed, so it is safe to unwrap
         let mut _local_permit = None;
         loop {
             _local_permit = Some(self.get_origin_lock(origin).acquire_owned().await.unwrap());
-            let wait_till = self.get_origin_locked_till(origin);
-            if Instant::now() >= wait_till {
-                break;
-            } else {
-                _local_permit = None;
-                sleep_until(wait_till).await;
+            match self.reserve_request(origin) {
+                Ok(()) => break,
+                Err(wait_till) => {
+                    _local_permit = None;
+                    sleep_until(wait_till).await;
+                }
             }
         }
-        let _global_permit = self.global_lock.clone().acquire_owned().await.unwrap();
+        let _global_permit = self.global_lock.acquire(class).await;
+        if let Some(partition) = self.state.lock().unwrap().get_mut(origin) {
+            partition.stats.issued += 1;
+            partition.stats.waited += started.elapsed();
+        }
         Ticket {
             _global_permit,
             _local_permit: _local_permit.unwrap(),
@@ -122,11 +525,61 @@ impl<Origin: Clone + Hash + Eq> Ticketer<Origin> {
 }
 
 pub struct Ticket {
-    _global_permit: OwnedSemaphorePermit,
+    _global_permit: GlobalPermit,
     // Local permit should be dropped after global permit, enforced by RFC 1857
     _local_permit: OwnedSemaphorePermit,
 }
 
+struct BucketState {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Caps the combined download rate, with one token per byte. The bucket holds up to a
+/// second's worth of tokens, and a chunk that takes more than are available puts it
+/// in debt, which later chunks have to wait out.
+pub struct TokenBucket {
+    bytes_per_sec: f64,
+    state: Mutex<BucketState>,
+}
+
+impl Debug for TokenBucket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TokenBucket({} bytes/s)", self.bytes_per_sec)
+    }
+}
+
+impl TokenBucket {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        TokenBucket {
+            bytes_per_sec: bytes_per_sec as f64,
+            state: Mutex::new(BucketState {
+                tokens: bytes_per_sec as f64,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take tokens for bytes that were just received, waiting if we are over the limit
+    pub async fn consume(&self, bytes: usize) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            let refill = (now - state.refilled_at).as_secs_f64() * self.bytes_per_sec;
+            state.tokens = (state.tokens + refill).min(self.bytes_per_sec) - bytes as f64;
+            state.refilled_at = now;
+            if state.tokens < 0.0 {
+                Duration::from_secs_f64(-state.tokens / self.bytes_per_sec)
+            } else {
+                Duration::ZERO
+            }
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -142,6 +595,8 @@ mod test {
             max_global: 2,
             max_per_site: 1,
             rate_limit_wait_time: Duration::new(60, 0),
+            max_per_minute: None,
+            per_origin: Vec::new(),
         };
         let ticketer = Ticketer::new(&policy);
         let origin1 = "foo".to_string();
@@ -189,6 +644,8 @@ mod test {
             max_global: 3,
             max_per_site: 2,
             rate_limit_wait_time: Duration::new(0, 5_000_000),
+            max_per_minute: None,
+            per_origin: Vec::new(),
         };
         let ticketer = Ticketer::new(&policy);
         let origin = "origin".to_string();
@@ -196,8 +653,15 @@ mod test {
             let _t1 = ticketer.get_ticket(&origin).await;
             let _t2 = ticketer.get_ticket(&origin).await;
             assert!(!ticketer.can_get_ticket(&origin));
+            let states = ticketer.origin_states();
+            assert_eq!(states.len(), 1);
+            assert_eq!(states[0].in_use, 2);
+            assert_eq!(states[0].locked_for, None);
         }
         assert!(ticketer.can_get_ticket(&origin));
+        assert_eq!(ticketer.origin_states()[0].in_use, 0);
+        ticketer.mark_origin_locked(&origin);
+        assert!(ticketer.origin_states()[0].locked_for.is_some());
     }
 
     #[tokio::test]
@@ -206,6 +670,8 @@ mod test {
             max_global: 1,
             max_per_site: 1,
             rate_limit_wait_time: Duration::new(0, 5_000_000),
+            max_per_minute: None,
+            per_origin: Vec::new(),
         };
         let ticketer = Ticketer::new(&policy);
         let start = Instant::now();
@@ -231,4 +697,188 @@ mod test {
         ticketer.mark_origin_locked(&origin);
         join!(f1(), f2());
     }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ticket_requests_per_minute() {
+        let policy = TicketPolicy {
+            max_global: 2,
+            max_per_site: 2,
+            rate_limit_wait_time: Duration::new(60, 0),
+            max_per_minute: Some(3),
+            per_origin: Vec::new(),
+        };
+        let ticketer = Ticketer::new(&policy);
+        let origin = "origin".to_string();
+        let start = Instant::now();
+        for _ in 0..3 {
+            ticketer.get_ticket(&origin).await;
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+        // Other origins have their own window
+        ticketer.get_ticket(&"other".to_string()).await;
+        assert!(start.elapsed() < Duration::from_secs(1));
+        // The fourth request has to wait for the first to leave the window
+        ticketer.get_ticket(&origin).await;
+        assert!(start.elapsed() >= REQUEST_WINDOW);
+        assert!(start.elapsed() < REQUEST_WINDOW + Duration::from_secs(1));
+
+        ticketer.mark_origin_locked(&origin);
+        let stats: HashMap<_, _> = ticketer.stats().into_iter().collect();
+        assert_eq!(stats["origin"].issued, 4);
+        assert!(stats["origin"].waited >= REQUEST_WINDOW);
+        assert_eq!(stats["origin"].lockouts, 1);
+        assert_eq!(stats["other"].issued, 1);
+        assert_eq!(stats["other"].lockouts, 0);
+        ticketer.reset_stats();
+        assert!(ticketer
+            .stats()
+            .iter()
+            .all(|(_, stats)| *stats == TicketStats::default()));
+    }
+
+    #[test]
+    fn test_origin_limits() {
+        let limit = OriginLimit::parse("api.mangadex.org=1/240").unwrap();
+        assert_eq!(
+            limit,
+            OriginLimit {
+                host: "api.mangadex.org".to_owned(),
+                max_per_site: 1,
+                max_per_minute: Some(240),
+            }
+        );
+        assert!(OriginLimit::parse("api.mangadex.org").is_err());
+        assert!(OriginLimit::parse("api.mangadex.org=0").is_err());
+        assert!(OriginLimit::parse("api.mangadex.org=1/x").is_err());
+
+        let policy = TicketPolicy {
+            max_per_site: 2,
+            per_origin: vec![limit, OriginLimit::parse("*.mangadex.network=5").unwrap()],
+            ..Default::default()
+        };
+        let origin = |url: &str| url::Url::parse(url).unwrap().origin();
+        assert_eq!(
+            policy.limits_for(&origin("https://api.mangadex.org/manga")),
+            (1, Some(240))
+        );
+        assert_eq!(
+            policy.limits_for(&origin("https://abc.xyz.mangadex.network/data")),
+            (5, None)
+        );
+        assert_eq!(policy.limits_for(&origin("https://mangadex.network/data")), (2, None));
+        assert_eq!(
+            policy.limits_for(&origin("https://uploads.mangadex.org/data")),
+            (2, None)
+        );
+
+        let ticketer = Ticketer::new(&policy);
+        let node = origin("https://abc.xyz.mangadex.network");
+        ticketer.ensure_exists(&node);
+        let mut permits = Vec::new();
+        for _ in 0..5 {
+            assert!(ticketer.can_get_ticket(&node));
+            permits.push(ticketer.get_origin_lock(&node).try_acquire_owned().unwrap());
+        }
+        assert!(!ticketer.can_get_ticket(&node));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ticket_rate_budget() {
+        let ticketer = Ticketer::new(&TicketPolicy::default());
+        let origin = "origin".to_string();
+        let start = Instant::now();
+        let resets_at = start + Duration::from_secs(60);
+        ticketer.record_budget(&origin, RateBudget::new(8, 4, Some(resets_at)));
+        // Plenty left, the first two go right away
+        ticketer.get_ticket(&origin).await;
+        ticketer.get_ticket(&origin).await;
+        assert!(start.elapsed() < Duration::from_secs(1));
+        // The last two are spread over the rest of the window
+        ticketer.get_ticket(&origin).await;
+        ticketer.get_ticket(&origin).await;
+        assert!(start.elapsed() >= Duration::from_secs(30));
+        assert_eq!(ticketer.origin_states()[0].budget, Some((0, 8)));
+        // The next has to wait for the reset
+        ticketer.get_ticket(&origin).await;
+        assert!(start.elapsed() >= Duration::from_secs(60));
+        assert_eq!(ticketer.origin_states()[0].budget, Some((7, 8)));
+    }
+
+    #[test]
+    fn test_rate_budget_from_headers() {
+        use reqwest::header::{HeaderMap, HeaderValue};
+        let mut headers = HeaderMap::new();
+        assert_eq!(RateBudget::from_headers(&headers), None);
+        headers.insert("x-ratelimit-limit", HeaderValue::from_static("40"));
+        headers.insert("x-ratelimit-remaining", HeaderValue::from_static("39"));
+        let budget = RateBudget::from_headers(&headers).unwrap();
+        assert_eq!((budget.limit, budget.remaining, budget.resets_at), (40, 39, None));
+        let reset = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + 60;
+        headers.insert("x-ratelimit-retry-after", HeaderValue::from(reset));
+        let resets_in = RateBudget::from_headers(&headers).unwrap().resets_at.unwrap() - Instant::now();
+        assert!(resets_in > Duration::from_secs(58) && resets_in <= Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn test_priority_tickets_go_first() {
+        let policy = TicketPolicy {
+            max_global: 1,
+            max_per_site: 4,
+            rate_limit_wait_time: Duration::new(60, 0),
+            max_per_minute: None,
+            per_origin: Vec::new(),
+        };
+        let ticketer = Ticketer::new(&policy);
+        let api = "api".to_string();
+        let images = "images".to_string();
+        let order = Mutex::new(Vec::new());
+        let first = ticketer.get_ticket(&api).await;
+        let normal = || async {
+            let _ticket = ticketer.get_ticket(&api).await;
+            order.lock().unwrap().push("normal");
+        };
+        let priority = || async {
+            // Start waiting after the normal request
+            tokio::task::yield_now().await;
+            let _ticket = ticketer.get_priority_ticket(&images).await;
+            order.lock().unwrap().push("priority");
+        };
+        let release = || async {
+            tokio::task::yield_now().await;
+            tokio::task::yield_now().await;
+            drop(first);
+        };
+        join!(normal(), priority(), release());
+        assert_eq!(*order.lock().unwrap(), vec!["priority", "normal"]);
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_waiters_release_permits() {
+        let policy = TicketPolicy {
+            max_global: 1,
+            max_per_site: 2,
+            rate_limit_wait_time: Duration::new(60, 0),
+            max_per_minute: None,
+            per_origin: Vec::new(),
+        };
+        let ticketer = Ticketer::new(&policy);
+        let origin = "origin".to_string();
+        let ticket = ticketer.get_ticket(&origin).await;
+        let waiting = tokio::time::timeout(Duration::from_millis(5), ticketer.get_priority_ticket(&origin)).await;
+        assert!(waiting.is_err());
+        drop(ticket);
+        assert!(ticketer.can_get_ticket(&origin));
+        ticketer.get_ticket(&origin).await;
+    }
+
+    #[tokio::test]
+    async fn test_token_bucket() {
+        let bucket = TokenBucket::new(1000);
+        let start = Instant::now();
+        // A full bucket lets the first second's worth through straight away
+        bucket.consume(1000).await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+        bucket.consume(100).await;
+        assert!(start.elapsed() >= Duration::from_millis(90));
+    }
 }
@@ -1,142 +1,878 @@
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
 
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ffi::OsStr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
-use log::debug;
+use log::{debug, error, info, warn};
 
-use crate::api::{chapter::ChapterData, manga::MangaFeedResponse};
-use crate::chapter::ChapterInfo;
+use crate::api::{
+    chapter::ChapterData,
+    manga::{MangaData, MangaFeedResponse, MangaResponse, TagData},
+    statistics::{MangaStatistics, MangaStatisticsResponse},
+};
+use crate::attestation::{load_or_create_key, Attestation, ATTESTATION_FILE_NAME};
+use crate::cache;
+use crate::chapter::METADATA_FILE_NAME;
+use crate::chapter::{completed_pages, ChapterInfo};
 use crate::common::*;
 use crate::context::ScrapeContext;
+use crate::cover::download_covers;
+use crate::info::{LanguageAvailability, NumberingGaps, TitleReport};
+use crate::journal::Journal;
+use crate::library::TitleRecord;
+use crate::names;
+use crate::naming::{is_contained, sanitize_component, NamingFields, PathTemplate};
+use crate::plan::{DownloadPlan, WorkEstimate};
+use crate::progress::chapter_weight;
 use crate::retry::{DownloadError, Result};
+use crate::selection::{DateRange, RangeSet};
+use crate::workspace::Workspace;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct TitleData {
+/// Most chapters MangaDex sends for one request for a feed
+pub const MAX_FEED_PAGE_SIZE: usize = 500;
+/// MangaDex doesn't page past this many results of a list, offset and limit together
+const MAX_FEED_WINDOW: usize = 10_000;
+
+/// The chapters of a feed, collected a page at a time. MangaDex counts the total for
+/// every page, so it changes when chapters are added or removed while we read, and
+/// chapters then move across pages: some come twice and others not at all.
+#[derive(Debug)]
+struct FeedPages {
+    page_size: usize,
     chapters: Vec<ChapterData>,
+    seen: HashSet<Uuid>,
+    offset: usize,
+    /// Requests made so far, which [`FeedPages::max_pages`] bounds
+    pages: usize,
+    total: Option<usize>,
+    /// Whether the total changed since the first page
+    changed: bool,
 }
 
-fn sanitize_chapter_name(name: &str) -> String {
-    let mut sanitized_name = String::new();
-    for c in name.chars() {
-        sanitized_name.push(match c {
-            '\x00' => '0',
-            '/' => '\\',
-            v => v,
-        });
+impl FeedPages {
+    fn new(page_size: usize) -> Self {
+        FeedPages {
+            page_size,
+            chapters: Vec::new(),
+            seen: HashSet::new(),
+            offset: 0,
+            pages: 0,
+            total: None,
+            changed: false,
+        }
+    }
+
+    /// Chapters to ask for in the next request, which mustn't reach past the window
+    fn limit(&self) -> usize {
+        self.page_size.min(MAX_FEED_WINDOW.saturating_sub(self.offset)).max(1)
+    }
+
+    /// How many requests reading the whole window takes, twice over to leave room for
+    /// pages that come back short
+    fn max_pages(&self) -> usize {
+        MAX_FEED_WINDOW.div_ceil(self.page_size.max(1)) * 2
+    }
+
+    /// Add the next page of the feed, which says there are total chapters. Returns
+    /// whether there are more pages to read.
+    fn add(&mut self, page: Vec<ChapterData>, total: usize) -> Result<bool> {
+        self.pages += 1;
+        if self.pages > self.max_pages() {
+            warn!("Gave up on the feed after {} pages", self.max_pages());
+            return Err(DownloadError::FeedTooLong(total));
+        }
+        if self.total.is_some_and(|previous| previous != total) {
+            debug!("The feed total changed from {:?} to {}", self.total, total);
+            self.changed = true;
+        }
+        self.total = Some(total);
+        if total > MAX_FEED_WINDOW {
+            return Err(DownloadError::FeedTooLong(total));
+        }
+        if page.is_empty() {
+            if self.offset < total {
+                warn!("The chapter list ended after {} of {} chapters", self.offset, total);
+            }
+            return Ok(false);
+        }
+        self.offset += page.len();
+        for chapter in page {
+            if self.seen.insert(chapter.id) {
+                self.chapters.push(chapter);
+            } else {
+                debug!("Chapter {} is in the feed twice", chapter.id);
+            }
+        }
+        Ok(self.offset < total)
+    }
+
+    /// Whether the chapters that were read add up to the total the feed gave last
+    fn is_consistent(&self) -> bool {
+        !self.changed && self.total.is_none_or(|total| self.chapters.len() >= total)
     }
-    sanitized_name
+}
+
+/// Line up the chapters of a title in every language by their numbers, to tell what
+/// each language has of them. Chapters without a number only add to the counts.
+fn language_availability(chapters: &[ChapterData]) -> Vec<LanguageAvailability> {
+    let mut numbers: Vec<f64> = Vec::new();
+    let mut languages: BTreeMap<&str, (Vec<f64>, usize)> = BTreeMap::new();
+    for chapter in chapters {
+        let (numbered, unnumbered) = languages
+            .entry(chapter.attributes.translated_language.as_str())
+            .or_default();
+        match chapter
+            .attributes
+            .chapter
+            .as_deref()
+            .and_then(|n| n.trim().parse().ok())
+        {
+            Some(number) => {
+                numbered.push(number);
+                numbers.push(number);
+            }
+            None => *unnumbered += 1,
+        }
+    }
+    numbers.sort_by(f64::total_cmp);
+    numbers.dedup();
+    let run = |(start, end): (f64, f64)| {
+        if start == end {
+            start.to_string()
+        } else {
+            format!("{}-{}", start, end)
+        }
+    };
+    languages
+        .into_iter()
+        .map(|(language, (mut numbered, unnumbered))| {
+            numbered.sort_by(f64::total_cmp);
+            // Several uploads of a chapter are still one chapter
+            numbered.dedup();
+            let mut missing = Vec::new();
+            let mut gap: Option<(f64, f64)> = None;
+            for &number in &numbers {
+                if numbered.binary_search_by(|n| n.total_cmp(&number)).is_ok() {
+                    missing.extend(gap.take().map(run));
+                } else {
+                    gap = Some((gap.map_or(number, |(start, _)| start), number));
+                }
+            }
+            missing.extend(gap.map(run));
+            LanguageAvailability {
+                language: language.to_owned(),
+                chapters: numbered.len() + unnumbered,
+                missing,
+            }
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TitleData {
+    id: Uuid,
+    manga: MangaData,
+    chapters: Vec<ChapterData>,
+    #[serde(default)]
+    lang_codes: Vec<String>,
+    #[serde(skip)]
+    path_template: PathTemplate,
+    /// Languages to name the title in, from `--title-lang`
+    #[serde(skip)]
+    title_langs: Vec<String>,
+    /// Chapter numbers to download, from `--chapters`
+    #[serde(skip)]
+    selection: Option<RangeSet<f64>>,
+    /// When chapters to download came out, from `--published-since` and `--published-before`
+    #[serde(skip)]
+    published: DateRange,
+    /// The chapters a download that is resumed had left to do
+    #[serde(skip)]
+    resumed: Option<HashSet<Uuid>>,
 }
 
 impl TitleData {
-    fn create_subdir_set(&self, base_path: &OsStr) -> Result<Vec<PathBuf>> {
+    fn choose_subdir_set(&self, base_path: &OsStr) -> Result<Vec<PathBuf>> {
         let mut subdir_set = Vec::new();
         debug!("Going to setup {} paths", self.chapters.len());
-        for (i, chapter) in self.chapters.iter().enumerate() {
-            let dir_num = i + 1;
-            let mut path = PathBuf::from(base_path);
-            let chapter_id = chapter.id;
-            // let chapter_name: &str = chapter.data.attributes.title.as_ref().unwrap_or("");
-            let chapter_name = if let Some(ref x) = chapter.attributes.title {
-                sanitize_chapter_name(x.as_str())
-            } else {
-                String::new()
-            };
-            debug!(
-                "Creating pathbuf from {:?}, {:?}, {:?}, {:?}",
-                path, dir_num, chapter_id, chapter_name
-            );
-            path.push(format!("md{:05} - {} - {}", dir_num, chapter_id, chapter_name));
-            debug!("Chose path {:?}", path);
-            if !path.is_dir() {
-                std::fs::create_dir(&path)?;
-                debug!("Created path {:?}", path);
+        let manga_title = self.display_title();
+        let authors = self.manga.creator_names("author");
+        // When downloading several languages, each one gets its own subtree unless the
+        // template already tells them apart
+        let split_by_language = self.lang_codes.len() > 1 && !self.path_template.uses("lang");
+        let mut language_indices: HashMap<&str, usize> = HashMap::new();
+        for chapter in self.chapters.iter() {
+            let language = chapter.attributes.translated_language.as_str();
+            let index = language_indices.entry(language).or_default();
+            *index += 1;
+            let fields = NamingFields::from_chapter(*index, chapter, manga_title, &authors);
+            debug!("Creating pathbuf from {:?}, {:?}", base_path, fields);
+            let mut relative = self.path_template.render(&fields);
+            if split_by_language {
+                relative = PathBuf::from(sanitize_component(language)).join(relative);
             }
+            if !is_contained(&relative) {
+                return Err(DownloadError::UnsafePath(relative));
+            }
+            let path = PathBuf::from(base_path).join(relative);
+            debug!("Chose path {:?}", path);
             subdir_set.push(path);
         }
+        // Templates without {id} give chapters with the same number, or none, the same
+        // directory, so each of those gets its id added to tell them apart
+        let mut counts: HashMap<&Path, usize> = HashMap::new();
+        for path in &subdir_set {
+            *counts.entry(path).or_default() += 1;
+        }
+        let shared: HashSet<PathBuf> = counts
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(path, _)| path.to_owned())
+            .collect();
+        for (path, chapter) in subdir_set.iter_mut().zip(&self.chapters) {
+            if shared.contains(path) {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                let id = chapter.id.to_string();
+                *path = path.with_file_name(format!("{} [{}]", name, &id[..8]));
+                debug!("Chapter {} shares its path, using {:?}", chapter.id, path);
+            }
+        }
         debug!("Successfully chose paths!");
         Ok(subdir_set)
     }
 
-    pub async fn download_for_title(title_id: Uuid, context: &ScrapeContext) -> Result<Self> {
-        let mut offset = 0usize;
-        let mut chapters: Vec<ChapterData> = Vec::new();
+    /// The chapters selected with `--chapters` and the date filters, and their directories. Paths are chosen
+    /// for the whole feed, so that `{index}` is the same whichever chapters are selected.
+    fn selected_chapters(&self, base_path: &OsStr) -> Result<Vec<(&ChapterData, PathBuf)>> {
+        let chapter_paths = self.choose_subdir_set(base_path)?;
+        Ok(self
+            .chapters
+            .iter()
+            .zip(chapter_paths)
+            .filter(|(chapter, _)| match self.selection {
+                Some(ref selection) => chapter
+                    .attributes
+                    .chapter
+                    .as_deref()
+                    .and_then(|number| number.trim().parse().ok())
+                    .is_some_and(|number| selection.contains(number)),
+                None => true,
+            })
+            .filter(|(chapter, _)| self.published.contains(chapter.release_date()))
+            .filter(|(chapter, _)| {
+                self.resumed
+                    .as_ref()
+                    .is_none_or(|resumed| resumed.contains(&chapter.id))
+            })
+            .collect())
+    }
+
+    async fn download_manga(title_id: Uuid, context: &ScrapeContext) -> Result<MangaResponse> {
+        let url = Url::parse(&format!(
+            "https://api.mangadex.org/manga/{}?includes[]=author&includes[]=artist",
+            title_id
+        ))
+        .unwrap();
+        debug!("Going to download manga information from {}", url);
+        context.http().get_json(&url).await
+    }
+
+    /// Rating, follows and comments of the title, which MangaDex only counts up every so
+    /// often
+    pub async fn download_statistics(&self, context: &ScrapeContext) -> Result<Option<MangaStatistics>> {
+        let url = Url::parse(&format!("https://api.mangadex.org/statistics/manga/{}", self.id)).unwrap();
+        debug!("Going to download title statistics from {}", url);
+        let mut response: MangaStatisticsResponse = context.http().get_json(&url).await?;
+        Ok(response.statistics.remove(&self.id))
+    }
+
+    /// Read the whole feed of a title. MangaDex only pages through the first
+    /// [`MAX_FEED_WINDOW`] chapters of a feed, so a longer one is read a language at a
+    /// time.
+    async fn download_feed(title_id: Uuid, lang_codes: &[String], context: &ScrapeContext) -> Result<Vec<ChapterData>> {
+        match Self::read_feed(title_id, lang_codes, context).await {
+            Err(DownloadError::FeedTooLong(total)) if lang_codes.len() > 1 => {
+                info!(
+                    "Title {} has {} chapters in the selected languages, reading them a language at a time",
+                    title_id, total
+                );
+                let mut chapters = Vec::new();
+                for lang_code in lang_codes {
+                    chapters.extend(Self::read_feed(title_id, std::slice::from_ref(lang_code), context).await?);
+                }
+                Ok(chapters)
+            }
+            result => result,
+        }
+    }
 
+    /// Read the feed of a title in the given languages. A feed that changes while it is
+    /// read is read again from the start, bypassing the response cache, once.
+    async fn read_feed(title_id: Uuid, lang_codes: &[String], context: &ScrapeContext) -> Result<Vec<ChapterData>> {
+        let languages: String = lang_codes
+            .iter()
+            .map(|lang_code| format!("&translatedLanguage[]={}", lang_code))
+            .collect();
+        let mut fresh = false;
         loop {
-            let url = Url::parse(&format!(
-                "https://api.mangadex.org/manga/{}/feed?offset={}&limit=500&translatedLanguage[]={}&order[volume]=asc&order[chapter]=asc",
-                title_id,
-                offset,
-                context.lang_code
-            )).unwrap();
-            debug!("Going to download manga title information from {}", url);
-            let origin = url.origin();
-            let mut resp = context
-                .with_retry_for_origin(&origin, || async {
-                    Ok(CLIENT
-                        .get(url.clone())
-                        .send()
-                        .await?
-                        .error_for_status()?
-                        .json::<MangaFeedResponse>()
-                        .await?)
-                })
-                .await?;
-            let num_just_added = resp.data.len();
-            chapters.append(&mut resp.data);
-            offset += num_just_added;
-            if offset >= resp.total {
-                break;
+            let mut feed = FeedPages::new(context.feed_page_size);
+            loop {
+                let url = Url::parse(&format!(
+                    "https://api.mangadex.org/manga/{}/feed?offset={}&limit={}{}&order[volume]=asc&order[chapter]=asc&includes[]=scanlation_group",
+                    title_id,
+                    feed.offset,
+                    feed.limit(),
+                    languages
+                )).unwrap();
+                debug!("Going to download manga title information from {}", url);
+                let resp: MangaFeedResponse = if fresh {
+                    context.http().get_json(&url).await?
+                } else {
+                    context.http().get_json_cached(&url, cache::FEED_TTL).await?
+                };
+                if !feed.add(resp.data, resp.total)? {
+                    break;
+                }
             }
+            if feed.is_consistent() || fresh {
+                if !feed.is_consistent() {
+                    warn!(
+                        "The chapter list of title {} kept changing while it was read, some chapters may be missing",
+                        title_id
+                    );
+                }
+                return Ok(feed.chapters);
+            }
+            warn!(
+                "The chapter list of title {} changed while it was read, reading it again",
+                title_id
+            );
+            fresh = true;
         }
+    }
+
+    pub async fn download_for_title(title_id: Uuid, lang_codes: &[String], context: &ScrapeContext) -> Result<Self> {
+        let manga = Self::download_manga(title_id, context).await?;
+        let mut chapters = Self::download_feed(title_id, lang_codes, context).await?;
+        // Chapters hosted elsewhere have no pages we could download
+        chapters.retain(|chapter| match chapter.attributes.external_url {
+            Some(ref url) => {
+                info!("Skipping chapter {}, it is hosted at {}", chapter.id, url);
+                false
+            }
+            None => true,
+        });
         debug!("Got Chapters");
-        Ok(TitleData { chapters })
+        Ok(TitleData {
+            id: title_id,
+            manga: manga.data,
+            chapters,
+            lang_codes: lang_codes.to_vec(),
+            path_template: context.path_template.clone(),
+            title_langs: context.title_langs.clone(),
+            selection: None,
+            published: context.published,
+            resumed: None,
+        })
     }
 
-    fn setup_title_bar(&self, length: u64, context: &ScrapeContext) -> indicatif::ProgressBar {
-        let style = indicatif::ProgressStyle::default_bar()
-            .template("<{elapsed_precise}> [{bar:80.yellow/red}] Downloading chapter {pos}/{len}")
-            .progress_chars("=>-");
-        let title_bar = context.progress.add(indicatif::ProgressBar::new(length));
-        title_bar.set_style(style);
-        title_bar.tick();
-        title_bar
+    /// The title of a download that was interrupted, to download the chapters it had
+    /// left with the template it used
+    pub fn from_journal(journal: Journal) -> Result<Self> {
+        let mut title = journal.title;
+        title.path_template = PathTemplate::parse(&journal.path_template).map_err(DownloadError::JournalError)?;
+        title.title_langs = journal.title_langs;
+        title.resumed = Some(journal.chapters.into_iter().collect());
+        Ok(title)
     }
 
-    pub async fn download_to_directory(self, path: &impl AsRef<OsStr>, context: &ScrapeContext) -> Result<()> {
-        use futures::stream::{FuturesUnordered, StreamExt};
-        let title_bar = self.setup_title_bar(self.chapters.len() as u64, context);
-        debug!("Determining chapter paths");
-        let chapter_paths = self.create_subdir_set(path.as_ref())?;
+    /// Fetch a title for downloading, or replay it from `--from-snapshot`, and save
+    /// it for `--snapshot-feed`
+    pub async fn resolve(title_id: Uuid, lang_codes: &[String], context: &ScrapeContext) -> Result<Self> {
+        let mut title = match context.from_snapshot {
+            Some(ref path) => {
+                info!("Using the chapter list of title {} from {:?}", title_id, path);
+                let mut title = Self::read_snapshot(path)?;
+                title.path_template = context.path_template.clone();
+                title.published = context.published;
+                title.title_langs = context.title_langs.clone();
+                title
+            }
+            None => Self::download_for_title(title_id, lang_codes, context).await?,
+        };
+        names::resolve_creator_names(&mut title.manga, context).await?;
+        names::resolve_group_names(&mut title.chapters, context).await?;
+        if let Some(ref path) = context.snapshot_feed {
+            info!("Saving the chapter list of title {} to {:?}", title_id, path);
+            std::fs::write(path, serde_json::to_vec_pretty(&title)?)?;
+        }
+        title.selection = context.chapters.clone();
+        Ok(title)
+    }
 
-        debug!("{:#?}", chapter_paths);
+    /// A title and its chapter list as saved with `--snapshot-feed`
+    pub fn read_snapshot(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
 
-        let mut tasks = self
-            .chapters
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// What every language has of the title, not only the ones being downloaded. This
+    /// reads the feed again without the language filter, unless the title came from
+    /// `--from-snapshot`, whose chapters are all there is to go by.
+    pub async fn language_availability(&self, context: &ScrapeContext) -> Result<Vec<LanguageAvailability>> {
+        if context.from_snapshot.is_some() {
+            return Ok(language_availability(&self.chapters));
+        }
+        let chapters = match Self::read_feed(self.id, &[], context).await {
+            Err(DownloadError::FeedTooLong(_)) => {
+                Self::download_feed(self.id, &self.manga.attributes.available_languages(), context).await?
+            }
+            result => result?,
+        };
+        Ok(language_availability(&chapters))
+    }
+
+    pub fn report(&self) -> TitleReport {
+        let mut report = TitleReport::new(&self.manga, &self.chapters);
+        report.title = self.display_title().map(str::to_owned);
+        report
+    }
+
+    /// Every chapter in the feed, before `--chapters` picks from them
+    pub fn chapters(&self) -> &[ChapterData] {
+        &self.chapters
+    }
+
+    /// The title in the language `--title-lang` asks for
+    pub fn display_title(&self) -> Option<&str> {
+        self.manga.attributes.title_in(&self.title_langs)
+    }
+
+    /// Save chapters with a different template than the one given on the command line,
+    /// e.g. the one a title was originally downloaded with
+    pub fn set_path_template(&mut self, path_template: PathTemplate) {
+        self.path_template = path_template;
+    }
+
+    /// Estimate the work for downloading into path from the page counts in the feed
+    pub fn estimate_work(&self, path: &impl AsRef<OsStr>, context: &ScrapeContext) -> Result<WorkEstimate> {
+        let mut chapters = 0;
+        let mut complete_chapters = 0;
+        let mut pages = 0;
+        for (chapter_data, path) in self.selected_chapters(path.as_ref())? {
+            if completed_pages(&path, chapter_data, context).is_some() {
+                complete_chapters += 1;
+            } else {
+                chapters += 1;
+                pages += chapter_data.attributes.pages;
+            }
+        }
+        Ok(WorkEstimate::new(chapters, complete_chapters, pages, &context.history))
+    }
+
+    /// Resolve every chapter and its pages like a download would, but only collect the
+    /// files that would be created. The at-home server is still asked for page lists.
+    pub async fn plan_download(
+        &self,
+        path: &impl AsRef<OsStr>,
+        plan: &mut DownloadPlan,
+        context: &ScrapeContext,
+    ) -> Result<()> {
+        for (chapter_data, path) in self.selected_chapters(path.as_ref())? {
+            if completed_pages(&path, chapter_data, context).is_some() {
+                plan.add_complete_chapter();
+                continue;
+            }
+            let mut chapter = ChapterInfo::from_chapter_data(chapter_data.clone(), context).await?;
+            plan.add_chapter(chapter.plan(&path, context)?);
+        }
+        Ok(())
+    }
+
+    pub async fn download_to_directory(
+        self,
+        path: &impl AsRef<OsStr>,
+        workspace: &Workspace,
+        context: &ScrapeContext,
+    ) -> Result<()> {
+        use futures::{
+            stream::{FuturesUnordered, StreamExt},
+            FutureExt,
+        };
+        if !context.title_filter.matches(&self.manga) {
+            context.print_status(format!(
+                "Skipping title {}, it doesn't pass --content-rating or --exclude-tag",
+                self.id
+            ));
+            return Ok(());
+        }
+        // Worth knowing before archiving a title that isn't complete on MangaDex
+        for gaps in NumberingGaps::find(&self.chapters) {
+            let name = self.display_title().unwrap_or("Untitled");
+            warn!("Chapter numbers of {} have gaps, {}", name, gaps);
+            context.stats.add_numbering_gaps(name, &gaps);
+        }
+        if let Some(layout) = context.layout {
+            layout.prepare(Path::new(path))?;
+        }
+        if context.write_metadata {
+            write_if_changed(
+                &Path::new(path).join(METADATA_FILE_NAME),
+                &serde_json::to_vec_pretty(&self.manga)?,
+            )?;
+        }
+        if let Some(selection) = context.covers {
+            download_covers(self.id, selection, Path::new(path), context).await?;
+        }
+        debug!("Determining chapter paths");
+        let chapters = self.selected_chapters(path.as_ref())?;
+
+        debug!("{:#?}", chapters.iter().map(|(_, path)| path).collect::<Vec<_>>());
+        let chapters: Vec<_> = chapters
             .into_iter()
-            .zip(chapter_paths.into_iter())
             .map(|(chapter_data, path)| {
+                let complete = completed_pages(&path, chapter_data, context);
+                (chapter_data, path, complete)
+            })
+            .collect();
+        // Only chapters that are downloaded are journaled, so that a re-run that finds
+        // nothing new writes nothing
+        let pending: Vec<Uuid> = chapters
+            .iter()
+            .filter(|(_, _, complete)| complete.is_none())
+            .map(|(chapter_data, _, _)| chapter_data.id)
+            .collect();
+        let journal_file = if pending.is_empty() {
+            None
+        } else {
+            let journal = Journal {
+                title: self.clone(),
+                path_template: self.path_template.as_str().to_owned(),
+                title_langs: self.title_langs.clone(),
+                chapters: pending,
+            };
+            Some(journal.start(Path::new(path))?)
+        };
+        let journal = journal_file.as_ref();
+
+        let title_bar = context.progress.title_bar(
+            self.display_title().unwrap_or("Untitled"),
+            chapters.iter().map(|(chapter, _, _)| chapter_weight(chapter)).sum(),
+        );
+        let series = self.display_title().map(str::to_owned);
+        let original_language = self.manga.attributes.original_language().map(str::to_owned);
+        let authors = self.manga.creator_names("author");
+        let artists = self.manga.creator_names("artist");
+        let description = self.manga.attributes.description().map(str::to_owned);
+        let (genres, tags): (Vec<_>, Vec<_>) = self
+            .manga
+            .attributes
+            .tags()
+            .into_iter()
+            .partition(|tag| tag.group() == Some("genre"));
+        let tag_names = |tags: Vec<TagData>| -> Vec<String> {
+            tags.iter()
+                .filter_map(|tag| tag.display_name().map(str::to_owned))
+                .collect()
+        };
+        let (genres, tags) = (tag_names(genres), tag_names(tags));
+        let chapter_count = chapters.len();
+        let mut tasks = chapters
+            .into_iter()
+            .map(|(chapter_data, path, complete)| {
                 let title_bar = &title_bar;
+                let series = series.as_deref();
+                let original_language = original_language.as_deref();
+                let (authors, artists) = (&authors, &artists);
+                let (description, genres, tags) = (&description, &genres, &tags);
+                let failed_path = path.clone();
                 async move {
-                    let chapter = ChapterInfo::from_chapter_data(chapter_data.clone(), context).await?;
+                    if let Some(pages) = complete {
+                        debug!("Chapter {} is already complete in {:?}", chapter_data.id, path);
+                        context.stats.add_skipped_chapter();
+                        title_bar.inc(chapter_weight(chapter_data));
+                        return Ok::<_, DownloadError>(Some(pages));
+                    }
+                    let mut chapter = ChapterInfo::from_chapter_data(chapter_data.clone(), context).await?;
+                    chapter.set_series(series.map(str::to_owned));
+                    chapter.set_original_language(original_language.map(str::to_owned));
+                    chapter.set_creators(authors.clone(), artists.clone());
+                    chapter.set_details(description.clone(), genres.clone(), tags.clone());
                     debug!("Got data for {}: {:?}", chapter_data.id, path);
-                    title_bar.set_position(title_bar.position() + 1);
                     if context.verbose {
                         debug!("Chapter API data: {:#?}", chapter);
                     }
-                    chapter.download_to_directory(&path, context).await?;
-                    Ok::<(), DownloadError>(())
+                    chapter.download_to_directory(&path, workspace, context).await?;
+                    title_bar.inc(chapter_weight(chapter_data));
+                    if let Some(journal) = journal {
+                        journal.chapter_done(chapter_data.id)?;
+                    }
+                    Ok(None)
                 }
+                .map(move |result| result.map_err(|e| (failed_path, e)))
             })
             .collect::<FuturesUnordered<_>>();
 
+        let mut complete_chapters = 0;
+        let mut complete_pages = 0;
+        let mut failed_chapters = 0;
+        let mut new_chapters = 0;
         while let Some(result) = tasks.next().await {
-            result?;
+            match result {
+                Ok(Some(pages)) => {
+                    complete_chapters += 1;
+                    complete_pages += pages;
+                }
+                Ok(None) => new_chapters += 1,
+                // Picked up again by the next run, like an interrupted chapter
+                Err((path, DownloadError::Skipped)) => info!("Skipped {:?}", path),
+                Err((path, e)) => {
+                    context.stats.add_failed_chapter();
+                    if !context.keep_going {
+                        return Err(e);
+                    }
+                    error!("Failed to download {:?}: {}", path, e);
+                    context.stats.add_failure(path.display().to_string(), &e);
+                    failed_chapters += 1;
+                }
+            }
+        }
+        drop(tasks);
+        if let Some(journal_file) = journal_file {
+            journal_file.finish();
+        }
+
+        // A re-run that finds nothing new leaves the library untouched
+        let up_to_date = complete_chapters == chapter_count;
+        let existing_record = TitleRecord::read(Path::new(path))?;
+        let status = self.manga.attributes.status;
+        if !up_to_date || existing_record.is_none_or(|record| record.status != status) {
+            let record = TitleRecord {
+                title_id: self.id,
+                lang_code: self.lang_codes.join(","),
+                path_template: Some(self.path_template.as_str().to_owned()),
+                status,
+                last_checked: chrono::Utc::now(),
+                title: self.display_title().map(str::to_owned),
+                authors: self.manga.creator_names("author"),
+                original_language: self.manga.attributes.original_language().map(str::to_owned),
+            };
+            record.write(Path::new(path))?;
+        }
+        if context.attest && (!up_to_date || !Path::new(path).join(ATTESTATION_FILE_NAME).exists()) {
+            let (title_id, root) = (self.id, PathBuf::from(path));
+            run_blocking(move || {
+                let key = load_or_create_key()?;
+                Attestation::create(title_id, &root, &key)?.write(&root)
+            })
+            .await?;
+        }
+        if failed_chapters == 0 && new_chapters > 0 {
+            context
+                .hooks
+                .title_done(
+                    &std::fs::canonicalize(path.as_ref())?,
+                    self.id.to_string(),
+                    self.display_title(),
+                    new_chapters,
+                    context,
+                )
+                .await;
+        }
+        if up_to_date {
+            context.print_status(format!(
+                "Title {} already complete ({} chapters, {} pages)",
+                self.id, complete_chapters, complete_pages
+            ));
         }
 
         title_bar.finish_and_clear();
+        if failed_chapters > 0 {
+            return Err(DownloadError::DownloadsFailed(failed_chapters));
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::layout::LibraryLayout;
+
+    fn feed_page(ids: std::ops::Range<u128>) -> Vec<ChapterData> {
+        ids.map(|id| {
+            serde_json::from_value(serde_json::json!({
+                "id": Uuid::from_u128(id),
+                "type": "chapter",
+                "attributes": {"translatedLanguage": "en", "pages": 1},
+                "relationships": []
+            }))
+            .unwrap()
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_language_availability() {
+        let chapters: Vec<ChapterData> = [
+            ("en", Some("1")),
+            ("en", Some("2")),
+            ("en", Some("2")),
+            ("en", Some("3")),
+            ("es-la", Some("1")),
+            ("es-la", Some("2.5")),
+            ("es-la", None),
+            ("fr", Some("3")),
+        ]
+        .iter()
+        .map(|(language, chapter)| {
+            serde_json::from_value(serde_json::json!({
+                "id": Uuid::from_u128(rand::random()),
+                "type": "chapter",
+                "attributes": {"chapter": chapter, "translatedLanguage": language, "pages": 1},
+                "relationships": []
+            }))
+            .unwrap()
+        })
+        .collect();
+        let availability: Vec<String> = language_availability(&chapters).iter().map(|l| l.to_string()).collect();
+        assert_eq!(
+            availability,
+            vec![
+                "en: 3 chapters, missing 2.5",
+                "es-la: 3 chapters, missing 2, 3",
+                "fr: 1 chapters, missing 1-2.5"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_feed_pages() {
+        let mut feed = FeedPages::new(2);
+        assert!(feed.add(feed_page(0..2), 4).unwrap());
+        assert!(!feed.add(feed_page(2..4), 4).unwrap());
+        assert!(feed.is_consistent());
+
+        // A chapter removed from the first page moves a chapter back onto it, which is
+        // then missed, and one added to it shows the last chapter again
+        let mut feed = FeedPages::new(2);
+        assert!(feed.add(feed_page(0..2), 4).unwrap());
+        assert!(!feed.add(feed_page(3..4), 3).unwrap());
+        assert!(!feed.is_consistent());
+        let mut feed = FeedPages::new(2);
+        assert!(feed.add(feed_page(0..2), 4).unwrap());
+        assert!(feed.add(feed_page(1..3), 5).unwrap());
+        assert!(!feed.add(feed_page(3..4), 5).unwrap());
+        assert_eq!(feed.chapters.len(), 4);
+        assert!(!feed.is_consistent());
+
+        // An empty page ends the feed, whatever its total says
+        let mut feed = FeedPages::new(2);
+        assert!(feed.add(feed_page(0..2), 4).unwrap());
+        assert!(!feed.add(Vec::new(), 4).unwrap());
+        assert!(!feed.is_consistent());
+
+        // Requests stay inside the window MangaDex pages through
+        let mut feed = FeedPages::new(MAX_FEED_PAGE_SIZE);
+        assert_eq!(feed.limit(), MAX_FEED_PAGE_SIZE);
+        feed.offset = MAX_FEED_WINDOW - 100;
+        assert_eq!(feed.limit(), 100);
+        assert!(matches!(
+            feed.add(feed_page(0..1), MAX_FEED_WINDOW + 1),
+            Err(DownloadError::FeedTooLong(10_001))
+        ));
+
+        // A feed that keeps coming back short is given up on rather than read forever
+        let mut feed = FeedPages::new(MAX_FEED_PAGE_SIZE);
+        let max_pages = feed.max_pages() as u128;
+        for i in 0..max_pages {
+            assert!(feed.add(feed_page(i..i + 1), MAX_FEED_WINDOW).unwrap());
+        }
+        assert!(matches!(
+            feed.add(feed_page(max_pages..max_pages + 1), MAX_FEED_WINDOW),
+            Err(DownloadError::FeedTooLong(MAX_FEED_WINDOW))
+        ));
+    }
+
+    #[test]
+    fn test_shared_chapter_paths() {
+        let chapter = |id: u128, number: Option<&str>| {
+            serde_json::json!({
+                "id": Uuid::from_u128(id),
+                "type": "chapter",
+                "attributes": {"chapter": number, "translatedLanguage": "en", "pages": 1},
+                "relationships": []
+            })
+        };
+        let mut title: TitleData = serde_json::from_value(serde_json::json!({
+            "id": Uuid::from_u128(1),
+            "manga": {
+                "id": Uuid::from_u128(1),
+                "type": "manga",
+                "attributes": {"title": {"en": "Komi-san"}},
+                "relationships": []
+            },
+            "chapters": [chapter(2 << 100, Some("1")), chapter(3 << 100, Some("2")), chapter(4 << 100, Some("2")),
+                         chapter(5 << 100, None), chapter(6 << 100, None)],
+            "lang_codes": ["en"]
+        }))
+        .unwrap();
+        title.set_path_template(LibraryLayout::Komga.path_template());
+        let names: Vec<String> = title
+            .choose_subdir_set(OsStr::new("lib"))
+            .unwrap()
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        let prefix = |id: u128| Uuid::from_u128(id << 100).to_string()[..8].to_owned();
+        assert_eq!(names[0], "Komi-san Ch. 0001 []");
+        assert_eq!(names[1], format!("Komi-san Ch. 0002 [] [{}]", prefix(3)));
+        assert_eq!(names[2], format!("Komi-san Ch. 0002 [] [{}]", prefix(4)));
+        assert_ne!(names[3], names[4]);
+        assert!(names[4].ends_with(&format!(" [{}]", prefix(6))));
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let snapshot = serde_json::json!({
+            "id": "76ee7069-23b4-493c-bc44-34ccbf3051a8",
+            "manga": {
+                "id": "76ee7069-23b4-493c-bc44-34ccbf3051a8",
+                "type": "manga",
+                "attributes": {"title": {"en": "Tomo-chan wa Onna no ko!"}, "status": "completed",
+                               "altTitles": [{"ja-ro": "Tomo-chan wa Onna no Ko!"}]},
+                "relationships": []
+            },
+            "chapters": [{
+                "id": "417d64e1-6c88-48f8-b507-ad43e9636888",
+                "type": "chapter",
+                "attributes": {"chapter": "953.5", "translatedLanguage": "en", "pages": 2},
+                "relationships": []
+            }],
+            "lang_codes": ["en"]
+        });
+        let mut title: TitleData = serde_json::from_value(snapshot).unwrap();
+        assert_eq!(title.display_title(), Some("Tomo-chan wa Onna no ko!"));
+        // Languages the title doesn't have fall through to the next one
+        title.title_langs = vec!["ja".to_owned(), "ja-ro".to_owned()];
+        assert_eq!(title.display_title(), Some("Tomo-chan wa Onna no Ko!"));
+        title.title_langs = vec!["ko".to_owned()];
+        assert_eq!(title.display_title(), Some("Tomo-chan wa Onna no ko!"));
+        assert_eq!(title.lang_codes, vec!["en"]);
+
+        let path = std::env::temp_dir().join(format!("mdscrape-test-{}.json", Uuid::from_u128(rand::random())));
+        let saved = serde_json::to_vec_pretty(&title).unwrap();
+        std::fs::write(&path, &saved).unwrap();
+        let replayed = TitleData::read_snapshot(&path).unwrap();
+        assert_eq!(replayed.id(), title.id());
+        // Saving a replayed snapshot again gives exactly the same file
+        assert_eq!(serde_json::to_vec_pretty(&replayed).unwrap(), saved);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
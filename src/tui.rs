@@ -1,31 +1,437 @@
-use log::{Log, Metadata, Record};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-pub enum RenderTarget {
-    Stdout,
+use log::{LevelFilter, Log, Metadata, Record};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Row, Table};
+use ratatui::Frame;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::common::OpaqueResult;
+use crate::context::ScrapeContext;
+use crate::retry::{DownloadError, Result};
+use crate::throttle::OriginState;
+use crate::units::{format_duration, format_size};
+
+/// How many log messages the dashboard keeps
+const MAX_MESSAGES: usize = 100;
+/// How often the dashboard is redrawn and checked for key presses
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// What the user wants a chapter on the dashboard to do
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChapterControl {
+    Running,
+    /// Don't start any more pages until it is resumed
+    Paused,
+    /// Give up on the chapter, it is downloaded again by the next run
+    Skipped,
+}
+
+#[derive(Clone, Debug)]
+struct ActiveChapter {
+    id: Uuid,
+    name: String,
+    done: usize,
+    pages: usize,
+    control: ChapterControl,
+}
+
+#[derive(Debug, Default)]
+struct DashboardState {
+    chapters: Vec<ActiveChapter>,
+    selected: usize,
+    messages: VecDeque<String>,
+}
+
+/// State shared between the downloads and the `--tui` dashboard: the chapters being
+/// downloaded, what the user asked them to do, and recent log messages
+#[derive(Debug, Default)]
+pub struct Dashboard {
+    state: Mutex<DashboardState>,
+    changed: Notify,
 }
 
-pub struct TUIProgressBar {}
+/// A chapter's place on the dashboard, removed when the download ends however it ends
+pub struct ChapterEntry {
+    dashboard: Arc<Dashboard>,
+    id: Uuid,
+}
+
+impl Drop for ChapterEntry {
+    fn drop(&mut self) {
+        let mut state = self.dashboard.state.lock().unwrap();
+        state.chapters.retain(|chapter| chapter.id != self.id);
+        state.selected = state.selected.min(state.chapters.len().saturating_sub(1));
+    }
+}
+
+impl Dashboard {
+    /// Show a chapter with `done` of its `pages` already on disk
+    pub fn start_chapter(self: &Arc<Self>, id: Uuid, name: String, pages: usize, done: usize) -> ChapterEntry {
+        self.state.lock().unwrap().chapters.push(ActiveChapter {
+            id,
+            name,
+            done,
+            pages,
+            control: ChapterControl::Running,
+        });
+        ChapterEntry {
+            dashboard: self.clone(),
+            id,
+        }
+    }
+
+    pub fn page_done(&self, id: Uuid) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(chapter) = state.chapters.iter_mut().find(|chapter| chapter.id == id) {
+            chapter.done += 1;
+        }
+    }
+
+    /// Called before each page of a chapter. Waits while the chapter is paused, and
+    /// fails once it is skipped.
+    pub async fn checkpoint(&self, id: Uuid) -> Result<()> {
+        loop {
+            // Registered before looking, so that a change in between isn't missed
+            let changed = self.changed.notified();
+            let control = {
+                let state = self.state.lock().unwrap();
+                state
+                    .chapters
+                    .iter()
+                    .find(|chapter| chapter.id == id)
+                    .map_or(ChapterControl::Running, |chapter| chapter.control)
+            };
+            match control {
+                ChapterControl::Running => return Ok(()),
+                ChapterControl::Skipped => return Err(DownloadError::Skipped),
+                ChapterControl::Paused => changed.await,
+            }
+        }
+    }
+
+    fn select(&self, offset: isize) {
+        let mut state = self.state.lock().unwrap();
+        let last = state.chapters.len().saturating_sub(1);
+        state.selected = state.selected.saturating_add_signed(offset).min(last);
+    }
+
+    fn control_selected(&self, change: impl FnOnce(ChapterControl) -> ChapterControl) {
+        let mut state = self.state.lock().unwrap();
+        let selected = state.selected;
+        if let Some(chapter) = state.chapters.get_mut(selected) {
+            chapter.control = change(chapter.control);
+        }
+        drop(state);
+        self.changed.notify_waiters();
+    }
+
+    fn toggle_pause(&self) {
+        self.control_selected(|control| match control {
+            ChapterControl::Running => ChapterControl::Paused,
+            ChapterControl::Paused => ChapterControl::Running,
+            ChapterControl::Skipped => ChapterControl::Skipped,
+        });
+    }
+
+    fn skip(&self) {
+        self.control_selected(|_| ChapterControl::Skipped);
+    }
+
+    pub fn add_message(&self, message: String) {
+        let mut state = self.state.lock().unwrap();
+        if state.messages.len() == MAX_MESSAGES {
+            state.messages.pop_front();
+        }
+        state.messages.push_back(message);
+    }
+}
 
-pub struct TUI {
-    render_info: Vec<TUIProgressBar>,
+/// Shows log messages on the dashboard, since anything written to the terminal
+/// would end up in the middle of it
+pub struct DashboardLogger {
+    dashboard: Arc<Dashboard>,
+    level: LevelFilter,
 }
 
-impl TUI {
-    fn create_progress_bar(&self) -> TUIProgressBar {
-        unimplemented!()
+impl DashboardLogger {
+    pub fn new(dashboard: Arc<Dashboard>, level: LevelFilter) -> Self {
+        DashboardLogger { dashboard, level }
     }
-    fn render(&mut self) {}
 }
 
-impl Log for TUI {
-    fn enabled(&self, _metadata: &Metadata) -> bool {
-        // TODO: this
-        false
+impl Log for DashboardLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
     }
+
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            // TODO: this
+            self.dashboard
+                .add_message(format!("{:<5} {}", record.level(), record.args()));
         }
     }
+
     fn flush(&self) {}
 }
+
+/// Everything else the dashboard shows, taken from the context on every redraw
+struct RunView {
+    pages: usize,
+    bytes: u64,
+    elapsed: Duration,
+    waiting: usize,
+    origins: Vec<OriginState<String>>,
+}
+
+impl RunView {
+    fn from_context(context: &ScrapeContext, started: Instant) -> Self {
+        let mut origins: Vec<OriginState<String>> = context
+            .throttle_states()
+            .into_iter()
+            .map(|state| OriginState {
+                origin: state.origin.ascii_serialization(),
+                in_use: state.in_use,
+                recent_requests: state.recent_requests,
+                locked_for: state.locked_for,
+                budget: state.budget,
+            })
+            .collect();
+        origins.sort_by(|a, b| a.origin.cmp(&b.origin));
+        RunView {
+            pages: context.stats.pages(),
+            bytes: context.stats.bytes(),
+            elapsed: started.elapsed(),
+            waiting: context.waiting_requests(),
+            origins,
+        }
+    }
+}
+
+fn render(frame: &mut Frame, dashboard: &Dashboard, run: &RunView) {
+    let state = dashboard.state.lock().unwrap();
+    let [totals_area, chapters_area, throttle_area, messages_area, help_area] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(5),
+        Constraint::Length(run.origins.len().clamp(1, 8) as u16 + 3),
+        Constraint::Length(8),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let seconds = run.elapsed.as_secs_f64().max(1.0);
+    let totals = format!(
+        "{} pages, {} in {} at {}/s, {} requests waiting for a connection",
+        run.pages,
+        format_size(run.bytes),
+        format_duration(run.elapsed),
+        format_size((run.bytes as f64 / seconds) as u64),
+        run.waiting
+    );
+    frame.render_widget(
+        Paragraph::new(totals).block(Block::bordered().title(" mdscrape ")),
+        totals_area,
+    );
+
+    let chapters: Vec<ListItem> = state
+        .chapters
+        .iter()
+        .map(|chapter| {
+            let control = match chapter.control {
+                ChapterControl::Running => "running",
+                ChapterControl::Paused => "paused",
+                ChapterControl::Skipped => "skipping",
+            };
+            ListItem::new(format!(
+                "{:>4}/{:<4} {:<8} {}",
+                chapter.done, chapter.pages, control, chapter.name
+            ))
+        })
+        .collect();
+    let title = format!(" Downloading {} chapters ", state.chapters.len());
+    let mut list_state = ListState::default().with_selected((!state.chapters.is_empty()).then_some(state.selected));
+    frame.render_stateful_widget(
+        List::new(chapters)
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED)),
+        chapters_area,
+        &mut list_state,
+    );
+
+    let rows = run.origins.iter().map(|origin| {
+        Row::new(vec![
+            origin.origin.clone(),
+            origin.in_use.to_string(),
+            origin.recent_requests.map_or_else(|| "-".to_owned(), |n| n.to_string()),
+            origin.budget.map_or_else(
+                || "-".to_owned(),
+                |(remaining, limit)| format!("{}/{}", remaining, limit),
+            ),
+            origin
+                .locked_for
+                .map_or_else(|| "-".to_owned(), |wait| format!("for {}", format_duration(wait))),
+        ])
+    });
+    let throttle = Table::new(
+        rows,
+        [
+            Constraint::Min(30),
+            Constraint::Length(8),
+            Constraint::Length(11),
+            Constraint::Length(10),
+            Constraint::Length(14),
+        ],
+    )
+    .header(
+        Row::new(vec!["Origin", "In use", "Last minute", "Budget", "Rate limited"])
+            .style(Style::new().add_modifier(Modifier::BOLD)),
+    )
+    .block(Block::bordered().title(" Throttle "));
+    frame.render_widget(throttle, throttle_area);
+
+    let shown = messages_area.height.saturating_sub(2) as usize;
+    let messages: Vec<ListItem> = state
+        .messages
+        .iter()
+        .skip(state.messages.len().saturating_sub(shown))
+        .map(|message| ListItem::new(message.as_str()))
+        .collect();
+    frame.render_widget(
+        List::new(messages).block(Block::bordered().title(" Recent messages ")),
+        messages_area,
+    );
+
+    frame.render_widget(
+        Paragraph::new("up/down: select chapter   p: pause/resume   s: skip   q: quit"),
+        help_area,
+    );
+}
+
+/// Handle the key presses waiting, returns whether the user asked to quit
+fn handle_keys(dashboard: &Dashboard) -> std::io::Result<bool> {
+    while event::poll(Duration::ZERO)? {
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => dashboard.select(-1),
+            KeyCode::Down | KeyCode::Char('j') => dashboard.select(1),
+            KeyCode::Char('p') | KeyCode::Char(' ') => dashboard.toggle_pause(),
+            KeyCode::Char('s') => dashboard.skip(),
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(true),
+            // The terminal is in raw mode, so Ctrl-C arrives as a key
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(true),
+            _ => {}
+        }
+    }
+    Ok(false)
+}
+
+/// Run the scrape with the full screen dashboard of `--tui`. Quitting the dashboard
+/// stops the scrape like Ctrl-C does.
+pub async fn run_with_dashboard(
+    dashboard: &Dashboard,
+    context: &ScrapeContext,
+    scrape: impl std::future::Future<Output = OpaqueResult<()>>,
+) -> OpaqueResult<()> {
+    let mut terminal = ratatui::try_init()?;
+    let started = Instant::now();
+    let ui = async {
+        let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+        loop {
+            interval.tick().await;
+            let run = RunView::from_context(context, started);
+            terminal.draw(|frame| render(frame, dashboard, &run))?;
+            if handle_keys(dashboard)? {
+                return Ok::<(), std::io::Error>(());
+            }
+        }
+    };
+    let result = tokio::select! {
+        result = scrape => result,
+        quit = ui => match quit {
+            Ok(()) => Err(DownloadError::Interrupted.into()),
+            Err(e) => Err(e.into()),
+        },
+    };
+    ratatui::try_restore()?;
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ratatui::backend::TestBackend;
+
+    #[tokio::test]
+    async fn test_pause_and_skip() {
+        let dashboard = Arc::new(Dashboard::default());
+        let first = Uuid::from_u128(1);
+        let second = Uuid::from_u128(2);
+        let _first_entry = dashboard.start_chapter(first, "Ch. 1".to_owned(), 10, 0);
+        let second_entry = dashboard.start_chapter(second, "Ch. 2".to_owned(), 10, 0);
+
+        dashboard.toggle_pause();
+        let paused = tokio::spawn({
+            let dashboard = dashboard.clone();
+            async move { dashboard.checkpoint(first).await }
+        });
+        assert!(dashboard.checkpoint(second).await.is_ok());
+        tokio::task::yield_now().await;
+        assert!(!paused.is_finished());
+        dashboard.toggle_pause();
+        assert!(paused.await.unwrap().is_ok());
+
+        dashboard.select(1);
+        dashboard.skip();
+        assert!(matches!(
+            dashboard.checkpoint(second).await,
+            Err(DownloadError::Skipped)
+        ));
+        drop(second_entry);
+        assert_eq!(dashboard.state.lock().unwrap().selected, 0);
+    }
+
+    #[test]
+    fn test_render() {
+        let dashboard = Arc::new(Dashboard::default());
+        let _entry = dashboard.start_chapter(Uuid::from_u128(1), "Vol. 1 Ch. 3".to_owned(), 20, 5);
+        dashboard.page_done(Uuid::from_u128(1));
+        dashboard.add_message("WARN  Page 4 is missing".to_owned());
+        let run = RunView {
+            pages: 6,
+            bytes: 3 * 1024 * 1024,
+            elapsed: Duration::from_secs(3),
+            waiting: 2,
+            origins: vec![OriginState {
+                origin: "https://api.mangadex.org".to_owned(),
+                in_use: 1,
+                recent_requests: None,
+                locked_for: Some(Duration::from_secs(90)),
+                budget: Some((0, 40)),
+            }],
+        };
+        let mut terminal = ratatui::Terminal::new(TestBackend::new(100, 30)).unwrap();
+        terminal.draw(|frame| render(frame, &dashboard, &run)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.contains("6 pages, 3.0 MiB in 3s at 1.0 MiB/s, 2 requests waiting"));
+        assert!(screen.contains("6/20   running  Vol. 1 Ch. 3"));
+        assert!(screen.contains("0/40"));
+        assert!(screen.contains("for 1m 30s"));
+        assert!(screen.contains("Page 4 is missing"));
+    }
+}
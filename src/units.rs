@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+/// Split "12.5MiB" into (12.5, "MiB")
+fn split_number(value: &str) -> Result<(f64, &str), String> {
+    let value = value.trim();
+    let unit_start = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(unit_start);
+    let number = number
+        .parse::<f64>()
+        .map_err(|_| format!("\"{}\" doesn't start with a number", value))?;
+    Ok((number, unit.trim()))
+}
+
+/// Parse a human friendly duration like `90s`, `12h`, `500ms` or `1h30m`. A plain
+/// number without a unit is interpreted as a multiple of `bare_unit`.
+pub fn parse_duration(value: &str, bare_unit: Duration) -> Result<Duration, String> {
+    if value.trim().is_empty() {
+        return Err("Expected a duration, e.g. 90s or 12h".to_owned());
+    }
+    let mut rest = value.trim();
+    let mut total = Duration::ZERO;
+    while !rest.is_empty() {
+        let (number, unit_and_rest) = split_number(rest)?;
+        let unit_len = unit_and_rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(unit_and_rest.len());
+        let (unit, next) = unit_and_rest.split_at(unit_len);
+        let unit = match unit.trim() {
+            "" if next.is_empty() && total.is_zero() => bare_unit,
+            "ms" => Duration::from_millis(1),
+            "s" | "sec" | "secs" => Duration::from_secs(1),
+            "m" | "min" | "mins" => Duration::from_secs(60),
+            "h" | "hr" | "hrs" => Duration::from_secs(60 * 60),
+            "d" | "day" | "days" => Duration::from_secs(24 * 60 * 60),
+            "w" | "week" | "weeks" => Duration::from_secs(7 * 24 * 60 * 60),
+            unit => {
+                return Err(format!(
+                    "Unknown duration unit \"{}\" in \"{}\", expected one of ms, s, m, h, d, w",
+                    unit, value
+                ))
+            }
+        };
+        total += unit.mul_f64(number);
+        rest = next;
+    }
+    Ok(total)
+}
+
+/// Parse a human friendly size like `2MiB`, `500k` or `1.5GB` into bytes. Both SI
+/// (kB, MB) and binary (KiB, MiB) prefixes are understood, a single letter prefix is
+/// treated as binary.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let (number, unit) = split_number(value)?;
+    let multiplier: u64 = match unit.to_ascii_lowercase().trim_end_matches("/s") {
+        "" | "b" => 1,
+        "kb" => 1000,
+        "mb" => 1000 * 1000,
+        "gb" => 1000 * 1000 * 1000,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        _ => {
+            return Err(format!(
+                "Unknown size unit \"{}\" in \"{}\", expected e.g. KiB, MiB, MB",
+                unit, value
+            ))
+        }
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Format a number of bytes for people, e.g. `1.5 GiB`
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64;
+    let mut unit = "B";
+    for next in UNITS {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next;
+    }
+    format!("{:.1} {}", value, unit)
+}
+
+/// Format a duration for people, to the nearest second, e.g. `1h 5m` or `42s`
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs_f64().round() as u64;
+    match (secs / 3600, secs % 3600 / 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {}s", m, s),
+        (h, m, _) => format!("{}h {}m", h, m),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        let second = Duration::from_secs(1);
+        assert_eq!(parse_duration("90s", second), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("12h", second), Ok(Duration::from_secs(12 * 3600)));
+        assert_eq!(parse_duration("1h30m", second), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("500ms", second), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("1.5", second), Ok(Duration::from_millis(1500)));
+        assert_eq!(
+            parse_duration("2", Duration::from_secs(3600)),
+            Ok(Duration::from_secs(7200))
+        );
+        assert!(parse_duration("", second).is_err());
+        assert!(parse_duration("12 parsecs", second).is_err());
+        assert!(parse_duration("h", second).is_err());
+        // A bare number is only allowed on its own
+        assert!(parse_duration("1h30", second).is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("2MiB"), Ok(2 * 1024 * 1024));
+        assert_eq!(parse_size("5MB"), Ok(5_000_000));
+        assert_eq!(parse_size("512k"), Ok(512 * 1024));
+        assert_eq!(parse_size("5MiB/s"), Ok(5 * 1024 * 1024));
+        assert_eq!(parse_size("100"), Ok(100));
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("5 parsecs").is_err());
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(100), "100 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_millis(4600)), "5s");
+        assert_eq!(format_duration(Duration::from_secs(125)), "2m 5s");
+        assert_eq!(format_duration(Duration::from_secs(3 * 3600 + 65)), "3h 1m");
+    }
+}
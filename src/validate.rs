@@ -0,0 +1,362 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::units;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+}
+
+impl ImageFormat {
+    /// The usual file extension of the format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Gif => "gif",
+            ImageFormat::Webp => "webp",
+        }
+    }
+}
+
+impl Display for ImageFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageFormat::Png => write!(f, "png"),
+            ImageFormat::Jpeg => write!(f, "jpeg"),
+            ImageFormat::Gif => write!(f, "gif"),
+            ImageFormat::Webp => write!(f, "webp"),
+        }
+    }
+}
+
+impl std::str::FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "png" => Ok(ImageFormat::Png),
+            "jpeg" | "jpg" => Ok(ImageFormat::Jpeg),
+            "gif" => Ok(ImageFormat::Gif),
+            "webp" => Ok(ImageFormat::Webp),
+            v => Err(format!(
+                "Unknown image format \"{}\", expected png, jpeg, gif or webp",
+                v
+            )),
+        }
+    }
+}
+
+/// How pages get their file extensions, set with `--page-extensions`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ExtensionScheme {
+    /// From what the image turns out to be, whatever its url says
+    #[default]
+    Detected,
+    /// From the url, as the at-home server names the page
+    Url,
+    /// The same extension for every page, for readers that only look for one
+    Fixed(String),
+}
+
+impl std::str::FromStr for ExtensionScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "detected" => Ok(ExtensionScheme::Detected),
+            "url" => Ok(ExtensionScheme::Url),
+            v => {
+                let extension = v.trim_start_matches('.').to_ascii_lowercase();
+                if extension.is_empty() || !extension.chars().all(|c| c.is_ascii_alphanumeric()) {
+                    return Err(format!(
+                        "Invalid page extensions \"{}\", expected detected, url or an extension like jpg",
+                        v
+                    ));
+                }
+                Ok(ExtensionScheme::Fixed(extension))
+            }
+        }
+    }
+}
+
+/// Identify the image in a file from its magic bytes
+pub fn detect_file_format(path: &Path) -> io::Result<Option<ImageFormat>> {
+    let mut header = Vec::new();
+    File::open(path)?.take(12).read_to_end(&mut header)?;
+    Ok(detect_format(&header))
+}
+
+/// Identify an image from its magic bytes
+pub fn detect_format(header: &[u8]) -> Option<ImageFormat> {
+    if header.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(ImageFormat::Png)
+    } else if header.starts_with(b"\xff\xd8\xff") {
+        Some(ImageFormat::Jpeg)
+    } else if header.starts_with(b"GIF87a") || header.starts_with(b"GIF89a") {
+        Some(ImageFormat::Gif)
+    } else if header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WEBP") {
+        Some(ImageFormat::Webp)
+    } else {
+        None
+    }
+}
+
+/// Offset of the start of frame marker of a JPEG, found by walking its segments
+fn jpeg_frame_header(data: &[u8]) -> Option<usize> {
+    let be16 = |i: usize| Some(u16::from_be_bytes(data.get(i..i + 2)?.try_into().ok()?) as usize);
+    let mut i = 2;
+    loop {
+        if *data.get(i)? != 0xff {
+            return None;
+        }
+        let marker = *data.get(i + 1)?;
+        match marker {
+            0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => return Some(i),
+            // Fill bytes and markers without a payload
+            0xff => i += 1,
+            0x01 | 0xd0..=0xd7 => i += 2,
+            _ => i += 2 + be16(i + 2)?,
+        }
+    }
+}
+
+/// Number of colour components of a JPEG: 1 for greyscale, 3 for YCbCr and 4 for CMYK
+pub fn jpeg_components(data: &[u8]) -> Option<u8> {
+    data.get(jpeg_frame_header(data)? + 9).copied()
+}
+
+/// Read the width and height of an image without decoding it
+pub fn image_dimensions(data: &[u8], format: ImageFormat) -> Option<(u32, u32)> {
+    let be16 = |i: usize| Some(u16::from_be_bytes(data.get(i..i + 2)?.try_into().ok()?) as u32);
+    let le16 = |i: usize| Some(u16::from_le_bytes(data.get(i..i + 2)?.try_into().ok()?) as u32);
+    let le24 = |i: usize| {
+        Some(u32::from_le_bytes([
+            *data.get(i)?,
+            *data.get(i + 1)?,
+            *data.get(i + 2)?,
+            0,
+        ]))
+    };
+    match format {
+        // The IHDR chunk always comes first
+        ImageFormat::Png => Some((
+            u32::from_be_bytes(data.get(16..20)?.try_into().ok()?),
+            u32::from_be_bytes(data.get(20..24)?.try_into().ok()?),
+        )),
+        ImageFormat::Gif => Some((
+            u16::from_le_bytes(data.get(6..8)?.try_into().ok()?) as u32,
+            u16::from_le_bytes(data.get(8..10)?.try_into().ok()?) as u32,
+        )),
+        ImageFormat::Jpeg => {
+            let i = jpeg_frame_header(data)?;
+            Some((be16(i + 7)?, be16(i + 5)?))
+        }
+        // The first chunk is a lossy or lossless frame, or the extended header
+        ImageFormat::Webp => match data.get(12..16)? {
+            b"VP8 " => Some((le16(26)? & 0x3fff, le16(28)? & 0x3fff)),
+            b"VP8L" => {
+                let bits = u32::from_le_bytes(data.get(21..25)?.try_into().ok()?);
+                Some(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+            }
+            b"VP8X" => Some((le24(24)? + 1, le24(27)? + 1)),
+            _ => None,
+        },
+    }
+}
+
+/// What counts as a valid page. Flaky nodes sometimes serve tiny placeholder images
+/// instead of the actual page, these can be rejected by setting a minimum size.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidationPolicy {
+    /// Check that pages are png, jpeg, gif or webp images
+    pub check_format: bool,
+    pub min_bytes: u64,
+    pub min_width: u32,
+    pub min_height: u32,
+    /// Empty to allow every format we know of
+    pub allowed_formats: Vec<ImageFormat>,
+}
+
+impl Default for ValidationPolicy {
+    fn default() -> Self {
+        ValidationPolicy {
+            check_format: false,
+            min_bytes: 1,
+            min_width: 0,
+            min_height: 0,
+            allowed_formats: Vec::new(),
+        }
+    }
+}
+
+impl ValidationPolicy {
+    pub fn set_min_bytes(&mut self, value: &str) -> Result<(), String> {
+        self.min_bytes = units::parse_size(value)?.max(1);
+        Ok(())
+    }
+
+    /// Parse dimensions like `400x300`
+    pub fn set_min_dimensions(&mut self, value: &str) -> Result<(), String> {
+        let (width, height) = value
+            .split_once(['x', 'X'])
+            .ok_or_else(|| format!("Expected dimensions as WIDTHxHEIGHT, got \"{}\"", value))?;
+        let parse = |v: &str| {
+            v.trim()
+                .parse()
+                .map_err(|_| format!("Invalid dimensions \"{}\"", value))
+        };
+        self.min_width = parse(width)?;
+        self.min_height = parse(height)?;
+        self.check_format = true;
+        Ok(())
+    }
+
+    pub fn set_allowed_formats(&mut self, value: &str) -> Result<(), String> {
+        self.allowed_formats = value.split(',').map(str::parse).collect::<Result<_, _>>()?;
+        self.check_format = true;
+        Ok(())
+    }
+
+    fn needs_dimensions(&self) -> bool {
+        self.min_width > 0 || self.min_height > 0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PageProblem {
+    Missing,
+    Empty,
+    TooSmall(u64),
+    UnknownFormat,
+    DisallowedFormat(ImageFormat),
+    TooFewPixels(u32, u32),
+}
+
+impl Display for PageProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PageProblem::Missing => write!(f, "file is missing"),
+            PageProblem::Empty => write!(f, "file is empty"),
+            PageProblem::TooSmall(bytes) => write!(f, "file is only {} bytes", bytes),
+            PageProblem::UnknownFormat => write!(f, "file is not a png, jpeg, gif or webp image"),
+            PageProblem::DisallowedFormat(format) => write!(f, "{} images are not allowed", format),
+            PageProblem::TooFewPixels(width, height) => write!(f, "image is only {}x{}", width, height),
+        }
+    }
+}
+
+/// Check that a downloaded page looks sane, returning what is wrong with it if not
+pub fn check_page(path: &Path, policy: &ValidationPolicy) -> io::Result<Option<PageProblem>> {
+    if !path.exists() {
+        return Ok(Some(PageProblem::Missing));
+    }
+    let len = path.metadata()?.len();
+    if len == 0 {
+        return Ok(Some(PageProblem::Empty));
+    }
+    if len < policy.min_bytes {
+        return Ok(Some(PageProblem::TooSmall(len)));
+    }
+    if policy.check_format {
+        let mut data = Vec::new();
+        let file = File::open(path)?;
+        // The dimensions of a jpeg can be anywhere after its metadata
+        let limit = if policy.needs_dimensions() { u64::MAX } else { 12 };
+        file.take(limit).read_to_end(&mut data)?;
+        let format = match detect_format(&data) {
+            Some(format) => format,
+            None => return Ok(Some(PageProblem::UnknownFormat)),
+        };
+        if !policy.allowed_formats.is_empty() && !policy.allowed_formats.contains(&format) {
+            return Ok(Some(PageProblem::DisallowedFormat(format)));
+        }
+        if policy.needs_dimensions() {
+            match image_dimensions(&data, format) {
+                Some((width, height)) if width >= policy.min_width && height >= policy.min_height => {}
+                Some((width, height)) => return Ok(Some(PageProblem::TooFewPixels(width, height))),
+                None => return Ok(Some(PageProblem::UnknownFormat)),
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(detect_format(b"\x89PNG\r\n\x1a\n...."), Some(ImageFormat::Png));
+        assert_eq!(detect_format(b"\xff\xd8\xff\xe0"), Some(ImageFormat::Jpeg));
+        assert_eq!(detect_format(b"GIF89a.."), Some(ImageFormat::Gif));
+        assert_eq!(detect_format(b"RIFF\x24\0\0\0WEBPVP8X"), Some(ImageFormat::Webp));
+        assert_eq!(detect_format(b"RIFF\x24\0\0\0WAVE"), None);
+        assert_eq!(detect_format(b"<html>"), None);
+        assert_eq!(detect_format(b""), None);
+    }
+
+    #[test]
+    fn test_extension_schemes() {
+        assert_eq!("detected".parse(), Ok(ExtensionScheme::Detected));
+        assert_eq!("url".parse(), Ok(ExtensionScheme::Url));
+        assert_eq!(".JPG".parse(), Ok(ExtensionScheme::Fixed("jpg".to_owned())));
+        assert!("".parse::<ExtensionScheme>().is_err());
+        assert!("../png".parse::<ExtensionScheme>().is_err());
+        assert_eq!(ImageFormat::Jpeg.extension(), "jpg");
+    }
+
+    #[test]
+    fn test_image_dimensions() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend_from_slice(&800u32.to_be_bytes());
+        png.extend_from_slice(&1200u32.to_be_bytes());
+        assert_eq!(image_dimensions(&png, ImageFormat::Png), Some((800, 1200)));
+
+        let gif = b"GIF89a\x20\x03\xb0\x04";
+        assert_eq!(image_dimensions(gif, ImageFormat::Gif), Some((800, 1200)));
+
+        // SOI, an APP0 segment with 2 bytes of payload, then SOF0
+        let jpeg = b"\xff\xd8\xff\xe0\x00\x04ab\xff\xc0\x00\x11\x08\x04\xb0\x03\x20";
+        assert_eq!(image_dimensions(jpeg, ImageFormat::Jpeg), Some((800, 1200)));
+        assert_eq!(image_dimensions(b"\xff\xd8\xff\xe0", ImageFormat::Jpeg), None);
+
+        // The extended header stores both sizes minus one in 24 bits
+        let webp = b"RIFF\x24\0\0\0WEBPVP8X\x0a\0\0\0\0\0\0\0\x1f\x03\0\xaf\x04\0";
+        assert_eq!(image_dimensions(webp, ImageFormat::Webp), Some((800, 1200)));
+    }
+
+    #[test]
+    fn test_check_page_policy() {
+        let dir = std::env::temp_dir().join(format!("mdscrape-test-{}", uuid::Uuid::from_u128(rand::random())));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("0001.gif");
+        std::fs::write(&path, b"GIF89a\x01\x00\x01\x00").unwrap();
+
+        let mut policy = ValidationPolicy::default();
+        assert_eq!(check_page(&path, &policy).unwrap(), None);
+        policy.set_min_dimensions("100x100").unwrap();
+        assert_eq!(
+            check_page(&path, &policy).unwrap(),
+            Some(PageProblem::TooFewPixels(1, 1))
+        );
+        policy.set_allowed_formats("png,jpeg").unwrap();
+        assert_eq!(
+            check_page(&path, &policy).unwrap(),
+            Some(PageProblem::DisallowedFormat(ImageFormat::Gif))
+        );
+        policy.set_min_bytes("1KiB").unwrap();
+        assert_eq!(check_page(&path, &policy).unwrap(), Some(PageProblem::TooSmall(10)));
+
+        assert!(policy.set_min_dimensions("100").is_err());
+        assert!(policy.set_allowed_formats("png,bmp").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,201 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use uuid::Uuid;
+
+use crate::manifest::MANIFEST_FILE_NAME;
+use crate::retry::Result;
+
+pub const WORKSPACE_DIR_NAME: &str = ".mdscrape-tmp";
+
+/// Staging area for in-progress chapters. Every chapter is downloaded into
+/// `.mdscrape-tmp/<chapter-uuid>/` under the output root, and only moved into its
+/// final location once all of its pages are present. Anything left in the
+/// workspace after a crash is incomplete, and can simply be deleted.
+#[derive(Clone, Debug)]
+pub struct Workspace {
+    output_root: PathBuf,
+    root: PathBuf,
+}
+
+impl Workspace {
+    pub fn new(output_root: &impl AsRef<Path>) -> Self {
+        Workspace {
+            output_root: output_root.as_ref().to_path_buf(),
+            root: output_root.as_ref().join(WORKSPACE_DIR_NAME),
+        }
+    }
+
+    /// The directory chapters are downloaded into, which the workspace is part of
+    pub fn output_root(&self) -> &Path {
+        &self.output_root
+    }
+
+    /// Make sure we can actually write to the output directory before spending any
+    /// requests, so that a read-only mount or a full disk is reported up front
+    pub fn check_writable(&self) -> Result<()> {
+        fs::create_dir_all(&self.root)?;
+        let probe = self.root.join(".write-probe");
+        fs::write(&probe, b"mdscrape")?;
+        fs::remove_file(&probe)?;
+        Ok(())
+    }
+
+    pub fn staging_path(&self, chapter_id: Uuid) -> PathBuf {
+        self.root.join(chapter_id.to_string())
+    }
+
+    /// Create a fresh staging directory for a chapter, discarding anything left
+    /// over from a previous (crashed) run.
+    pub fn stage_chapter(&self, chapter_id: Uuid) -> Result<StagedChapter> {
+        let path = self.staging_path(chapter_id);
+        if path.exists() {
+            debug!("Removing stale staging directory {:?}", path);
+            fs::remove_dir_all(&path)?;
+        }
+        fs::create_dir_all(&path)?;
+        Ok(StagedChapter {
+            path,
+            committed: false,
+            keep_unfinished: false,
+        })
+    }
+
+    /// Stage a chapter for `--existing resume`: whatever a previous run left in its
+    /// staging directory is kept, and so is anything this run leaves behind.
+    pub fn resume_chapter(&self, chapter_id: Uuid) -> Result<StagedChapter> {
+        let path = self.staging_path(chapter_id);
+        if path.exists() {
+            debug!("Resuming staged chapter in {:?}", path);
+        }
+        fs::create_dir_all(&path)?;
+        Ok(StagedChapter {
+            path,
+            committed: false,
+            keep_unfinished: true,
+        })
+    }
+
+    /// Move a completed chapter into place. If the destination doesn't exist yet the
+    /// whole directory is renamed in one step, otherwise the staged files are moved
+    /// into it one by one. The manifest goes last in that case, so that a chapter
+    /// interrupted halfway through still has the old manifest and isn't taken for
+    /// complete.
+    pub fn commit_chapter(&self, mut staging: StagedChapter, destination: &Path) -> Result<()> {
+        if !destination.exists() {
+            if let Some(parent) = destination.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            debug!("Moving {:?} into place at {:?}", staging, destination);
+            fs::rename(&staging, destination)?;
+            staging.committed = true;
+            return Ok(());
+        }
+        let mut entries = fs::read_dir(&staging)?.collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name() == MANIFEST_FILE_NAME);
+        for entry in entries {
+            fs::rename(entry.path(), destination.join(entry.file_name()))?;
+        }
+        fs::remove_dir(&staging)?;
+        staging.committed = true;
+        Ok(())
+    }
+
+    /// Remove the workspace root if nothing is left staged in it. This also happens
+    /// when the workspace is dropped, whether or not the run succeeded.
+    pub fn cleanup(&self) {
+        if fs::remove_dir(&self.root).is_ok() {
+            debug!("Removed empty workspace {:?}", self.root);
+        }
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        self.cleanup();
+    }
+}
+
+/// A chapter that is being downloaded into the workspace. If it is dropped without
+/// being committed, e.g. because a page failed, its staging directory is removed so
+/// that nothing half finished is left behind, unless it is kept to be resumed.
+#[derive(Debug)]
+pub struct StagedChapter {
+    path: PathBuf,
+    committed: bool,
+    keep_unfinished: bool,
+}
+
+impl std::ops::Deref for StagedChapter {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for StagedChapter {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for StagedChapter {
+    fn drop(&mut self) {
+        if !self.committed && !self.keep_unfinished && self.path.exists() {
+            debug!("Discarding unfinished chapter in {:?}", self.path);
+            if let Err(e) = fs::remove_dir_all(&self.path) {
+                log::warn!("Failed to remove {:?}: {}", self.path, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stage_and_commit() {
+        let base = std::env::temp_dir().join(format!("mdscrape-test-{}", Uuid::from_u128(rand::random())));
+        let workspace = Workspace::new(&base);
+        let chapter_id = Uuid::from_u128(rand::random());
+
+        let staging = workspace.stage_chapter(chapter_id).unwrap();
+        fs::write(staging.join("0001.png"), b"page").unwrap();
+        let destination = base.join("chapter");
+        let staging_path = staging.to_path_buf();
+        workspace.commit_chapter(staging, &destination).unwrap();
+        assert!(destination.join("0001.png").exists());
+        assert!(!staging_path.exists());
+
+        // Committing into an existing directory merges the files
+        let staging = workspace.stage_chapter(chapter_id).unwrap();
+        fs::write(staging.join("0002.png"), b"page").unwrap();
+        workspace.commit_chapter(staging, &destination).unwrap();
+        assert!(destination.join("0001.png").exists());
+        assert!(destination.join("0002.png").exists());
+
+        // A chapter that is never committed cleans up after itself
+        let staging = workspace.stage_chapter(chapter_id).unwrap();
+        fs::write(staging.join("0003.png"), b"page").unwrap();
+        let staging_path = staging.to_path_buf();
+        drop(staging);
+        assert!(!staging_path.exists());
+        assert!(!destination.join("0003.png").exists());
+
+        // Unless it is resumed later
+        let staging = workspace.resume_chapter(chapter_id).unwrap();
+        fs::write(staging.join("0003.png"), b"page").unwrap();
+        drop(staging);
+        let staging = workspace.resume_chapter(chapter_id).unwrap();
+        assert!(staging.join("0003.png").exists());
+        workspace.commit_chapter(staging, &destination).unwrap();
+        assert!(destination.join("0003.png").exists());
+
+        workspace.cleanup();
+        assert!(!base.join(WORKSPACE_DIR_NAME).exists());
+        fs::remove_dir_all(&base).unwrap();
+    }
+}